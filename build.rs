@@ -0,0 +1,29 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The gRPC service is declared here instead of compiled from proto/walletmanager/v1 so
+    // building doesn't need protoc; the message types live in src/grpc.rs.
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let wallet_watch = Service::builder()
+            .name("WalletWatch")
+            .package("walletmanager.v1")
+            .method(
+                Method::builder()
+                    .name("watch_wallets")
+                    .route_name("WatchWallets")
+                    .input_type("crate::grpc::WatchFilter")
+                    .output_type("crate::grpc::WalletUpdate")
+                    .codec_path("tonic_prost::ProstCodec")
+                    .server_streaming()
+                    .build(),
+            )
+            .build();
+        Builder::new().compile(&[wallet_watch]);
+    }
+
+    #[cfg(feature = "napi")]
+    napi_build::setup();
+}
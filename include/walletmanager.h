@@ -0,0 +1,67 @@
+#ifndef WALLETMANAGER_H
+#define WALLETMANAGER_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of `wm_submit_csv_row`.
+typedef enum WmStatus {
+  // The transaction was applied.
+  WM_STATUS_APPLIED = 0,
+  // The transaction was valid but rejected, e.g. a withdrawal exceeding the available funds.
+  WM_STATUS_REJECTED = 1,
+  // The row isn't a transaction.
+  WM_STATUS_INVALID_ROW = 2,
+  // A pointer argument was null or the row isn't UTF-8.
+  WM_STATUS_INVALID_ARGUMENT = 3,
+} WmStatus;
+
+// An engine created by `wm_create`.
+typedef struct WmEngine WmEngine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an engine, to be released with `wm_destroy`.
+WmEngine *wm_create(void);
+
+// Applies one row in the CSV format of the input, without a header:
+// `type,client,tx,amount[,timestamp]`.
+//
+// # Safety
+//
+// `engine` must come from `wm_create` and `row` must be a NUL-terminated string.
+WmStatus wm_submit_csv_row(const WmEngine *engine, const char *row);
+
+// The wallets as a JSON array of `{client, available, held, total, locked}` objects ordered by
+// client, or null if `engine` is null. The string is released with `wm_free_string`.
+//
+// # Safety
+//
+// `engine` must come from `wm_create`.
+char *wm_export_json(const WmEngine *engine);
+
+// Releases a string returned by `wm_export_json`; null is ignored.
+//
+// # Safety
+//
+// `string` must come from `wm_export_json` and not be used afterwards.
+void wm_free_string(char *string);
+
+// Releases an engine; null is ignored.
+//
+// # Safety
+//
+// `engine` must come from `wm_create` and not be used afterwards.
+void wm_destroy(WmEngine *engine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WALLETMANAGER_H */
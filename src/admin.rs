@@ -0,0 +1,456 @@
+//! Control plane of a running server. `--admin-socket` accepts one JSON request per line on a Unix
+//! socket and answers each with one JSON line; the `admin` subcommand is its client, so operators
+//! can intervene without restarting the server.
+
+use crate::analytics::DepositVolume;
+use crate::export::{ExportOptions, write_wallets_csv};
+use crate::merkle::{BalanceTree, InclusionProof};
+use crate::queue::QueueStats;
+use crate::replica::ReplicaWallet;
+use crate::reservation::ReservationId;
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Amount, Client, Envelope, Tenant, TransactionId};
+use crate::wallet_manager::ProjectedBalance;
+use anyhow::{Context, bail};
+use clap::Subcommand;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Notify;
+
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum AdminCommand {
+    /// Reject every further transaction of a client until it is unfrozen
+    Freeze { client: u16 },
+    /// Accept transactions of a frozen client again
+    Unfreeze { client: u16 },
+    /// Lift the lock a chargeback put on a client's wallet
+    Unlock { client: u16 },
+    /// Print the wallet export, with sequence numbers, as of now
+    Snapshot,
+    /// Print wallet, failure, sequence and queue counters as JSON
+    Stats,
+    /// Print a client's deposit volume over the last hour and day as JSON
+    Volume { client: u16 },
+    /// Print a client's balance as JSON, from the read replica if the server keeps one
+    Balance { client: u16 },
+    /// Print the balance a transaction, given as a CSV or JSON row, would leave its wallet with,
+    /// without applying it
+    Simulate { row: String },
+    /// Hold funds of a client for a pending operation and print the reservation id
+    Reserve {
+        client: u16,
+        /// Transaction id the reservation is withdrawn as once committed
+        tx: u32,
+        amount: Amount,
+    },
+    /// Withdraw the funds of a reservation
+    Commit { reservation: u64 },
+    /// Make the funds of a reservation available again
+    Release { reservation: u64 },
+    /// Print the Merkle inclusion proof of a client's current balance as JSON
+    Proof { client: u16 },
+    /// Release dispute holds older than `--dispute-expiry-secs` as of the latest input timestamp
+    Expire,
+    /// Stop reading input, write the outputs and exit, like Ctrl-C
+    Drain,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Request {
+    #[serde(flatten)]
+    command: AdminCommand,
+    tenant: Option<Tenant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Reply {
+    Ok,
+    Snapshot(String),
+    Stats(Stats),
+    Volume(DepositVolume),
+    Balance(ReplicaWallet),
+    Projected(ProjectedBalance),
+    Reserved(ReservationId),
+    Proof(InclusionProof),
+    Released(usize),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Stats {
+    pub wallets: usize,
+    pub failures: usize,
+    /// Sequence number of the last transaction that reached the namespace.
+    pub last_seq: u64,
+    /// The input queue shared by every namespace, as of its latest sample.
+    pub queue: QueueStats,
+}
+
+/// The socket file of a bound admin socket, removed when dropped.
+#[derive(Debug)]
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Binds the admin socket at `path`, readable and writable by the owner only since requests
+/// aren't authenticated. A socket a crashed server left behind is replaced; one a running
+/// server still listens on, or any other file, is an error. The returned guard removes the
+/// socket file again.
+pub fn bind(path: &Path) -> anyhow::Result<(UnixListener, SocketFile)> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("another server listens on {}", path.display());
+        }
+        warn!("Removing the stale admin socket {}", path.display());
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    let socket = SocketFile(path.to_path_buf());
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict {}", path.display()))?;
+    Ok((listener, socket))
+}
+
+/// Answers admin requests until the process exits. `drain` is notified when a client asks the
+/// server to stop.
+pub async fn serve(listener: UnixListener, registry: Arc<TenantRegistry>, drain: Arc<Notify>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, registry.clone(), drain.clone()));
+            }
+            Err(e) => warn!("Failed to accept admin connection: {e}"),
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, registry: Arc<TenantRegistry>, drain: Arc<Notify>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let reply = match serde_json::from_str(&line) {
+            Ok(request) => handle(request, &registry, &drain),
+            Err(e) => Reply::Error(format!("invalid request: {e}")),
+        };
+        let mut line = serde_json::to_vec(&reply).expect("replies serialize");
+        line.push(b'\n');
+        if writer.write_all(&line).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn handle(request: Request, registry: &TenantRegistry, drain: &Notify) -> Reply {
+    let tenant = request.tenant;
+    let manager = registry.manager(tenant.as_ref());
+    match request.command {
+        AdminCommand::Freeze { client } | AdminCommand::Unfreeze { client } => {
+            let freeze = matches!(request.command, AdminCommand::Freeze { .. });
+            if manager.set_frozen(Client::new(client), freeze) {
+                info!("Admin set client {client} frozen: {freeze}");
+                Reply::Ok
+            } else {
+                Reply::Error(format!("no wallet for client {client}"))
+            }
+        }
+        AdminCommand::Unlock { client } => {
+            if manager.unlock(Client::new(client)) {
+                info!("Admin unlocked client {client}");
+                Reply::Ok
+            } else {
+                Reply::Error(format!("no wallet for client {client}"))
+            }
+        }
+        AdminCommand::Snapshot => {
+            let options = ExportOptions {
+                seq: true,
+                status: true,
+                ..ExportOptions::default()
+            };
+            let mut csv = Vec::new();
+            match write_wallets_csv(&mut csv, &manager.export_wallets(), &options) {
+                Ok(()) => Reply::Snapshot(String::from_utf8(csv).expect("CSV export is UTF-8")),
+                Err(e) => Reply::Error(e.to_string()),
+            }
+        }
+        AdminCommand::Stats => Reply::Stats(Stats {
+            wallets: manager.wallet_count(),
+            failures: manager.failure_count(),
+            last_seq: manager.last_sequence(),
+            queue: registry.queue().latest(),
+        }),
+        AdminCommand::Volume { client } => {
+            Reply::Volume(manager.deposit_volume(Client::new(client)))
+        }
+        AdminCommand::Balance { client } => {
+            let client = Client::new(client);
+            let wallet = match registry.read_replica() {
+                Some(replica) => replica.wallet(tenant.as_ref(), client),
+                None => manager.wallet(client).as_ref().map(ReplicaWallet::from),
+            };
+            match wallet {
+                Some(wallet) => Reply::Balance(wallet),
+                None => Reply::Error(format!("no wallet for client {client:?}")),
+            }
+        }
+        AdminCommand::Simulate { row } => match Envelope::from_line(&row) {
+            Some(envelope) => match manager.simulate(envelope.transaction) {
+                Ok(projected) => Reply::Projected(projected),
+                Err(failure) => Reply::Error(failure.reason),
+            },
+            None => Reply::Error(format!("invalid transaction row {row:?}")),
+        },
+        AdminCommand::Reserve { client, tx, amount } => {
+            match manager.reserve(Client::new(client), TransactionId::new(tx), amount) {
+                Ok(reservation) => Reply::Reserved(reservation),
+                Err(failure) => Reply::Error(failure.reason),
+            }
+        }
+        AdminCommand::Commit { reservation } | AdminCommand::Release { reservation } => {
+            let id = ReservationId::new(reservation);
+            let settled = match request.command {
+                AdminCommand::Commit { .. } => manager.commit(id),
+                _ => Ok(manager.release(id)),
+            };
+            match settled {
+                Ok(Some(_)) => Reply::Ok,
+                Ok(None) => Reply::Error(format!("no reservation {reservation}")),
+                Err(failure) => Reply::Error(failure.reason),
+            }
+        }
+        AdminCommand::Proof { client } => {
+            match BalanceTree::new(&manager.export_wallets()).proof(Client::new(client)) {
+                Some(proof) => Reply::Proof(proof),
+                None => Reply::Error(format!("no wallet for client {client}")),
+            }
+        }
+        AdminCommand::Expire => {
+            let released = manager.expire_disputes();
+            info!("Admin released {released} expired holds");
+            Reply::Released(released)
+        }
+        AdminCommand::Drain => {
+            info!("Admin requested a drain");
+            drain.notify_one();
+            Reply::Ok
+        }
+    }
+}
+
+/// Sends `command` to the server listening on `socket` and prints its answer.
+pub async fn run(
+    socket: &Path,
+    tenant: Option<String>,
+    command: AdminCommand,
+) -> anyhow::Result<()> {
+    let tenant = tenant
+        .map(|tenant| {
+            Tenant::parse(&tenant).with_context(|| format!("invalid tenant id {tenant:?}"))
+        })
+        .transpose()?;
+    let stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("failed to connect to {}", socket.display()))?;
+    match request(stream, Request { command, tenant }).await? {
+        Reply::Ok => Ok(()),
+        Reply::Snapshot(csv) => {
+            print!("{csv}");
+            Ok(())
+        }
+        Reply::Stats(stats) => {
+            println!("{}", serde_json::to_string(&stats)?);
+            Ok(())
+        }
+        Reply::Volume(volume) => {
+            println!("{}", serde_json::to_string(&volume)?);
+            Ok(())
+        }
+        Reply::Balance(wallet) => {
+            println!("{}", serde_json::to_string(&wallet)?);
+            Ok(())
+        }
+        Reply::Projected(projected) => {
+            println!("{}", serde_json::to_string(&projected)?);
+            Ok(())
+        }
+        Reply::Reserved(reservation) => {
+            println!("reservation {}", reservation.id());
+            Ok(())
+        }
+        Reply::Proof(proof) => {
+            anyhow::ensure!(
+                proof.verify(&proof.root),
+                "the proof doesn't lead to its root"
+            );
+            println!("{}", serde_json::to_string(&proof)?);
+            Ok(())
+        }
+        Reply::Released(released) => {
+            println!("released {released} expired holds");
+            Ok(())
+        }
+        Reply::Error(e) => bail!(e),
+    }
+}
+
+async fn request(stream: UnixStream, request: Request) -> anyhow::Result<Reply> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_vec(&request)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .context("server closed the connection without answering")?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::transaction::Transaction;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_admin_requests_control_running_registry() {
+        let socket =
+            std::env::temp_dir().join(format!("walletmanagermock-admin-{}", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let drain = Arc::new(Notify::new());
+        let (listener, socket_file) = bind(&socket).unwrap();
+        tokio::spawn(serve(listener, registry.clone(), drain.clone()));
+        let deposit = |tx: u32| {
+            Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        registry.apply(deposit(1)).unwrap();
+        let send = |command: AdminCommand| {
+            let socket = socket.clone();
+            async move {
+                let stream = UnixStream::connect(socket).await.unwrap();
+                request(
+                    stream,
+                    Request {
+                        command,
+                        tenant: None,
+                    },
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        assert_eq!(send(AdminCommand::Freeze { client: 1 }).await, Reply::Ok);
+        registry.apply(deposit(2)).unwrap_err();
+        assert_eq!(send(AdminCommand::Unfreeze { client: 1 }).await, Reply::Ok);
+        registry.apply(deposit(3)).unwrap();
+        assert!(matches!(
+            send(AdminCommand::Freeze { client: 2 }).await,
+            Reply::Error(_)
+        ));
+        assert_eq!(
+            send(AdminCommand::Stats).await,
+            Reply::Stats(Stats {
+                wallets: 1,
+                failures: 1,
+                last_seq: 3,
+                queue: QueueStats::default(),
+            })
+        );
+        assert_eq!(
+            send(AdminCommand::Snapshot).await,
+            Reply::Snapshot(
+                "client,available,held,total,locked,status,seq\n1,20.0000,0.0000,20.0000,false,active,3\n"
+                    .to_string()
+            )
+        );
+        let Reply::Balance(wallet) = send(AdminCommand::Balance { client: 1 }).await else {
+            panic!("expected a balance");
+        };
+        assert_eq!((wallet.total, wallet.seq), (Amount::from_major(20, 0), 3));
+        let Reply::Projected(projected) = send(AdminCommand::Simulate {
+            row: "withdrawal,1,9,5.0".to_string(),
+        })
+        .await
+        else {
+            panic!("expected a projected balance");
+        };
+        assert_eq!(projected.available, Amount::from_major(15, 0));
+        let reserve = AdminCommand::Reserve {
+            client: 1,
+            tx: 10,
+            amount: Amount::from_major(5, 0),
+        };
+        let Reply::Reserved(reservation) = send(reserve).await else {
+            panic!("expected a reservation");
+        };
+        let release = AdminCommand::Release {
+            reservation: reservation.id(),
+        };
+        assert_eq!(send(release.clone()).await, Reply::Ok);
+        assert!(matches!(send(release).await, Reply::Error(_)));
+        let Reply::Proof(proof) = send(AdminCommand::Proof { client: 1 }).await else {
+            panic!("expected a proof");
+        };
+        assert_eq!(proof.leaf, "1,20.0000,0.0000,20.0000");
+        assert!(
+            proof.verify(&BalanceTree::new(&registry.default_manager().export_wallets()).root())
+        );
+        assert_eq!(send(AdminCommand::Drain).await, Reply::Ok);
+        drain.notified().await;
+        drop(socket_file);
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_bind_restricts_and_replaces_stale_sockets() {
+        let socket = std::env::temp_dir().join(format!(
+            "walletmanagermock-admin-stale-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&socket);
+        // A crashed server leaves its socket file without a listener.
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(socket.exists());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let _entered = runtime.enter();
+        let (listener, socket_file) = bind(&socket).unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(bind(&socket).is_err());
+
+        drop(listener);
+        drop(socket_file);
+        assert!(!socket.exists());
+        std::fs::write(&socket, "not a socket").unwrap();
+        assert!(bind(&socket).is_err());
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
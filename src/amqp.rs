@@ -0,0 +1,172 @@
+//! RabbitMQ source. Deliveries are acked only once applied; failed transactions are rejected so
+//! the broker dead-letters them, except for failures that an out-of-order delivery can cause,
+//! which are requeued once.
+
+use crate::input::{MessageDecoder, ReadSummary};
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Failure, FailureKind};
+use futures::StreamExt;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, QueueDeclareOptions,
+};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{Connection, ConnectionProperties};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub struct AmqpOptions {
+    pub url: String,
+    pub queue: String,
+    /// Exchange the queue dead-letters rejected deliveries to, set when declaring the queue.
+    pub dead_letter_exchange: Option<String>,
+    /// Deliveries the broker sends before waiting for acks.
+    pub prefetch: u16,
+    pub decoder: MessageDecoder,
+}
+
+/// What happens to a delivery once it has been processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ack,
+    Requeue,
+    DeadLetter,
+}
+
+impl Outcome {
+    fn of(result: Option<&Result<(), Failure>>, redelivered: bool) -> Self {
+        match result {
+            Some(Ok(())) => Outcome::Ack,
+            // A dispute can overtake the deposit it refers to; give it one more chance.
+            Some(Err(failure))
+                if !redelivered
+                    && matches!(
+                        failure.kind,
+                        FailureKind::NoWallet | FailureKind::TransactionNotFound
+                    ) =>
+            {
+                Outcome::Requeue
+            }
+            Some(Err(_)) | None => Outcome::DeadLetter,
+        }
+    }
+}
+
+/// Consumes the queue until `shutdown` completes. Message payloads are decoded by
+/// `options.decoder`.
+pub async fn consume(
+    options: &AmqpOptions,
+    registry: Arc<TenantRegistry>,
+    err_send: UnboundedSender<Failure>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<ReadSummary> {
+    let connection = Connection::connect(&options.url, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    channel
+        .basic_qos(options.prefetch, BasicQosOptions::default())
+        .await?;
+    let mut arguments = FieldTable::default();
+    if let Some(exchange) = &options.dead_letter_exchange {
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(exchange.as_str().into()),
+        );
+    }
+    channel
+        .queue_declare(
+            &options.queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            arguments,
+        )
+        .await?;
+    let mut deliveries = channel
+        .basic_consume(
+            &options.queue,
+            "walletmanagermock",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    info!("Consuming queue {}", options.queue);
+
+    let mut summary = ReadSummary::default();
+    tokio::pin!(shutdown);
+    loop {
+        let delivery = tokio::select! {
+            delivery = deliveries.next() => match delivery {
+                Some(delivery) => delivery?,
+                None => break,
+            },
+            _ = &mut shutdown => break,
+        };
+        summary.rows_read += 1;
+        let envelope = options.decoder.decode(&delivery.data).await;
+        if envelope.is_none() {
+            summary.rows_skipped += 1;
+        }
+        let result = envelope.map(|envelope| {
+            let transaction = envelope.transaction;
+            let res = registry.apply(envelope);
+            summary.applied.record(&transaction, res.is_ok());
+            res
+        });
+        let outcome = Outcome::of(result.as_ref(), delivery.redelivered);
+        if outcome == Outcome::Ack {
+            delivery.ack(BasicAckOptions::default()).await?;
+        } else {
+            let requeue = outcome == Outcome::Requeue;
+            delivery
+                .nack(BasicNackOptions {
+                    requeue,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        if let Some(Err(failure)) = result {
+            registry.deliver_failure(&err_send, failure);
+        }
+        if registry.aborted() {
+            summary.applied.stopped_early = true;
+            break;
+        }
+    }
+    if let Err(e) = connection.close(200, "shutting down").await {
+        warn!("Failed to close the AMQP connection: {e}");
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Client, TransactionId};
+
+    #[test]
+    fn test_outcome() {
+        let failure = |kind| {
+            Err(Failure::new(
+                Client::new(1),
+                TransactionId::new(1),
+                kind,
+                String::new(),
+            ))
+        };
+        assert_eq!(Outcome::of(Some(&Ok(())), false), Outcome::Ack);
+        assert_eq!(Outcome::of(None, false), Outcome::DeadLetter);
+        assert_eq!(
+            Outcome::of(Some(&failure(FailureKind::TransactionNotFound)), false),
+            Outcome::Requeue
+        );
+        assert_eq!(
+            Outcome::of(Some(&failure(FailureKind::TransactionNotFound)), true),
+            Outcome::DeadLetter
+        );
+        assert_eq!(
+            Outcome::of(Some(&failure(FailureKind::InsufficientFunds)), false),
+            Outcome::DeadLetter
+        );
+    }
+}
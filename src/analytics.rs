@@ -0,0 +1,116 @@
+//! Rolling aggregates maintained while transactions are applied, so that rules and reports can
+//! read them without a second pass over the input. Windows are measured in input time.
+
+use crate::transaction::{Amount, Client, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const HOUR_SECS: i64 = 3600;
+pub const DAY_SECS: i64 = Timestamp::SECONDS_PER_DAY;
+
+/// Sum of the amounts recorded within the last `window` seconds.
+#[derive(Debug, Clone)]
+struct RollingSum {
+    window: i64,
+    entries: VecDeque<(Timestamp, Amount)>,
+    sum: Amount,
+}
+
+impl RollingSum {
+    fn new(window: i64) -> Self {
+        RollingSum {
+            window,
+            entries: VecDeque::new(),
+            sum: Amount::zero(),
+        }
+    }
+
+    /// Drops the amounts that fell out of the window ending at `now`.
+    fn advance(&mut self, now: Timestamp) {
+        while let Some((timestamp, amount)) = self.entries.front()
+            && now.as_secs() - timestamp.as_secs() >= self.window
+        {
+            self.sum -= *amount;
+            self.entries.pop_front();
+        }
+        if self.entries.is_empty() {
+            // Resets the rounding error accumulated by adding and removing amounts.
+            self.sum = Amount::zero();
+        }
+    }
+
+    fn add(&mut self, timestamp: Timestamp, amount: Amount) {
+        self.advance(timestamp);
+        self.entries.push_back((timestamp, amount));
+        self.sum += amount;
+    }
+}
+
+/// Deposit volume of one wallet over the last hour and the last day.
+#[derive(Debug, Clone)]
+pub struct DepositWindows {
+    hour: RollingSum,
+    day: RollingSum,
+}
+
+impl Default for DepositWindows {
+    fn default() -> Self {
+        DepositWindows {
+            hour: RollingSum::new(HOUR_SECS),
+            day: RollingSum::new(DAY_SECS),
+        }
+    }
+}
+
+impl DepositWindows {
+    /// Records a deposit; the input has to be roughly in timestamp order, as a deposit older than
+    /// the window is dropped with the next one.
+    pub fn record(&mut self, timestamp: Timestamp, amount: Amount) {
+        self.hour.add(timestamp, amount);
+        self.day.add(timestamp, amount);
+    }
+
+    /// Moves both windows to end at `now`.
+    pub fn advance(&mut self, now: Timestamp) {
+        self.hour.advance(now);
+        self.day.advance(now);
+    }
+
+    pub fn volume(&self, client: Client) -> DepositVolume {
+        DepositVolume {
+            client,
+            last_hour: self.hour.sum,
+            last_day: self.day.sum,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepositVolume {
+    pub client: Client,
+    pub last_hour: Amount,
+    pub last_day: Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposits_leave_windows_as_time_passes() {
+        let mut windows = DepositWindows::default();
+        let client = Client::new(1);
+        windows.record(Timestamp::from_secs(0), Amount::from_major(10, 0));
+        windows.record(Timestamp::from_secs(1800), Amount::from_major(5, 0));
+        let volume = windows.volume(client);
+        assert_eq!(volume.last_hour, Amount::from_major(15, 0));
+        assert_eq!(volume.last_day, Amount::from_major(15, 0));
+
+        windows.advance(Timestamp::from_secs(HOUR_SECS));
+        assert_eq!(windows.volume(client).last_hour, Amount::from_major(5, 0));
+        windows.record(Timestamp::from_secs(DAY_SECS), Amount::from_major(1, 0));
+        let volume = windows.volume(client);
+        assert_eq!(volume.last_hour, Amount::from_major(1, 0));
+        assert_eq!(volume.last_day, Amount::from_major(6, 0));
+    }
+}
@@ -0,0 +1,994 @@
+#[cfg(unix)]
+use crate::admin;
+#[cfg(feature = "amqp")]
+use crate::amqp;
+#[cfg(feature = "avro")]
+use crate::avro;
+#[cfg(all(feature = "schema-registry", any(feature = "nats", feature = "amqp")))]
+use crate::avro::registry::SchemaRegistry;
+use crate::batching::BatchPolicy;
+use crate::cli::{self, Cli, InputFormat, Sink};
+use crate::config::{
+    Config, ConfigFile, ConfigLayers, FailurePolicy, Settings, load_joint_wallets,
+};
+use crate::cutoff::DaySummary;
+use crate::dedupe::DedupeWindow;
+use crate::dormancy::DormancyPolicy;
+use crate::events::EventHub;
+use crate::export::{
+    DeltaBaseline, ExportOptions, read_wallets_csv, write_csv_report,
+    write_partitioned_wallets_csv, write_wallets_csv,
+};
+use crate::exposure::{
+    AgeBuckets, exposure_report, segmented_exposure_report, write_exposure_report,
+};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+#[cfg(any(feature = "nats", feature = "amqp"))]
+use crate::input::MessageDecoder;
+use crate::input::{CsvOptions, ReadSummary, is_stdin, open_input, stream_grouped_csv};
+use crate::ledger::write_ledger;
+use crate::merkle::BalanceTree;
+#[cfg(feature = "nats")]
+use crate::nats;
+#[cfg(feature = "webhook")]
+use crate::outbox;
+use crate::persistence::PersistenceOptions;
+#[cfg(feature = "profile")]
+use crate::profile;
+use crate::progress::Progress;
+use crate::provenance::RunMetadata;
+use crate::queue::QueueAlerts;
+use crate::rejection::RejectionReport;
+use crate::replica::ReadReplica;
+#[cfg(feature = "rest")]
+use crate::rest;
+use crate::segment::{SegmentSummary, Segments, segment_summary};
+#[cfg(feature = "rest")]
+use crate::server;
+use crate::source::{
+    CsvSource, RecordFormat, RecordSource, TransactionSource, stream_into_channel,
+};
+use crate::tenant::TenantRegistry;
+use crate::timeformat::TimestampFormat;
+use crate::transaction::{Client, Envelope, Failure, Tenant, Timestamp, TransactionId};
+use crate::wallet::Wallet;
+use crate::wallet_manager::{RunReport, WalletManager};
+use crate::watermark::Watermark;
+#[cfg(feature = "webhook")]
+use crate::webhook;
+use crate::{cutoff, progress, provenance, queue, reload, statement, tcp, wire};
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{Notify, broadcast};
+use tokio::task::{self, JoinHandle};
+
+/// The command line of the `walletmanagermock` binary: parses the arguments and runs on a
+/// runtime sized by them.
+pub fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = cli.worker_threads {
+        runtime.worker_threads(threads.get());
+    }
+    if let Some(threads) = cli.blocking_threads {
+        runtime.max_blocking_threads(threads.get());
+    }
+    runtime.build()?.block_on(run(cli, &matches))
+}
+
+async fn run(cli: Cli, matches: &ArgMatches) -> anyhow::Result<(), Box<dyn std::error::Error>> {
+    // `WM_*` variables rank below the config file, flags above it.
+    let from_env = |id: &str| matches.value_source(id) == Some(ValueSource::EnvVariable);
+    #[cfg(unix)]
+    if let Some(cli::Command::Admin {
+        socket,
+        tenant,
+        command,
+    }) = cli.command
+    {
+        return Ok(admin::run(&socket, tenant, command).await?);
+    }
+    #[cfg(feature = "profile")]
+    let profile = cli
+        .profile
+        .clone()
+        .map(profile::Profile::start)
+        .transpose()?;
+    provenance::record_inputs(cli.metadata_output.is_some());
+    progress::track_inputs(cli.progress.is_some());
+    let config_file = match &cli.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    let mut flag_settings = Settings {
+        min_balance: cli.min_balance,
+        max_deposit: cli.max_deposit,
+        max_withdrawal: cli.max_withdrawal,
+        on_failure: cli.on_failure,
+        on_missing_wallet: cli.on_missing_wallet,
+        on_dispute_missing_wallet: cli.on_dispute_missing_wallet,
+        max_failures: cli.max_failures,
+        risk_flag: cli.risk_flag,
+        risk_hold: cli.risk_hold,
+        risk_reject: cli.risk_reject,
+        chargeback_freeze_ratio: cli.chargeback_freeze_ratio,
+        chargeback_window: cli.chargeback_window,
+        chargeback_min_count: cli.chargeback_min_count,
+        max_dispute_cycles: cli.max_dispute_cycles,
+        unlock_on_representment: cli.unlock_on_representment.then_some(true),
+        dispute_expiry_secs: cli.dispute_expiry_secs,
+    };
+    let mut env_settings = flag_settings.clone();
+    env_settings.retain(from_env);
+    flag_settings.retain(|id| !from_env(id));
+    let joint_owners = match &cli.joint_wallets {
+        Some(path) => load_joint_wallets(path)?,
+        None => HashMap::new(),
+    };
+    let config = Config {
+        keep_ledger: cli.ledger_export.is_some(),
+        keep_quarantine: cli.quarantine_output.is_some(),
+        keep_lifecycle: cli.lifecycle_audit.is_some(),
+        dispute_deferral: cli
+            .defer_unmatched_disputes
+            .then_some(cli.dispute_defer_limit),
+        joint_owners,
+        map_shards: cli.map_shards,
+        skip_journal: cli.no_journal,
+        journal_dir: cli.journal_dir.clone(),
+        undelivered_failures: cli.undelivered_failures.clone(),
+        persistence: cli.resume.clone().map(|dir| PersistenceOptions {
+            dir,
+            fsync: cli.wal_fsync,
+            checkpoint_every: cli.checkpoint_every,
+        }),
+        session: cli.session.clone(),
+        ..Config::default()
+    };
+    if let Some(dir) = &config.journal_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create the journal directory {}", dir.display()))?;
+    }
+    let layers = ConfigLayers {
+        base: config,
+        env: env_settings,
+        flags: flag_settings,
+        score_risk: cli.risk_report.is_some(),
+        client_min_balances: cli.client_min_balances.clone(),
+    };
+    let registry = TenantRegistry::try_new(layers.resolve(&config_file)?, config_file.tenants)
+        .context("failed to set up the wallet manager")?;
+    let mut events = None;
+    let replica = cli.read_replica.then(|| {
+        let events = events.get_or_insert_with(|| EventHub::new(1024));
+        (Arc::new(ReadReplica::default()), events.subscribe())
+    });
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = cli.grpc_listen {
+        let events = events.get_or_insert_with(|| EventHub::new(1024));
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving wallet updates over gRPC on {addr}");
+        tokio::spawn(grpc::serve(listener, events.clone()));
+    }
+    let registry = match events {
+        Some(events) => registry.with_events(events),
+        None => registry,
+    };
+    let registry = match &replica {
+        Some((replica, _)) => registry.with_read_replica(replica.clone()),
+        None => registry,
+    };
+    if cli.batch_min > cli.batch_max {
+        return Err("--batch-min is larger than --batch-max".into());
+    }
+    let registry = registry.with_batching(BatchPolicy {
+        min: cli.batch_min.get(),
+        max: cli.batch_max.get(),
+    });
+    let registry = if cli.fair_scheduling {
+        registry.with_fair_scheduling(cli.fair_window)
+    } else {
+        registry
+    };
+    if let Some(path) = &cli.initial_state {
+        let manager = registry.default_manager();
+        for wallet in read_initial_state(path)? {
+            manager.restore(wallet);
+        }
+    }
+    if cli.resume.is_some() {
+        for (tenant, recovery) in registry.recover()? {
+            let namespace = tenant.as_ref().map_or("default", Tenant::as_str);
+            info!(
+                "Recovered {} wallets of the {namespace} namespace from seq {}, replaying {} \
+                 transactions{}",
+                recovery.wallets,
+                recovery.snapshot_seq,
+                recovery.replayed,
+                if recovery.torn {
+                    " and dropping a torn one"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+    let registry = Arc::new(registry);
+    if let Some((replica, updates)) = replica {
+        tokio::spawn(replica.follow(updates, registry.clone()));
+    }
+    let alerts = QueueAlerts {
+        max_depth: cli.queue_depth_warn,
+        max_age: cli.queue_age_warn_ms.map(Duration::from_millis),
+    };
+    tokio::spawn(queue::monitor(
+        registry.queue().clone(),
+        alerts,
+        Duration::from_secs(1),
+    ));
+    let progress = cli
+        .progress
+        .map(|_| Arc::new(Progress::new(registry.clone())));
+    if let Some(progress) = &progress {
+        tokio::spawn(progress.clone().report(Duration::from_secs(1)));
+    }
+    if cli.watch_config
+        && let Some(path) = &cli.config
+    {
+        tokio::spawn(reload::watch(
+            path.clone(),
+            layers,
+            registry.clone(),
+            Duration::from_secs(1),
+        ));
+    }
+    let (flag, file) = (cli.timestamp_format.clone(), config_file.timestamp_format);
+    let timestamp_format = if from_env("timestamp_format") {
+        file.or(flag)
+    } else {
+        flag.or(file)
+    }
+    .unwrap_or_default();
+    let segments = cli.segments.as_deref().map(Segments::load).transpose()?;
+    #[cfg(feature = "rest")]
+    if let Some(addr) = cli.rest_listen {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Accepting batch uploads on http://{addr}/batches");
+        tokio::spawn(rest::serve(
+            listener,
+            registry.clone(),
+            csv_options(&cli, &registry, timestamp_format.clone()),
+            cli.rest_upload_limit,
+            cli.rest_retained_batches,
+        ));
+    }
+    let drain = Arc::new(Notify::new());
+    #[cfg(unix)]
+    let _admin_socket = match &cli.admin_socket {
+        Some(path) => {
+            let (listener, socket) = admin::bind(path)?;
+            info!("Accepting admin commands on {}", path.display());
+            tokio::spawn(admin::serve(listener, registry.clone(), drain.clone()));
+            Some(socket)
+        }
+        None => None,
+    };
+    let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let tx_receiver = registry.queue().meter(tx_receiver);
+    let (err_sender, err_receiver) = tokio::sync::mpsc::unbounded_channel();
+    // With `serve`, failures are streamed to the clients of `GET /failures` as well.
+    let failure_feed = cli.serve_addr().map(|_| broadcast::Sender::new(1024));
+    let err_receiver = match &failure_feed {
+        Some(feed) => tee_failures(err_receiver, feed.clone()),
+        None => err_receiver,
+    };
+    let wallet_manager_runner = tokio::spawn({
+        let registry = registry.clone();
+        let err_sender = err_sender.clone();
+        let business_days = match (cli.cutoff, &cli.daily_output_dir) {
+            (Some(cutoff), Some(dir)) => Some((cutoff, day_closer(&cli, registry.clone(), dir)?)),
+            _ => None,
+        };
+        async move {
+            match business_days {
+                Some((cutoff, close_day)) => {
+                    cutoff::run(&registry, tx_receiver, err_sender, cutoff, close_day).await
+                }
+                None => Ok(registry.run(tx_receiver, err_sender).await),
+            }
+        }
+    });
+
+    let (err_receiver, rejections) = match &cli.errors {
+        Some(_) => {
+            let (receiver, rejections) = collect_rejections(err_receiver);
+            (receiver, Some(rejections))
+        }
+        None => (err_receiver, None),
+    };
+    let error_runner = spawn_failure_sink(&cli, err_receiver)?;
+
+    let mut summary = read_input(
+        &cli,
+        &registry,
+        tx_sender,
+        err_sender,
+        failure_feed,
+        &drain,
+        timestamp_format,
+    )
+    .await?;
+
+    let mut report = wallet_manager_runner.await??;
+    // Streaming sources apply transactions themselves rather than queueing them.
+    report.merge(std::mem::take(&mut summary.applied));
+    // Every failure sender is gone now, so this returns once the last failures are delivered.
+    if let Err(e) = error_runner.await {
+        error!("The failure sink stopped early: {e}");
+    }
+    let default_manager = registry.default_manager();
+    let spool = default_manager.failure_spool();
+    spool.flush()?;
+    if spool.spooled() > 0 {
+        warn!(
+            "{} failures were spooled as the failure sink was gone",
+            spool.spooled()
+        );
+    }
+    let mut rejections = match rejections {
+        Some(rejections) => Some(rejections.await?),
+        None => None,
+    };
+    if let Some(rejections) = &mut rejections {
+        for origin in std::mem::take(&mut summary.invalid_rows) {
+            rejections.record_invalid_row(origin);
+        }
+    }
+    for failure in spool.take() {
+        warn!("Undelivered failure: {failure}");
+        if let Some(rejections) = &mut rejections {
+            rejections.record(failure);
+        }
+    }
+    // Written before a run aborted by the failure policy stops, as it tells why.
+    if let (Some(path), Some(rejections)) = (&cli.errors, &mut rejections) {
+        rejections.write(path, cli.errors_format)?;
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    if registry.aborted() {
+        return Err("aborted: too many failed transactions (see --max-failures)".into());
+    }
+    if cli.resume.is_some() {
+        registry.checkpoint()?;
+    }
+
+    let tenants = registry.tenant_managers();
+    if !tenants.is_empty() && cli.tenant_output_dir.is_none() && cli.partition_dir.is_none() {
+        return Err(
+            "input contains tenants, pass --tenant-output-dir or --partition-dir to export them"
+                .into(),
+        );
+    }
+    write_outputs(&cli, None, &registry.default_manager(), segments.as_ref())?;
+    for (tenant, wallet_manager) in &tenants {
+        write_outputs(&cli, Some(tenant), wallet_manager, segments.as_ref())?;
+    }
+    #[cfg(feature = "profile")]
+    if let Some(profile) = profile {
+        profile.finish()?;
+    }
+    if let Some(path) = &cli.metadata_output {
+        let config_hash = cli
+            .config
+            .as_deref()
+            .map(provenance::file_hash)
+            .transpose()?;
+        let namespaces = std::iter::once((None, registry.default_manager())).chain(
+            tenants
+                .into_iter()
+                .map(|(tenant, manager)| (Some(tenant), manager)),
+        );
+        for (tenant, wallet_manager) in namespaces {
+            RunMetadata {
+                engine_version: env!("CARGO_PKG_VERSION"),
+                tenant: tenant.as_ref().map(Tenant::as_str),
+                arguments: std::env::args().skip(1).collect(),
+                config_hash: config_hash.clone(),
+                inputs: provenance::input_checksums(),
+                rows: &summary,
+                wallets: wallet_manager.export_wallets().len(),
+                state_hash: wallet_manager.state_hash(),
+            }
+            .write(&tenant_path(path, tenant.as_ref()))?;
+        }
+    }
+    if cli.summary {
+        let segments = match &segments {
+            Some(segments) => {
+                // Every namespace's wallets, as the counts of the run cover all of them.
+                let mut wallets = registry.default_manager().export_wallets();
+                for (_, wallet_manager) in registry.tenant_managers() {
+                    wallets.extend(wallet_manager.export_wallets());
+                }
+                segment_summary(&wallets, segments)
+            }
+            None => Vec::new(),
+        };
+        let summary = RunSummary {
+            read: summary,
+            run: report,
+            segments,
+        };
+        eprintln!("{}", serde_json::to_string(&summary)?);
+    }
+    Ok(())
+}
+
+/// Feeds the transactions of the selected source to the registry. Streaming sources apply
+/// transactions themselves and run until Ctrl-C or an admin drain; files are streamed through
+/// `tx_sender`.
+async fn read_input(
+    cli: &Cli,
+    registry: &Arc<TenantRegistry>,
+    tx_sender: UnboundedSender<Envelope>,
+    err_sender: UnboundedSender<Failure>,
+    failure_feed: Option<broadcast::Sender<Failure>>,
+    drain: &Notify,
+    timestamp_format: TimestampFormat,
+) -> anyhow::Result<ReadSummary> {
+    let shutdown = async {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = drain.notified() => {}
+        }
+    };
+    #[cfg(feature = "rest")]
+    if let (Some(addr), Some(failures)) = (cli.serve_addr(), failure_feed) {
+        anyhow::ensure!(
+            cli.input.is_none() && cli.listen.is_none(),
+            "serve takes transactions over HTTP instead of an input file or --listen"
+        );
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving transactions on http://{addr}");
+        let batches = rest::router(
+            registry.clone(),
+            csv_options(cli, registry, timestamp_format),
+            cli.rest_upload_limit,
+            cli.rest_retained_batches,
+        );
+        return server::serve(
+            listener,
+            registry.clone(),
+            tx_sender,
+            failures,
+            batches,
+            shutdown,
+        )
+        .await;
+    }
+    #[cfg(not(feature = "rest"))]
+    let _ = failure_feed;
+    if let Some(addr) = cli.listen {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Listening for transactions on {addr}");
+        let summary = tcp::serve(
+            listener,
+            registry.clone(),
+            err_sender,
+            cli.listen_buffer,
+            match cli.format {
+                InputFormat::Binary => tcp::Framing::Binary,
+                _ => tcp::Framing::Lines,
+            },
+            shutdown,
+        )
+        .await;
+        return Ok(summary);
+    }
+    #[cfg(any(feature = "nats", feature = "amqp"))]
+    let decoder = MessageDecoder {
+        #[cfg(feature = "schema-registry")]
+        schema_registry: cli
+            .schema_registry
+            .as_deref()
+            .map(|url| Arc::new(SchemaRegistry::new(url))),
+    };
+    #[cfg(feature = "nats")]
+    if let (Some(url), Some(stream)) = (&cli.nats_url, &cli.nats_stream) {
+        let options = nats::NatsOptions {
+            url: url.clone(),
+            stream: stream.clone(),
+            consumer: cli.nats_consumer.clone(),
+            failure_subject: cli.nats_failure_subject.clone(),
+            wallet_subject: cli.nats_wallet_subject.clone(),
+            decoder,
+        };
+        return nats::consume(&options, registry.clone(), err_sender, shutdown).await;
+    }
+    #[cfg(feature = "amqp")]
+    if let (Some(url), Some(queue)) = (&cli.amqp_url, &cli.amqp_queue) {
+        let options = amqp::AmqpOptions {
+            url: url.clone(),
+            queue: queue.clone(),
+            dead_letter_exchange: cli.amqp_dead_letter_exchange.clone(),
+            prefetch: cli.amqp_prefetch,
+            decoder,
+        };
+        return amqp::consume(&options, registry.clone(), err_sender, shutdown).await;
+    }
+
+    let input = cli
+        .input
+        .clone()
+        .expect("clap requires an input file without a streaming source");
+    let format = cli.format.resolve(&input)?;
+    let csv_options = csv_options(cli, registry, timestamp_format);
+    if cli.stream_closed_wallets {
+        anyhow::ensure!(
+            format == InputFormat::Csv,
+            "--stream-closed-wallets needs CSV input"
+        );
+        drop(tx_sender);
+        let options = export_options(cli, &registry.default_manager());
+        return stream_grouped_csv(input, csv_options, registry.clone(), err_sender, options).await;
+    }
+    anyhow::ensure!(
+        cli.merge_inputs.is_empty() || format == InputFormat::Csv,
+        "--merge needs CSV input"
+    );
+    anyhow::ensure!(
+        csv_options.watermark.is_none() || format == InputFormat::Csv,
+        "--skip-before-tx and --skip-before-timestamp need CSV input"
+    );
+    let source: Box<dyn TransactionSource> = match format {
+        InputFormat::Csv => {
+            let inputs: Vec<PathBuf> = std::iter::once(input)
+                .chain(cli.merge_inputs.iter().cloned())
+                .collect();
+            anyhow::ensure!(
+                inputs.iter().filter(|path| is_stdin(path)).count() <= 1,
+                "only one input can be read from stdin"
+            );
+            Box::new(CsvSource {
+                paths: inputs,
+                options: csv_options,
+                dedupe: cli.dedupe_window.map(DedupeWindow::new),
+                origins: cli.errors.is_some(),
+            })
+        }
+        InputFormat::Binary => record_source(cli, input, RecordFormat::Binary),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => record_source(cli, input, RecordFormat::Avro),
+        #[cfg(feature = "jsonl")]
+        InputFormat::Jsonl => record_source(cli, input, RecordFormat::JsonLines),
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => record_source(cli, input, RecordFormat::Parquet),
+        format => {
+            let client = Client::new(cli.statement_client.unwrap_or_default());
+            return stream_statement_into_channel(
+                input,
+                format,
+                client,
+                cli.statement_first_tx,
+                tx_sender,
+            )
+            .await;
+        }
+    };
+    stream_into_channel(source, tx_sender).await
+}
+
+fn record_source(cli: &Cli, path: PathBuf, format: RecordFormat) -> Box<dyn TransactionSource> {
+    Box::new(RecordSource {
+        path,
+        format,
+        origins: cli.errors.is_some(),
+    })
+}
+
+fn csv_options(
+    cli: &Cli,
+    registry: &TenantRegistry,
+    timestamp_format: TimestampFormat,
+) -> CsvOptions {
+    CsvOptions {
+        amount_locale: cli.amount_locale,
+        lenient_amounts: cli.lenient_amounts,
+        timestamp_format,
+        trailer_mismatch: cli.trailer_mismatch,
+        schema: cli.schema,
+        currency: cli.input_currency.clone(),
+        watermark: match (cli.skip_before_tx, cli.skip_before_timestamp) {
+            (Some(tx), _) => Some(Watermark::Tx(tx)),
+            (None, Some(secs)) => Some(Watermark::Timestamp(Timestamp::from_secs(secs))),
+            (None, None) => None,
+        },
+        limits: registry.default_manager().config().limits.clone(),
+    }
+}
+
+/// Writes the wallets of every namespace as of the end of a business day into `dir` and appends
+/// the day to `days.csv` there.
+fn day_closer(
+    cli: &Cli,
+    registry: Arc<TenantRegistry>,
+    dir: &Path,
+) -> anyhow::Result<impl FnMut(&DaySummary) -> anyhow::Result<()> + Send + 'static> {
+    std::fs::create_dir_all(dir)?;
+    let mut days = csv::Writer::from_path(dir.join("days.csv"))?;
+    let options = export_options(cli, &registry.default_manager());
+    let dir = dir.to_path_buf();
+    // With --delta-output every namespace is compared against its previous snapshot; tenants
+    // appearing later start from a full snapshot.
+    let delta = cli.delta_output.is_some();
+    let mut baselines: HashMap<Option<Tenant>, DeltaBaseline> = HashMap::new();
+    if let Some(path) = &cli.delta_output {
+        baselines.insert(None, load_baseline(path, None)?);
+    }
+    Ok(move |summary: &DaySummary| {
+        let path = dir.join(format!("wallets-{}.csv", summary.day));
+        let namespaces = std::iter::once((None, registry.default_manager())).chain(
+            registry
+                .tenant_managers()
+                .into_iter()
+                .map(|(tenant, manager)| (Some(tenant), manager)),
+        );
+        for (tenant, manager) in namespaces {
+            let options = ExportOptions {
+                quarantined: manager.failure_policy() == FailurePolicy::Quarantine,
+                ..options.clone()
+            };
+            let wallets = manager.export_wallets();
+            let file = File::create(tenant_path(&path, tenant.as_ref()))?;
+            if delta {
+                let baseline = baselines.entry(tenant).or_default();
+                write_wallets_csv(file, &baseline.changed(&wallets), &options)?;
+                *baseline = DeltaBaseline::new(wallets);
+            } else {
+                write_wallets_csv(file, &wallets, &options)?;
+            }
+        }
+        days.serialize(summary)?;
+        days.flush()?;
+        Ok(())
+    })
+}
+
+/// Delivers failed transactions to the webhook when one is configured, logs them otherwise.
+fn spawn_failure_sink(
+    cli: &Cli,
+    failures: UnboundedReceiver<Failure>,
+) -> anyhow::Result<JoinHandle<()>> {
+    if cli.sink == Sink::Null {
+        return Ok(tokio::spawn(drop_failures(failures)));
+    }
+    #[cfg(feature = "webhook")]
+    if let Some(url) = &cli.failure_webhook {
+        let sink = webhook::WebhookSink::new(webhook::WebhookOptions {
+            url: url.clone(),
+            batch_size: cli.failure_batch_size,
+            flush_interval: Duration::from_millis(cli.failure_flush_ms),
+            spool: cli.failure_spool.clone(),
+            initial_backoff: Duration::from_millis(500),
+            max_attempts: 5,
+        });
+        if let Some(dir) = &cli.outbox_dir {
+            let outbox = outbox::Outbox::open(dir, cli.outbox_fsync)?;
+            let poll_interval = Duration::from_millis(cli.failure_flush_ms);
+            return Ok(tokio::spawn(async move {
+                outbox::run(&outbox, sink, failures, poll_interval).await
+            }));
+        }
+        return Ok(tokio::spawn(sink.run(failures)));
+    }
+    #[cfg(not(feature = "webhook"))]
+    let _ = cli;
+    Ok(tokio::spawn(log_failures(failures)))
+}
+
+/// Forwards `failures` to the returned receiver and to the subscribers of `feed`.
+fn tee_failures(
+    mut failures: UnboundedReceiver<Failure>,
+    feed: broadcast::Sender<Failure>,
+) -> UnboundedReceiver<Failure> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(failure) = failures.recv().await {
+            let _ = feed.send(failure.clone());
+            if sender.send(failure).is_err() {
+                // Leaves the failures to the spool, as if the sink had taken them directly.
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Records the failures for the rejection report on their way to the returned receiver. The
+/// report is complete once every failure sender is gone.
+fn collect_rejections(
+    mut failures: UnboundedReceiver<Failure>,
+) -> (UnboundedReceiver<Failure>, JoinHandle<RejectionReport>) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let collector = tokio::spawn(async move {
+        let mut rejections = RejectionReport::default();
+        while let Some(failure) = failures.recv().await {
+            rejections.record(failure.clone());
+            if sender.send(failure).is_err() {
+                // Leaves the failures to the spool, which the report takes them from.
+                break;
+            }
+        }
+        rejections
+    });
+    (receiver, collector)
+}
+
+async fn drop_failures(mut failures: UnboundedReceiver<Failure>) {
+    while failures.recv().await.is_some() {}
+}
+
+async fn log_failures(mut failures: UnboundedReceiver<Failure>) {
+    while let Some(failure) = failures.recv().await {
+        info!("Transaction failed: {failure}"); // Would handle failure. Maybe send notification to customer..
+    }
+}
+
+/// Writes the reports and the wallet export of one tenant namespace. The default namespace goes
+/// to stdout and the configured report paths, tenants get their own files.
+fn write_outputs(
+    cli: &Cli,
+    tenant: Option<&Tenant>,
+    wallet_manager: &WalletManager,
+    segments: Option<&Segments>,
+) -> anyhow::Result<()> {
+    if let Some(inactive_days) = cli.dormancy_days {
+        let policy = DormancyPolicy {
+            inactive_days,
+            fee: cli.dormancy_fee,
+        };
+        let dormant = wallet_manager.apply_dormancy(&policy);
+        if let Some(path) = &cli.dormancy_report {
+            write_csv_report(&tenant_path(path, tenant), &dormant)?;
+        }
+    }
+
+    if cli.verify_totals {
+        wallet_manager.verify_totals()?;
+    }
+
+    if let Some(path) = &cli.risk_report {
+        write_csv_report(&tenant_path(path, tenant), &wallet_manager.risk_scores())?;
+    }
+    if let Some(path) = &cli.risk_journal {
+        write_csv_report(&tenant_path(path, tenant), &wallet_manager.risk_decisions())?;
+    }
+
+    if let Some(path) = &cli.lifecycle_audit {
+        write_csv_report(
+            &tenant_path(path, tenant),
+            &wallet_manager.lifecycle_events(),
+        )?;
+    }
+
+    if let Some(path) = &cli.quarantine_output {
+        write_csv_report(
+            &tenant_path(path, tenant),
+            &wallet_manager.quarantined_transactions(),
+        )?;
+    }
+
+    if let Some(path) = &cli.session_report {
+        write_csv_report(
+            &tenant_path(path, tenant),
+            &wallet_manager.session_activity(),
+        )?;
+    }
+
+    if let Some(path) = &cli.house_report {
+        let house = wallet_manager.house_accounts();
+        write_csv_report(
+            &tenant_path(path, tenant),
+            &house.report(wallet_manager.wallet_totals()),
+        )?;
+    }
+
+    if let Some(path) = &cli.exposure_report {
+        let wallets = wallet_manager.export_wallets();
+        let now = wallet_manager.latest_timestamp();
+        let buckets = AgeBuckets::new(cli.exposure_buckets.clone());
+        let rows = match segments {
+            Some(segments) => segmented_exposure_report(&wallets, now, &buckets, segments),
+            None => exposure_report(&wallets, now, &buckets),
+        };
+        write_exposure_report(&tenant_path(path, tenant), &rows, cli.exposure_format)?;
+    }
+
+    if let (Some(path), Some(segments)) = (&cli.segment_report, segments) {
+        write_csv_report(
+            &tenant_path(path, tenant),
+            &segment_summary(&wallet_manager.export_wallets(), segments),
+        )?;
+    }
+
+    if let Some(path) = &cli.ledger_export {
+        write_ledger(
+            BufWriter::new(File::create(tenant_path(path, tenant))?),
+            &wallet_manager.ledger_entries(),
+            cli.ledger_format,
+            &cli.ledger_commodity,
+        )?;
+    }
+
+    let mut wallets = wallet_manager.export_wallets();
+    if cli.state_hash {
+        let hash = wallet_manager.state_hash();
+        match tenant {
+            Some(tenant) => eprintln!("state hash ({}): {hash}", tenant.as_str()),
+            None => eprintln!("state hash: {hash}"),
+        }
+    }
+    if cli.merkle_root {
+        let root = BalanceTree::new(&wallets).root();
+        match tenant {
+            Some(tenant) => eprintln!("merkle root ({}): {root}", tenant.as_str()),
+            None => eprintln!("merkle root: {root}"),
+        }
+    }
+    if let Some(path) = &cli.binary_snapshot {
+        wire::write_wallets(
+            BufWriter::new(File::create(tenant_path(path, tenant))?),
+            &wallets,
+        )?;
+    }
+    if let Some(path) = &cli.delta_output {
+        wallets = load_baseline(path, tenant)?.changed(&wallets);
+    }
+    #[cfg(feature = "avro")]
+    if let Some(path) = &cli.avro_output {
+        avro::write_wallets(
+            BufWriter::new(File::create(tenant_path(path, tenant))?),
+            &wallets,
+        )?;
+    }
+    let options = export_options(cli, wallet_manager);
+    if let (Some(partitions), Some(dir)) = (cli.output_partitions, &cli.partition_dir) {
+        std::fs::create_dir_all(dir)?;
+        write_partitioned_wallets_csv(wallets, partitions.into(), &options, |n| {
+            tenant_path(&dir.join(format!("wallets-{n}.csv")), tenant)
+        })?;
+        return Ok(());
+    }
+    match (tenant, &cli.tenant_output_dir) {
+        _ if cli.sink == Sink::Null => {}
+        (Some(tenant), Some(dir)) => {
+            let path = dir.join(format!("{}.csv", tenant.as_str()));
+            write_wallets_csv(File::create(path)?, wallets.as_slice(), &options)?
+        }
+        // The default namespace was already written while streaming.
+        (None, _) if cli.stream_closed_wallets => {}
+        _ => write_wallets_csv(io::stdout(), wallets.as_slice(), &options)?,
+    }
+    Ok(())
+}
+
+/// Reads a wallet export, or a binary snapshot recognized by its header.
+fn read_initial_state(path: &Path) -> anyhow::Result<Vec<Wallet>> {
+    let mut input = io::BufReader::new(File::open(path)?);
+    if input.fill_buf()?.starts_with(wire::WALLETS_MAGIC) {
+        return wire::read_wallets(input);
+    }
+    Ok(read_wallets_csv(input)?)
+}
+
+/// Reads the previous snapshot of a namespace for --delta-output. A tenant without one, e.g. one
+/// new since, gets an empty baseline so that all its wallets are exported.
+fn load_baseline(path: &Path, tenant: Option<&Tenant>) -> anyhow::Result<DeltaBaseline> {
+    let path = tenant_path(path, tenant);
+    if tenant.is_some() && !path.exists() {
+        return Ok(DeltaBaseline::default());
+    }
+    let file = File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(DeltaBaseline::new(read_wallets_csv(file)?))
+}
+
+fn export_options(cli: &Cli, wallet_manager: &WalletManager) -> ExportOptions {
+    ExportOptions {
+        dormant: cli.flag_dormant,
+        owners: cli.joint_wallets.is_some(),
+        quarantined: wallet_manager.failure_policy() == FailurePolicy::Quarantine,
+        stats: cli.client_stats,
+        status: cli.wallet_status,
+        seq: cli.export_seq,
+        lock_reason: cli.lock_reason,
+        columns: cli.columns.clone(),
+        skip_header: cli.no_header,
+    }
+}
+
+/// Derives the per-tenant variant of a report path, e.g. `dormancy.csv` -> `dormancy-acme.csv`.
+fn tenant_path(path: &Path, tenant: Option<&Tenant>) -> PathBuf {
+    let Some(tenant) = tenant else {
+        return path.to_path_buf();
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}-{}.{}", tenant.as_str(), ext.to_string_lossy()),
+        None => format!("{stem}-{}", tenant.as_str()),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Written to stderr with `--summary`.
+#[derive(Debug, Serialize)]
+struct RunSummary {
+    #[serde(flatten)]
+    read: ReadSummary,
+    run: RunReport,
+    /// With `--segments`, the wallets of every segment.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<SegmentSummary>,
+}
+
+/// Books the entries of a bank statement on `client`, numbering them from `first_tx`.
+pub async fn stream_statement_into_channel(
+    path: PathBuf,
+    format: InputFormat,
+    client: Client,
+    first_tx: u32,
+    tx_sender: UnboundedSender<Envelope>,
+) -> anyhow::Result<ReadSummary> {
+    let input = task::spawn_blocking(move || {
+        let mut input = String::new();
+        open_input(&path)?.read_to_string(&mut input)?;
+        Ok::<_, io::Error>(input)
+    })
+    .await??;
+    let entries = match format {
+        InputFormat::Ofx => statement::ofx::parse(&input),
+        InputFormat::Qif => statement::qif::parse(&input),
+        #[cfg(feature = "iso20022")]
+        InputFormat::Camt053 => statement::iso20022::parse_camt053(&input)?,
+        #[cfg(feature = "iso20022")]
+        InputFormat::Pain001 => statement::iso20022::parse_pain001(&input)?,
+        InputFormat::Auto => unreachable!("the format is resolved before reading"),
+        InputFormat::Csv => unreachable!("CSV input is streamed row by row"),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => unreachable!("Avro input is streamed record by record"),
+        InputFormat::Binary => unreachable!("binary input is streamed record by record"),
+        #[cfg(feature = "jsonl")]
+        InputFormat::Jsonl => unreachable!("JSON Lines input is streamed record by record"),
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => unreachable!("Parquet input is streamed record by record"),
+    };
+    let mut summary = ReadSummary::default();
+    for (tx, entry) in (first_tx..).zip(entries) {
+        summary.rows_read += 1;
+        match entry.into_envelope(client, TransactionId::new(tx)) {
+            Some(envelope) => {
+                if tx_sender.send(envelope).is_err() {
+                    break;
+                }
+            }
+            None => summary.rows_skipped += 1,
+        }
+    }
+    Ok(summary)
+}
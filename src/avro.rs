@@ -0,0 +1,299 @@
+//! Avro encoding of transactions and wallets: object container files for batch input and
+//! output, plus decoding of Confluent-framed messages whose writer schema lives in a schema
+//! registry (enabled by the `schema-registry` feature).
+
+use crate::transaction::{Envelope, Tenant, Timestamp, Transaction};
+use crate::wallet::Wallet;
+use apache_avro::{Reader, Schema, Writer, from_value};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::sync::LazyLock;
+
+pub const TRANSACTION_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Transaction",
+    "namespace": "walletmanagermock",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "double"], "default": null},
+        {"name": "timestamp", "type": ["null", "long"], "default": null},
+        {"name": "tenant", "type": ["null", "string"], "default": null}
+    ]
+}"#;
+
+pub const WALLET_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Wallet",
+    "namespace": "walletmanagermock",
+    "fields": [
+        {"name": "client", "type": "int"},
+        {"name": "available", "type": "string"},
+        {"name": "held", "type": "string"},
+        {"name": "total", "type": "string"},
+        {"name": "locked", "type": "boolean"}
+    ]
+}"#;
+
+static TRANSACTION: LazyLock<Schema> =
+    LazyLock::new(|| Schema::parse_str(TRANSACTION_SCHEMA).expect("valid transaction schema"));
+static WALLET: LazyLock<Schema> =
+    LazyLock::new(|| Schema::parse_str(WALLET_SCHEMA).expect("valid wallet schema"));
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AvroTransaction {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: i32,
+    tx: i64,
+    amount: Option<f64>,
+    timestamp: Option<i64>,
+    tenant: Option<String>,
+}
+
+impl AvroTransaction {
+    fn into_envelope(self) -> Option<Envelope> {
+        let transaction = Transaction::from_parts(
+            &self.transaction_type,
+            self.client.try_into().ok()?,
+            self.tx.try_into().ok()?,
+            self.amount.map(|a| a as f32),
+        )?;
+        Some(Envelope {
+            timestamp: self.timestamp.map(Timestamp::from_secs),
+            tenant: match self.tenant {
+                Some(tenant) => Some(Tenant::parse(&tenant)?),
+                None => None,
+            },
+            ..Envelope::from(transaction)
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AvroWallet {
+    client: i32,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+/// Reads an Avro object container file of transactions. Files written with an older or newer
+/// version of the schema are resolved against ours; records that don't form a valid transaction
+/// are returned as `None`.
+pub fn read_transactions<R: Read>(
+    reader: R,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<Option<Envelope>>>> {
+    let reader = Reader::builder(reader)
+        .reader_schema(&TRANSACTION)
+        .build()?;
+    Ok(reader.map(|value| {
+        let record: AvroTransaction = from_value(&value?)?;
+        Ok(record.into_envelope())
+    }))
+}
+
+pub fn write_wallets<W: Write>(writer: W, wallets: &[Wallet]) -> anyhow::Result<()> {
+    let mut writer = Writer::new(&WALLET, writer)?;
+    for wallet in wallets {
+        writer.append_ser(AvroWallet {
+            client: wallet.client.id().into(),
+            available: wallet.balance.available.to_string(),
+            held: wallet.balance.held.to_string(),
+            total: wallet.balance.total.to_string(),
+            locked: wallet.locked,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "schema-registry")]
+pub mod registry {
+    use super::{AvroTransaction, TRANSACTION};
+    use crate::transaction::Envelope;
+    use apache_avro::{Schema, from_value, reader::datum::GenericDatumReader};
+    use dashmap::DashMap;
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    /// Client for a Confluent-compatible schema registry, caching writer schemas by id. Used to
+    /// decode the messages of streaming sources, which carry only a schema id per record.
+    pub struct SchemaRegistry {
+        url: String,
+        http: reqwest::Client,
+        schemas: DashMap<u32, Arc<Schema>>,
+    }
+
+    #[derive(Deserialize)]
+    struct SchemaResponse {
+        schema: String,
+    }
+
+    impl SchemaRegistry {
+        pub fn new(url: impl Into<String>) -> Self {
+            SchemaRegistry {
+                url: url.into().trim_end_matches('/').to_string(),
+                http: reqwest::Client::new(),
+                schemas: DashMap::new(),
+            }
+        }
+
+        /// Decodes a message in the Confluent wire format: a zero magic byte, the big-endian
+        /// schema id, then the Avro datum written with that schema.
+        pub async fn decode(&self, payload: &[u8]) -> anyhow::Result<Option<Envelope>> {
+            let [0, a, b, c, d, datum @ ..] = payload else {
+                anyhow::bail!("payload is not in the Confluent wire format");
+            };
+            let writer_schema = self.schema(u32::from_be_bytes([*a, *b, *c, *d])).await?;
+            let value = GenericDatumReader::builder(&writer_schema)
+                .reader_schema(&TRANSACTION)
+                .build()?
+                .read_value(&mut &datum[..])?;
+            let record: AvroTransaction = from_value(&value)?;
+            Ok(record.into_envelope())
+        }
+
+        async fn schema(&self, id: u32) -> anyhow::Result<Arc<Schema>> {
+            if let Some(schema) = self.schemas.get(&id) {
+                return Ok(schema.clone());
+            }
+            let response: SchemaResponse = self
+                .http
+                .get(format!("{}/schemas/ids/{id}", self.url))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let schema = Arc::new(Schema::parse_str(&response.schema)?);
+            self.schemas.insert(id, schema.clone());
+            Ok(schema)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::transaction::{Client, Transaction, TransactionId};
+        use apache_avro::types::Record;
+        use apache_avro::writer::datum::GenericDatumWriter;
+
+        /// A registry knowing schema 42 and a dispute message written with it.
+        fn registry_and_dispute() -> (SchemaRegistry, Vec<u8>) {
+            // Writer schema of an older producer that didn't send timestamps or tenants.
+            let writer_schema = Schema::parse_str(
+                r#"{"type": "record", "name": "Transaction", "fields": [
+                    {"name": "type", "type": "string"},
+                    {"name": "client", "type": "int"},
+                    {"name": "tx", "type": "long"},
+                    {"name": "amount", "type": ["null", "double"], "default": null}
+                ]}"#,
+            )
+            .unwrap();
+            let registry = SchemaRegistry::new("http://registry.invalid");
+            registry.schemas.insert(42, Arc::new(writer_schema.clone()));
+
+            let mut record = Record::new(&writer_schema).unwrap();
+            record.put("type", "dispute");
+            record.put("client", 3);
+            record.put("tx", 9i64);
+            record.put("amount", None::<f64>);
+            let mut payload = vec![0, 0, 0, 0, 42];
+            let datum = GenericDatumWriter::builder(&writer_schema)
+                .build()
+                .unwrap()
+                .write_value_to_vec(record)
+                .unwrap();
+            payload.extend(datum);
+            (registry, payload)
+        }
+
+        fn dispute() -> Envelope {
+            Envelope::from(Transaction::Dispute {
+                client: Client::new(3),
+                tx_id: TransactionId::new(9),
+            })
+        }
+
+        #[tokio::test]
+        async fn test_decode_confluent_wire_format() {
+            let (registry, payload) = registry_and_dispute();
+
+            let envelope = registry.decode(&payload).await.unwrap();
+            assert_eq!(envelope, Some(dispute()));
+            assert!(registry.decode(&[1, 2]).await.is_err());
+        }
+
+        #[cfg(any(feature = "nats", feature = "amqp"))]
+        #[tokio::test]
+        async fn test_message_decoder_decodes_through_the_registry() {
+            use crate::input::MessageDecoder;
+
+            let (registry, payload) = registry_and_dispute();
+            let decoder = MessageDecoder {
+                schema_registry: Some(Arc::new(registry)),
+            };
+            assert_eq!(decoder.decode(&payload).await, Some(dispute()));
+            assert_eq!(decoder.decode(b"dispute,3,9,").await, None);
+
+            let decoder = MessageDecoder::default();
+            assert_eq!(decoder.decode(b"dispute,3,9,").await, Some(dispute()));
+            assert_eq!(decoder.decode(&payload).await, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, TransactionId};
+
+    #[test]
+    fn test_read_transactions_container() {
+        let mut writer = Writer::new(&TRANSACTION, Vec::new()).unwrap();
+        writer
+            .append_ser(AvroTransaction {
+                transaction_type: "deposit".to_string(),
+                client: 1,
+                tx: 7,
+                amount: Some(2.5),
+                timestamp: Some(60),
+                tenant: None,
+            })
+            .unwrap();
+        writer
+            .append_ser(AvroTransaction {
+                transaction_type: "deposit".to_string(),
+                client: 1,
+                tx: 8,
+                amount: None,
+                timestamp: None,
+                tenant: None,
+            })
+            .unwrap();
+        let bytes = writer.into_inner().unwrap();
+
+        let envelopes: Vec<_> = read_transactions(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(
+            envelopes,
+            vec![
+                Some(Envelope {
+                    timestamp: Some(Timestamp::from_secs(60)),
+                    ..Envelope::from(Transaction::Deposit {
+                        client: Client::new(1),
+                        tx_id: TransactionId::new(7),
+                        amount: Amount::from_major(2, 5000),
+                    })
+                }),
+                None,
+            ]
+        );
+    }
+}
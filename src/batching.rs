@@ -0,0 +1,52 @@
+//! Sizes of the batches the wallet managers take off the queue from the input reader: single
+//! transactions while the queue is short, for latency, and growing batches while a backlog
+//! builds up, so that the queue is locked and the consumer woken up less often.
+
+/// Bounds of the batch size. The size doubles while more transactions wait than the last batch
+/// took and halves once fewer than a quarter of that wait; equal bounds fix it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchPolicy {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        BatchPolicy { min: 1, max: 1024 }
+    }
+}
+
+impl BatchPolicy {
+    /// Size of the next batch after one of `size` that left `waiting` transactions queued.
+    pub fn next_size(&self, size: usize, waiting: usize) -> usize {
+        let next = if waiting > size {
+            size.saturating_mul(2)
+        } else if waiting < size / 4 {
+            size / 2
+        } else {
+            size
+        };
+        next.clamp(self.min.max(1), self.max.max(self.min).max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_size_follows_backlog() {
+        let policy = BatchPolicy { min: 2, max: 16 };
+        let mut size = policy.min;
+        let mut sizes = Vec::new();
+        for waiting in [100, 100, 100, 100, 100, 10, 3, 0, 0, 0] {
+            size = policy.next_size(size, waiting);
+            sizes.push(size);
+        }
+        assert_eq!(sizes, [4, 8, 16, 16, 16, 16, 8, 4, 2, 2]);
+
+        let fixed = BatchPolicy { min: 8, max: 8 };
+        assert_eq!(fixed.next_size(8, 0), 8);
+        assert_eq!(fixed.next_size(8, 1_000), 8);
+    }
+}
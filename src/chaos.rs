@@ -0,0 +1,220 @@
+//! Fault injection for the failure delivery paths. Whatever faults are injected, every failure
+//! must eventually end up somewhere it can be collected, without going missing:
+//!
+//! - The failure spool sees its consumer drop away and its file become unwritable at random.
+//! - The failure outbox, with `webhook`, sees deliveries fail or stall at random, the engine
+//!   "crash" in the middle of a delivery pass, the cursor fail to be stored and producers drop
+//!   their channel early. Delivery is at least once, so records may repeat after a fault.
+
+#[cfg(feature = "webhook")]
+mod outbox {
+    use crate::durability::FsyncPolicy;
+    use crate::outbox::{self, Outbox, Target};
+    use crate::simulation::Rng;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    const ROUNDS: u32 = 10;
+    const RECORDS_PER_ROUND: u32 = 8;
+
+    /// Refuses a third of the deliveries and delays all of them, while `flaky` is set.
+    struct ChaosTarget {
+        rng: Mutex<Rng>,
+        flaky: AtomicBool,
+        delivered: Mutex<Vec<u32>>,
+    }
+
+    impl Target for &ChaosTarget {
+        async fn deliver(&self, record: &str) -> bool {
+            let (delay, refuse) = {
+                let mut rng = self.rng.lock().unwrap();
+                (rng.delay(), rng.below(3) == 0)
+            };
+            tokio::time::sleep(delay).await;
+            if self.flaky.load(Ordering::Relaxed) && refuse {
+                return false;
+            }
+            let record: serde_json::Value = serde_json::from_str(record).unwrap();
+            let tx = record["tx"].as_u64().unwrap() as u32;
+            self.delivered.lock().unwrap().push(tx);
+            true
+        }
+    }
+
+    /// Makes storing the delivery cursor fail by putting a directory where its temporary file goes.
+    fn break_cursor_store(dir: &Path, broken: bool) {
+        let tmp = dir.join("cursor.tmp");
+        if broken {
+            let _ = fs::create_dir(tmp);
+        } else {
+            let _ = fs::remove_dir(tmp);
+        }
+    }
+
+    /// Returns whether any record was delivered more than once.
+    async fn chaos_run(seed: u64, dir: &Path) -> bool {
+        let mut rng = Rng::new(seed);
+        let target = ChaosTarget {
+            rng: Mutex::new(Rng::new(rng.next())),
+            flaky: AtomicBool::new(true),
+            delivered: Mutex::new(Vec::new()),
+        };
+        let mut journaled = Vec::new();
+
+        for round in 0..ROUNDS {
+            // Every round is a restart that has to pick up where the last one stopped.
+            let outbox = Outbox::open(dir, FsyncPolicy::Always).unwrap();
+            break_cursor_store(dir, rng.below(4) == 0);
+
+            let (records, received) = mpsc::unbounded_channel();
+            let sent = rng.below(u64::from(RECORDS_PER_ROUND)) as u32 + 1;
+            for i in 0..sent {
+                let tx = round * RECORDS_PER_ROUND + i;
+                records.send(serde_json::json!({ "tx": tx })).unwrap();
+                journaled.push(tx);
+            }
+            // The producer goes away early; what it sent is still journaled before `run` returns.
+            drop(records);
+            let crash_after = Duration::from_micros(rng.below(5_000));
+            let run = outbox::run(&outbox, &target, received, Duration::from_millis(1));
+            if tokio::time::timeout(crash_after, run).await.is_err() {
+                // Crashed mid-delivery; records journaled afterwards queue up behind the rest.
+                let tx = 1_000_000 + round;
+                outbox
+                    .append_all(&[serde_json::json!({ "tx": tx })])
+                    .unwrap();
+                journaled.push(tx);
+            }
+        }
+
+        break_cursor_store(dir, false);
+        target.flaky.store(false, Ordering::Relaxed);
+        Outbox::open(dir, FsyncPolicy::Always)
+            .unwrap()
+            .deliver_pending(&&target)
+            .await
+            .unwrap();
+
+        let delivered = target.delivered.lock().unwrap();
+        let mut first_deliveries: Vec<u32> = Vec::new();
+        for tx in delivered.iter() {
+            if !first_deliveries.contains(tx) {
+                first_deliveries.push(*tx);
+            }
+        }
+        assert_eq!(first_deliveries, journaled, "seed {seed}");
+        assert_eq!(fs::metadata(dir.join("journal.jsonl")).unwrap().len(), 0);
+        delivered.len() > first_deliveries.len()
+    }
+
+    #[test]
+    fn test_outbox_recovers_from_injected_faults() {
+        let mut redelivered = false;
+        for seed in 0..20 {
+            let dir = std::env::temp_dir().join(format!(
+                "walletmanagermock-chaos-{}-{seed}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            let recovered_with_repeats = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .start_paused(true)
+                .build()
+                .unwrap()
+                .block_on(chaos_run(seed, &dir));
+            redelivered |= recovered_with_repeats;
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        assert!(redelivered, "no fault ever hit a delivery");
+    }
+}
+
+mod spool {
+    use crate::simulation::Rng;
+    use crate::spool::FailureSpool;
+    use crate::transaction::{Client, Failure, TransactionId};
+    use std::fs;
+    use std::path::Path;
+    use tokio::sync::mpsc;
+
+    const FAILURES: u32 = 50;
+
+    /// Returns whether any failure was spooled to the file and any kept in memory.
+    fn chaos_run(seed: u64, path: &Path) -> (bool, bool) {
+        let mut rng = Rng::new(seed);
+        let spool = FailureSpool::new(Some(path.to_path_buf()));
+        let (err_send, mut err_recv) = mpsc::unbounded_channel::<Failure>();
+        let mut received = Vec::new();
+        let consumer_leaves_at = rng.below(u64::from(FAILURES)) as u32;
+        // A directory where the spool file goes makes opening it fail until it is removed.
+        let unwritable = rng.below(2) == 0;
+        if unwritable {
+            fs::create_dir(path).unwrap();
+        }
+        let writable_at = rng.below(u64::from(FAILURES)) as u32;
+
+        for tx in 0..FAILURES {
+            if tx == consumer_leaves_at {
+                while let Ok(failure) = err_recv.try_recv() {
+                    received.push(failure.tx.id());
+                }
+                err_recv.close();
+            }
+            if unwritable && tx == writable_at {
+                fs::remove_dir(path).unwrap();
+            }
+            let failure = Failure::insufficient_funds(Client::new(1), TransactionId::new(tx));
+            spool.deliver(&err_send, failure);
+        }
+        while let Ok(failure) = err_recv.try_recv() {
+            received.push(failure.tx.id());
+        }
+
+        spool.flush().unwrap();
+        let kept: Vec<u32> = spool.take().iter().map(|failure| failure.tx.id()).collect();
+        let filed: Vec<u32> = fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| {
+                let failure: serde_json::Value = serde_json::from_str(line).unwrap();
+                failure["tx"].as_u64().unwrap() as u32
+            })
+            .collect();
+        let _ = fs::remove_file(path);
+
+        assert_eq!(
+            spool.spooled(),
+            (kept.len() + filed.len()) as u64,
+            "seed {seed}"
+        );
+        let mut delivered: Vec<u32> = received
+            .iter()
+            .chain(&kept)
+            .chain(&filed)
+            .copied()
+            .collect();
+        delivered.sort_unstable();
+        assert_eq!(delivered, (0..FAILURES).collect::<Vec<_>>(), "seed {seed}");
+        (!filed.is_empty(), !kept.is_empty())
+    }
+
+    #[test]
+    fn test_spool_keeps_every_failure_through_injected_faults() {
+        let path = std::env::temp_dir().join(format!(
+            "walletmanagermock-chaos-spool-{}.jsonl",
+            std::process::id()
+        ));
+        let (mut filed, mut kept) = (false, false);
+        for seed in 0..20 {
+            let _ = fs::remove_file(&path);
+            let (any_filed, any_kept) = chaos_run(seed, &path);
+            filed |= any_filed;
+            kept |= any_kept;
+        }
+        assert!(filed && kept, "no fault ever hit the spool file");
+    }
+}
@@ -0,0 +1,915 @@
+#[cfg(unix)]
+use crate::admin::AdminCommand;
+use crate::config::{FailurePolicy, MissingWallet};
+use crate::cutoff::Cutoff;
+use crate::durability::FsyncPolicy;
+use crate::export;
+use crate::exposure::ReportFormat;
+use crate::ledger::LedgerFormat;
+use crate::locale::AmountLocale;
+use crate::progress::ProgressFormat;
+use crate::schema::Schema;
+use crate::timeformat::TimestampFormat;
+use crate::trailer::TrailerMismatch;
+use crate::transaction::Amount;
+use clap::builder::PossibleValuesParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Applies a CSV stream of transactions to client wallets",
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[cfg(any(unix, feature = "rest"))]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input CSV with `type, client, tx, amount` columns and optional `timestamp` and `tenant`
+    /// columns; `-` reads stdin
+    #[arg(required_unless_present_any = STREAMING_SOURCES, env = "WM_INPUT")]
+    pub input: Option<PathBuf>,
+
+    /// Further CSV inputs to interleave with INPUT: each file has to be in timestamp order, and
+    /// their transactions are applied in timestamp order across all of them
+    #[arg(
+        long = "merge",
+        value_name = "PATH",
+        requires = "input",
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_MERGE_INPUTS"
+    )]
+    pub merge_inputs: Vec<PathBuf>,
+
+    /// Accept transactions over TCP instead of reading a file, one CSV row
+    /// (`type,client,tx,amount[,timestamp[,tenant]]`) or JSON object per line, until Ctrl-C
+    #[arg(long, value_name = "ADDR", conflicts_with = "input", env = "WM_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// Lines a connection may read ahead of processing before the socket stops being read
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1024",
+        requires = "listen",
+        env = "WM_LISTEN_BUFFER"
+    )]
+    pub listen_buffer: NonZeroUsize,
+
+    /// Warn on stderr when more than N transactions wait between the input reader and the
+    /// wallets; --listen connections apply their transactions without this queue
+    #[arg(long, value_name = "N", env = "WM_QUEUE_DEPTH_WARN")]
+    pub queue_depth_warn: Option<usize>,
+
+    /// Warn on stderr when the oldest waiting transaction has waited longer than this
+    #[arg(long, value_name = "MS", env = "WM_QUEUE_AGE_WARN_MS")]
+    pub queue_age_warn_ms: Option<u64>,
+
+    /// Serve the clients with queued transactions in turn, one transaction each, so that a burst
+    /// from one client doesn't delay the others; each client's transactions still apply in order
+    #[arg(long, conflicts_with = "cutoff", env = "WM_FAIR_SCHEDULING")]
+    pub fair_scheduling: bool,
+
+    /// Transactions --fair-scheduling takes off the queue ahead of processing to choose from
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 4096,
+        requires = "fair_scheduling",
+        env = "WM_FAIR_WINDOW"
+    )]
+    pub fair_window: usize,
+
+    /// Smallest batch the wallets take off the queue; batches grow from this size while a
+    /// backlog builds up and shrink back once it clears
+    #[arg(long, value_name = "N", default_value = "1", env = "WM_BATCH_MIN")]
+    pub batch_min: NonZeroUsize,
+
+    /// Largest batch the wallets take off the queue; equal to --batch-min for a fixed size
+    #[arg(long, value_name = "N", default_value = "1024", env = "WM_BATCH_MAX")]
+    pub batch_max: NonZeroUsize,
+
+    /// Threads running the engine and the input readers; one per CPU core by default
+    #[arg(long, value_name = "N", env = "WM_WORKER_THREADS")]
+    pub worker_threads: Option<NonZeroUsize>,
+
+    /// Upper bound of the threads parsing files and doing other blocking work
+    #[arg(long, value_name = "N", env = "WM_BLOCKING_THREADS")]
+    pub blocking_threads: Option<NonZeroUsize>,
+
+    /// Lock shards of the in-memory wallet and journal maps, a power of two; more shards mean
+    /// less contention between worker threads at the cost of memory. This sizes the maps only,
+    /// transactions are still applied by the worker threads as they come
+    #[arg(long, value_name = "N", value_parser = parse_shards, env = "WM_MAP_SHARDS")]
+    pub map_shards: Option<usize>,
+
+    /// Where the wallet export and the failures go; `null` discards both, for benchmarking the
+    /// input and the engine without output
+    #[arg(long, value_enum, default_value_t = Sink::Stdout, env = "WM_SINK")]
+    pub sink: Sink,
+
+    /// Don't keep deposits and withdrawals in memory after applying them, so that every dispute
+    /// fails; for benchmarking
+    #[arg(long, env = "WM_NO_JOURNAL")]
+    pub no_journal: bool,
+
+    /// Keep deposits and withdrawals in a file under this directory rather than in memory, so
+    /// that inputs of any length are processed in constant memory; the file is removed on exit
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "no_journal",
+        env = "WM_JOURNAL_DIR"
+    )]
+    pub journal_dir: Option<PathBuf>,
+
+    /// Hold back disputes of transactions that haven't arrived yet, as out-of-order feeds deliver
+    /// them, and apply them once the transaction does
+    #[arg(
+        long,
+        conflicts_with_all = STREAMING_SOURCES,
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_DEFER_UNMATCHED_DISPUTES",
+    )]
+    pub defer_unmatched_disputes: bool,
+
+    /// Later transactions a held back dispute waits for its transaction before it fails
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10_000,
+        requires = "defer_unmatched_disputes",
+        env = "WM_DISPUTE_DEFER_LIMIT"
+    )]
+    pub dispute_defer_limit: u64,
+
+    /// Times a transaction may be disputed, each new dispute following the resolution of the
+    /// previous one
+    #[arg(long, value_name = "N", env = "WM_MAX_DISPUTE_CYCLES")]
+    pub max_dispute_cycles: Option<u32>,
+
+    /// Lift the lock a chargeback put on a wallet when the chargeback is re-presented
+    #[arg(long, env = "WM_UNLOCK_ON_REPRESENTMENT")]
+    pub unlock_on_representment: bool,
+
+    /// Release the hold of a dispute that is neither resolved nor charged back this many seconds
+    /// after it was opened, going by the input timestamps
+    #[arg(long, value_name = "SECS", env = "WM_DISPUTE_EXPIRY_SECS")]
+    pub dispute_expiry_secs: Option<i64>,
+
+    /// Accept `admin` commands on this Unix socket while running
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH", env = "WM_ADMIN_SOCKET")]
+    pub admin_socket: Option<PathBuf>,
+
+    /// Answer balance queries from a replica following the wallet events, so that they never
+    /// contend with the transactions being applied; answers may trail them slightly
+    #[arg(long, env = "WM_READ_REPLICA")]
+    pub read_replica: bool,
+
+    /// For CSV input grouped by client: write each wallet of the default namespace as soon as its
+    /// group ends (at the next client or a `close,<client>` row) and forget it, instead of
+    /// holding every wallet until the end
+    #[arg(long, conflicts_with_all = ["listen", "dormancy_days", "verify_totals", "dedupe_window"], env = "WM_STREAM_CLOSED_WALLETS")]
+    pub stream_closed_wallets: bool,
+
+    /// End of the business day for multi-day input in timestamp order, e.g. `17:00 UTC` or
+    /// `17:00 +02:00`; transactions at or after it count towards the next day
+    #[arg(
+        long,
+        value_name = "HH:MM TZ",
+        requires = "daily_output_dir",
+        conflicts_with_all = STREAMING_SOURCES,
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_CUTOFF",
+    )]
+    pub cutoff: Option<Cutoff>,
+
+    /// Directory receiving a wallet snapshot per business day (`wallets-<day>.csv`) and a
+    /// `days.csv` summary of each day's transactions
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "cutoff",
+        env = "WM_DAILY_OUTPUT_DIR"
+    )]
+    pub daily_output_dir: Option<PathBuf>,
+
+    /// Format of the input file; `auto` tells JSON Lines, Parquet and Avro inputs by their
+    /// extension and reads anything else as CSV
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto, env = "WM_FORMAT")]
+    pub format: InputFormat,
+
+    /// Number format of the CSV amount column
+    #[arg(long, value_enum, value_name = "LOCALE", default_value_t = AmountLocale::Plain, env = "WM_AMOUNT_LOCALE")]
+    pub amount_locale: AmountLocale,
+
+    /// Column layout of CSV inputs that don't name theirs in a `#version: <n>` first line
+    #[arg(long, value_enum, default_value_t = Schema::V1, env = "WM_SCHEMA")]
+    pub schema: Schema,
+
+    /// Currency of the wallets; rows of inputs with a currency column (schema v2) in another
+    /// currency are skipped
+    #[arg(long, value_name = "CODE", env = "WM_INPUT_CURRENCY")]
+    pub input_currency: Option<String>,
+
+    /// Format of the CSV timestamp column: `secs` (default) or `millis` since the Unix epoch,
+    /// `rfc3339`, or a strftime pattern such as `%d/%m/%Y %H:%M %z`; overrides the config file
+    #[arg(long, value_name = "FORMAT", env = "WM_TIMESTAMP_FORMAT")]
+    pub timestamp_format: Option<TimestampFormat>,
+
+    /// Accept CSV amounts padded with whitespace or in scientific notation (`1.5e2`)
+    #[arg(long, env = "WM_LENIENT_AMOUNTS")]
+    pub lenient_amounts: bool,
+
+    /// Client the entries of a statement (OFX, QIF, camt.053 or pain.001) are booked on
+    #[arg(long, value_name = "ID", required_if_eq_any = STATEMENT_FORMATS, env = "WM_STATEMENT_CLIENT")]
+    pub statement_client: Option<u16>,
+
+    /// Transaction id given to the first statement entry, later entries count up from it
+    #[arg(
+        long,
+        value_name = "TX",
+        default_value_t = 1,
+        env = "WM_STATEMENT_FIRST_TX"
+    )]
+    pub statement_first_tx: u32,
+
+    /// Wallet export or --binary-snapshot of a previous run to start from; its transactions
+    /// can't be disputed, and disputes open in it can't be resolved or charged back, so their
+    /// funds stay held. --resume keeps both
+    #[arg(long, value_name = "PATH", env = "WM_INITIAL_STATE")]
+    pub initial_state: Option<PathBuf>,
+
+    /// Log every transaction to a write-ahead log in this directory and checkpoint the wallets
+    /// there; on startup, recover what a crashed run left in it before reading new input
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "initial_state",
+        env = "WM_RESUME"
+    )]
+    pub resume: Option<PathBuf>,
+
+    /// Checkpoint the wallets to the --resume directory every N transactions, emptying its log
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "resume",
+        env = "WM_CHECKPOINT_EVERY"
+    )]
+    pub checkpoint_every: Option<u64>,
+
+    /// When logged transactions reach the disk: `always`, at most `<N>ms` later, or `never`
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "always",
+        requires = "resume",
+        env = "WM_WAL_FSYNC"
+    )]
+    pub wal_fsync: FsyncPolicy,
+
+    /// Skip the rows up to the first deposit or withdrawal with at least this id, which the run
+    /// producing --initial-state applied already; use the `next_watermark_tx` of its --summary
+    #[arg(
+        long,
+        value_name = "TX",
+        requires = "initial_state",
+        env = "WM_SKIP_BEFORE_TX"
+    )]
+    pub skip_before_tx: Option<u32>,
+
+    /// Skip the rows up to the first one stamped at or after this time, in seconds since the Unix
+    /// epoch, which the run producing --initial-state applied already
+    #[arg(
+        long,
+        value_name = "SECS",
+        requires = "initial_state",
+        conflicts_with = "skip_before_tx",
+        env = "WM_SKIP_BEFORE_TIMESTAMP"
+    )]
+    pub skip_before_timestamp: Option<i64>,
+
+    /// TOML file with account rules and `[tenants.<id>]` overrides; flags take precedence
+    #[arg(long, value_name = "PATH", env = "WM_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Re-read the config file and --client-min-balances whenever they change, applying the
+    /// new rules to the following transactions without restarting
+    #[arg(long, requires = "config", env = "WM_WATCH_CONFIG")]
+    pub watch_config: bool,
+
+    /// Directory receiving one `<tenant>.csv` wallet export per tenant
+    #[arg(long, value_name = "DIR", env = "WM_TENANT_OUTPUT_DIR")]
+    pub tenant_output_dir: Option<PathBuf>,
+
+    /// Split the wallet export into N files by client id modulo N, written in parallel into
+    /// --partition-dir as `wallets-<n>.csv` (`wallets-<n>-<tenant>.csv` for tenants)
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "partition_dir",
+        conflicts_with = "stream_closed_wallets",
+        value_parser = clap::value_parser!(u16).range(1..),
+        env = "WM_OUTPUT_PARTITIONS",
+    )]
+    pub output_partitions: Option<u16>,
+
+    /// Directory receiving the partitions of --output-partitions
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "output_partitions",
+        env = "WM_PARTITION_DIR"
+    )]
+    pub partition_dir: Option<PathBuf>,
+
+    /// Flag wallets without activity for this many days (relative to the latest input timestamp)
+    #[arg(long, value_name = "DAYS", env = "WM_DORMANCY_DAYS")]
+    pub dormancy_days: Option<i64>,
+
+    /// Fee deducted from the available funds of every dormant wallet
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_amount, requires = "dormancy_days", env = "WM_DORMANCY_FEE")]
+    pub dormancy_fee: Option<Amount>,
+
+    /// Write the dormancy report as CSV to this path
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "dormancy_days",
+        env = "WM_DORMANCY_REPORT"
+    )]
+    pub dormancy_report: Option<PathBuf>,
+
+    /// Add a `dormant` column to the wallet export
+    #[arg(long, requires = "dormancy_days", env = "WM_FLAG_DORMANT")]
+    pub flag_dormant: bool,
+
+    /// Write the house account balances (settlement, client liability, chargeback losses, fee
+    /// income) as CSV to this path
+    #[arg(long, value_name = "PATH", env = "WM_HOUSE_REPORT")]
+    pub house_report: Option<PathBuf>,
+
+    /// Write the risk exposure report, wallets grouped by the age of their oldest open dispute,
+    /// to this path
+    #[arg(long, value_name = "PATH", env = "WM_EXPOSURE_REPORT")]
+    pub exposure_report: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Csv, env = "WM_EXPOSURE_FORMAT")]
+    pub exposure_format: ReportFormat,
+
+    /// Upper bounds in days of the dispute age groups of the exposure report
+    #[arg(
+        long,
+        value_name = "DAYS,...",
+        value_delimiter = ',',
+        default_value = "1,7,30",
+        env = "WM_EXPOSURE_BUCKETS"
+    )]
+    pub exposure_buckets: Vec<i64>,
+
+    /// CSV file mapping clients to segments such as vip, retail or test, which the exposure
+    /// report and the --summary are broken down by
+    #[arg(long, value_name = "PATH", env = "WM_SEGMENTS")]
+    pub segments: Option<PathBuf>,
+
+    /// Write the wallet and transaction counts and the balances of every segment as CSV to
+    /// this path
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "segments",
+        env = "WM_SEGMENT_REPORT"
+    )]
+    pub segment_report: Option<PathBuf>,
+
+    /// Write every balance movement as a beancount or ledger-cli journal to this path
+    #[arg(long, value_name = "PATH", env = "WM_LEDGER_EXPORT")]
+    pub ledger_export: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = LedgerFormat::Beancount, env = "WM_LEDGER_FORMAT")]
+    pub ledger_format: LedgerFormat,
+
+    /// Commodity used for the amounts of the ledger export
+    #[arg(
+        long,
+        value_name = "CODE",
+        default_value = "USD",
+        env = "WM_LEDGER_COMMODITY"
+    )]
+    pub ledger_commodity: String,
+
+    /// Reject withdrawals that would leave less than this amount available
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_amount, env = "WM_MIN_BALANCE")]
+    pub min_balance: Option<Amount>,
+
+    /// CSV file with `client, minimum_balance` rows overriding `--min-balance` per client
+    #[arg(long, value_name = "PATH", env = "WM_CLIENT_MIN_BALANCES")]
+    pub client_min_balances: Option<PathBuf>,
+
+    /// CSV file with `wallet, client` rows authorizing additional clients on a wallet; adds an
+    /// `owners` column to the export
+    #[arg(long, value_name = "PATH", env = "WM_JOINT_WALLETS")]
+    pub joint_wallets: Option<PathBuf>,
+
+    /// Reject deposits larger than this amount
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_amount, env = "WM_MAX_DEPOSIT")]
+    pub max_deposit: Option<Amount>,
+
+    /// Reject withdrawals larger than this amount
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_amount, env = "WM_MAX_WITHDRAWAL")]
+    pub max_withdrawal: Option<Amount>,
+
+    /// Risk score from which a transaction is applied but reported as a warning
+    #[arg(long, value_name = "SCORE", env = "WM_RISK_FLAG")]
+    pub risk_flag: Option<f32>,
+
+    /// Risk score from which a transaction is applied and its wallet frozen until an admin
+    /// unfreezes it
+    #[arg(long, value_name = "SCORE", env = "WM_RISK_HOLD")]
+    pub risk_hold: Option<f32>,
+
+    /// Risk score from which a transaction is rejected
+    #[arg(long, value_name = "SCORE", env = "WM_RISK_REJECT")]
+    pub risk_reject: Option<f32>,
+
+    /// Write the risk scores and chargeback ratios of every client as CSV to this path
+    #[arg(long, value_name = "PATH", env = "WM_RISK_REPORT")]
+    pub risk_report: Option<PathBuf>,
+
+    /// Write every wallet freeze decided by risk monitoring as CSV to this path
+    #[arg(long, value_name = "PATH", env = "WM_RISK_JOURNAL")]
+    pub risk_journal: Option<PathBuf>,
+
+    /// Freeze a wallet once its chargebacks per deposit exceed this ratio
+    #[arg(long, value_name = "RATIO", env = "WM_CHARGEBACK_FREEZE_RATIO")]
+    pub chargeback_freeze_ratio: Option<f32>,
+
+    /// Latest deposits and chargebacks of a wallet the chargeback ratio is taken over
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "chargeback_freeze_ratio",
+        env = "WM_CHARGEBACK_WINDOW"
+    )]
+    pub chargeback_window: Option<usize>,
+
+    /// Chargebacks within the window before the chargeback ratio can freeze a wallet
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "chargeback_freeze_ratio",
+        env = "WM_CHARGEBACK_MIN_COUNT"
+    )]
+    pub chargeback_min_count: Option<usize>,
+
+    /// Drop rows that exactly repeat one of the previous N rows
+    #[arg(long, value_name = "N", env = "WM_DEDUPE_WINDOW")]
+    pub dedupe_window: Option<usize>,
+
+    /// Also write the wallet export as an Avro object container file to this path
+    #[cfg(feature = "avro")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_AVRO_OUTPUT"
+    )]
+    pub avro_output: Option<PathBuf>,
+
+    /// Also write the final wallets, open disputes included, as a binary snapshot that
+    /// --initial-state reads back
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_BINARY_SNAPSHOT"
+    )]
+    pub binary_snapshot: Option<PathBuf>,
+
+    /// Consume transactions from a NATS JetStream stream at this server until Ctrl-C, one line
+    /// protocol record per message
+    #[cfg(feature = "nats")]
+    #[arg(long, value_name = "URL", conflicts_with_all = ["input", "listen"], requires = "nats_stream", env = "WM_NATS_URL")]
+    pub nats_url: Option<String>,
+
+    #[cfg(feature = "nats")]
+    #[arg(long, value_name = "STREAM", env = "WM_NATS_STREAM")]
+    pub nats_stream: Option<String>,
+
+    /// Durable consumer name; a restarted engine resumes after the last acknowledged message
+    #[cfg(feature = "nats")]
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "walletmanagermock",
+        env = "WM_NATS_CONSUMER"
+    )]
+    pub nats_consumer: String,
+
+    /// Publish every failed transaction as JSON to this subject
+    #[cfg(feature = "nats")]
+    #[arg(
+        long,
+        value_name = "SUBJECT",
+        requires = "nats_url",
+        env = "WM_NATS_FAILURE_SUBJECT"
+    )]
+    pub nats_failure_subject: Option<String>,
+
+    /// Publish the updated wallet as JSON to `<SUBJECT>.<client>` after every transaction
+    #[cfg(feature = "nats")]
+    #[arg(
+        long,
+        value_name = "SUBJECT",
+        requires = "nats_url",
+        env = "WM_NATS_WALLET_SUBJECT"
+    )]
+    pub nats_wallet_subject: Option<String>,
+
+    /// Consume transactions from this RabbitMQ server until Ctrl-C, one line protocol record per
+    /// message
+    #[cfg(feature = "amqp")]
+    #[arg(long, value_name = "URL", conflicts_with_all = ["input", "listen"], requires = "amqp_queue", env = "WM_AMQP_URL")]
+    pub amqp_url: Option<String>,
+
+    #[cfg(feature = "amqp")]
+    #[arg(long, value_name = "QUEUE", env = "WM_AMQP_QUEUE")]
+    pub amqp_queue: Option<String>,
+
+    /// Exchange rejected deliveries are dead-lettered to, set as argument of the declared queue
+    #[cfg(feature = "amqp")]
+    #[arg(
+        long,
+        value_name = "EXCHANGE",
+        requires = "amqp_url",
+        env = "WM_AMQP_DEAD_LETTER_EXCHANGE"
+    )]
+    pub amqp_dead_letter_exchange: Option<String>,
+
+    /// Unacknowledged deliveries the broker may send ahead
+    #[cfg(feature = "amqp")]
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        env = "WM_AMQP_PREFETCH"
+    )]
+    pub amqp_prefetch: u16,
+
+    /// Decode NATS and RabbitMQ messages as Confluent-framed Avro, fetching their writer schemas
+    /// from the schema registry at this URL, instead of the line protocol
+    #[cfg(all(feature = "schema-registry", any(feature = "nats", feature = "amqp")))]
+    #[arg(long, value_name = "URL", env = "WM_SCHEMA_REGISTRY")]
+    pub schema_registry: Option<String>,
+
+    /// Serve the gRPC `WatchWallets` stream of wallet updates on this address
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDR", env = "WM_GRPC_LISTEN")]
+    pub grpc_listen: Option<SocketAddr>,
+
+    /// Accept CSV batches as multipart uploads to `POST /batches` on this address while serving
+    /// `--listen`; `GET /batches/{id}` reports each batch's status, summary and failures
+    #[cfg(feature = "rest")]
+    #[arg(long, value_name = "ADDR", requires = "listen", env = "WM_REST_LISTEN")]
+    pub rest_listen: Option<SocketAddr>,
+
+    /// Largest batch upload `--rest-listen` and `serve` accept
+    #[cfg(feature = "rest")]
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 64 << 20,
+        env = "WM_REST_UPLOAD_LIMIT"
+    )]
+    pub rest_upload_limit: usize,
+
+    /// Finished batches `--rest-listen` and `serve` keep reporting, the oldest dropped first
+    #[cfg(feature = "rest")]
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        env = "WM_REST_RETAINED_BATCHES"
+    )]
+    pub rest_retained_batches: usize,
+
+    /// Append failures to this JSON Lines file if the failure sink stops taking them, instead of
+    /// keeping them in memory; processing goes on either way
+    #[arg(long, value_name = "PATH", env = "WM_UNDELIVERED_FAILURES")]
+    pub undelivered_failures: Option<PathBuf>,
+
+    /// Write every rejected transaction with the input row it was read from, and the rejections
+    /// per failure kind, to this path; as CSV, the counts go to a `.summary.csv` file next to it
+    #[arg(long, value_name = "PATH", env = "WM_ERRORS")]
+    pub errors: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Csv, env = "WM_ERRORS_FORMAT")]
+    pub errors_format: ReportFormat,
+
+    /// POST failed transactions as JSON arrays to this URL instead of logging them
+    #[cfg(feature = "webhook")]
+    #[arg(long, value_name = "URL", env = "WM_FAILURE_WEBHOOK")]
+    pub failure_webhook: Option<String>,
+
+    /// Failures sent in one webhook request at most
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        env = "WM_FAILURE_BATCH_SIZE"
+    )]
+    pub failure_batch_size: usize,
+
+    /// Send a partial batch of failures after this many milliseconds
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        env = "WM_FAILURE_FLUSH_MS"
+    )]
+    pub failure_flush_ms: u64,
+
+    /// File keeping failure batches the webhook didn't accept, resent once it is reachable again
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "failures.spool.jsonl",
+        env = "WM_FAILURE_SPOOL"
+    )]
+    pub failure_spool: PathBuf,
+
+    /// Journal failures in this directory before POSTing them one by one to `--failure-webhook`,
+    /// so they are delivered at least once even if the engine crashes; replaces the spool
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "failure_webhook",
+        env = "WM_OUTBOX_DIR"
+    )]
+    pub outbox_dir: Option<PathBuf>,
+
+    /// When journaled failures reach the disk: `always` before they are delivered, `<N>ms` at
+    /// most N milliseconds later with bursts sharing one fsync, or `never`, leaving it to the OS
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "always",
+        requires = "outbox_dir",
+        env = "WM_OUTBOX_FSYNC"
+    )]
+    pub outbox_fsync: FsyncPolicy,
+
+    /// What to do when a transaction fails
+    #[arg(long, value_enum, value_name = "POLICY", env = "WM_ON_FAILURE")]
+    pub on_failure: Option<FailurePolicy>,
+
+    /// What a withdrawal from a client without a wallet does
+    #[arg(long, value_enum, value_name = "POLICY", env = "WM_ON_MISSING_WALLET")]
+    pub on_missing_wallet: Option<MissingWallet>,
+
+    /// What a dispute, resolve, chargeback or re-presentment from a client without a wallet does
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        env = "WM_ON_DISPUTE_MISSING_WALLET"
+    )]
+    pub on_dispute_missing_wallet: Option<MissingWallet>,
+
+    /// Failed transactions tolerated before `--on-failure abort` stops processing
+    #[arg(long, value_name = "N", env = "WM_MAX_FAILURES")]
+    pub max_failures: Option<usize>,
+
+    /// Write only these columns of the wallet export, in this order; optional columns also need
+    /// the flag enabling them
+    #[arg(long, value_name = "COLUMN,...", value_delimiter = ',', value_parser = PossibleValuesParser::new(export::COLUMNS), env = "WM_COLUMNS")]
+    pub columns: Option<Vec<String>>,
+
+    /// Leave out the header row of the wallet export
+    #[arg(long, env = "WM_NO_HEADER")]
+    pub no_header: bool,
+
+    /// Add `deposits, withdrawals, disputes, failures` counter columns to the wallet export
+    #[arg(long, env = "WM_CLIENT_STATS")]
+    pub client_stats: bool,
+
+    /// Add a `status` column with `active`, `frozen` (by an admin) or `locked` (by a chargeback)
+    #[arg(long, env = "WM_WALLET_STATUS")]
+    pub wallet_status: bool,
+
+    /// Add a `lock_reason` column telling why a wallet is locked, frozen or quarantined:
+    /// `chargeback:<tx>`, `admin`, `risk_score`, `chargeback_ratio` or `quarantine:<tx>`
+    #[arg(long, env = "WM_LOCK_REASON")]
+    pub lock_reason: bool,
+
+    /// Wallet export of a previous run; only wallets whose balances or status changed since are
+    /// exported. Daily snapshots after the first are compared against the day before
+    #[arg(
+        long,
+        value_name = "PREVIOUS",
+        conflicts_with = "stream_closed_wallets",
+        env = "WM_DELTA_OUTPUT"
+    )]
+    pub delta_output: Option<PathBuf>,
+
+    /// Add a `seq` column with the sequence number of the last transaction applied to each
+    /// wallet, so the export can serve as an --initial-state snapshot to replay a journal over
+    #[arg(long, env = "WM_EXPORT_SEQ")]
+    pub export_seq: bool,
+
+    /// Write every wallet status transition (created, locked, frozen, unfrozen, quarantined,
+    /// closed) with the triggering transaction, sequence number and lock reason to this CSV file
+    #[arg(long, value_name = "PATH", env = "WM_LIFECYCLE_AUDIT")]
+    pub lifecycle_audit: Option<PathBuf>,
+
+    /// Write the transactions turned away by frozen, quarantined or chargeback-locked wallets to
+    /// this CSV file, with the wallet's state at the time; it can be passed back as input once
+    /// they are unfrozen or unlocked
+    #[arg(long, value_name = "PATH", env = "WM_QUARANTINE_OUTPUT")]
+    pub quarantine_output: Option<PathBuf>,
+
+    /// Name the session, e.g. `2024-06-01`, the transactions of this run belong to unless a
+    /// `session` column says otherwise; sessions share the wallets but are accounted for apart
+    #[arg(long, value_name = "NAME", env = "WM_SESSION")]
+    pub session: Option<String>,
+
+    /// Write the transaction counts and net balance changes of every session in every wallet to
+    /// this CSV file
+    #[arg(long, value_name = "PATH", env = "WM_SESSION_REPORT")]
+    pub session_report: Option<PathBuf>,
+
+    /// Fail the run if the wallet totals don't add up to the deposits minus withdrawals,
+    /// chargebacks and fees of the journal
+    #[arg(long, env = "WM_VERIFY_TOTALS")]
+    pub verify_totals: bool,
+
+    /// What to do when the rows of a CSV input don't match its `trailer,,<records>,<sum>` row
+    #[arg(long, value_enum, default_value_t = TrailerMismatch::Fail, env = "WM_TRAILER_MISMATCH")]
+    pub trailer_mismatch: TrailerMismatch,
+
+    /// Print a hash of every namespace's balances and open disputes to stderr, to compare runs
+    /// or implementations
+    #[arg(long, env = "WM_STATE_HASH")]
+    pub state_hash: bool,
+
+    /// Print the Merkle root of every namespace's final balances to stderr, for attesting to
+    /// them; `admin proof` gives a client's inclusion proof
+    #[arg(long, env = "WM_MERKLE_ROOT")]
+    pub merkle_root: bool,
+
+    /// Write a JSON sidecar with the engine version, arguments, config file and input
+    /// checksums, row counts and state hash of the run next to every namespace's export
+    #[arg(long, value_name = "PATH", env = "WM_METADATA_OUTPUT")]
+    pub metadata_output: Option<PathBuf>,
+
+    /// Sample the CPU while the run processes its input and write the profile to this path as a
+    /// flamegraph SVG
+    #[cfg(feature = "profile")]
+    #[arg(long, value_name = "PATH", env = "WM_PROFILE")]
+    pub profile: Option<PathBuf>,
+
+    /// Report progress on stderr every second while the input is processed, as one JSON object
+    /// per line with the rows read, processed and failed and the estimated time left
+    #[arg(long, value_name = "FORMAT", env = "WM_PROGRESS")]
+    pub progress: Option<ProgressFormat>,
+
+    /// Print a JSON summary of the run to stderr
+    #[arg(long, env = "WM_SUMMARY")]
+    pub summary: bool,
+}
+
+#[cfg(any(unix, feature = "rest"))]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Control a running instance started with --admin-socket
+    #[cfg(unix)]
+    Admin {
+        /// Socket the instance listens on
+        #[arg(long, value_name = "PATH")]
+        socket: PathBuf,
+        /// Namespace to act on instead of the default one
+        #[arg(long)]
+        tenant: Option<String>,
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Take transactions over HTTP instead of reading a file, until Ctrl-C or an admin drain:
+    /// `POST /transactions`, `GET /wallets/{client}`, `GET /disputes`, `GET /failures` and the
+    /// batch endpoints of --rest-listen. The other options go before the subcommand
+    #[cfg(feature = "rest")]
+    Serve {
+        /// Address to serve on
+        #[arg(
+            long,
+            value_name = "ADDR",
+            default_value = "127.0.0.1:8080",
+            env = "WM_SERVE_ADDR"
+        )]
+        addr: SocketAddr,
+    },
+}
+
+impl Cli {
+    /// Address of the `serve` subcommand.
+    pub fn serve_addr(&self) -> Option<SocketAddr> {
+        #[cfg(feature = "rest")]
+        if let Some(Command::Serve { addr }) = &self.command {
+            return Some(*addr);
+        }
+        None
+    }
+}
+
+/// Sources that replace the input file.
+const STREAMING_SOURCES: &[&str] = &[
+    "listen",
+    #[cfg(feature = "nats")]
+    "nats_url",
+    #[cfg(feature = "amqp")]
+    "amqp_url",
+];
+
+const STATEMENT_FORMATS: [(&str, &str); 4] = [
+    ("format", "ofx"),
+    ("format", "qif"),
+    ("format", "camt053"),
+    ("format", "pain001"),
+];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Stdout,
+    Null,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Auto,
+    Csv,
+    /// Open Financial Exchange bank statement
+    Ofx,
+    /// Quicken Interchange Format bank statement
+    Qif,
+    /// ISO 20022 camt.053 bank-to-customer statement
+    #[cfg(feature = "iso20022")]
+    Camt053,
+    /// ISO 20022 pain.001 customer credit transfer initiation
+    #[cfg(feature = "iso20022")]
+    Pain001,
+    /// Avro object container file of transaction records
+    #[cfg(feature = "avro")]
+    Avro,
+    /// Length-prefixed binary transaction records, also accepted by --listen
+    Binary,
+    /// JSON object per line, as accepted by --listen
+    #[cfg(feature = "jsonl")]
+    Jsonl,
+    /// Parquet file with the columns of the CSV input
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl InputFormat {
+    /// The format the input at `path` is read in: `self`, or for `Auto` the one its extension
+    /// names, CSV if none does.
+    pub fn resolve(self, path: &Path) -> anyhow::Result<InputFormat> {
+        if self != InputFormat::Auto {
+            return Ok(self);
+        }
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        // Named after the formats, which only exist with their features.
+        let format = match extension.as_deref() {
+            Some("jsonl" | "ndjson") => "jsonl",
+            Some("parquet") => "parquet",
+            Some("avro") => "avro",
+            _ => return Ok(InputFormat::Csv),
+        };
+        InputFormat::from_str(format, false).map_err(|_| {
+            anyhow::anyhow!("{}: reading it needs the {format} feature", path.display())
+        })
+    }
+}
+
+pub fn parse_amount(s: &str) -> Result<Amount, String> {
+    s.parse()
+}
+
+fn parse_shards(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(shards) if shards > 1 && shards.is_power_of_two() => Ok(shards),
+        _ => Err(format!("{s} is not a power of two above 1")),
+    }
+}
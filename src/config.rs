@@ -0,0 +1,431 @@
+use crate::enrich::{Enricher, LookupSpec};
+use crate::persistence::PersistenceOptions;
+use crate::risk::{ChargebackPolicy, RiskScorer, RiskThresholds, WeightedScorer};
+use crate::timeformat::TimestampFormat;
+use crate::transaction::{Amount, Client, Failure, Tenant, Transaction};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Account rules applied by `WalletManager`.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Minimum available balance every withdrawal has to leave behind.
+    pub minimum_balance: Option<Amount>,
+    /// Per-client minimum balances, taking precedence over `minimum_balance`.
+    pub client_minimum_balances: HashMap<Client, Amount>,
+    pub limits: TransactionLimits,
+    /// Additional owners of joint wallets, mapped to the client id that keys the wallet.
+    pub joint_owners: HashMap<Client, Client>,
+    /// Record every balance movement for the plain-text-accounting export.
+    pub keep_ledger: bool,
+    /// Keep the transactions turned away by frozen or quarantined wallets for re-applying.
+    pub keep_quarantine: bool,
+    /// Record every wallet status transition for the lifecycle audit.
+    pub keep_lifecycle: bool,
+    pub failure_policy: FailurePolicy,
+    /// For withdrawals from clients without a wallet.
+    pub missing_wallet: MissingWallet,
+    /// For disputes, resolves, chargebacks and re-presentments from clients without a wallet.
+    pub missing_wallet_on_dispute: MissingWallet,
+    /// Failures tolerated before `FailurePolicy::Abort` stops processing.
+    pub max_failures: usize,
+    /// Scores every transaction when set.
+    pub risk_scorer: Option<Arc<dyn RiskScorer>>,
+    /// Adds attributes from lookup tables to every transaction before it is applied.
+    pub enricher: Option<Arc<Enricher>>,
+    pub risk_thresholds: RiskThresholds,
+    pub chargeback_policy: ChargebackPolicy,
+    /// Parks disputes of transactions not seen yet for up to this many later transactions,
+    /// applying them once the transaction arrives, instead of failing them right away.
+    pub dispute_deferral: Option<u64>,
+    /// Times a transaction may be disputed, each dispute after the first following the
+    /// resolution of the previous one. Unlimited when unset.
+    pub max_dispute_cycles: Option<u32>,
+    /// Lift the chargeback lock of a wallet when a chargeback is re-presented.
+    pub unlock_on_representment: bool,
+    /// Seconds of input time after which the hold of an open dispute is released.
+    pub dispute_expiry_secs: Option<i64>,
+    /// Lock shards of the wallet and journal maps, a power of two above 1. Dashmap's default,
+    /// four per core, when unset.
+    pub map_shards: Option<usize>,
+    /// Forget deposits and withdrawals once applied, failing every dispute, to measure the
+    /// throughput of the engine without its largest map.
+    pub skip_journal: bool,
+    /// Keep the journal in a file under this directory instead of memory, so that memory use
+    /// stays flat however long the input, at the cost of a read per dispute.
+    pub journal_dir: Option<PathBuf>,
+    /// Append failures to this JSON Lines file once their consumer is gone, rather than keeping
+    /// them in memory, as `run` goes on either way.
+    pub undelivered_failures: Option<PathBuf>,
+    /// Log every applied transaction ahead of applying it and checkpoint the wallets, so that
+    /// `WalletManager::recover` can rebuild the state after a crash.
+    pub persistence: Option<PersistenceOptions>,
+    /// Session of the transactions without a `session` attribute of their own.
+    pub session: Option<String>,
+}
+
+/// What happens after a transaction fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Report the failure and carry on with the next transaction
+    #[default]
+    Continue,
+    /// Stop processing once more than `max_failures` transactions failed
+    Abort,
+    /// Freeze the failing client's wallet, rejecting its further transactions until reviewed
+    Quarantine,
+}
+
+/// What a transaction from a client without a wallet does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingWallet {
+    /// Reject the transaction as one from an unknown client
+    #[default]
+    Reject,
+    /// Open an empty wallet for the client, so the transaction fails against it, e.g. a
+    /// withdrawal for insufficient funds
+    Create,
+}
+
+impl Config {
+    pub fn minimum_balance_for(&self, client: Client) -> Amount {
+        self.client_minimum_balances
+            .get(&client)
+            .copied()
+            .or(self.minimum_balance)
+            .unwrap_or_else(Amount::zero)
+    }
+
+    /// The client id keying the wallet `client` operates on.
+    pub fn wallet_of(&self, client: Client) -> Client {
+        self.joint_owners.get(&client).copied().unwrap_or(client)
+    }
+
+    /// Every client authorized on `wallet`, starting with the wallet's own client id.
+    pub fn owners_of(&self, wallet: Client) -> Vec<Client> {
+        let mut others: Vec<Client> = self
+            .joint_owners
+            .iter()
+            .filter(|(_, w)| **w == wallet)
+            .map(|(owner, _)| *owner)
+            .collect();
+        others.sort();
+        std::iter::once(wallet).chain(others).collect()
+    }
+}
+
+/// Upper bounds for a single transaction, meant to catch fat-finger rows. CSV rows over the
+/// limits of the default namespace are rejected as invalid rows when parsed, see
+/// `CsvOptions::limits`. Transactions are checked again when applied, failing as
+/// `AmountOverLimit`, for the limits of other tenants, reloaded limits and the other inputs.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionLimits {
+    pub max_deposit: Option<Amount>,
+    pub max_withdrawal: Option<Amount>,
+}
+
+impl TransactionLimits {
+    pub fn check(&self, transaction: &Transaction) -> Result<(), Failure> {
+        let (amount, limit, client, tx_id) = match *transaction {
+            Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            } => (amount, self.max_deposit, client, tx_id),
+            Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount,
+            } => (amount, self.max_withdrawal, client, tx_id),
+            _ => return Ok(()),
+        };
+        match limit {
+            Some(limit) if amount > limit => Err(Failure::amount_over_limit(client, tx_id, limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Overridable settings, as found at the top level of the config file and in its
+/// `[tenants.<id>]` tables.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Settings {
+    pub min_balance: Option<Amount>,
+    pub max_deposit: Option<Amount>,
+    pub max_withdrawal: Option<Amount>,
+    pub on_failure: Option<FailurePolicy>,
+    pub on_missing_wallet: Option<MissingWallet>,
+    pub on_dispute_missing_wallet: Option<MissingWallet>,
+    pub max_failures: Option<usize>,
+    pub risk_flag: Option<f32>,
+    pub risk_hold: Option<f32>,
+    pub risk_reject: Option<f32>,
+    pub chargeback_freeze_ratio: Option<f32>,
+    pub chargeback_window: Option<usize>,
+    pub chargeback_min_count: Option<usize>,
+    pub max_dispute_cycles: Option<u32>,
+    pub unlock_on_representment: Option<bool>,
+    pub dispute_expiry_secs: Option<i64>,
+}
+
+impl Settings {
+    pub fn sets_risk_thresholds(&self) -> bool {
+        self.risk_flag.is_some() || self.risk_hold.is_some() || self.risk_reject.is_some()
+    }
+
+    /// Unsets the fields whose names `keep` rejects, e.g. the ones not taken from the
+    /// environment.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        fn clear<T>(field: &mut Option<T>, name: &str, keep: &impl Fn(&str) -> bool) {
+            if !keep(name) {
+                *field = None;
+            }
+        }
+        clear(&mut self.min_balance, "min_balance", &keep);
+        clear(&mut self.max_deposit, "max_deposit", &keep);
+        clear(&mut self.max_withdrawal, "max_withdrawal", &keep);
+        clear(&mut self.on_failure, "on_failure", &keep);
+        clear(&mut self.on_missing_wallet, "on_missing_wallet", &keep);
+        clear(
+            &mut self.on_dispute_missing_wallet,
+            "on_dispute_missing_wallet",
+            &keep,
+        );
+        clear(&mut self.max_failures, "max_failures", &keep);
+        clear(&mut self.risk_flag, "risk_flag", &keep);
+        clear(&mut self.risk_hold, "risk_hold", &keep);
+        clear(&mut self.risk_reject, "risk_reject", &keep);
+        clear(
+            &mut self.chargeback_freeze_ratio,
+            "chargeback_freeze_ratio",
+            &keep,
+        );
+        clear(&mut self.chargeback_window, "chargeback_window", &keep);
+        clear(
+            &mut self.chargeback_min_count,
+            "chargeback_min_count",
+            &keep,
+        );
+        clear(&mut self.max_dispute_cycles, "max_dispute_cycles", &keep);
+        clear(
+            &mut self.unlock_on_representment,
+            "unlock_on_representment",
+            &keep,
+        );
+        clear(&mut self.dispute_expiry_secs, "dispute_expiry_secs", &keep);
+    }
+
+    /// Overwrites the fields of `config` that are set in these settings.
+    pub fn apply_to(&self, config: &mut Config) {
+        if let Some(min_balance) = self.min_balance {
+            config.minimum_balance = Some(min_balance);
+        }
+        if let Some(max_deposit) = self.max_deposit {
+            config.limits.max_deposit = Some(max_deposit);
+        }
+        if let Some(max_withdrawal) = self.max_withdrawal {
+            config.limits.max_withdrawal = Some(max_withdrawal);
+        }
+        if let Some(on_failure) = self.on_failure {
+            config.failure_policy = on_failure;
+        }
+        if let Some(missing_wallet) = self.on_missing_wallet {
+            config.missing_wallet = missing_wallet;
+        }
+        if let Some(missing_wallet) = self.on_dispute_missing_wallet {
+            config.missing_wallet_on_dispute = missing_wallet;
+        }
+        if let Some(max_failures) = self.max_failures {
+            config.max_failures = max_failures;
+        }
+        if let Some(flag) = self.risk_flag {
+            config.risk_thresholds.flag = Some(flag);
+        }
+        if let Some(hold) = self.risk_hold {
+            config.risk_thresholds.hold = Some(hold);
+        }
+        if let Some(reject) = self.risk_reject {
+            config.risk_thresholds.reject = Some(reject);
+        }
+        if let Some(ratio) = self.chargeback_freeze_ratio {
+            config.chargeback_policy.max_ratio = Some(ratio);
+        }
+        if let Some(window) = self.chargeback_window {
+            config.chargeback_policy.window = window;
+        }
+        if let Some(min_count) = self.chargeback_min_count {
+            config.chargeback_policy.min_chargebacks = min_count;
+        }
+        if let Some(max_cycles) = self.max_dispute_cycles {
+            config.max_dispute_cycles = Some(max_cycles);
+        }
+        if let Some(unlock) = self.unlock_on_representment {
+            config.unlock_on_representment = unlock;
+        }
+        if let Some(expiry) = self.dispute_expiry_secs {
+            config.dispute_expiry_secs = Some(expiry);
+        }
+    }
+}
+
+/// Contents of the TOML file passed with `--config`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub tenants: HashMap<Tenant, Settings>,
+    /// Format of the `timestamp` input column, e.g. a strftime pattern.
+    pub timestamp_format: Option<TimestampFormat>,
+    /// Lookup tables enriching every transaction, applied in order.
+    #[serde(default)]
+    pub enrich: Vec<LookupSpec>,
+    /// Risk score added per transaction attribute, keyed `name=value`.
+    #[serde(default)]
+    pub risk_attribute_weights: HashMap<String, f32>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// The options the config file is layered between, kept to rebuild the configuration whenever
+/// the file changes.
+#[derive(Debug, Clone)]
+pub struct ConfigLayers {
+    /// Everything the config file can't set.
+    pub base: Config,
+    /// Settings taken from `WM_*` variables, which the config file overrides.
+    pub env: Settings,
+    /// Settings taken from flags, which override the config file.
+    pub flags: Settings,
+    /// Score every transaction for the risk report, even without thresholds.
+    pub score_risk: bool,
+    /// The `client, minimum_balance` CSV file, reloaded along with the config file.
+    pub client_min_balances: Option<PathBuf>,
+}
+
+impl ConfigLayers {
+    pub fn resolve(&self, file: &ConfigFile) -> anyhow::Result<Config> {
+        let mut config = self.base.clone();
+        self.env.apply_to(&mut config);
+        file.settings.apply_to(&mut config);
+        self.flags.apply_to(&mut config);
+        if self.score_risk
+            || config.risk_thresholds.is_set()
+            || file.tenants.values().any(Settings::sets_risk_thresholds)
+        {
+            config.risk_scorer = Some(Arc::new(WeightedScorer {
+                per_attribute: file.risk_attribute_weights.clone(),
+                ..WeightedScorer::default()
+            }));
+        }
+        if !file.enrich.is_empty() {
+            config.enricher = Some(Arc::new(Enricher::load(&file.enrich)?));
+        }
+        if let Some(path) = &self.client_min_balances {
+            config.client_minimum_balances = load_client_minimum_balances(path)?;
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientMinimumBalance {
+    client: Client,
+    minimum_balance: Amount,
+}
+
+/// Loads a `client, minimum_balance` CSV file.
+pub fn load_client_minimum_balances(path: &Path) -> anyhow::Result<HashMap<Client, Amount>> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut balances = HashMap::new();
+    for row in csv_reader.deserialize() {
+        let row: ClientMinimumBalance = row?;
+        balances.insert(row.client, row.minimum_balance);
+    }
+    Ok(balances)
+}
+
+#[derive(Deserialize)]
+struct JointOwner {
+    wallet: Client,
+    client: Client,
+}
+
+/// Loads a CSV file of joint wallet owners, one `wallet, client` row per owner, as a map from each
+/// owning client to the client id keying its wallet. A client may own at most one wallet besides
+/// its own, and a joint owner can't be the key of another joint wallet.
+pub fn load_joint_wallets(path: &Path) -> anyhow::Result<HashMap<Client, Client>> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut owners = HashMap::new();
+    for row in csv_reader.deserialize() {
+        let row: JointOwner = row?;
+        if row.wallet == row.client {
+            continue;
+        }
+        if let Some(wallet) = owners.insert(row.client, row.wallet)
+            && wallet != row.wallet
+        {
+            anyhow::bail!(
+                "client {:?} is a joint owner of more than one wallet",
+                row.client
+            );
+        }
+    }
+    if let Some(wallet) = owners.values().find(|w| owners.contains_key(w)) {
+        anyhow::bail!("wallet {wallet:?} is itself a joint owner of another wallet");
+    }
+    Ok(owners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{FailureKind, TransactionId};
+
+    #[test]
+    fn test_limits_apply_per_transaction_type() {
+        let limits = TransactionLimits {
+            max_deposit: Some(Amount::from_major(1000000, 0)),
+            max_withdrawal: None,
+        };
+        let client = Client::new(1);
+        let tx_id = TransactionId::new(1);
+        let amount = Amount::from_major(2000000, 0);
+
+        let failure = limits
+            .check(&Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            })
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AmountOverLimit);
+        assert!(
+            limits
+                .check(&Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount,
+                })
+                .is_ok()
+        );
+        assert!(
+            limits
+                .check(&Transaction::Dispute { client, tx_id })
+                .is_ok()
+        );
+    }
+}
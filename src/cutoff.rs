@@ -0,0 +1,187 @@
+//! Business days for multi-day input. Transactions at or after the daily cut-off belong to the
+//! next business day; whenever the input crosses into a new day the finished day is closed, e.g.
+//! to write a wallet snapshot and a summary row for it.
+
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Amount, Envelope, Failure, Timestamp, Transaction};
+use crate::wallet_manager::RunReport;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Time of day a business day ends, as `HH:MM` followed by `UTC` or a fixed offset such as
+/// `+02:00`. Named zones aren't supported, so the offset doesn't follow daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cutoff {
+    /// Seconds after local midnight.
+    time: i64,
+    /// Seconds east of UTC.
+    offset: i64,
+}
+
+impl Cutoff {
+    /// The business day `timestamp` belongs to.
+    pub fn business_day(&self, timestamp: Timestamp) -> BusinessDay {
+        let local = timestamp.as_secs() + self.offset;
+        // A midnight cut-off keeps calendar days; any later one moves the rest of the day over.
+        let shifted = if self.time == 0 {
+            local
+        } else {
+            local - self.time + Timestamp::SECONDS_PER_DAY
+        };
+        BusinessDay(Timestamp::from_secs(shifted).to_date())
+    }
+}
+
+impl FromStr for Cutoff {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid cut-off {s:?}, expected e.g. `17:00 UTC` or `17:00 +02:00`");
+        let (time, zone) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        let time = parse_hours_minutes(time).filter(|t| *t < Timestamp::SECONDS_PER_DAY);
+        let offset = match zone.trim() {
+            "UTC" | "Z" => Some(0),
+            zone => match zone.split_at_checked(1) {
+                Some(("+", offset)) => parse_hours_minutes(offset),
+                Some(("-", offset)) => parse_hours_minutes(offset).map(|o| -o),
+                _ => None,
+            },
+        };
+        match (time, offset) {
+            (Some(time), Some(offset)) => Ok(Cutoff { time, offset }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// `HH:MM` as seconds.
+fn parse_hours_minutes(s: &str) -> Option<i64> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    ((0..24).contains(&hours) && (0..60).contains(&minutes)).then_some(hours * 3600 + minutes * 60)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BusinessDay((i32, u32, u32));
+
+impl fmt::Display for BusinessDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.0;
+        write!(f, "{year:04}-{month:02}-{day:02}")
+    }
+}
+
+impl Serialize for BusinessDay {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// One row of the per-day summary.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DaySummary {
+    pub day: BusinessDay,
+    pub transactions: u64,
+    pub failures: u64,
+    pub deposits: Amount,
+    pub withdrawals: Amount,
+}
+
+impl DaySummary {
+    fn new(day: BusinessDay) -> Self {
+        DaySummary {
+            day,
+            transactions: 0,
+            failures: 0,
+            deposits: Amount::zero(),
+            withdrawals: Amount::zero(),
+        }
+    }
+}
+
+/// Applies the transactions like `TenantRegistry::run`, calling `close_day` with the summary of
+/// every business day once the input moves past it and for the last day at the end. The input
+/// has to be in timestamp order; transactions without a timestamp count towards the current day.
+pub async fn run(
+    registry: &TenantRegistry,
+    mut tx_recv: UnboundedReceiver<Envelope>,
+    err_send: UnboundedSender<Failure>,
+    cutoff: Cutoff,
+    mut close_day: impl FnMut(&DaySummary) -> anyhow::Result<()>,
+) -> anyhow::Result<RunReport> {
+    let started = Instant::now();
+    let mut report = RunReport::default();
+    let mut current: Option<DaySummary> = None;
+    while let Some(envelope) = tx_recv.recv().await {
+        registry.queue().record_dequeue(tx_recv.len());
+        // Starts the first day as well, which has no finished day before it.
+        if let Some(day) = envelope.timestamp.map(|t| cutoff.business_day(t))
+            && current.as_ref().is_none_or(|summary| summary.day != day)
+            && let Some(finished) = current.replace(DaySummary::new(day))
+        {
+            close_day(&finished)?;
+        }
+        let transaction = envelope.transaction;
+        let res = registry.apply(envelope);
+        report.record(&transaction, res.is_ok());
+        if let Some(summary) = &mut current {
+            summary.transactions += 1;
+            match (&res, transaction) {
+                (Err(_), _) => summary.failures += 1,
+                (Ok(()), Transaction::Deposit { amount, .. }) => summary.deposits += amount,
+                (Ok(()), Transaction::Withdrawal { amount, .. }) => summary.withdrawals += amount,
+                _ => {}
+            }
+        }
+        if let Err(e) = res {
+            registry.deliver_failure(&err_send, e);
+            if registry.aborted() {
+                report.stopped_early = true;
+                break;
+            }
+        }
+        if !registry.forward_deferred_failures(&err_send, &mut report) {
+            report.stopped_early = true;
+            break;
+        }
+    }
+    if !report.stopped_early {
+        registry.expire_pending_disputes();
+        registry.forward_deferred_failures(&err_send, &mut report);
+    }
+    if let Some(finished) = current {
+        close_day(&finished)?;
+    }
+    report.duration = started.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transactions_after_cutoff_roll_into_next_day() {
+        let cutoff: Cutoff = "17:00 +02:00".parse().unwrap();
+        let day = |secs| cutoff.business_day(Timestamp::from_secs(secs)).to_string();
+        let jan_1 = Timestamp::from_date(2024, 1, 1).unwrap().as_secs();
+        // 14:59 UTC is 16:59 local time.
+        assert_eq!(day(jan_1 + 14 * 3600 + 59 * 60), "2024-01-01");
+        assert_eq!(day(jan_1 + 15 * 3600), "2024-01-02");
+        assert_eq!(day(jan_1 - 3 * 3600), "2024-01-01");
+
+        let midnight: Cutoff = "00:00 UTC".parse().unwrap();
+        assert_eq!(
+            midnight
+                .business_day(Timestamp::from_secs(jan_1))
+                .to_string(),
+            "2024-01-01"
+        );
+        assert!("25:00 UTC".parse::<Cutoff>().is_err());
+        assert!("17:00 Europe/Berlin".parse::<Cutoff>().is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use csv::StringRecord;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Drops rows that exactly repeat one of the last `capacity` rows, for upstream systems that
+/// occasionally emit the same row twice.
+pub struct DedupeWindow {
+    capacity: usize,
+    recent: VecDeque<u64>,
+    counts: HashMap<u64, usize>,
+    dropped: u64,
+}
+
+impl DedupeWindow {
+    pub fn new(capacity: usize) -> Self {
+        DedupeWindow {
+            capacity,
+            recent: VecDeque::with_capacity(capacity),
+            counts: HashMap::with_capacity(capacity),
+            dropped: 0,
+        }
+    }
+
+    /// Returns `true` (and counts the row as dropped) when the row is a duplicate within the
+    /// window; otherwise remembers it.
+    pub fn is_duplicate(&mut self, row: &StringRecord) -> bool {
+        let hash = Self::hash_row(row);
+        if self.counts.contains_key(&hash) {
+            self.dropped += 1;
+            return true;
+        }
+        if self.capacity == 0 {
+            return false;
+        }
+        if self.recent.len() == self.capacity
+            && let Some(evicted) = self.recent.pop_front()
+            && let Some(count) = self.counts.get_mut(&evicted)
+        {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&evicted);
+            }
+        }
+        self.recent.push_back(hash);
+        *self.counts.entry(hash).or_default() += 1;
+        false
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn hash_row(row: &StringRecord) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for field in row.iter() {
+            field.trim().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drops_repeats_within_window_only() {
+        let mut window = DedupeWindow::new(2);
+        let a = StringRecord::from(vec!["deposit", "1", "1", "1.0"]);
+        let b = StringRecord::from(vec!["deposit", "1", "2", "1.0"]);
+        let c = StringRecord::from(vec!["deposit", "1", "3", "1.0"]);
+
+        assert!(!window.is_duplicate(&a));
+        assert!(window.is_duplicate(&a));
+        assert!(!window.is_duplicate(&b));
+        assert!(!window.is_duplicate(&c));
+        // `a` has slid out of the window by now.
+        assert!(!window.is_duplicate(&a));
+        assert_eq!(window.dropped(), 1);
+    }
+}
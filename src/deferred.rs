@@ -0,0 +1,72 @@
+//! Disputes that arrive before the transaction they dispute, as out-of-order feeds deliver them.
+//! They are parked until the transaction shows up or too many later transactions went by.
+
+use crate::transaction::{Client, TransactionId};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, Default)]
+pub struct PendingDisputes {
+    /// Sequence number each pending dispute was parked at.
+    parked: HashMap<(Client, TransactionId), u64>,
+    /// Parked disputes in sequence order; entries taken since are skipped when expiring.
+    order: VecDeque<(u64, Client, TransactionId)>,
+}
+
+impl PendingDisputes {
+    /// Parks a dispute of `tx_id`, returning `false` if one is parked already.
+    pub fn park(&mut self, client: Client, tx_id: TransactionId, seq: u64) -> bool {
+        if self.parked.contains_key(&(client, tx_id)) {
+            return false;
+        }
+        self.parked.insert((client, tx_id), seq);
+        self.order.push_back((seq, client, tx_id));
+        true
+    }
+
+    /// Removes the dispute parked for `tx_id`, returning whether there was one.
+    pub fn take(&mut self, client: Client, tx_id: TransactionId) -> bool {
+        self.parked.remove(&(client, tx_id)).is_some()
+    }
+
+    /// Removes and returns the disputes parked more than `limit` transactions before `seq`.
+    pub fn expire(&mut self, seq: u64, limit: u64) -> Vec<(Client, TransactionId, u64)> {
+        let mut expired = Vec::new();
+        while let Some(&(parked_at, client, tx_id)) = self.order.front()
+            && parked_at.saturating_add(limit) < seq
+        {
+            self.order.pop_front();
+            if self.parked.get(&(client, tx_id)) == Some(&parked_at) {
+                self.parked.remove(&(client, tx_id));
+                expired.push((client, tx_id, parked_at));
+            }
+        }
+        expired
+    }
+
+    /// Removes and returns every parked dispute, e.g. at the end of the input.
+    pub fn drain(&mut self) -> Vec<(Client, TransactionId, u64)> {
+        self.expire(u64::MAX, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parked_disputes_expire_after_limit() {
+        let mut pending = PendingDisputes::default();
+        let (client, tx) = (Client::new(1), TransactionId::new);
+        assert!(pending.park(client, tx(1), 1));
+        assert!(!pending.park(client, tx(1), 2));
+        assert!(pending.park(client, tx(2), 3));
+        assert!(pending.take(client, tx(1)));
+        assert!(!pending.take(client, tx(1)));
+
+        assert_eq!(pending.expire(5, 2), vec![]);
+        assert_eq!(pending.expire(6, 2), vec![(client, tx(2), 3)]);
+        assert!(pending.park(client, tx(3), 7));
+        assert_eq!(pending.drain(), vec![(client, tx(3), 7)]);
+        assert_eq!(pending.drain(), vec![]);
+    }
+}
@@ -0,0 +1,17 @@
+use crate::transaction::{Amount, Client, Timestamp};
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct DormancyPolicy {
+    pub inactive_days: i64,
+    pub fee: Option<Amount>,
+}
+
+/// One row of the dormancy report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DormantWallet {
+    pub client: Client,
+    pub last_activity: Timestamp,
+    pub days_inactive: i64,
+    pub fee_charged: Amount,
+}
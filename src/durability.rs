@@ -0,0 +1,132 @@
+//! Durability of append-only journals on disk: how soon appended records are flushed to stable
+//! storage, trading the records a crash may lose for write throughput. Records are appended in
+//! groups, each written at once and synced at most once, so that a burst costs one fsync.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Every group is on disk before `append` returns.
+    #[default]
+    Always,
+    /// Groups are synced at most this long after they were appended.
+    Every(Duration),
+    /// Flushing is left to the operating system.
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            s => s
+                .strip_suffix("ms")
+                .and_then(|ms| ms.trim().parse().ok())
+                .map(|ms| FsyncPolicy::Every(Duration::from_millis(ms)))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid fsync policy {s:?}, expected `always`, `never` or e.g. `100ms`"
+                    )
+                }),
+        }
+    }
+}
+
+/// An append-only file synced according to its `FsyncPolicy`.
+#[derive(Debug)]
+pub struct JournalFile {
+    file: File,
+    policy: FsyncPolicy,
+    synced_at: Instant,
+    unsynced: bool,
+}
+
+impl JournalFile {
+    pub fn open(path: &Path, policy: FsyncPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JournalFile {
+            file,
+            policy,
+            synced_at: Instant::now(),
+            unsynced: false,
+        })
+    }
+
+    #[cfg(feature = "webhook")]
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Appends a group of records with a single write.
+    pub fn append(&mut self, group: &[u8]) -> io::Result<()> {
+        self.file.write_all(group)?;
+        self.unsynced = true;
+        match self.policy {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Every(interval) if self.synced_at.elapsed() >= interval => self.sync(),
+            FsyncPolicy::Every(_) | FsyncPolicy::Never => Ok(()),
+        }
+    }
+
+    /// Syncs what was appended since the last sync, unless the policy is `Never`.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced && self.policy != FsyncPolicy::Never {
+            self.file.sync_data()?;
+            self.unsynced = false;
+            self.synced_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Whether an `Every` policy wants the appended groups synced by now.
+    #[cfg(any(test, feature = "webhook"))]
+    pub fn sync_due(&self) -> bool {
+        match self.policy {
+            FsyncPolicy::Every(interval) => self.unsynced && self.synced_at.elapsed() >= interval,
+            FsyncPolicy::Always | FsyncPolicy::Never => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_decides_when_groups_are_synced() {
+        assert_eq!("always".parse(), Ok(FsyncPolicy::Always));
+        assert_eq!("never".parse(), Ok(FsyncPolicy::Never));
+        assert_eq!(
+            "250ms".parse(),
+            Ok(FsyncPolicy::Every(Duration::from_millis(250)))
+        );
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+
+        let path = std::env::temp_dir().join(format!("journal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut journal =
+            JournalFile::open(&path, FsyncPolicy::Every(Duration::from_secs(3_600))).unwrap();
+        journal.append(b"a\nb\n").unwrap();
+        assert!(journal.unsynced);
+        assert!(!journal.sync_due());
+        journal.sync().unwrap();
+        assert!(!journal.unsynced);
+
+        let mut journal = JournalFile::open(&path, FsyncPolicy::Always).unwrap();
+        journal.append(b"c\n").unwrap();
+        assert!(!journal.unsynced);
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\nb\nc\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+//! Enrichment of transactions from lookup tables before they are applied, e.g. mapping the
+//! merchant id of an input column to a category or attaching a currency by client. Enriched
+//! fields are attributes of the `Envelope`, which risk rules and the ledger export read.
+
+use crate::transaction::Envelope;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Named values attached to a transaction: the input columns without a meaning of their own and
+/// the fields added by enrichment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes(BTreeMap<String, String>);
+
+impl Attributes {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.insert(name.into(), value.into());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl FromIterator<(String, String)> for Attributes {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Attributes(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Attributes {
+    /// `name=value` pairs separated by `;`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A lookup table as configured in an `[[enrich]]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LookupSpec {
+    /// Attribute the looked up value is stored as.
+    pub field: String,
+    /// `client`, or the attribute whose value is looked up.
+    pub key: String,
+    /// CSV file with a header row, mapping the first column to the second.
+    pub table: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+struct LookupTable {
+    field: String,
+    key: String,
+    values: HashMap<String, String>,
+}
+
+impl LookupTable {
+    fn load(spec: &LookupSpec) -> anyhow::Result<Self> {
+        Ok(LookupTable {
+            field: spec.field.clone(),
+            key: spec.key.clone(),
+            values: load_table(&spec.table)?,
+        })
+    }
+
+    fn lookup(&self, envelope: &Envelope) -> Option<&String> {
+        match self.key.as_str() {
+            "client" => self
+                .values
+                .get(&envelope.transaction.client().id().to_string()),
+            attribute => self.values.get(envelope.attributes.get(attribute)?),
+        }
+    }
+}
+
+fn load_table(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut values = HashMap::new();
+    for row in csv_reader.records() {
+        let row = row?;
+        if let (Some(key), Some(value)) = (row.get(0), row.get(1)) {
+            values.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(values)
+}
+
+/// Adds the fields of its lookup tables to every transaction. Tables are applied in order, so a
+/// table can look up a field added by an earlier one, and fields already present are kept.
+#[derive(Debug, Clone, Default)]
+pub struct Enricher {
+    tables: Vec<LookupTable>,
+}
+
+impl Enricher {
+    pub fn load(specs: &[LookupSpec]) -> anyhow::Result<Self> {
+        let tables = specs
+            .iter()
+            .map(LookupTable::load)
+            .collect::<Result<_, _>>()?;
+        Ok(Enricher { tables })
+    }
+
+    pub fn enrich(&self, envelope: &mut Envelope) {
+        for table in &self.tables {
+            if envelope.attributes.get(&table.field).is_some() {
+                continue;
+            }
+            if let Some(value) = table.lookup(envelope).cloned() {
+                envelope.attributes.insert(table.field.clone(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, Transaction, TransactionId};
+
+    fn table(field: &str, key: &str, values: &[(&str, &str)]) -> LookupTable {
+        LookupTable {
+            field: field.into(),
+            key: key.into(),
+            values: values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_tables_enrich_in_order_without_overwriting() {
+        let enricher = Enricher {
+            tables: vec![
+                table("category", "merchant", &[("m1", "groceries")]),
+                table("segment", "category", &[("groceries", "retail")]),
+                table("currency", "client", &[("1", "EUR"), ("2", "USD")]),
+            ],
+        };
+        let deposit = |client: u16| Transaction::Deposit {
+            client: Client::new(client),
+            tx_id: TransactionId::new(1),
+            amount: Amount::from_major(1, 0),
+        };
+        let mut envelope = Envelope::from(deposit(1));
+        envelope.attributes.insert("merchant", "m1");
+        enricher.enrich(&mut envelope);
+        assert_eq!(
+            envelope.attributes.to_string(),
+            "category=groceries;currency=EUR;merchant=m1;segment=retail"
+        );
+
+        let mut envelope = Envelope::from(deposit(2));
+        envelope.attributes.insert("currency", "GBP");
+        enricher.enrich(&mut envelope);
+        assert_eq!(envelope.attributes.to_string(), "currency=GBP");
+    }
+}
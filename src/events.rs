@@ -0,0 +1,64 @@
+//! Wallet update events. Every successfully applied transaction publishes the balance change it
+//! caused on the wallet; subscribers such as the gRPC watch stream receive them as they happen.
+
+use crate::ledger::Movement;
+use crate::transaction::{Client, Tenant, TransactionId};
+use crate::wallet::Balance;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletEvent {
+    pub tenant: Option<Tenant>,
+    /// Sequence number of the transaction within its wallet manager.
+    pub seq: u64,
+    pub client: Client,
+    pub tx_id: TransactionId,
+    pub movement: Movement,
+    /// Change of each balance caused by the transaction.
+    pub delta: Balance,
+    pub balance: Balance,
+    pub locked: bool,
+}
+
+/// Fans wallet events out to subscribers. Subscribers that fall more than the hub capacity behind
+/// lose the oldest events.
+#[derive(Debug, Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<WalletEvent>,
+    tenant: Option<Tenant>,
+}
+
+impl EventHub {
+    pub fn new(capacity: usize) -> Self {
+        EventHub {
+            sender: broadcast::channel(capacity).0,
+            tenant: None,
+        }
+    }
+
+    /// A handle publishing into the same hub on behalf of `tenant`.
+    pub fn for_tenant(&self, tenant: Option<Tenant>) -> Self {
+        EventHub {
+            sender: self.sender.clone(),
+            tenant,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WalletEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Whether anyone listens, so publishers can skip building events nobody receives.
+    pub fn is_watched(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.tenant.as_ref()
+    }
+
+    pub fn publish(&self, event: WalletEvent) {
+        // Having no subscriber is not an error, events are simply dropped.
+        let _ = self.sender.send(event);
+    }
+}
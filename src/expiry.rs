@@ -0,0 +1,67 @@
+//! Holds that lapse: a dispute nobody resolves or charges back within the configured time is
+//! released back to the available funds, as card schemes drop stale authorizations.
+
+use crate::transaction::{Client, Timestamp, TransactionId};
+use std::collections::VecDeque;
+
+/// A hold placed at `opened`, identified by the dispute cycle it belongs to so that a hold of an
+/// earlier cycle of the same transaction doesn't release a later one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hold {
+    pub opened: Timestamp,
+    pub client: Client,
+    pub tx_id: TransactionId,
+    pub cycle: u32,
+}
+
+/// Holds in the order they were placed; the caller checks whether a due hold is still in place.
+#[derive(Debug, Clone, Default)]
+pub struct ExpiringHolds {
+    order: VecDeque<Hold>,
+}
+
+impl ExpiringHolds {
+    pub fn place(&mut self, hold: Hold) {
+        self.order.push_back(hold);
+    }
+
+    /// Removes and returns the holds placed more than `ttl_secs` before `now`.
+    pub fn expire(&mut self, now: Timestamp, ttl_secs: i64) -> Vec<Hold> {
+        let mut due = Vec::new();
+        while let Some(hold) = self.order.front()
+            && hold.opened.as_secs().saturating_add(ttl_secs) < now.as_secs()
+        {
+            due.extend(self.order.pop_front());
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_expire_after_ttl() {
+        let hold = |opened, tx| Hold {
+            opened: Timestamp::from_secs(opened),
+            client: Client::new(1),
+            tx_id: TransactionId::new(tx),
+            cycle: 1,
+        };
+        let mut holds = ExpiringHolds::default();
+        holds.place(hold(100, 1));
+        holds.place(hold(150, 2));
+
+        assert_eq!(holds.expire(Timestamp::from_secs(160), 60), vec![]);
+        assert_eq!(
+            holds.expire(Timestamp::from_secs(161), 60),
+            vec![hold(100, 1)]
+        );
+        assert_eq!(
+            holds.expire(Timestamp::from_secs(1_000), 60),
+            vec![hold(150, 2)]
+        );
+        assert_eq!(holds.expire(Timestamp::from_secs(1_000), 60), vec![]);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::transaction::{Amount, Client};
+use crate::wallet::Wallet;
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Optional columns appended to the wallet export.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub dormant: bool,
+    pub owners: bool,
+    pub quarantined: bool,
+    /// `status` column telling active, frozen and chargeback-locked wallets apart.
+    pub status: bool,
+    /// `deposits, withdrawals, disputes, failures` counters.
+    pub stats: bool,
+    /// `seq` column with the last sequence number applied to each wallet, which lets the export
+    /// serve as a snapshot to replay the journal over.
+    pub seq: bool,
+    /// `lock_reason` column telling why a locked, frozen or quarantined wallet is, e.g.
+    /// `chargeback:17` or `admin`.
+    pub lock_reason: bool,
+    /// Columns to write, in this order, instead of every enabled column.
+    pub columns: Option<Vec<String>>,
+    pub skip_header: bool,
+}
+
+/// Every column the wallet export can have, in their default order.
+pub const COLUMNS: [&str; 15] = [
+    "client",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "status",
+    "lock_reason",
+    "dormant",
+    "owners",
+    "quarantined",
+    "deposits",
+    "withdrawals",
+    "disputes",
+    "failures",
+    "seq",
+];
+
+/// One row of the wallet export. Columns that are `None` are left out of the file entirely, so
+/// the default export keeps the plain `client, available, held, total, locked` layout.
+#[derive(Serialize)]
+struct WalletRecord {
+    client: Client,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+    /// Empty for an unrestricted wallet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dormant: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owners: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantined: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deposits: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    withdrawals: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disputes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+
+impl WalletRecord {
+    fn new(wallet: &Wallet, options: &ExportOptions) -> Self {
+        WalletRecord {
+            client: wallet.client(),
+            available: wallet.available(),
+            held: wallet.held(),
+            total: wallet.total(),
+            locked: wallet.is_locked(),
+            status: options.status.then(|| wallet.status()),
+            lock_reason: options.lock_reason.then(|| {
+                wallet
+                    .lock_reason()
+                    .map(|reason| reason.to_string())
+                    .unwrap_or_default()
+            }),
+            dormant: options.dormant.then_some(wallet.dormant),
+            owners: options.owners.then(|| {
+                wallet
+                    .owners()
+                    .map(|c| c.id().to_string())
+                    .collect::<Vec<_>>()
+                    .join(";")
+            }),
+            quarantined: options.quarantined.then_some(wallet.quarantined),
+            deposits: options.stats.then_some(wallet.stats.deposits),
+            withdrawals: options.stats.then_some(wallet.stats.withdrawals),
+            disputes: options.stats.then_some(wallet.stats.disputes),
+            failures: options.stats.then_some(wallet.stats.failures),
+            seq: options.seq.then_some(wallet.last_seq),
+        }
+    }
+
+    /// The value of `column`, or `None` if it isn't part of this record.
+    fn field(&self, column: &str) -> Option<String> {
+        Some(match column {
+            "client" => self.client.id().to_string(),
+            "available" => self.available.to_string(),
+            "held" => self.held.to_string(),
+            "total" => self.total.to_string(),
+            "locked" => self.locked.to_string(),
+            "status" => self.status?.to_string(),
+            "lock_reason" => self.lock_reason.clone()?,
+            "dormant" => self.dormant?.to_string(),
+            "owners" => self.owners.clone()?,
+            "quarantined" => self.quarantined?.to_string(),
+            "deposits" => self.deposits?.to_string(),
+            "withdrawals" => self.withdrawals?.to_string(),
+            "disputes" => self.disputes?.to_string(),
+            "failures" => self.failures?.to_string(),
+            "seq" => self.seq?.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// Writes the wallet export one wallet at a time, so wallets can be written as soon as they are
+/// final.
+pub struct WalletCsvWriter<W: io::Write> {
+    wtr: Writer<W>,
+    options: ExportOptions,
+}
+
+impl<W: io::Write> WalletCsvWriter<W> {
+    pub fn new(writer: W, options: &ExportOptions) -> csv::Result<Self> {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(!options.skip_header)
+            .from_writer(writer);
+        if let Some(columns) = &options.columns {
+            // Optional columns exist only when enabled, which is the same for every wallet.
+            let template = WalletRecord::new(&Wallet::new(Client::new(0)), options);
+            if let Some(column) = columns.iter().find(|c| template.field(c).is_none()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("column `{column}` is not enabled for this export"),
+                )
+                .into());
+            }
+            if !options.skip_header {
+                wtr.write_record(columns)?;
+            }
+        }
+        Ok(WalletCsvWriter {
+            wtr,
+            options: options.clone(),
+        })
+    }
+
+    pub fn write(&mut self, wallet: &Wallet) -> csv::Result<()> {
+        let record = WalletRecord::new(wallet, &self.options);
+        match &self.options.columns {
+            None => self.wtr.serialize(record),
+            Some(columns) => self
+                .wtr
+                .write_record(columns.iter().filter_map(|c| record.field(c))),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+pub fn write_wallets_csv<W: io::Write>(
+    writer: W,
+    wallets: &[Wallet],
+    options: &ExportOptions,
+) -> csv::Result<()> {
+    let mut wtr = WalletCsvWriter::new(writer, options)?;
+    for wallet in wallets {
+        wtr.write(wallet)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Splits the wallet export into `partitions` files by client id modulo `partitions`, writing
+/// them in parallel to `path_of(n)`. Every partition gets a file, even if it has no wallets.
+pub fn write_partitioned_wallets_csv(
+    wallets: Vec<Wallet>,
+    partitions: usize,
+    options: &ExportOptions,
+    path_of: impl Fn(usize) -> PathBuf,
+) -> csv::Result<()> {
+    let mut buckets: Vec<Vec<Wallet>> = (0..partitions).map(|_| Vec::new()).collect();
+    for wallet in wallets {
+        buckets[usize::from(wallet.client().id()) % partitions].push(wallet);
+    }
+    thread::scope(|scope| {
+        let writers: Vec<_> = buckets
+            .into_iter()
+            .enumerate()
+            .map(|(n, bucket)| {
+                let path = path_of(n);
+                scope.spawn(move || write_wallets_csv(File::create(path)?, &bucket, options))
+            })
+            .collect();
+        writers
+            .into_iter()
+            .try_for_each(|writer| writer.join().expect("partition writer panicked"))
+    })
+}
+
+/// Reads a wallet export, e.g. to start from the state a previous run ended with.
+pub fn read_wallets_csv<R: io::Read>(reader: R) -> csv::Result<Vec<Wallet>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect()
+}
+
+/// Wallet state a delta export is taken against, e.g. the export of the previous run.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaBaseline(HashMap<Client, Wallet>);
+
+impl DeltaBaseline {
+    pub fn new(wallets: Vec<Wallet>) -> Self {
+        DeltaBaseline(wallets.into_iter().map(|w| (w.client(), w)).collect())
+    }
+
+    /// The wallets that are new or whose balances or status differ from the baseline, compared
+    /// at the precision amounts are exported with. Wallets missing from `wallets` aren't
+    /// reported.
+    pub fn changed(&self, wallets: &[Wallet]) -> Vec<Wallet> {
+        wallets
+            .iter()
+            .filter(|wallet| {
+                self.0.get(&wallet.client()).is_none_or(|previous| {
+                    !previous.available().same_to_precision(wallet.available())
+                        || !previous.held().same_to_precision(wallet.held())
+                        || !previous.total().same_to_precision(wallet.total())
+                        || previous.status() != wallet.status()
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn write_csv_report<T: Serialize>(path: &Path, rows: &[T]) -> csv::Result<()> {
+    let mut wtr = Writer::from_path(path)?;
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::LockReason;
+    use crate::transaction::TransactionId;
+
+    #[test]
+    fn test_selected_columns_without_header() {
+        let wallets = [Wallet::new(Client::new(7))];
+        let mut options = ExportOptions {
+            columns: Some(vec!["total".to_string(), "client".to_string()]),
+            skip_header: true,
+            ..ExportOptions::default()
+        };
+        let mut out = Vec::new();
+        write_wallets_csv(&mut out, &wallets, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0.0000,7\n");
+
+        options.columns = Some(vec!["client".to_string(), "deposits".to_string()]);
+        assert!(write_wallets_csv(Vec::new(), &wallets, &options).is_err());
+        options.stats = true;
+        options.skip_header = false;
+        let mut out = Vec::new();
+        write_wallets_csv(&mut out, &wallets, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "client,deposits\n7,0\n");
+    }
+
+    #[test]
+    fn test_export_reads_back() {
+        let mut wallet = Wallet::new(Client::new(3));
+        wallet.deposit(TransactionId::new(1), Amount::from_major(12, 5_000));
+        wallet
+            .dispute(TransactionId::new(1), Amount::from_major(2, 0), None)
+            .unwrap();
+        wallet.locked = true;
+        wallet.frozen = true;
+        wallet.lock_reasons = vec![
+            LockReason::ChargeBack(TransactionId::new(1)),
+            LockReason::Admin,
+        ];
+        let options = ExportOptions {
+            stats: true,
+            status: true,
+            lock_reason: true,
+            ..ExportOptions::default()
+        };
+        let mut out = Vec::new();
+        write_wallets_csv(&mut out, std::slice::from_ref(&wallet), &options).unwrap();
+
+        let mut expected = wallet.clone();
+        expected.lock_reasons = vec![LockReason::Admin];
+        expected.open_disputes.clear();
+        expected.dispute_cycles.clear();
+        expected.disputed_at.clear();
+        assert_eq!(read_wallets_csv(out.as_slice()).unwrap(), vec![expected]);
+    }
+
+    #[test]
+    fn test_partitions_split_wallets_by_client() {
+        let dir = std::env::temp_dir().join(format!(
+            "walletmanagermock-partitions-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wallets = (1..=5).map(|c| Wallet::new(Client::new(c))).collect();
+        let path_of = |n| dir.join(format!("wallets-{n}.csv"));
+        write_partitioned_wallets_csv(wallets, 3, &ExportOptions::default(), path_of).unwrap();
+
+        let clients: Vec<Vec<u16>> = (0..3)
+            .map(|n| {
+                read_wallets_csv(File::open(path_of(n)).unwrap())
+                    .unwrap()
+                    .iter()
+                    .map(|w| w.client().id())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(clients, vec![vec![3], vec![1, 4], vec![2, 5]]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delta_keeps_new_and_changed_wallets() {
+        let wallet = |client: u16, amount: u64| {
+            let mut wallet = Wallet::new(Client::new(client));
+            wallet.deposit(TransactionId::new(1), Amount::from_major(amount, 0));
+            wallet
+        };
+        let mut previous = Vec::new();
+        write_wallets_csv(
+            &mut previous,
+            &[wallet(1, 5), wallet(2, 5), wallet(3, 5)],
+            &ExportOptions::default(),
+        )
+        .unwrap();
+        let baseline = DeltaBaseline::new(read_wallets_csv(previous.as_slice()).unwrap());
+
+        let mut locked = wallet(3, 5);
+        locked.locked = true;
+        let current = [wallet(1, 5), wallet(2, 7), locked, wallet(4, 5)];
+        let clients: Vec<u16> = baseline
+            .changed(&current)
+            .iter()
+            .map(|w| w.client().id())
+            .collect();
+        assert_eq!(clients, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Risk exposure of the whole portfolio: wallets grouped by the age of their oldest open dispute,
+//! with the funds available, held and under dispute and the negative balances of each group.
+
+use crate::export::write_csv_report;
+use crate::segment::Segments;
+use crate::transaction::{Amount, Timestamp};
+use crate::wallet::Wallet;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+/// Upper bounds of the dispute age groups in days, e.g. `[1, 7, 30]` for under a day, under a
+/// week, under a month and older.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgeBuckets(Vec<i64>);
+
+impl AgeBuckets {
+    pub fn new(mut days: Vec<i64>) -> Self {
+        days.sort_unstable();
+        days.dedup();
+        AgeBuckets(days)
+    }
+
+    /// Labels of the groups in report order, starting with wallets without open disputes.
+    fn labels(&self) -> Vec<String> {
+        let mut lower = 0;
+        let mut labels = vec!["none".to_string()];
+        for &upper in &self.0 {
+            labels.push(format!("{lower}-{upper}d"));
+            lower = upper;
+        }
+        labels.push(format!(">={lower}d"));
+        labels
+    }
+
+    /// Index into `labels` of a wallet whose oldest open dispute is `age_days` old.
+    fn index(&self, age_days: i64) -> usize {
+        1 + self
+            .0
+            .iter()
+            .take_while(|&&upper| age_days >= upper)
+            .count()
+    }
+}
+
+/// One group of the exposure report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExposureRow {
+    /// Segment of the wallets in the group, in reports broken down by segment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    /// Age of the oldest open dispute of the wallets in the group; `unknown` for disputes from
+    /// input without timestamps.
+    pub bucket: String,
+    pub clients: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub open_disputes: usize,
+    pub disputed: Amount,
+    /// Wallets with a negative available balance, e.g. after disputing funds already withdrawn.
+    pub negative_balances: usize,
+    pub negative_amount: Amount,
+}
+
+impl ExposureRow {
+    fn new(bucket: String) -> Self {
+        ExposureRow {
+            segment: None,
+            bucket,
+            clients: 0,
+            available: Amount::zero(),
+            held: Amount::zero(),
+            open_disputes: 0,
+            disputed: Amount::zero(),
+            negative_balances: 0,
+            negative_amount: Amount::zero(),
+        }
+    }
+
+    fn add(&mut self, wallet: &Wallet) {
+        self.clients += 1;
+        self.available += wallet.available();
+        self.held += wallet.held();
+        self.open_disputes += wallet.open_disputes().len();
+        self.disputed += wallet.open_disputes().values().copied().sum();
+        if wallet.available() < Amount::zero() {
+            self.negative_balances += 1;
+            self.negative_amount += wallet.available();
+        }
+    }
+}
+
+/// Groups `wallets` by the age of their oldest open dispute as of `now`. Every configured group
+/// is reported, the `unknown` one only if a wallet has disputes from input without timestamps.
+pub fn exposure_report<'a>(
+    wallets: impl IntoIterator<Item = &'a Wallet>,
+    now: Option<Timestamp>,
+    buckets: &AgeBuckets,
+) -> Vec<ExposureRow> {
+    let mut rows: Vec<ExposureRow> = buckets.labels().into_iter().map(ExposureRow::new).collect();
+    let mut unknown = ExposureRow::new("unknown".to_string());
+    for wallet in wallets {
+        if wallet.open_disputes().is_empty() {
+            rows[0].add(wallet);
+            continue;
+        }
+        let oldest = wallet
+            .open_disputes()
+            .keys()
+            .map(|tx| wallet.disputed_at(*tx))
+            .min()
+            .flatten();
+        match (oldest, now) {
+            (Some(opened), Some(now)) => rows[buckets.index(now.days_since(opened))].add(wallet),
+            _ => unknown.add(wallet),
+        }
+    }
+    if unknown.clients > 0 {
+        rows.push(unknown);
+    }
+    rows
+}
+
+/// `exposure_report` of the wallets of every segment, ordered by segment.
+pub fn segmented_exposure_report(
+    wallets: &[Wallet],
+    now: Option<Timestamp>,
+    buckets: &AgeBuckets,
+    segments: &Segments,
+) -> Vec<ExposureRow> {
+    let mut rows = Vec::new();
+    for (segment, wallets) in segments.group(wallets) {
+        rows.extend(
+            exposure_report(wallets, now, buckets)
+                .into_iter()
+                .map(|row| ExposureRow {
+                    segment: Some(segment.to_string()),
+                    ..row
+                }),
+        );
+    }
+    rows
+}
+
+pub fn write_exposure_report(
+    path: &Path,
+    rows: &[ExposureRow],
+    format: ReportFormat,
+) -> anyhow::Result<()> {
+    match format {
+        ReportFormat::Csv => write_csv_report(path, rows)?,
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), rows)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Client, TransactionId};
+
+    #[test]
+    fn test_wallets_grouped_by_oldest_dispute() {
+        let day = |days| Timestamp::from_secs(days * Timestamp::SECONDS_PER_DAY);
+        let wallet = |client, disputes: &[(u32, Option<i64>)]| {
+            let mut wallet = Wallet::new(Client::new(client));
+            wallet.deposit(TransactionId::new(0), Amount::from_major(10, 0));
+            for &(tx, opened) in disputes {
+                let tx = TransactionId::new(tx);
+                wallet.dispute(tx, Amount::from_major(4, 0), None).unwrap();
+                if let Some(opened) = opened {
+                    wallet.disputed_at.insert(tx, day(opened));
+                }
+            }
+            wallet
+        };
+        let wallets = [
+            wallet(1, &[]),
+            wallet(2, &[(1, Some(9)), (2, Some(2))]),
+            wallet(3, &[(3, Some(9)), (4, Some(9)), (5, Some(9))]),
+            wallet(4, &[(6, None)]),
+        ];
+        let rows = exposure_report(&wallets, Some(day(10)), &AgeBuckets::new(vec![7, 1]));
+
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|row| (row.bucket.as_str(), row.clients, row.open_disputes))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("none", 1, 0),
+                ("0-1d", 0, 0),
+                ("1-7d", 1, 3),
+                (">=7d", 1, 2),
+                ("unknown", 1, 1),
+            ]
+        );
+        assert_eq!(rows[2].negative_balances, 1);
+        assert_eq!(rows[2].negative_amount, -Amount::from_major(2, 0));
+        assert_eq!(rows[3].disputed, Amount::from_major(8, 0));
+        assert_eq!(rows[3].available, Amount::from_major(2, 0));
+    }
+
+    #[test]
+    fn test_exposure_broken_down_by_segment() {
+        let path = std::env::temp_dir().join(format!("exposure-{}.csv", std::process::id()));
+        std::fs::write(&path, "client,segment\n1,vip\n2,vip\n").unwrap();
+        let segments = Segments::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let wallets: Vec<_> = (1..=3)
+            .map(|client| {
+                let mut wallet = Wallet::new(Client::new(client));
+                wallet.deposit(TransactionId::new(1), Amount::from_major(5, 0));
+                wallet
+            })
+            .collect();
+
+        let rows = segmented_exposure_report(&wallets, None, &AgeBuckets::new(vec![7]), &segments);
+
+        let none: Vec<_> = rows
+            .iter()
+            .filter(|row| row.bucket == "none")
+            .map(|row| (row.segment.as_deref(), row.clients, row.available))
+            .collect();
+        assert_eq!(
+            none,
+            [
+                (Some("unassigned"), 1, Amount::from_major(5, 0)),
+                (Some("vip"), 2, Amount::from_major(10, 0)),
+            ]
+        );
+        assert_eq!(rows.len(), 6);
+    }
+}
@@ -0,0 +1,157 @@
+//! Round-robin scheduling of queued transactions across clients, so that a client sending a
+//! burst doesn't hold up the transactions of quieter clients queued behind it. Each client's
+//! transactions keep their order, and so do transactions carrying a sequence number, e.g.
+//! replayed from a journal, which are skipped if applied out of order.
+
+use crate::transaction::{Client, Envelope, Tenant};
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// The inbox a transaction waits in; `None` for the one of sequenced transactions.
+type Inbox = Option<(Option<Tenant>, Client)>;
+
+/// Client inboxes served in turn, one transaction at a time.
+#[derive(Debug)]
+pub struct FairQueue {
+    /// Transactions taken off the channel ahead of processing, at most.
+    window: usize,
+    inboxes: HashMap<Inbox, VecDeque<Envelope>>,
+    /// Inboxes with waiting transactions, the next one to serve in front.
+    turns: VecDeque<Inbox>,
+    len: usize,
+}
+
+impl FairQueue {
+    pub fn new(window: usize) -> Self {
+        FairQueue {
+            window: window.max(1),
+            inboxes: HashMap::new(),
+            turns: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    /// Transactions waiting in the inboxes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, envelope: Envelope) {
+        let inbox = match envelope.seq {
+            Some(_) => None,
+            None => Some((envelope.tenant.clone(), envelope.transaction.client())),
+        };
+        let waiting = self.inboxes.entry(inbox.clone()).or_default();
+        if waiting.is_empty() {
+            self.turns.push_back(inbox);
+        }
+        waiting.push_back(envelope);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Envelope> {
+        let inbox = self.turns.pop_front()?;
+        let waiting = self.inboxes.get_mut(&inbox)?;
+        let envelope = waiting.pop_front();
+        if waiting.is_empty() {
+            self.inboxes.remove(&inbox);
+        } else {
+            self.turns.push_back(inbox);
+        }
+        self.len -= 1;
+        envelope
+    }
+
+    /// The next transaction to apply, `None` once the channel is closed and every inbox empty.
+    /// Waits for the channel only while every inbox is empty.
+    pub async fn next(&mut self, tx_recv: &mut UnboundedReceiver<Envelope>) -> Option<Envelope> {
+        if self.is_empty() {
+            self.push(tx_recv.recv().await?);
+        }
+        while self.len < self.window
+            && let Ok(envelope) = tx_recv.try_recv()
+        {
+            self.push(envelope);
+        }
+        self.pop()
+    }
+
+    /// Like `next`, appending up to `limit` transactions to `batch`; returns how many, 0 once
+    /// the channel is closed and every inbox empty.
+    pub async fn next_batch(
+        &mut self,
+        tx_recv: &mut UnboundedReceiver<Envelope>,
+        batch: &mut Vec<Envelope>,
+        limit: usize,
+    ) -> usize {
+        let Some(envelope) = self.next(tx_recv).await else {
+            return 0;
+        };
+        batch.push(envelope);
+        let mut taken = 1;
+        while taken < limit
+            && let Some(envelope) = self.pop()
+        {
+            batch.push(envelope);
+            taken += 1;
+        }
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Transaction, TransactionId};
+
+    fn deposit(client: u16, tx: u32) -> Envelope {
+        Envelope::from(Transaction::Deposit {
+            client: Client::new(client),
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(1, 0),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_clients_take_turns() {
+        let (tx_send, mut tx_recv) = tokio::sync::mpsc::unbounded_channel();
+        // A burst of client 1 ahead of clients 2 and 3, and two sequenced transactions.
+        for tx in 1..=4 {
+            tx_send.send(deposit(1, tx)).unwrap();
+        }
+        tx_send.send(deposit(2, 5)).unwrap();
+        tx_send.send(deposit(3, 6)).unwrap();
+        for (tx, seq) in [(7, 2), (8, 1)] {
+            tx_send
+                .send(Envelope {
+                    seq: Some(seq),
+                    ..deposit(tx as u16, tx)
+                })
+                .unwrap();
+        }
+        tx_send.send(deposit(2, 9)).unwrap();
+        drop(tx_send);
+
+        let mut queue = FairQueue::new(16);
+        let mut order = Vec::new();
+        while let Some(envelope) = queue.next(&mut tx_recv).await {
+            order.push(envelope.transaction.tx_id().id());
+        }
+        assert_eq!(order, [1, 5, 6, 7, 2, 9, 8, 3, 4]);
+        assert_eq!(queue.len(), 0);
+
+        // Nothing is taken ahead of processing with a window of one.
+        let (tx_send, mut tx_recv) = tokio::sync::mpsc::unbounded_channel();
+        for tx in [1, 2] {
+            tx_send.send(deposit(1, tx)).unwrap();
+        }
+        tx_send.send(deposit(2, 3)).unwrap();
+        let mut queue = FairQueue::new(1);
+        queue.next(&mut tx_recv).await.unwrap();
+        assert_eq!(tx_recv.len(), 2);
+    }
+}
@@ -0,0 +1,135 @@
+//! C ABI for embedding the engine in-process, e.g. in a C++ settlement system. The declarations
+//! are in `include/walletmanager.h`, generated with `cbindgen --config cbindgen.toml`.
+//!
+//! An engine is a single-tenant wallet manager with the default configuration. It may be used
+//! from several threads at once, except for `wm_destroy`.
+
+use crate::transaction::Envelope;
+use crate::wallet_manager::WalletManager;
+use std::ffi::{CStr, CString, c_char};
+use std::ptr;
+
+/// An engine created by `wm_create`.
+pub struct WmEngine {
+    manager: WalletManager,
+}
+
+/// Outcome of `wm_submit_csv_row`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WmStatus {
+    /// The transaction was applied.
+    Applied = 0,
+    /// The transaction was valid but rejected, e.g. a withdrawal exceeding the available funds.
+    Rejected = 1,
+    /// The row isn't a transaction.
+    InvalidRow = 2,
+    /// A pointer argument was null or the row isn't UTF-8.
+    InvalidArgument = 3,
+}
+
+/// Creates an engine, to be released with `wm_destroy`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wm_create() -> *mut WmEngine {
+    Box::into_raw(Box::new(WmEngine {
+        manager: WalletManager::init(),
+    }))
+}
+
+/// Applies one row in the CSV format of the input, without a header:
+/// `type,client,tx,amount[,timestamp]`.
+///
+/// # Safety
+///
+/// `engine` must come from `wm_create` and `row` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wm_submit_csv_row(
+    engine: *const WmEngine,
+    row: *const c_char,
+) -> WmStatus {
+    if engine.is_null() || row.is_null() {
+        return WmStatus::InvalidArgument;
+    }
+    let (engine, row) = unsafe { (&*engine, CStr::from_ptr(row)) };
+    let Ok(row) = row.to_str() else {
+        return WmStatus::InvalidArgument;
+    };
+    match Envelope::from_line(row).map(|envelope| engine.manager.apply(envelope)) {
+        Some(Ok(())) => WmStatus::Applied,
+        Some(Err(_)) => WmStatus::Rejected,
+        None => WmStatus::InvalidRow,
+    }
+}
+
+/// The wallets as a JSON array of `{client, available, held, total, locked}` objects ordered by
+/// client, or null if `engine` is null. The string is released with `wm_free_string`.
+///
+/// # Safety
+///
+/// `engine` must come from `wm_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wm_export_json(engine: *const WmEngine) -> *mut c_char {
+    let Some(engine) = (unsafe { engine.as_ref() }) else {
+        return ptr::null_mut();
+    };
+    let mut wallets = engine.manager.export_wallets();
+    wallets.sort_unstable_by_key(|wallet| wallet.client());
+    let json = serde_json::to_string(&wallets).expect("wallets serialize to JSON");
+    CString::new(json)
+        .expect("JSON has no NUL bytes")
+        .into_raw()
+}
+
+/// Releases a string returned by `wm_export_json`; null is ignored.
+///
+/// # Safety
+///
+/// `string` must come from `wm_export_json` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wm_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+/// Releases an engine; null is ignored.
+///
+/// # Safety
+///
+/// `engine` must come from `wm_create` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wm_destroy(engine: *mut WmEngine) {
+    if !engine.is_null() {
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_lifecycle_through_c_abi() {
+        let engine = wm_create();
+        let submit = |row: &CStr| unsafe { wm_submit_csv_row(engine, row.as_ptr()) };
+        assert_eq!(submit(c"deposit,2,1,3.0"), WmStatus::Applied);
+        assert_eq!(submit(c"deposit,1,2,1.5"), WmStatus::Applied);
+        assert_eq!(submit(c"withdrawal,1,3,2.0"), WmStatus::Rejected);
+        assert_eq!(submit(c"refund,1,4,2.0"), WmStatus::InvalidRow);
+        assert_eq!(
+            unsafe { wm_submit_csv_row(ptr::null(), c"deposit,1,5,1.0".as_ptr()) },
+            WmStatus::InvalidArgument
+        );
+
+        let json = unsafe { wm_export_json(engine) };
+        let wallets: serde_json::Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(json) }.to_str().unwrap()).unwrap();
+        assert_eq!(wallets[0]["client"], 1);
+        assert_eq!(wallets[1]["client"], 2);
+        assert_eq!(wallets[0]["available"], "1.5000");
+        unsafe {
+            wm_free_string(json);
+            wm_destroy(engine);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! gRPC service streaming wallet updates to subscribers such as downstream risk systems, fed by
+//! the wallet event hub. The schema is published in `proto/walletmanager/v1/walletmanager.proto`.
+
+use crate::events::{EventHub, WalletEvent};
+use crate::ledger::Movement;
+use std::pin::Pin;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/walletmanager.v1.WalletWatch.rs"));
+}
+
+#[cfg(test)]
+use generated::wallet_watch_client::WalletWatchClient;
+use generated::wallet_watch_server::{WalletWatch, WalletWatchServer};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchFilter {
+    /// Only stream updates of these clients; every client when empty.
+    #[prost(uint32, repeated, tag = "1")]
+    pub clients: Vec<u32>,
+    /// Only stream updates of this tenant; the empty string selects the default namespace.
+    #[prost(string, optional, tag = "2")]
+    pub tenant: Option<String>,
+}
+
+impl WatchFilter {
+    fn matches(&self, event: &WalletEvent) -> bool {
+        let client_matches =
+            self.clients.is_empty() || self.clients.contains(&event.client.id().into());
+        let tenant_matches = match &self.tenant {
+            Some(tenant) => tenant == event.tenant.as_ref().map_or("", |t| t.as_str()),
+            None => true,
+        };
+        client_matches && tenant_matches
+    }
+}
+
+/// The change one transaction made to a wallet. Amounts are decimal strings.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WalletUpdate {
+    #[prost(string, tag = "1")]
+    pub tenant: String,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    /// One of `deposit`, `withdrawal`, `hold`, `release`, `chargeback`, `representment`, `fee`.
+    #[prost(string, tag = "4")]
+    pub movement: String,
+    #[prost(string, tag = "5")]
+    pub available_delta: String,
+    #[prost(string, tag = "6")]
+    pub held_delta: String,
+    #[prost(string, tag = "7")]
+    pub total_delta: String,
+    #[prost(string, tag = "8")]
+    pub available: String,
+    #[prost(string, tag = "9")]
+    pub held: String,
+    #[prost(string, tag = "10")]
+    pub total: String,
+    #[prost(bool, tag = "11")]
+    pub locked: bool,
+    /// Sequence number of the transaction within its tenant.
+    #[prost(uint64, tag = "12")]
+    pub seq: u64,
+}
+
+impl From<&WalletEvent> for WalletUpdate {
+    fn from(event: &WalletEvent) -> Self {
+        let movement = match event.movement {
+            Movement::Deposit => "deposit",
+            Movement::Withdrawal => "withdrawal",
+            Movement::Hold => "hold",
+            Movement::Release => "release",
+            Movement::ChargeBack => "chargeback",
+            Movement::Representment => "representment",
+            Movement::Fee => "fee",
+            Movement::WithdrawalHold => "withdrawal_hold",
+            Movement::WithdrawalRelease => "withdrawal_release",
+            Movement::WithdrawalReversal => "withdrawal_reversal",
+        };
+        WalletUpdate {
+            tenant: event
+                .tenant
+                .as_ref()
+                .map_or_else(String::new, |t| t.as_str().to_string()),
+            client: event.client.id().into(),
+            tx: event.tx_id.id(),
+            movement: movement.to_string(),
+            available_delta: event.delta.available.to_string(),
+            held_delta: event.delta.held.to_string(),
+            total_delta: event.delta.total.to_string(),
+            available: event.balance.available.to_string(),
+            held: event.balance.held.to_string(),
+            total: event.balance.total.to_string(),
+            locked: event.locked,
+            seq: event.seq,
+        }
+    }
+}
+
+struct WatchService {
+    events: EventHub,
+}
+
+#[tonic::async_trait]
+impl WalletWatch for WatchService {
+    type WatchWalletsStream = Pin<Box<dyn Stream<Item = Result<WalletUpdate, Status>> + Send>>;
+
+    async fn watch_wallets(
+        &self,
+        request: Request<WatchFilter>,
+    ) -> Result<Response<Self::WatchWalletsStream>, Status> {
+        let filter = request.into_inner();
+        let updates =
+            BroadcastStream::new(self.events.subscribe()).filter_map(move |event| match event {
+                Ok(event) => filter
+                    .matches(&event)
+                    .then(|| Ok(WalletUpdate::from(&event))),
+                // Ends the stream; the subscriber has to resynchronise from a wallet export.
+                Err(BroadcastStreamRecvError::Lagged(dropped)) => Some(Err(Status::data_loss(
+                    format!("subscriber fell behind, {dropped} updates were dropped"),
+                ))),
+            });
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+/// Serves the `walletmanager.v1.WalletWatch` service on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, events: EventHub) -> anyhow::Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(WalletWatchServer::new(WatchService { events }))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::tenant::TenantRegistry;
+    use crate::transaction::{Amount, Client, Envelope, Tenant, Transaction, TransactionId};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_watch_wallets_streams_filtered_updates() {
+        let events = EventHub::new(16);
+        let registry =
+            TenantRegistry::new(Config::default(), HashMap::new()).with_events(events.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, events));
+
+        let mut client = WalletWatchClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        let mut updates = client
+            .watch_wallets(WatchFilter {
+                clients: vec![1],
+                tenant: Some(String::new()),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let deposit = |client, tx| Transaction::Deposit {
+            client: Client::new(client),
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(5, 0),
+        };
+        registry
+            .apply(Envelope {
+                tenant: Some(Tenant::new("acme")),
+                ..deposit(1, 1).into()
+            })
+            .unwrap();
+        registry.apply(deposit(2, 2).into()).unwrap();
+        registry.apply(deposit(1, 3).into()).unwrap();
+        registry
+            .apply(
+                Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(3),
+                }
+                .into(),
+            )
+            .unwrap();
+
+        let update = updates.message().await.unwrap().unwrap();
+        assert_eq!((update.client, update.tx), (1, 3));
+        assert_eq!(update.movement, "deposit");
+        assert_eq!(update.available_delta, "5.0000");
+        let update = updates.message().await.unwrap().unwrap();
+        assert_eq!(update.movement, "hold");
+        assert_eq!(update.available_delta, "-5.0000");
+        assert_eq!(update.held_delta, "5.0000");
+        assert_eq!(update.total, "5.0000");
+    }
+}
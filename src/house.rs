@@ -0,0 +1,107 @@
+use crate::transaction::Amount;
+use serde::Serialize;
+
+/// The house side of every client movement, kept so that the books balance:
+/// `settlement + withdrawal_receivables == client_liability + chargeback_losses + fee_income`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HouseAccounts {
+    /// Funds received from deposits minus funds paid out by withdrawals.
+    pub settlement: Amount,
+    /// What the house owes its clients, mirroring the sum of wallet totals.
+    pub client_liability: Amount,
+    /// Funds clawed back from clients by chargebacks, less those re-presented.
+    pub chargeback_losses: Amount,
+    /// Fees taken from client wallets.
+    pub fee_income: Amount,
+    /// Disputed and charged back withdrawals, credited back to their clients and to be recovered
+    /// from the payees.
+    pub withdrawal_receivables: Amount,
+    /// Client funds carried over from a previous run, part of the settlement as well.
+    pub opening_balances: Amount,
+}
+
+impl HouseAccounts {
+    pub fn new() -> Self {
+        HouseAccounts {
+            settlement: Amount::zero(),
+            client_liability: Amount::zero(),
+            chargeback_losses: Amount::zero(),
+            fee_income: Amount::zero(),
+            withdrawal_receivables: Amount::zero(),
+            opening_balances: Amount::zero(),
+        }
+    }
+
+    pub fn opening_balance(&mut self, amount: Amount) {
+        self.settlement += amount;
+        self.client_liability += amount;
+        self.opening_balances += amount;
+    }
+
+    pub fn deposit(&mut self, amount: Amount) {
+        self.settlement += amount;
+        self.client_liability += amount;
+    }
+
+    pub fn withdrawal(&mut self, amount: Amount) {
+        self.settlement -= amount;
+        self.client_liability -= amount;
+    }
+
+    pub fn charge_back(&mut self, amount: Amount) {
+        self.client_liability -= amount;
+        self.chargeback_losses += amount;
+    }
+
+    /// A chargeback reversed by a re-presentment, owed to the client again.
+    pub fn representment(&mut self, amount: Amount) {
+        self.client_liability += amount;
+        self.chargeback_losses -= amount;
+    }
+
+    /// A withdrawal under dispute, owed to the client again until the dispute is resolved.
+    pub fn dispute_withdrawal(&mut self, amount: Amount) {
+        self.client_liability += amount;
+        self.withdrawal_receivables += amount;
+    }
+
+    /// A withdrawal dispute resolved in the payee's favour.
+    pub fn settle_withdrawal_dispute(&mut self, amount: Amount) {
+        self.client_liability -= amount;
+        self.withdrawal_receivables -= amount;
+    }
+
+    pub fn fee(&mut self, amount: Amount) {
+        self.client_liability -= amount;
+        self.fee_income += amount;
+    }
+
+    /// Report rows, with the summed wallet totals alongside the liability they should match.
+    pub fn report(&self, wallet_totals: Amount) -> Vec<HouseAccountRow> {
+        [
+            ("settlement", self.settlement),
+            ("client_liability", self.client_liability),
+            ("chargeback_losses", self.chargeback_losses),
+            ("fee_income", self.fee_income),
+            ("withdrawal_receivables", self.withdrawal_receivables),
+            ("opening_balances", self.opening_balances),
+            ("wallet_totals", wallet_totals),
+        ]
+        .into_iter()
+        .map(|(account, balance)| HouseAccountRow { account, balance })
+        .collect()
+    }
+}
+
+impl Default for HouseAccounts {
+    fn default() -> Self {
+        HouseAccounts::new()
+    }
+}
+
+/// One row of the house account report.
+#[derive(Debug, Clone, Serialize)]
+pub struct HouseAccountRow {
+    pub account: &'static str,
+    pub balance: Amount,
+}
@@ -0,0 +1,246 @@
+//! Opening inputs, reading CSV and the counters the run summary reports. The readers feeding
+//! the channel of the wallet managers are in `source`.
+
+use crate::config::TransactionLimits;
+use crate::export::{ExportOptions, WalletCsvWriter};
+use crate::locale::AmountLocale;
+use crate::progress::{self, ProgressReader};
+use crate::provenance::{self, ChecksumReader};
+use crate::schema::{self, Schema};
+use crate::tenant::TenantRegistry;
+use crate::timeformat::TimestampFormat;
+use crate::trailer::{ControlTotals, TrailerMismatch};
+use crate::transaction::{Client, Columns, Envelope, Failure, Origin, field};
+use crate::wallet_manager::RunReport;
+use crate::watermark::{ProcessedPrefix, Watermark};
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task;
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Opens an input for streaming, `-` being stdin. Inputs are read front to back exactly once, so
+/// pipes and FIFOs, e.g. fed by a decompressor, work as well as regular files.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    let mut input: Box<dyn io::Read + Send> = if is_stdin(path) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    if progress::tracking() {
+        let size = std::fs::metadata(path)
+            .ok()
+            .filter(|metadata| metadata.is_file() && !is_stdin(path))
+            .map(|metadata| metadata.len());
+        input = Box::new(ProgressReader::new(size, input));
+    }
+    if provenance::recording() {
+        input = Box::new(ChecksumReader::new(path, input));
+    }
+    Ok(input)
+}
+
+/// Counters about the input, written to stderr with `--summary`.
+#[derive(Debug, Default, Serialize)]
+pub struct ReadSummary {
+    pub rows_read: u64,
+    pub rows_skipped: u64,
+    pub duplicates_dropped: u64,
+    /// Rows of the prefix a previous run applied already, see `--skip-before-tx`.
+    pub rows_before_watermark: u64,
+    /// Highest deposit or withdrawal id read plus one, to pass as `--skip-before-tx` once the
+    /// input has grown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_watermark_tx: Option<u32>,
+    /// What sources applying the transactions themselves, rather than sending them to the
+    /// channel of the wallet managers, applied; reported with the run's own counts.
+    #[serde(skip)]
+    pub applied: RunReport,
+    /// Where the skipped rows were read, for sources recording it, see `Envelope::origin`.
+    #[serde(skip)]
+    pub invalid_rows: Vec<Origin>,
+}
+
+/// How CSV inputs are read.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub amount_locale: AmountLocale,
+    /// Whether amounts may be padded or in scientific notation, see `AmountLocale::parse`.
+    pub lenient_amounts: bool,
+    pub timestamp_format: TimestampFormat,
+    pub trailer_mismatch: TrailerMismatch,
+    /// Layout of inputs without a `#version:` line.
+    pub schema: Schema,
+    /// Currency that rows with a currency column have to be in.
+    pub currency: Option<String>,
+    /// End of the prefix applied by a previous run.
+    pub watermark: Option<Watermark>,
+    /// Caps of the deposit and withdrawal rows, which are reported as invalid rows above them.
+    pub limits: TransactionLimits,
+}
+
+pub(crate) type CsvReader = csv::Reader<io::BufReader<Box<dyn Read + Send>>>;
+
+impl CsvOptions {
+    /// Opens a CSV input, reading its `#version:` line and header row.
+    pub(crate) fn open(&self, path: &Path, flexible: bool) -> anyhow::Result<(CsvReader, Columns)> {
+        self.reader(open_input(path)?, path, flexible)
+    }
+
+    /// Reads the `#version:` line and header row of `input`, which `path` names in errors.
+    pub(crate) fn reader(
+        &self,
+        input: Box<dyn Read + Send>,
+        path: &Path,
+        flexible: bool,
+    ) -> anyhow::Result<(CsvReader, Columns)> {
+        let mut input = io::BufReader::new(input);
+        let schema = schema::read_version(&mut input)
+            .with_context(|| format!("reading {}", path.display()))?
+            .unwrap_or(self.schema);
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::Headers)
+            .flexible(flexible)
+            .from_reader(input);
+        let columns = schema
+            .columns(csv_reader.headers()?)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        let columns = Columns {
+            amount_locale: self.amount_locale,
+            lenient_amounts: self.lenient_amounts,
+            timestamp_format: self.timestamp_format.clone(),
+            expected_currency: self.currency.clone(),
+            limits: self.limits.clone(),
+            ..columns
+        };
+        Ok((csv_reader, columns))
+    }
+}
+
+/// Decodes the messages of the broker sources: Confluent-framed Avro when a schema registry is
+/// set, line protocol records (see `Envelope::from_line`) otherwise.
+#[cfg(any(feature = "nats", feature = "amqp"))]
+#[derive(Clone, Default)]
+pub struct MessageDecoder {
+    #[cfg(feature = "schema-registry")]
+    pub schema_registry: Option<Arc<crate::avro::registry::SchemaRegistry>>,
+}
+
+#[cfg(any(feature = "nats", feature = "amqp"))]
+impl MessageDecoder {
+    /// The transaction in `payload`, `None` if it doesn't hold a valid one.
+    pub async fn decode(&self, payload: &[u8]) -> Option<Envelope> {
+        #[cfg(feature = "schema-registry")]
+        if let Some(registry) = &self.schema_registry {
+            return registry.decode(payload).await.unwrap_or_else(|e| {
+                log::warn!("Skipping message: {e:#}");
+                None
+            });
+        }
+        str::from_utf8(payload).ok().and_then(Envelope::from_line)
+    }
+}
+
+/// Applies a CSV grouped by client straight to the registry, writing each wallet of the default
+/// namespace to stdout once its group ends: at a row of another client, at a `close,<client>`
+/// row or at the end of the input. Transactions of a client whose wallet was closed already,
+/// i.e. of input that isn't grouped after all, fail rather than open a second wallet.
+pub async fn stream_grouped_csv(
+    path: PathBuf,
+    csv_options: CsvOptions,
+    registry: Arc<TenantRegistry>,
+    err_sender: UnboundedSender<Failure>,
+    options: ExportOptions,
+) -> anyhow::Result<ReadSummary> {
+    task::spawn_blocking(move || {
+        let (mut csv_reader, columns) = csv_options.open(&path, true)?;
+        let mut wallets = WalletCsvWriter::new(io::stdout(), &options)?;
+        let manager = registry.default_manager();
+        let mut close = |client: Client| -> anyhow::Result<()> {
+            if let Some(wallet) = manager.close(client)? {
+                wallets.write(&wallet)?;
+            }
+            Ok(())
+        };
+        let mut summary = ReadSummary::default();
+        let mut group = None;
+        let mut closed = HashSet::new();
+        let mut totals = ControlTotals::default();
+        let mut prefix = ProcessedPrefix::new(csv_options.watermark);
+        let mut stopped = false;
+
+        for csv_row in csv_reader.records() {
+            let csv_row = csv_row?;
+            summary.rows_read += 1;
+            if totals.record(&csv_row, &columns) {
+                continue;
+            }
+            if field(&csv_row, columns.transaction_type) == Some("close") {
+                match field(&csv_row, columns.client).and_then(|s| s.parse().ok()) {
+                    Some(client) => {
+                        close(Client::new(client))?;
+                        closed.insert(Client::new(client));
+                    }
+                    None => summary.rows_skipped += 1,
+                }
+                continue;
+            }
+            let Some(mut envelope) = Envelope::from_csv_row(&csv_row, &columns) else {
+                summary.rows_skipped += 1;
+                continue;
+            };
+            if prefix.skips(&envelope) {
+                continue;
+            }
+            let transaction = envelope.transaction;
+            if envelope.tenant.is_none() {
+                let client = transaction.client();
+                if let Some(previous) = group.replace(client)
+                    && previous != client
+                {
+                    close(previous)?;
+                    closed.insert(previous);
+                }
+                if closed.contains(&client) {
+                    let mut failure = Failure::wallet_closed(client, transaction.tx_id());
+                    failure.origin = envelope.origin.take();
+                    summary.applied.record(&transaction, false);
+                    registry.deliver_failure(&err_sender, failure);
+                    continue;
+                }
+            }
+            let res = registry.apply(envelope);
+            summary.applied.record(&transaction, res.is_ok());
+            if let Err(e) = res {
+                registry.deliver_failure(&err_sender, e);
+                if registry.aborted() {
+                    stopped = true;
+                    summary.applied.stopped_early = true;
+                    break;
+                }
+            }
+        }
+        if let Some(client) = group {
+            close(client)?;
+        }
+        wallets.flush()?;
+        summary.rows_before_watermark = prefix.skipped();
+        summary.next_watermark_tx = prefix.next_watermark();
+        if !stopped {
+            csv_options
+                .trailer_mismatch
+                .enforce(&path, totals.verify())?;
+        }
+
+        Ok::<_, anyhow::Error>(summary)
+    })
+    .await?
+}
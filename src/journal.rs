@@ -0,0 +1,588 @@
+//! The transaction journal: the deposits and withdrawals disputes may still refer to, by
+//! transaction id. `MemoryJournal` keeps them in maps that grow with the input; `DiskJournal`
+//! keeps them in a file instead, one fixed-size record per transaction id, so that memory stays
+//! flat however long the input.
+
+use crate::transaction::{Amount, Client, Transaction, TransactionId};
+use crate::wallet_manager::sharded_map;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A journaled deposit or withdrawal with the sequence number it was applied at, if it was
+/// numbered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JournalEntry {
+    pub transaction: Transaction,
+    pub seq: Option<u64>,
+}
+
+/// Where a wallet manager keeps its journal. Transaction ids are unique across clients, so every
+/// transaction is found by its id alone. Only journals kept outside of memory fail with I/O
+/// errors.
+pub trait Journal: fmt::Debug + Send + Sync {
+    /// Remembers a deposit or withdrawal.
+    fn record(&self, entry: JournalEntry) -> io::Result<()>;
+
+    /// The journaled transaction `tx_id`, whichever client it belongs to.
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<JournalEntry>>;
+
+    /// The client the transaction `tx_id` was journaled for, even once forgotten.
+    fn owner(&self, tx_id: TransactionId) -> io::Result<Option<Client>>;
+
+    /// Every journaled transaction of `client`.
+    fn transactions_of(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>>;
+
+    /// Removes the transactions of `client` and their ids, returning them, e.g. to move them to
+    /// another journal.
+    fn take(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>>;
+
+    /// Drops the transactions of a closed client, so they are neither found nor counted in the
+    /// funds any more. Their ids stay used, by the same owner.
+    fn forget(&self, client: Client) -> io::Result<()>;
+
+    /// Deposits minus withdrawals of the whole journal.
+    fn funds(&self) -> Amount;
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool;
+
+    /// Starts keeping what changes from now on for `rollback` to undo, replacing the previous
+    /// savepoint.
+    fn savepoint(&self);
+
+    /// Undoes what changed since the savepoint.
+    fn rollback(&self) -> io::Result<()>;
+}
+
+/// Deposits minus withdrawals of `entries`.
+pub fn funds_of<'a>(entries: impl IntoIterator<Item = &'a JournalEntry>) -> Amount {
+    entries
+        .into_iter()
+        .map(|entry| match entry.transaction {
+            Transaction::Deposit { amount, .. } => amount,
+            Transaction::Withdrawal { amount, .. } => -amount,
+            _ => Amount::zero(),
+        })
+        .sum()
+}
+
+type ClientJournals = DashMap<Client, HashMap<TransactionId, JournalEntry>>;
+
+#[derive(Debug, Default)]
+pub struct MemoryJournal {
+    clients: ClientJournals,
+    owners: DashMap<TransactionId, Client>,
+    saved: Mutex<Option<(ClientJournals, DashMap<TransactionId, Client>)>>,
+}
+
+impl MemoryJournal {
+    /// A journal with `shards` lock shards per map, Dashmap's default when unset.
+    pub fn new(shards: Option<usize>) -> Self {
+        MemoryJournal {
+            clients: sharded_map(shards),
+            owners: sharded_map(shards),
+            saved: Mutex::new(None),
+        }
+    }
+}
+
+impl Journal for MemoryJournal {
+    fn record(&self, entry: JournalEntry) -> io::Result<()> {
+        let transaction = entry.transaction;
+        self.owners
+            .insert(transaction.tx_id(), transaction.client());
+        self.clients
+            .entry(transaction.client())
+            .or_default()
+            .insert(transaction.tx_id(), entry);
+        Ok(())
+    }
+
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<JournalEntry>> {
+        let Some(client) = self.owner(tx_id)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .clients
+            .get(&client)
+            .and_then(|txs| txs.get(&tx_id).copied()))
+    }
+
+    fn owner(&self, tx_id: TransactionId) -> io::Result<Option<Client>> {
+        Ok(self.owners.get(&tx_id).map(|owner| *owner))
+    }
+
+    fn transactions_of(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>> {
+        Ok(self
+            .clients
+            .get(&client)
+            .map(|txs| txs.clone())
+            .unwrap_or_default())
+    }
+
+    fn take(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>> {
+        let transactions = self
+            .clients
+            .remove(&client)
+            .map(|(_, transactions)| transactions)
+            .unwrap_or_default();
+        for tx_id in transactions.keys() {
+            self.owners.remove(tx_id);
+        }
+        Ok(transactions)
+    }
+
+    fn forget(&self, client: Client) -> io::Result<()> {
+        self.clients.remove(&client);
+        Ok(())
+    }
+
+    fn funds(&self) -> Amount {
+        self.clients.iter().map(|txs| funds_of(txs.values())).sum()
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    fn savepoint(&self) {
+        *self.saved.lock().expect("journal savepoint lock poisoned") =
+            Some((self.clients.clone(), self.owners.clone()));
+    }
+
+    fn rollback(&self) -> io::Result<()> {
+        let Some((clients, owners)) = self
+            .saved
+            .lock()
+            .expect("journal savepoint lock poisoned")
+            .take()
+        else {
+            return Ok(());
+        };
+        self.clients.clear();
+        for (client, transactions) in clients {
+            self.clients.insert(client, transactions);
+        }
+        self.owners.clear();
+        for (tx_id, client) in owners {
+            self.owners.insert(tx_id, client);
+        }
+        Ok(())
+    }
+}
+
+/// Bytes per record: kind, padding, client, amount and sequence number, zero without one.
+const RECORD_LEN: usize = 16;
+const EMPTY: u8 = 0;
+const DEPOSIT: u8 = 1;
+const WITHDRAWAL: u8 = 2;
+/// A transaction of a closed client: only its owner is kept.
+const FORGOTTEN: u8 = 3;
+
+fn encode(entry: &JournalEntry) -> [u8; RECORD_LEN] {
+    let transaction = &entry.transaction;
+    let (kind, amount) = match *transaction {
+        Transaction::Deposit { amount, .. } => (DEPOSIT, amount),
+        Transaction::Withdrawal { amount, .. } => (WITHDRAWAL, amount),
+        _ => return [EMPTY; RECORD_LEN],
+    };
+    let mut record = [EMPTY; RECORD_LEN];
+    record[0] = kind;
+    record[2..4].copy_from_slice(&transaction.client().id().to_le_bytes());
+    record[4..8].copy_from_slice(&amount.as_f32().to_le_bytes());
+    record[8..].copy_from_slice(&entry.seq.unwrap_or(0).to_le_bytes());
+    record
+}
+
+/// The client of a record, also of a forgotten one.
+fn owner_of(record: [u8; RECORD_LEN]) -> Option<Client> {
+    (record[0] != EMPTY).then(|| Client::new(u16::from_le_bytes([record[2], record[3]])))
+}
+
+fn decode(tx_id: TransactionId, record: [u8; RECORD_LEN]) -> Option<JournalEntry> {
+    let client = Client::new(u16::from_le_bytes([record[2], record[3]]));
+    let amount = Amount::try_from(f32::from_le_bytes([
+        record[4], record[5], record[6], record[7],
+    ]))
+    .ok()?;
+    let transaction = match record[0] {
+        DEPOSIT => Transaction::Deposit {
+            client,
+            tx_id,
+            amount,
+        },
+        WITHDRAWAL => Transaction::Withdrawal {
+            client,
+            tx_id,
+            amount,
+        },
+        _ => return None,
+    };
+    let seq = u64::from_le_bytes(record[8..].try_into().expect("8 bytes of sequence number"));
+    Some(JournalEntry {
+        transaction,
+        seq: (seq != 0).then_some(seq),
+    })
+}
+
+/// A journal file of one record per transaction id, at the offset the id gives, so neither the
+/// records nor an index of them are kept in memory. Ids never used are holes of the file, which
+/// most file systems don't store. Records are looked up with one read; going through the
+/// transactions of one client reads the whole file, which only `rebuild` and moving a wallet to
+/// another manager do, and forgetting a closed client.
+///
+/// The records a savepoint's batch overwrites are kept in a second file, `.undo` next to the
+/// journal, so that a savepoint held over a long batch doesn't grow memory either. That file
+/// takes `UNDO_LEN` bytes per record overwritten by the longest batch so far.
+///
+/// The files are removed on drop: the journal serves the disputes of one run, and a later run
+/// starts from the wallet export, not from the journal.
+#[derive(Debug)]
+pub struct DiskJournal {
+    path: PathBuf,
+    undo_path: PathBuf,
+    state: Mutex<DiskState>,
+}
+
+/// Bytes per entry of the undo file: the transaction id, then the record it had.
+const UNDO_LEN: usize = 4 + RECORD_LEN;
+
+/// The funds and record count at the savepoint, and how many overwritten records the undo file
+/// holds since.
+type Undo = (Amount, usize, u64);
+
+#[derive(Debug)]
+struct DiskState {
+    file: File,
+    undo_file: File,
+    funds: Amount,
+    records: usize,
+    undo: Option<Undo>,
+}
+
+impl DiskState {
+    fn read(&mut self, tx_id: TransactionId) -> io::Result<[u8; RECORD_LEN]> {
+        let mut record = [EMPTY; RECORD_LEN];
+        self.file
+            .seek(SeekFrom::Start(u64::from(tx_id.id()) * RECORD_LEN as u64))?;
+        match self.file.read_exact(&mut record) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok([EMPTY; RECORD_LEN]),
+            res => res.map(|_| record),
+        }
+    }
+
+    /// Writes `record` in place of the one of `tx_id`, keeping the funds and the count up to date.
+    fn write(&mut self, tx_id: TransactionId, record: [u8; RECORD_LEN]) -> io::Result<()> {
+        let previous = self.read(tx_id)?;
+        self.file
+            .seek(SeekFrom::Start(u64::from(tx_id.id()) * RECORD_LEN as u64))?;
+        self.file.write_all(&record)?;
+        if let Some(previous) = decode(tx_id, previous) {
+            self.funds -= funds_of([&previous]);
+            self.records -= 1;
+        }
+        if let Some(entry) = decode(tx_id, record) {
+            self.funds += funds_of([&entry]);
+            self.records += 1;
+        }
+        if let Some((_, _, overwritten)) = &mut self.undo {
+            let mut entry = [0; UNDO_LEN];
+            entry[..4].copy_from_slice(&tx_id.id().to_le_bytes());
+            entry[4..].copy_from_slice(&previous);
+            self.undo_file
+                .seek(SeekFrom::Start(*overwritten * UNDO_LEN as u64))?;
+            self.undo_file.write_all(&entry)?;
+            *overwritten += 1;
+        }
+        Ok(())
+    }
+
+    /// Entry `idx` of the undo file.
+    fn overwritten(&mut self, idx: u64) -> io::Result<(TransactionId, [u8; RECORD_LEN])> {
+        let mut entry = [0; UNDO_LEN];
+        self.undo_file
+            .seek(SeekFrom::Start(idx * UNDO_LEN as u64))?;
+        self.undo_file.read_exact(&mut entry)?;
+        let tx_id = u32::from_le_bytes(entry[..4].try_into().expect("4 bytes of transaction id"));
+        let record = entry[4..]
+            .try_into()
+            .expect("a record after the transaction id");
+        Ok((TransactionId::new(tx_id), record))
+    }
+
+    /// Every record of `client`.
+    fn scan(&mut self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut transactions = HashMap::new();
+        let mut record = [EMPTY; RECORD_LEN];
+        let mut id = 0u32;
+        loop {
+            match reader.read_exact(&mut record) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                res => res?,
+            }
+            let tx_id = TransactionId::new(id);
+            if let Some(entry) = decode(tx_id, record)
+                && entry.transaction.client() == client
+            {
+                transactions.insert(tx_id, entry);
+            }
+            id = id.wrapping_add(1);
+        }
+        Ok(transactions)
+    }
+}
+
+impl DiskJournal {
+    /// A journal in a new file under `dir`.
+    pub fn create_in(dir: &Path) -> io::Result<Self> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!(
+            "journal-{}-{}.bin",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let undo_path = path.with_extension("undo");
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(path)
+        };
+        let file = open(&path)?;
+        let undo_file = match open(&undo_path) {
+            Ok(undo_file) => undo_file,
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+        Ok(DiskJournal {
+            path,
+            undo_path,
+            state: Mutex::new(DiskState {
+                file,
+                undo_file,
+                funds: Amount::zero(),
+                records: 0,
+                undo: None,
+            }),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, DiskState> {
+        self.state.lock().expect("journal lock poisoned")
+    }
+}
+
+impl Drop for DiskJournal {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(&self.undo_path);
+    }
+}
+
+impl Journal for DiskJournal {
+    fn record(&self, entry: JournalEntry) -> io::Result<()> {
+        self.state()
+            .write(entry.transaction.tx_id(), encode(&entry))
+    }
+
+    fn get(&self, tx_id: TransactionId) -> io::Result<Option<JournalEntry>> {
+        Ok(decode(tx_id, self.state().read(tx_id)?))
+    }
+
+    fn owner(&self, tx_id: TransactionId) -> io::Result<Option<Client>> {
+        Ok(owner_of(self.state().read(tx_id)?))
+    }
+
+    fn transactions_of(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>> {
+        self.state().scan(client)
+    }
+
+    fn take(&self, client: Client) -> io::Result<HashMap<TransactionId, JournalEntry>> {
+        let mut state = self.state();
+        let transactions = state.scan(client)?;
+        for &tx_id in transactions.keys() {
+            state.write(tx_id, [EMPTY; RECORD_LEN])?;
+        }
+        Ok(transactions)
+    }
+
+    fn forget(&self, client: Client) -> io::Result<()> {
+        let mut state = self.state();
+        let transactions = state.scan(client)?;
+        let mut forgotten = [EMPTY; RECORD_LEN];
+        forgotten[0] = FORGOTTEN;
+        forgotten[2..4].copy_from_slice(&client.id().to_le_bytes());
+        for &tx_id in transactions.keys() {
+            state.write(tx_id, forgotten)?;
+        }
+        Ok(())
+    }
+
+    fn funds(&self) -> Amount {
+        self.state().funds
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.state().records == 0
+    }
+
+    fn savepoint(&self) {
+        let mut state = self.state();
+        state.undo = Some((state.funds, state.records, 0));
+    }
+
+    fn rollback(&self) -> io::Result<()> {
+        let mut state = self.state();
+        let Some((funds, records, overwritten)) = state.undo.take() else {
+            return Ok(());
+        };
+        for idx in (0..overwritten).rev() {
+            let (tx_id, record) = state.overwritten(idx)?;
+            state.write(tx_id, record)?;
+        }
+        state.funds = funds;
+        state.records = records;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journals_agree() {
+        let disk = DiskJournal::create_in(&std::env::temp_dir()).unwrap();
+        let path = disk.path().to_path_buf();
+        let journals: [Box<dyn Journal>; 2] = [Box::new(MemoryJournal::new(None)), Box::new(disk)];
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        let deposit = JournalEntry {
+            transaction: Transaction::Deposit {
+                client: alice,
+                tx_id: TransactionId::new(7),
+                amount: Amount::from_major(10, 0),
+            },
+            seq: Some(2),
+        };
+        // Recorded without a sequence number.
+        let withdrawal = JournalEntry {
+            transaction: Transaction::Withdrawal {
+                client: bob,
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(4, 0),
+            },
+            seq: None,
+        };
+        for journal in &journals {
+            assert!(journal.is_empty());
+            journal.record(deposit).unwrap();
+            journal.record(withdrawal).unwrap();
+            assert_eq!(journal.get(TransactionId::new(7)).unwrap(), Some(deposit));
+            assert_eq!(journal.get(TransactionId::new(5)).unwrap(), None);
+            assert_eq!(journal.get(TransactionId::new(9_000)).unwrap(), None);
+            assert_eq!(journal.owner(TransactionId::new(3)).unwrap(), Some(bob));
+            assert_eq!(journal.funds(), Amount::from_major(6, 0));
+            assert_eq!(
+                journal.transactions_of(alice).unwrap(),
+                HashMap::from([(TransactionId::new(7), deposit)])
+            );
+
+            journal.savepoint();
+            assert_eq!(journal.take(bob).unwrap().len(), 1);
+            assert_eq!(journal.owner(TransactionId::new(3)).unwrap(), None);
+            assert_eq!(journal.funds(), Amount::from_major(10, 0));
+            journal.rollback().unwrap();
+            assert_eq!(
+                journal.get(TransactionId::new(3)).unwrap(),
+                Some(withdrawal)
+            );
+            assert_eq!(journal.funds(), Amount::from_major(6, 0));
+        }
+        drop(journals);
+        assert!(!path.exists());
+        assert!(!path.with_extension("undo").exists());
+    }
+
+    #[test]
+    fn test_disk_journal_keeps_its_undo_log_on_disk() {
+        let journal = DiskJournal::create_in(&std::env::temp_dir()).unwrap();
+        let deposit = |tx_id| JournalEntry {
+            transaction: Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx_id),
+                amount: Amount::from_major(1, 0),
+            },
+            seq: Some(u64::from(tx_id)),
+        };
+        journal.record(deposit(1)).unwrap();
+        journal.savepoint();
+        for tx_id in 2..=1_001 {
+            journal.record(deposit(tx_id)).unwrap();
+        }
+        let undo = std::fs::metadata(journal.path().with_extension("undo")).unwrap();
+        assert_eq!(undo.len(), 1_000 * UNDO_LEN as u64);
+
+        journal.rollback().unwrap();
+        assert_eq!(journal.funds(), Amount::from_major(1, 0));
+        assert_eq!(journal.get(TransactionId::new(1_001)).unwrap(), None);
+        assert_eq!(
+            journal.get(TransactionId::new(1)).unwrap(),
+            Some(deposit(1))
+        );
+    }
+
+    #[test]
+    fn test_journals_agree_on_forgotten_clients() {
+        let journals: [Box<dyn Journal>; 2] = [
+            Box::new(MemoryJournal::new(None)),
+            Box::new(DiskJournal::create_in(&std::env::temp_dir()).unwrap()),
+        ];
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        let deposit = |client, tx_id| JournalEntry {
+            transaction: Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx_id),
+                amount: Amount::from_major(5, 0),
+            },
+            seq: Some(u64::from(tx_id)),
+        };
+        for journal in &journals {
+            journal.record(deposit(alice, 1)).unwrap();
+            journal.record(deposit(alice, 2)).unwrap();
+            journal.record(deposit(bob, 3)).unwrap();
+
+            journal.savepoint();
+            journal.forget(alice).unwrap();
+            assert_eq!(journal.get(TransactionId::new(1)).unwrap(), None);
+            assert_eq!(journal.owner(TransactionId::new(1)).unwrap(), Some(alice));
+            assert!(journal.transactions_of(alice).unwrap().is_empty());
+            assert_eq!(journal.funds(), Amount::from_major(5, 0));
+            assert_eq!(
+                journal.get(TransactionId::new(3)).unwrap(),
+                Some(deposit(bob, 3))
+            );
+
+            journal.rollback().unwrap();
+            assert_eq!(journal.transactions_of(alice).unwrap().len(), 2);
+            assert_eq!(journal.funds(), Amount::from_major(15, 0));
+        }
+    }
+}
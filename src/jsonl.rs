@@ -0,0 +1,59 @@
+//! JSON Lines input, also known as NDJSON: one JSON object per line with the CSV column names
+//! as keys, as `Envelope::from_json` reads them. Blank lines are ignored.
+
+use crate::transaction::Envelope;
+use std::io::BufRead;
+
+/// The transactions of `reader`, `None` for lines that aren't a valid transaction.
+pub fn read_transactions<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = anyhow::Result<Option<Envelope>>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(Ok(Envelope::from_json(&line))),
+        Err(e) => Some(Err(e.into())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, Tenant, Timestamp, Transaction, TransactionId};
+
+    #[test]
+    fn test_read_transactions_lines() {
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 7, \"amount\": 2.5, \"timestamp\": 60}\n\
+                     \n\
+                     {\"type\": \"deposit\", \"client\": 1}\n\
+                     {\"type\": \"dispute\", \"client\": 1, \"tx\": 7, \"tenant\": \"acme\", \"batch\": 3}\n";
+
+        let envelopes: Vec<_> = read_transactions(input.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(
+            envelopes,
+            vec![
+                Some(Envelope {
+                    timestamp: Some(Timestamp::from_secs(60)),
+                    ..Envelope::from(Transaction::Deposit {
+                        client: Client::new(1),
+                        tx_id: TransactionId::new(7),
+                        amount: Amount::from_major(2, 5000),
+                    })
+                }),
+                None,
+                Some(Envelope {
+                    tenant: Some(Tenant::new("acme")),
+                    attributes: [("batch".to_string(), "3".to_string())]
+                        .into_iter()
+                        .collect(),
+                    ..Envelope::from(Transaction::Dispute {
+                        client: Client::new(1),
+                        tx_id: TransactionId::new(7),
+                    })
+                }),
+            ]
+        );
+    }
+}
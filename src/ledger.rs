@@ -0,0 +1,201 @@
+//! Plain-text-accounting export of every balance movement, so a run can be audited with
+//! beancount or ledger-cli. Accounts are seen from the house's side: client funds are
+//! liabilities, split per client into available and held funds.
+
+use crate::enrich::Attributes;
+use crate::transaction::{Amount, Client, Timestamp, Transaction, TransactionId};
+use clap::ValueEnum;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Deposit,
+    Withdrawal,
+    Hold,
+    Release,
+    ChargeBack,
+    Representment,
+    Fee,
+    /// A disputed withdrawal credited back to the client, held and owed to the house by the payee.
+    WithdrawalHold,
+    WithdrawalRelease,
+    WithdrawalReversal,
+}
+
+impl Movement {
+    pub fn of(transaction: &Transaction) -> Self {
+        match transaction {
+            Transaction::Deposit { .. } => Movement::Deposit,
+            Transaction::Withdrawal { .. } => Movement::Withdrawal,
+            Transaction::Dispute { .. } => Movement::Hold,
+            Transaction::Resolve { .. } => Movement::Release,
+            Transaction::ChargeBack { .. } => Movement::ChargeBack,
+            Transaction::Represent { .. } => Movement::Representment,
+        }
+    }
+
+    /// The movement a dispute, resolve or chargeback makes on a withdrawal rather than a deposit.
+    pub fn on_withdrawal(self) -> Self {
+        match self {
+            Movement::Hold => Movement::WithdrawalHold,
+            Movement::Release => Movement::WithdrawalRelease,
+            Movement::ChargeBack => Movement::WithdrawalReversal,
+            movement => movement,
+        }
+    }
+}
+
+/// A movement of `amount` applied to a client's wallet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub timestamp: Option<Timestamp>,
+    /// Sequence number of the transaction, none for entries the manager books itself such as fees.
+    pub seq: Option<u64>,
+    pub client: Client,
+    pub tx_id: Option<TransactionId>,
+    pub movement: Movement,
+    pub amount: Amount,
+    /// Attributes of the transaction, written as metadata.
+    pub attributes: Attributes,
+}
+
+impl LedgerEntry {
+    /// The account receiving `amount` and the account giving it up.
+    fn accounts(&self) -> (String, String) {
+        let client =
+            |bucket: &str| format!("Liabilities:Clients:Client{}:{bucket}", self.client.id());
+        match self.movement {
+            Movement::Deposit => ("Assets:Settlement".into(), client("Available")),
+            Movement::Withdrawal => (client("Available"), "Assets:Settlement".into()),
+            Movement::Hold => (client("Available"), client("Held")),
+            Movement::Release => (client("Held"), client("Available")),
+            Movement::ChargeBack => (client("Held"), "Liabilities:Chargebacks".into()),
+            Movement::Representment => ("Liabilities:Chargebacks".into(), client("Available")),
+            Movement::Fee => (client("Available"), "Income:Fees".into()),
+            Movement::WithdrawalHold => ("Assets:Receivables:Withdrawals".into(), client("Held")),
+            Movement::WithdrawalRelease => {
+                (client("Held"), "Assets:Receivables:Withdrawals".into())
+            }
+            Movement::WithdrawalReversal => (client("Held"), client("Available")),
+        }
+    }
+
+    fn narration(&self) -> String {
+        let movement = match self.movement {
+            Movement::Deposit => "deposit",
+            Movement::Withdrawal => "withdrawal",
+            Movement::Hold => "dispute",
+            Movement::Release => "resolve",
+            Movement::ChargeBack => "chargeback",
+            Movement::Representment => "represent",
+            Movement::Fee => "fee",
+            Movement::WithdrawalHold => "withdrawal dispute",
+            Movement::WithdrawalRelease => "withdrawal resolve",
+            Movement::WithdrawalReversal => "withdrawal chargeback",
+        };
+        match self.tx_id {
+            Some(tx_id) => format!("{movement} tx {}", tx_id.id()),
+            None => movement.to_string(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerFormat {
+    Beancount,
+    Ledger,
+}
+
+pub fn write_ledger<W: Write>(
+    mut writer: W,
+    entries: &[LedgerEntry],
+    format: LedgerFormat,
+    commodity: &str,
+) -> io::Result<()> {
+    let date = |timestamp: Option<Timestamp>| {
+        let (year, month, day) = timestamp.unwrap_or(Timestamp::from_secs(0)).to_date();
+        match format {
+            LedgerFormat::Beancount => format!("{year:04}-{month:02}-{day:02}"),
+            LedgerFormat::Ledger => format!("{year:04}/{month:02}/{day:02}"),
+        }
+    };
+
+    if format == LedgerFormat::Beancount {
+        let accounts: BTreeSet<String> = entries
+            .iter()
+            .flat_map(|e| {
+                let (to, from) = e.accounts();
+                [to, from]
+            })
+            .collect();
+        let opened = date(entries.iter().filter_map(|e| e.timestamp).min());
+        for account in accounts {
+            writeln!(writer, "{opened} open {account} {commodity}")?;
+        }
+        writeln!(writer)?;
+    }
+
+    for entry in entries {
+        let (to, from) = entry.accounts();
+        let header = match format {
+            LedgerFormat::Beancount => {
+                format!("{} * \"{}\"", date(entry.timestamp), entry.narration())
+            }
+            LedgerFormat::Ledger => format!("{} {}", date(entry.timestamp), entry.narration()),
+        };
+        writeln!(writer, "{header}")?;
+        if let Some(seq) = entry.seq {
+            match format {
+                LedgerFormat::Beancount => writeln!(writer, "  seq: {seq}")?,
+                LedgerFormat::Ledger => writeln!(writer, "  ; seq: {seq}")?,
+            }
+        }
+        for (name, value) in entry.attributes.iter() {
+            match format {
+                LedgerFormat::Beancount => writeln!(writer, "  {name}: {value:?}")?,
+                LedgerFormat::Ledger => writeln!(writer, "  ; {name}: {value}")?,
+            }
+        }
+        writeln!(writer, "  {to}  {} {commodity}", entry.amount)?;
+        writeln!(writer, "  {from}  {} {commodity}", -entry.amount)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_beancount_entries_balance() {
+        let entries = vec![LedgerEntry {
+            timestamp: Some(Timestamp::from_secs(1_704_153_600)),
+            seq: Some(3),
+            client: Client::new(7),
+            tx_id: Some(TransactionId::new(1)),
+            movement: Movement::Deposit,
+            amount: Amount::from_major(12, 5000),
+            attributes: [("category".to_string(), "groceries".to_string())]
+                .into_iter()
+                .collect(),
+        }];
+        let mut out = Vec::new();
+
+        write_ledger(&mut out, &entries, LedgerFormat::Beancount, "USD").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2024-01-02 open Assets:Settlement USD\n\
+             2024-01-02 open Liabilities:Clients:Client7:Available USD\n\
+             \n\
+             2024-01-02 * \"deposit tx 1\"\n\
+             \x20 seq: 3\n\
+             \x20 category: \"groceries\"\n\
+             \x20 Assets:Settlement  12.5000 USD\n\
+             \x20 Liabilities:Clients:Client7:Available  -12.5000 USD\n\
+             \n"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! The wallet engine as a library: deposits, withdrawals and the disputes that follow them
+//! applied to client wallets, as the `walletmanagermock` binary does for CSV files and streams.
+//!
+//! `process_transactions` runs a stream of transactions through a fresh engine and returns the
+//! resulting wallets. Services that keep the engine around drive a `WalletManager` directly, one
+//! `apply` per transaction or `run` over a channel, or a `TenantRegistry` to keep the wallets
+//! of several institutions apart. The types they take and return are re-exported at the crate
+//! root; the modules behind them are internal.
+
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::pin;
+use std::time::Instant;
+
+#[cfg(unix)]
+mod admin;
+#[cfg(feature = "amqp")]
+mod amqp;
+mod analytics;
+mod app;
+#[cfg(feature = "avro")]
+mod avro;
+mod batching;
+#[cfg(test)]
+mod chaos;
+mod cli;
+mod config;
+mod cutoff;
+mod dedupe;
+mod deferred;
+mod dormancy;
+mod durability;
+mod enrich;
+mod events;
+mod expiry;
+mod export;
+mod exposure;
+mod fairness;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod house;
+mod input;
+mod journal;
+#[cfg(feature = "jsonl")]
+mod jsonl;
+mod ledger;
+mod lifecycle;
+mod locale;
+mod merge;
+mod merkle;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "napi")]
+mod node;
+#[cfg(feature = "webhook")]
+mod outbox;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod persistence;
+#[cfg(feature = "profile")]
+mod profile;
+mod progress;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+mod provenance;
+mod quarantine;
+mod queue;
+#[cfg(test)]
+mod reference;
+mod rejection;
+mod reload;
+mod replica;
+mod reservation;
+#[cfg(feature = "rest")]
+mod rest;
+mod ring;
+mod risk;
+mod schema;
+mod segment;
+#[cfg(feature = "rest")]
+mod server;
+mod session;
+#[cfg(test)]
+mod simulation;
+mod snapshot;
+mod source;
+mod spool;
+mod statement;
+mod tcp;
+mod tenant;
+mod timeformat;
+mod trailer;
+mod transaction;
+mod transfer;
+mod wal;
+mod wallet;
+mod wallet_manager;
+mod watermark;
+#[cfg(feature = "webhook")]
+mod webhook;
+mod wire;
+
+// The modules are internal; what embedding the engine takes is re-exported here.
+#[cfg(feature = "schema-registry")]
+pub use avro::registry::SchemaRegistry;
+pub use config::{Config, FailurePolicy, MissingWallet, Settings, TransactionLimits};
+pub use dormancy::{DormancyPolicy, DormantWallet};
+pub use durability::FsyncPolicy;
+pub use enrich::Attributes;
+pub use events::{EventHub, WalletEvent};
+pub use persistence::{PersistenceOptions, Recovery};
+pub use reservation::{Reservation, ReservationId};
+pub use ring::{Cluster, HashRing, ShardId};
+pub use risk::{ChargebackPolicy, RiskDecision, RiskScore, RiskScorer, RiskThresholds};
+pub use tenant::TenantRegistry;
+pub use transaction::{
+    Amount, Client, Envelope, Failure, FailureKind, Origin, Tenant, Timestamp, Transaction,
+    TransactionId,
+};
+pub use transfer::Leg;
+pub use wallet::{Balance, Wallet};
+pub use wallet_manager::{Handoff, ProjectedBalance, Rebuilt, RunReport, Savepoint, WalletManager};
+
+/// Entry point of the `walletmanagermock` binary, not part of the library's API.
+#[doc(hidden)]
+pub use app::main as cli_main;
+
+/// What `process_transactions` ends up with.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub run: RunReport,
+    /// Every wallet, ordered by client.
+    pub wallets: Vec<Wallet>,
+    /// The transactions that failed, in input order.
+    pub failures: Vec<Failure>,
+}
+
+/// Applies `transactions` in order to a new `WalletManager` with the default configuration.
+pub async fn process_transactions(transactions: impl Stream<Item = Transaction>) -> Report {
+    let started = Instant::now();
+    let wallet_manager = WalletManager::init();
+    let mut report = Report::default();
+    let mut transactions = pin!(transactions);
+    while let Some(transaction) = poll_fn(|cx| transactions.as_mut().poll_next(cx)).await {
+        let res = wallet_manager.apply(transaction.into());
+        report.run.record(&transaction, res.is_ok());
+        if let Err(failure) = res {
+            report.failures.push(failure);
+        }
+    }
+    report.run.duration = started.elapsed();
+    report.wallets = wallet_manager.export_wallets();
+    report.wallets.sort_by_key(Wallet::client);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    struct Iter<I>(I);
+
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_transactions() {
+        let deposit = |client, tx| Transaction::Deposit {
+            client: Client::new(client),
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(10, 0),
+        };
+        let transactions = vec![
+            deposit(2, 1),
+            deposit(1, 2),
+            Transaction::Withdrawal {
+                client: Client::new(2),
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(25, 0),
+            },
+        ];
+        let report = process_transactions(Iter(transactions.into_iter())).await;
+
+        assert_eq!((report.run.processed, report.run.failed), (3, 1));
+        assert_eq!(report.failures[0].tx, TransactionId::new(3));
+        let clients: Vec<_> = report.wallets.iter().map(Wallet::client).collect();
+        assert_eq!(clients, [Client::new(1), Client::new(2)]);
+        assert!(
+            report
+                .wallets
+                .iter()
+                .all(|w| w.available() == Amount::from_major(10, 0))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Audit trail of wallet status transitions, for regulatory requests asking when and why a
+//! wallet was opened, restricted or closed.
+
+use crate::transaction::{Client, Timestamp, TransactionId};
+use crate::wallet::Wallet;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LifecycleChange {
+    Created,
+    /// Locked by a chargeback.
+    Locked,
+    /// Unlocked by a re-presentment.
+    Unlocked,
+    /// Frozen by an administrator or the risk rules.
+    Frozen,
+    Unfrozen,
+    /// Frozen by `FailurePolicy::Quarantine` after a failure.
+    Quarantined,
+    Closed,
+}
+
+/// Why a wallet was locked, frozen or quarantined, as the `lock_reason` export column and the
+/// lifecycle audit write it: `chargeback:<tx>`, `admin`, `risk_score`, `chargeback_ratio` or
+/// `quarantine:<tx>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// Locked by the chargeback of this transaction.
+    ChargeBack(TransactionId),
+    /// Frozen by an administrator.
+    Admin,
+    /// Frozen by a risk score reaching the hold threshold.
+    RiskScore,
+    /// Frozen by the chargeback policy once the chargeback ratio got too high.
+    ChargeBackRatio,
+    /// Quarantined by `FailurePolicy::Quarantine` after this transaction failed.
+    Quarantine(TransactionId),
+}
+
+impl LockReason {
+    /// The restriction this reason is given for.
+    pub fn change(self) -> LifecycleChange {
+        match self {
+            LockReason::ChargeBack(_) => LifecycleChange::Locked,
+            LockReason::Admin | LockReason::RiskScore | LockReason::ChargeBackRatio => {
+                LifecycleChange::Frozen
+            }
+            LockReason::Quarantine(_) => LifecycleChange::Quarantined,
+        }
+    }
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::ChargeBack(tx) => write!(f, "chargeback:{}", tx.id()),
+            LockReason::Admin => f.write_str("admin"),
+            LockReason::RiskScore => f.write_str("risk_score"),
+            LockReason::ChargeBackRatio => f.write_str("chargeback_ratio"),
+            LockReason::Quarantine(tx) => write!(f, "quarantine:{}", tx.id()),
+        }
+    }
+}
+
+impl FromStr for LockReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tx = |id: &str| id.parse().map(TransactionId::new).ok();
+        let reason = match s.trim().split_once(':') {
+            None => match s.trim() {
+                "admin" => Some(LockReason::Admin),
+                "risk_score" => Some(LockReason::RiskScore),
+                "chargeback_ratio" => Some(LockReason::ChargeBackRatio),
+                _ => None,
+            },
+            Some(("chargeback", id)) => tx(id).map(LockReason::ChargeBack),
+            Some(("quarantine", id)) => tx(id).map(LockReason::Quarantine),
+            Some(_) => None,
+        };
+        reason.ok_or_else(|| format!("invalid lock reason {s:?}"))
+    }
+}
+
+impl Serialize for LockReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The parts of a wallet's state its lifecycle is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifecycleState {
+    locked: bool,
+    frozen: bool,
+    quarantined: bool,
+}
+
+impl LifecycleState {
+    pub fn of(wallet: &Wallet) -> Self {
+        LifecycleState {
+            locked: wallet.locked,
+            frozen: wallet.frozen,
+            quarantined: wallet.quarantined,
+        }
+    }
+}
+
+impl LifecycleChange {
+    /// The transitions leading from `before` to `after`, `None` standing for no wallet.
+    pub fn between(
+        before: Option<LifecycleState>,
+        after: Option<LifecycleState>,
+    ) -> Vec<LifecycleChange> {
+        let mut changes = Vec::new();
+        let (before, after) = match (before, after) {
+            (None, None) => return changes,
+            (Some(_), None) => return vec![LifecycleChange::Closed],
+            (None, Some(after)) => {
+                changes.push(LifecycleChange::Created);
+                let initial = LifecycleState {
+                    locked: false,
+                    frozen: false,
+                    quarantined: false,
+                };
+                (initial, after)
+            }
+            (Some(before), Some(after)) => (before, after),
+        };
+        match (before.locked, after.locked) {
+            (false, true) => changes.push(LifecycleChange::Locked),
+            (true, false) => changes.push(LifecycleChange::Unlocked),
+            _ => {}
+        }
+        match (before.frozen, after.frozen) {
+            (false, true) => changes.push(LifecycleChange::Frozen),
+            (true, false) => changes.push(LifecycleChange::Unfrozen),
+            _ => {}
+        }
+        if after.quarantined && !before.quarantined {
+            changes.push(LifecycleChange::Quarantined);
+        }
+        changes
+    }
+}
+
+/// One row of the lifecycle audit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LifecycleEvent {
+    /// Sequence number of the triggering transaction, or the last one applied before an
+    /// administrative change.
+    pub seq: u64,
+    pub client: Client,
+    pub change: LifecycleChange,
+    /// The triggering transaction; none for administrative changes.
+    pub tx: Option<TransactionId>,
+    pub timestamp: Option<Timestamp>,
+    /// Why the wallet was locked, frozen or quarantined; none for other changes.
+    pub reason: Option<LockReason>,
+}
@@ -0,0 +1,148 @@
+use clap::ValueEnum;
+
+/// How amounts in the CSV input are written. Amounts using a comma for decimals have to be quoted
+/// in a comma separated file, e.g. `deposit,1,1,"1.234,56"`.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmountLocale {
+    /// `1234.56`, no thousands separators
+    #[default]
+    Plain,
+    /// `1,234.56`
+    En,
+    /// `1.234,56`
+    De,
+    /// `1 234,56`, with a plain or non-breaking space
+    Fr,
+    /// `1'234.56`
+    Ch,
+}
+
+impl AmountLocale {
+    /// The amount in `s`. Only `lenient` parsing accepts amounts padded with whitespace or
+    /// written in scientific notation (`1.5e2`).
+    pub fn parse(self, s: &str, lenient: bool) -> Option<f32> {
+        self.normalize(s, lenient)?.parse().ok()
+    }
+
+    /// The amount in exact ten-thousandths, for sums that must not pick up float rounding such as
+    /// control totals. Amounts with more than four decimals or in scientific notation are `None`.
+    pub fn parse_minor_units(self, s: &str, lenient: bool) -> Option<i128> {
+        let normalized = self.normalize(s, lenient)?;
+        let (integer, fraction) = normalized.split_once('.').unwrap_or((&normalized, ""));
+        if fraction.len() > 4
+            || !(integer.bytes().chain(fraction.bytes())).all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let integer: i128 = match integer {
+            "" => 0,
+            integer => integer.parse().ok()?,
+        };
+        let fraction: i128 = format!("{fraction:0<4}").parse().ok()?;
+        Some(integer * 10_000 + fraction)
+    }
+
+    /// `s` as a plain decimal, e.g. `1234.5` for `1.234,5` in `De`.
+    fn normalize(self, s: &str, lenient: bool) -> Option<String> {
+        let s = if lenient { s.trim() } else { s };
+        let (separators, decimal): (&[char], char) = match self {
+            AmountLocale::Plain => (&[], '.'),
+            AmountLocale::En => (&[','], '.'),
+            AmountLocale::De => (&['.'], ','),
+            AmountLocale::Fr => (&[' ', '\u{a0}', '\u{202f}'], ','),
+            AmountLocale::Ch => (&['\''], '.'),
+        };
+        let (integer, fraction) = match s.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (s, None),
+        };
+        // Separators must group thousands, so that `1,5` isn't read as fifteen.
+        let groups: Vec<&str> = if separators.is_empty() {
+            vec![integer]
+        } else {
+            integer.split(separators).collect()
+        };
+        if let [first, rest @ ..] = groups.as_slice()
+            && !rest.is_empty()
+            && (first.is_empty() || first.len() > 3 || rest.iter().any(|g| g.len() != 3))
+        {
+            return None;
+        }
+        let mut normalized = groups.concat();
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        // Plain digits only, which also keeps `inf` and `NaN` out. The integer part may be left
+        // out, as in `.5`.
+        let allowed = |c: char| {
+            c.is_ascii_digit() || c == '.' || (lenient && matches!(c, 'e' | 'E' | '+' | '-'))
+        };
+        let digits = normalized.strip_prefix('.').unwrap_or(&normalized);
+        if !digits.starts_with(|c: char| c.is_ascii_digit()) || !normalized.chars().all(allowed) {
+            return None;
+        }
+        Some(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_locale_amounts() {
+        assert_eq!(AmountLocale::Plain.parse("1234.5", false), Some(1234.5));
+        assert_eq!(AmountLocale::Plain.parse("1,234.5", false), None);
+        assert_eq!(AmountLocale::Plain.parse(".5", false), Some(0.5));
+        assert_eq!(AmountLocale::Plain.parse(".", false), None);
+        assert_eq!(
+            AmountLocale::En.parse("1,234,567.5", false),
+            Some(1234567.5)
+        );
+        assert_eq!(AmountLocale::En.parse("1234.5", false), Some(1234.5));
+        assert_eq!(AmountLocale::En.parse("1,5", false), None);
+        assert_eq!(AmountLocale::En.parse("1.234,5", false), None);
+        assert_eq!(AmountLocale::De.parse("1.234,5", false), Some(1234.5));
+        assert_eq!(AmountLocale::De.parse("0,25", false), Some(0.25));
+        assert_eq!(AmountLocale::Fr.parse("1\u{a0}234,5", false), Some(1234.5));
+        assert_eq!(AmountLocale::Ch.parse("1'234.5", false), Some(1234.5));
+    }
+
+    #[test]
+    fn test_parse_exact_minor_units() {
+        assert_eq!(
+            AmountLocale::En.parse_minor_units("12,345,678.9012", false),
+            Some(123_456_789_012)
+        );
+        assert_eq!(
+            AmountLocale::De.parse_minor_units("0,5", false),
+            Some(5_000)
+        );
+        assert_eq!(
+            AmountLocale::Plain.parse_minor_units("7", false),
+            Some(70_000)
+        );
+        assert_eq!(
+            AmountLocale::Plain.parse_minor_units(".5", false),
+            Some(5_000)
+        );
+        assert_eq!(
+            AmountLocale::Plain.parse_minor_units("0.00001", false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lenient_accepts_padding_and_exponents() {
+        for locale in [AmountLocale::Plain, AmountLocale::En] {
+            assert_eq!(locale.parse("1.5e2", false), None);
+            assert_eq!(locale.parse(" 1.5 ", false), None);
+            assert_eq!(locale.parse("1.5e2", true), Some(150.0));
+            assert_eq!(locale.parse(" 1.5 ", true), Some(1.5));
+        }
+        assert_eq!(AmountLocale::De.parse("1,5E-1", true), Some(0.15));
+        assert_eq!(AmountLocale::Plain.parse("inf", true), None);
+        assert_eq!(AmountLocale::Plain.parse("+1", false), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! K-way merge of inputs that are each in timestamp order into one stream in timestamp order,
+//! so that transactions from several feeds are applied in the order they happened.
+
+use crate::transaction::Timestamp;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+struct Source<I: Iterator> {
+    iter: I,
+    head: Option<I::Item>,
+    /// Timestamp of the latest item with one, which items without a timestamp are sorted by so
+    /// that they keep their place within their input.
+    last: i64,
+}
+
+/// Yields the items of every source, always taking the one with the earliest timestamp next.
+/// Ties go to the source listed first.
+pub struct SortedMerge<I: Iterator, K> {
+    sources: Vec<Source<I>>,
+    heap: BinaryHeap<Reverse<(i64, usize)>>,
+    timestamp: K,
+}
+
+impl<I, K> SortedMerge<I, K>
+where
+    I: Iterator,
+    K: Fn(&I::Item) -> Option<Timestamp>,
+{
+    pub fn new(sources: impl IntoIterator<Item = I>, timestamp: K) -> Self {
+        let mut merge = SortedMerge {
+            sources: sources
+                .into_iter()
+                .map(|iter| Source {
+                    iter,
+                    head: None,
+                    last: i64::MIN,
+                })
+                .collect(),
+            heap: BinaryHeap::new(),
+            timestamp,
+        };
+        for index in 0..merge.sources.len() {
+            merge.advance(index);
+        }
+        merge
+    }
+
+    /// Reads the next item of a source and queues it.
+    fn advance(&mut self, index: usize) {
+        let source = &mut self.sources[index];
+        source.head = source.iter.next();
+        if let Some(item) = &source.head {
+            if let Some(timestamp) = (self.timestamp)(item) {
+                source.last = timestamp.as_secs();
+            }
+            self.heap.push(Reverse((source.last, index)));
+        }
+    }
+}
+
+impl<I, K> Iterator for SortedMerge<I, K>
+where
+    I: Iterator,
+    K: Fn(&I::Item) -> Option<Timestamp>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let Reverse((_, index)) = self.heap.pop()?;
+        let item = self.sources[index].head.take();
+        self.advance(index);
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merges_sorted_sources_by_timestamp() {
+        let a = vec![(Some(1), "a1"), (None, "a-"), (Some(5), "a5")];
+        let b = vec![(Some(0), "b0"), (Some(1), "b1"), (Some(7), "b7")];
+        let c = vec![(Some(3), "c3")];
+        let merged: Vec<&str> = SortedMerge::new([a, b, c].map(Vec::into_iter), |(t, _)| {
+            t.map(Timestamp::from_secs)
+        })
+        .map(|(_, name)| name)
+        .collect();
+        assert_eq!(merged, ["b0", "a1", "a-", "b1", "c3", "a5", "b7"]);
+    }
+}
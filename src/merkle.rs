@@ -0,0 +1,203 @@
+//! Merkle tree over the final balances, so that the published root attests to every balance
+//! while a client can be shown its own balance is included without revealing the others.
+//! Leaves are the `client,available,held,total` rows of the export in client order, hashed with
+//! SHA-256 and domain separated from inner nodes as in RFC 6962.
+
+use crate::transaction::Client;
+use crate::wallet::Wallet;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+type Hash = [u8; 32];
+
+fn leaf_hash(leaf: &str) -> Hash {
+    Sha256::new()
+        .chain_update([0x00])
+        .chain_update(leaf)
+        .finalize()
+        .into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([0x01])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+pub fn to_hex(hash: &[u8; 32]) -> String {
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Hash> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+pub struct BalanceTree {
+    clients: Vec<Client>,
+    leaves: Vec<String>,
+    /// Hashes of every level, from the leaves up to the root. A node without a sibling is
+    /// carried up to the next level as it is.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl BalanceTree {
+    pub fn new(wallets: &[Wallet]) -> Self {
+        let mut rows: Vec<(Client, String)> = wallets
+            .iter()
+            .map(|w| {
+                let leaf = format!(
+                    "{},{},{},{}",
+                    w.client().id(),
+                    w.available(),
+                    w.held(),
+                    w.total()
+                );
+                (w.client(), leaf)
+            })
+            .collect();
+        rows.sort_by_key(|(client, _)| client.id());
+        let (clients, leaves): (Vec<_>, Vec<_>) = rows.into_iter().unzip();
+        let mut levels = vec![
+            leaves
+                .iter()
+                .map(|leaf| leaf_hash(leaf))
+                .collect::<Vec<_>>(),
+        ];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let next = levels
+                .last()
+                .expect("checked above")
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        BalanceTree {
+            clients,
+            leaves,
+            levels,
+        }
+    }
+
+    /// Root hash as hex; the hash of nothing for no wallets.
+    pub fn root(&self) -> String {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => to_hex(root),
+            None => to_hex(&Sha256::digest(b"").into()),
+        }
+    }
+
+    /// Proof that the balance of `client` is part of the tree.
+    pub fn proof(&self, client: Client) -> Option<InclusionProof> {
+        let position = self.clients.binary_search(&client).ok()?;
+        let mut index = position;
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                let side = if sibling < index {
+                    Side::Left
+                } else {
+                    Side::Right
+                };
+                path.push(ProofStep {
+                    side,
+                    hash: to_hex(hash),
+                });
+            }
+            index /= 2;
+        }
+        Some(InclusionProof {
+            leaf: self.leaves[position].clone(),
+            path,
+            root: self.root(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A sibling hash on the way from the leaf to the root, and on which side it goes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// The client's row, `client,available,held,total`.
+    pub leaf: String,
+    pub path: Vec<ProofStep>,
+    /// Root the proof was made for.
+    pub root: String,
+}
+
+impl InclusionProof {
+    /// Whether the path leads from the leaf to `root`, as published.
+    pub fn verify(&self, root: &str) -> bool {
+        let mut hash = leaf_hash(&self.leaf);
+        for step in &self.path {
+            let Some(sibling) = from_hex(&step.hash) else {
+                return false;
+            };
+            hash = match step.side {
+                Side::Left => node_hash(&sibling, &hash),
+                Side::Right => node_hash(&hash, &sibling),
+            };
+        }
+        to_hex(&hash).eq_ignore_ascii_case(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, TransactionId};
+
+    #[test]
+    fn test_every_balance_has_a_proof_of_the_root() {
+        let wallets: Vec<Wallet> = (1..=5)
+            .rev()
+            .map(|client| {
+                let mut wallet = Wallet::new(Client::new(client));
+                wallet.deposit(
+                    TransactionId::new(client.into()),
+                    Amount::from_major(client.into(), 0),
+                );
+                wallet
+            })
+            .collect();
+        let tree = BalanceTree::new(&wallets);
+        let root = tree.root();
+        for client in 1..=5 {
+            let proof = tree.proof(Client::new(client)).unwrap();
+            assert!(proof.verify(&root), "proof of client {client}");
+            assert_eq!(proof.root, root);
+        }
+        let mut forged = tree.proof(Client::new(3)).unwrap();
+        forged.leaf = "3,300.0000,0.0000,300.0000".into();
+        assert!(!forged.verify(&root));
+        assert!(tree.proof(Client::new(6)).is_none());
+        assert_ne!(BalanceTree::new(&wallets[1..]).root(), root);
+    }
+}
@@ -0,0 +1,140 @@
+//! NATS JetStream source and sink: transactions are pulled from a stream through a durable
+//! consumer, failures and updated wallets are published back to plain subjects.
+
+use crate::input::{MessageDecoder, ReadSummary};
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Envelope, Failure};
+use async_nats::jetstream::{self, consumer::PullConsumer, consumer::pull};
+use futures::StreamExt;
+use log::info;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub struct NatsOptions {
+    pub url: String,
+    pub stream: String,
+    /// Name of the durable consumer, so a restarted engine resumes after the last acked message.
+    pub consumer: String,
+    pub failure_subject: Option<String>,
+    /// Updated wallets are published to `<subject>.<client>`, or `<subject>.<tenant>.<client>`.
+    pub wallet_subject: Option<String>,
+    pub decoder: MessageDecoder,
+}
+
+/// Consumes the stream until `shutdown` completes. Message payloads are decoded by
+/// `options.decoder`. A message is acked only once its outcome has been published, so an
+/// engine that dies mid-message gets it redelivered.
+pub async fn consume(
+    options: &NatsOptions,
+    registry: Arc<TenantRegistry>,
+    err_send: UnboundedSender<Failure>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<ReadSummary> {
+    let nats = async_nats::connect(&options.url).await?;
+    let jetstream = jetstream::new(nats.clone());
+    let consumer: PullConsumer = jetstream
+        .get_stream(&options.stream)
+        .await?
+        .get_or_create_consumer(
+            &options.consumer,
+            pull::Config {
+                durable_name: Some(options.consumer.clone()),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let mut messages = consumer.messages().await?;
+    info!(
+        "Consuming stream {} as {}",
+        options.stream, options.consumer
+    );
+
+    let mut summary = ReadSummary::default();
+    tokio::pin!(shutdown);
+    loop {
+        let message = tokio::select! {
+            message = messages.next() => match message {
+                Some(message) => message?,
+                None => break,
+            },
+            _ = &mut shutdown => break,
+        };
+        summary.rows_read += 1;
+        let envelope = options.decoder.decode(&message.payload).await;
+        match envelope {
+            Some(envelope) => {
+                let transaction = envelope.transaction;
+                let client = transaction.client();
+                let manager = registry.manager(envelope.tenant.as_ref());
+                let wallet_subject = options
+                    .wallet_subject
+                    .as_deref()
+                    .map(|prefix| wallet_subject(prefix, &envelope));
+                let res = manager.apply(envelope);
+                summary.applied.record(&transaction, res.is_ok());
+                match res {
+                    Ok(()) => {
+                        if let (Some(subject), Some(wallet)) =
+                            (wallet_subject, manager.wallet(client))
+                        {
+                            publish(&nats, subject, &wallet).await?;
+                        }
+                    }
+                    Err(failure) => {
+                        if let Some(subject) = &options.failure_subject {
+                            publish(&nats, subject.clone(), &failure).await?;
+                        }
+                        registry.deliver_failure(&err_send, failure);
+                    }
+                }
+            }
+            None => summary.rows_skipped += 1,
+        }
+        message.ack().await.map_err(|e| anyhow::anyhow!(e))?;
+        if registry.aborted() {
+            summary.applied.stopped_early = true;
+            break;
+        }
+    }
+    nats.flush().await?;
+    Ok(summary)
+}
+
+fn wallet_subject(prefix: &str, envelope: &Envelope) -> String {
+    let client = envelope.transaction.client().id();
+    match &envelope.tenant {
+        Some(tenant) => format!("{prefix}.{}.{client}", tenant.as_str()),
+        None => format!("{prefix}.{client}"),
+    }
+}
+
+async fn publish(
+    nats: &async_nats::Client,
+    subject: String,
+    value: &impl Serialize,
+) -> anyhow::Result<()> {
+    nats.publish(subject, serde_json::to_vec(value)?.into())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Client, Tenant, Transaction, TransactionId};
+
+    #[test]
+    fn test_wallet_subject() {
+        let envelope = Envelope::from(Transaction::Dispute {
+            client: Client::new(7),
+            tx_id: TransactionId::new(1),
+        });
+        assert_eq!(wallet_subject("wallets", &envelope), "wallets.7");
+        let envelope = Envelope {
+            tenant: Some(Tenant::new("acme")),
+            ..envelope
+        };
+        assert_eq!(wallet_subject("wallets", &envelope), "wallets.acme.7");
+    }
+}
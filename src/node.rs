@@ -0,0 +1,136 @@
+//! Node.js bindings, so TypeScript tooling can process batches and query wallets in-process
+//! instead of running the CLI and parsing its output:
+//!
+//! ```js
+//! const { Engine } = require('./walletmanager.node');
+//! const engine = new Engine();
+//! engine.processCsv('type,client,tx,amount\ndeposit,1,1,1.5\n'); // { applied: 1, ... }
+//! engine.wallet(1); // { client: 1, available: '1.5000', held: '0.0000', ... }
+//! ```
+
+use crate::transaction::{Client, Columns, Envelope};
+use crate::wallet::Wallet;
+use crate::wallet_manager::WalletManager;
+use napi_derive::napi;
+
+/// A single-tenant engine with the default configuration.
+#[napi]
+pub struct Engine {
+    manager: WalletManager,
+}
+
+/// Outcome of a batch.
+#[napi(object)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BatchResult {
+    pub applied: u32,
+    /// Valid transactions the engine refused, e.g. withdrawals exceeding the available funds.
+    pub rejected: u32,
+    /// Rows that aren't transactions.
+    pub skipped: u32,
+}
+
+/// A wallet as in the CSV export, amounts as decimal strings.
+#[napi(object, js_name = "Wallet")]
+#[derive(Debug, PartialEq, Eq)]
+pub struct JsWallet {
+    pub client: u32,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+impl From<&Wallet> for JsWallet {
+    fn from(wallet: &Wallet) -> Self {
+        JsWallet {
+            client: wallet.client().id().into(),
+            available: wallet.available().to_string(),
+            held: wallet.held().to_string(),
+            total: wallet.total().to_string(),
+            locked: wallet.is_locked(),
+        }
+    }
+}
+
+#[napi]
+impl Engine {
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Engine {
+            manager: WalletManager::init(),
+        }
+    }
+
+    /// Applies a CSV batch with a header row, in the layout of the CLI input.
+    #[napi]
+    pub fn process_csv(&self, csv: String) -> napi::Result<BatchResult> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::Headers)
+            .from_reader(csv.as_bytes());
+        let columns = Columns::from_headers(csv_reader.headers().map_err(to_napi)?);
+        let mut result = BatchResult::default();
+        for csv_row in csv_reader.records() {
+            match Envelope::from_csv_row(&csv_row.map_err(to_napi)?, &columns)
+                .map(|envelope| self.manager.apply(envelope))
+            {
+                Some(Ok(())) => result.applied += 1,
+                Some(Err(_)) => result.rejected += 1,
+                None => result.skipped += 1,
+            }
+        }
+        Ok(result)
+    }
+
+    #[napi]
+    pub fn wallet(&self, client: u16) -> Option<JsWallet> {
+        self.manager
+            .wallet(Client::new(client))
+            .map(|wallet| JsWallet::from(&wallet))
+    }
+
+    /// Every wallet, ordered by client.
+    #[napi]
+    pub fn wallets(&self) -> Vec<JsWallet> {
+        let mut wallets = self.manager.export_wallets();
+        wallets.sort_unstable_by_key(|wallet| wallet.client());
+        wallets.iter().map(JsWallet::from).collect()
+    }
+}
+
+fn to_napi(e: csv::Error) -> napi::Error {
+    napi::Error::from_reason(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_then_wallet_queries() {
+        let engine = Engine::new();
+        let result = engine
+            .process_csv(
+                "type,client,tx,amount\n\
+                 deposit,2,1,3.0\n\
+                 deposit,1,2,1.5\n\
+                 withdrawal,1,3,2.0\n\
+                 refund,1,4,1.0\n"
+                    .to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            BatchResult {
+                applied: 2,
+                rejected: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(engine.wallet(1).unwrap().available, "1.5000");
+        assert_eq!(engine.wallet(3), None);
+        let clients: Vec<_> = engine.wallets().iter().map(|w| w.client).collect();
+        assert_eq!(clients, [1, 2]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Store-and-forward outbox for external notifications. Records are journaled to a local file
+//! before anything tries to deliver them; a dispatcher delivers them in order and persists how
+//! far it got, so notifications survive crashes and are delivered at least once. How many
+//! journaled records a crash of the machine may lose depends on the `FsyncPolicy`.
+
+use crate::durability::{FsyncPolicy, JournalFile};
+use log::{error, warn};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Destination of outbox records, e.g. a webhook.
+pub trait Target {
+    /// Delivers one JSON record, returning whether the destination accepted it.
+    fn deliver(&self, record: &str) -> impl Future<Output = bool> + Send;
+}
+
+pub struct Outbox {
+    journal_path: PathBuf,
+    cursor_path: PathBuf,
+    journal: Mutex<JournalFile>,
+}
+
+/// Records journaled with a single write at most.
+const GROUP_LIMIT: usize = 256;
+
+impl Outbox {
+    /// Opens the outbox in `dir`, keeping whatever a previous run left undelivered.
+    pub fn open(dir: &Path, fsync: FsyncPolicy) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let journal_path = dir.join("journal.jsonl");
+        let journal = JournalFile::open(&journal_path, fsync)?;
+        Ok(Outbox {
+            journal_path,
+            cursor_path: dir.join("cursor"),
+            journal: Mutex::new(journal),
+        })
+    }
+
+    /// Journals `records` as JSON lines with a single write and at most one sync.
+    pub fn append_all<R: Serialize>(&self, records: &[R]) -> io::Result<()> {
+        let mut group = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut group, record)?;
+            group.push(b'\n');
+        }
+        self.journal().append(&group)
+    }
+
+    fn journal(&self) -> MutexGuard<'_, JournalFile> {
+        self.journal.lock().expect("outbox journal lock poisoned")
+    }
+
+    /// Byte offset of the first record not yet delivered.
+    fn cursor(&self) -> io::Result<u64> {
+        match fs::read_to_string(&self.cursor_path) {
+            Ok(cursor) => cursor
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn commit(&self, cursor: u64) -> io::Result<()> {
+        let tmp = self.cursor_path.with_extension("tmp");
+        fs::write(&tmp, cursor.to_string())?;
+        fs::rename(tmp, &self.cursor_path)
+    }
+
+    /// Undelivered records with the journal offset right after each of them.
+    fn pending(&self) -> io::Result<Vec<(u64, String)>> {
+        let cursor = self.cursor()?;
+        let mut journal = File::open(&self.journal_path)?;
+        journal.seek(SeekFrom::Start(cursor))?;
+        let mut unread = String::new();
+        journal.read_to_string(&mut unread)?;
+
+        let mut offset = cursor;
+        let mut records = Vec::new();
+        // A line without its newline is still being written and waits for the next pass.
+        for line in unread.split_inclusive('\n').filter(|l| l.ends_with('\n')) {
+            offset += line.len() as u64;
+            records.push((offset, line.trim_end().to_string()));
+        }
+        Ok(records)
+    }
+
+    /// Delivers the pending records in order, stopping at the first one `target` refuses.
+    /// Returns the number of records delivered.
+    pub async fn deliver_pending(&self, target: &impl Target) -> io::Result<usize> {
+        let mut delivered = 0;
+        for (offset, record) in self.pending()? {
+            if !target.deliver(&record).await {
+                break;
+            }
+            self.commit(offset)?;
+            delivered += 1;
+        }
+        self.compact()?;
+        Ok(delivered)
+    }
+
+    /// Empties the journal once everything in it has been delivered.
+    fn compact(&self) -> io::Result<()> {
+        let journal = self.journal();
+        if journal.file().metadata()?.len() == self.cursor()? {
+            journal.file().set_len(0)?;
+            self.commit(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Journals every record received, while a dispatcher delivers the journal to `target` every
+/// `poll_interval`. Returns after the channel closed and a last delivery pass; records the
+/// target still refused stay journaled for the next run.
+pub async fn run<R: Serialize>(
+    outbox: &Outbox,
+    target: impl Target,
+    mut records: UnboundedReceiver<R>,
+    poll_interval: Duration,
+) {
+    let journal = async {
+        let mut group = Vec::new();
+        while records.recv_many(&mut group, GROUP_LIMIT).await > 0 {
+            if let Err(e) = outbox.append_all(&group) {
+                error!(
+                    "Failed to journal {} outbox records, dropping them: {e}",
+                    group.len()
+                );
+            }
+            group.clear();
+        }
+    };
+    let dispatcher = async {
+        let mut ticker = tokio::time::interval(poll_interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = outbox.deliver_pending(&target).await {
+                warn!("Outbox delivery failed: {e}");
+            }
+        }
+    };
+    // Groups appended in a quiet spell are synced once their interval is up.
+    let syncer = async {
+        let FsyncPolicy::Every(interval) = outbox.journal().policy() else {
+            return std::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let mut journal = outbox.journal();
+            if journal.sync_due()
+                && let Err(e) = journal.sync()
+            {
+                warn!("Outbox journal sync failed: {e}");
+            }
+        }
+    };
+    tokio::select! {
+        () = journal => {}
+        () = dispatcher => {}
+        () = syncer => {}
+    }
+    if let Err(e) = outbox.journal().sync() {
+        warn!("Outbox journal sync failed: {e}");
+    }
+    if let Err(e) = outbox.deliver_pending(&target).await {
+        warn!("Outbox delivery failed: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Accepts records until `capacity` of them were delivered.
+    struct Recorder {
+        capacity: usize,
+        delivered: Mutex<Vec<String>>,
+        attempts: AtomicUsize,
+    }
+
+    impl Target for Recorder {
+        async fn deliver(&self, record: &str) -> bool {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            let mut delivered = self.delivered.lock().unwrap();
+            if delivered.len() == self.capacity {
+                return false;
+            }
+            delivered.push(record.to_string());
+            true
+        }
+    }
+
+    fn recorder(capacity: usize) -> Recorder {
+        Recorder {
+            capacity,
+            delivered: Mutex::new(Vec::new()),
+            attempts: AtomicUsize::new(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_records_survive_reopening() {
+        let dir =
+            std::env::temp_dir().join(format!("walletmanagermock-outbox-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let outbox = Outbox::open(&dir, FsyncPolicy::Always).unwrap();
+        for tx in 1..=3 {
+            outbox
+                .append_all(&[serde_json::json!({ "tx": tx })])
+                .unwrap();
+        }
+        let flaky = recorder(1);
+        assert_eq!(outbox.deliver_pending(&flaky).await.unwrap(), 1);
+        assert_eq!(flaky.attempts.load(Ordering::Relaxed), 2);
+        drop(outbox);
+
+        let outbox = Outbox::open(&dir, FsyncPolicy::Never).unwrap();
+        let target = recorder(usize::MAX);
+        assert_eq!(outbox.deliver_pending(&target).await.unwrap(), 2);
+        assert_eq!(
+            *target.delivered.lock().unwrap(),
+            vec![r#"{"tx":2}"#, r#"{"tx":3}"#]
+        );
+        assert_eq!(fs::metadata(dir.join("journal.jsonl")).unwrap().len(), 0);
+        assert_eq!(outbox.deliver_pending(&target).await.unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Parquet input: one row per transaction, with the columns of the CSV input. `amount` may be
+//! a float, double or decimal column and `timestamp` an integer of seconds or a timestamp
+//! column; nulls count as missing values. Columns without a meaning of their own become
+//! attributes of the transaction.
+//!
+//! Parquet files are read from their footer, so files are read in place while stdin is loaded
+//! into memory first.
+
+use crate::input::{is_stdin, open_input};
+use crate::provenance;
+use crate::transaction::Envelope;
+use ::parquet::file::reader::{FileReader, SerializedFileReader};
+use ::parquet::record::reader::RowIter;
+use ::parquet::record::{Field, Row};
+use bytes::Bytes;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The transactions of the input at `path`, `None` for rows that aren't a valid transaction.
+pub fn read_transactions(
+    path: &Path,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<Option<Envelope>>>> {
+    let file: Box<dyn FileReader> = if is_stdin(path) {
+        let mut bytes = Vec::new();
+        open_input(path)?.read_to_end(&mut bytes)?;
+        Box::new(SerializedFileReader::new(Bytes::from(bytes))?)
+    } else {
+        if provenance::recording() {
+            // The file isn't read front to back, so its checksum takes a pass of its own.
+            io::copy(&mut open_input(path)?, &mut io::sink())?;
+        }
+        Box::new(SerializedFileReader::new(File::open(path)?)?)
+    };
+    Ok(RowIter::from_file_into(file).map(|row| Ok(into_envelope(row?))))
+}
+
+fn into_envelope(row: Row) -> Option<Envelope> {
+    let columns: Map<String, Value> = row
+        .into_columns()
+        .into_iter()
+        .filter_map(|(name, field)| Some((name, json(field)?)))
+        .collect();
+    Envelope::from_json_value(Value::Object(columns))
+}
+
+/// The value of `field` as `Envelope::from_json_value` reads it, `None` for a null.
+fn json(field: Field) -> Option<Value> {
+    Some(match field {
+        Field::Null => return None,
+        Field::Bool(value) => value.into(),
+        Field::Byte(value) => value.into(),
+        Field::Short(value) => value.into(),
+        Field::Int(value) => value.into(),
+        Field::Long(value) => value.into(),
+        Field::UByte(value) => value.into(),
+        Field::UShort(value) => value.into(),
+        Field::UInt(value) => value.into(),
+        Field::ULong(value) => value.into(),
+        Field::Float(value) => value.into(),
+        Field::Double(value) => value.into(),
+        Field::Decimal(_) => field.to_string().parse::<f64>().ok()?.into(),
+        Field::TimestampMillis(millis) => millis.div_euclid(1_000).into(),
+        Field::TimestampMicros(micros) => micros.div_euclid(1_000_000).into(),
+        Field::Str(value) => value.into(),
+        field => field.to_string().into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, Timestamp, Transaction, TransactionId};
+    use ::parquet::column::writer::ColumnWriter;
+    use ::parquet::data_type::ByteArray;
+    use ::parquet::file::writer::SerializedFileWriter;
+    use ::parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_read_transactions_rows() {
+        let schema = parse_message_type(
+            "message transaction {
+                REQUIRED BYTE_ARRAY type (UTF8);
+                REQUIRED INT32 client;
+                REQUIRED INT64 tx;
+                OPTIONAL DOUBLE amount;
+                OPTIONAL INT64 timestamp (TIMESTAMP(MILLIS, true));
+                OPTIONAL BYTE_ARRAY batch (UTF8);
+            }",
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        let mut writer =
+            SerializedFileWriter::new(&mut bytes, Arc::new(schema), Default::default()).unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column().unwrap() {
+            match column.untyped() {
+                ColumnWriter::ByteArrayColumnWriter(writer) if index == 0 => {
+                    let types = ["deposit", "withdrawal", "dispute"].map(ByteArray::from);
+                    writer.write_batch(&types, None, None).unwrap();
+                }
+                ColumnWriter::ByteArrayColumnWriter(writer) => {
+                    let batches = [ByteArray::from("b1")];
+                    writer
+                        .write_batch(&batches, Some(&[1, 0, 0]), None)
+                        .unwrap();
+                }
+                ColumnWriter::Int32ColumnWriter(writer) => {
+                    writer.write_batch(&[1, 1, 1], None, None).unwrap();
+                }
+                ColumnWriter::Int64ColumnWriter(writer) if index == 2 => {
+                    writer.write_batch(&[7, 8, 7], None, None).unwrap();
+                }
+                ColumnWriter::Int64ColumnWriter(writer) => {
+                    writer
+                        .write_batch(&[60_500], Some(&[1, 0, 0]), None)
+                        .unwrap();
+                }
+                ColumnWriter::DoubleColumnWriter(writer) => {
+                    writer.write_batch(&[2.5], Some(&[1, 0, 0]), None).unwrap();
+                }
+                _ => unreachable!(),
+            }
+            column.close().unwrap();
+            index += 1;
+        }
+        row_group.close().unwrap();
+        writer.close().unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("transactions-{}.parquet", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let envelopes: Vec<_> = read_transactions(&path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(
+            envelopes,
+            vec![
+                Some(Envelope {
+                    timestamp: Some(Timestamp::from_secs(60)),
+                    attributes: [("batch".to_string(), "b1".to_string())]
+                        .into_iter()
+                        .collect(),
+                    ..Envelope::from(Transaction::Deposit {
+                        client: Client::new(1),
+                        tx_id: TransactionId::new(7),
+                        amount: Amount::from_major(2, 5000),
+                    })
+                }),
+                None,
+                Some(Envelope::from(Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(7),
+                })),
+            ]
+        );
+        std::fs::write(&path, "type,client,tx,amount\n").unwrap();
+        assert!(read_transactions(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Crash recovery of a wallet manager from a directory holding its last checkpoint,
+//! `snapshot.bin`, and a write-ahead log of what it applied since, `wal.log`. Recovering loads
+//! the snapshot, replays the log over it and takes a fresh checkpoint, so that processing goes
+//! on from where the crashed run left off.
+//!
+//! Checkpoints cover the wallets and their journal slices. Risk history, deferred disputes,
+//! dispute expiry and the reports kept in memory start over; the funds of the restored wallets
+//! count as opening balances of the house accounts. Pending reservations are dropped with their
+//! funds released, while committed ones are logged and replayed as withdrawals.
+
+use crate::durability::FsyncPolicy;
+use crate::snapshot::{self, Snapshot};
+use crate::transaction::Envelope;
+use crate::wal::{self, Wal};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistenceOptions {
+    /// Directory of the snapshot and the log, one per wallet manager.
+    pub dir: PathBuf,
+    /// When appended transactions reach the disk.
+    pub fsync: FsyncPolicy,
+    /// Take a checkpoint every this many transactions, emptying the log; only on request
+    /// otherwise.
+    pub checkpoint_every: Option<u64>,
+}
+
+/// What `WalletManager::recover` found and replayed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recovery {
+    /// Sequence number of the snapshot recovered from, 0 without one.
+    pub snapshot_seq: u64,
+    pub wallets: usize,
+    /// Transactions of the log applied over the snapshot, failed ones included.
+    pub replayed: usize,
+    /// Whether the log ended in a torn frame, the transaction a crash interrupted.
+    pub torn: bool,
+}
+
+/// The log and checkpoint state of one wallet manager.
+#[derive(Debug)]
+pub struct Persistence {
+    options: PersistenceOptions,
+    wal: Mutex<Wal>,
+    /// Held shared while a transaction is applied and exclusively while a checkpoint is taken,
+    /// so checkpoints never see half of a transaction.
+    gate: RwLock<()>,
+    checkpointed_seq: AtomicU64,
+}
+
+impl Persistence {
+    /// Opens the log in `options.dir`, creating the directory if needed.
+    pub fn open(options: PersistenceOptions) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&options.dir)?;
+        let wal = Wal::open(&options.dir.join("wal.log"), options.fsync)?;
+        Ok(Persistence {
+            options,
+            wal: Mutex::new(wal),
+            gate: RwLock::new(()),
+            checkpointed_seq: AtomicU64::new(0),
+        })
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.options.dir.join("snapshot.bin")
+    }
+
+    pub(crate) fn applying(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().expect("persistence gate poisoned")
+    }
+
+    pub(crate) fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gate.write().expect("persistence gate poisoned")
+    }
+
+    /// Appends `envelopes`, one transaction or the legs of one multi-wallet operation, to the
+    /// log under the sequence number `seq` hands out, if any, and returns it. The number is taken
+    /// under the lock of the log, so concurrent appliers write their frames in sequence order.
+    ///
+    /// # Panics
+    ///
+    /// On I/O errors of the log, past which a crash would lose applied transactions.
+    pub(crate) fn log(
+        &self,
+        envelopes: &[Envelope],
+        seq: impl FnOnce() -> Option<u64>,
+    ) -> Option<u64> {
+        let mut wal = self.wal.lock().expect("write-ahead log lock poisoned");
+        let seq = seq()?;
+        for envelope in envelopes {
+            let envelope = Envelope {
+                seq: Some(seq),
+                ..envelope.clone()
+            };
+            wal.append(&envelope)
+                .expect("failed to append to the write-ahead log");
+        }
+        Some(seq)
+    }
+
+    /// Whether `checkpoint_every` transactions were applied since the last checkpoint.
+    pub(crate) fn checkpoint_due(&self, seq: u64) -> bool {
+        self.options
+            .checkpoint_every
+            .is_some_and(|every| seq >= self.checkpointed_seq.load(Ordering::Relaxed) + every)
+    }
+
+    /// Saves `snapshot` and empties the log it makes redundant.
+    pub(crate) fn save(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let mut wal = self.wal.lock().expect("write-ahead log lock poisoned");
+        wal.sync()?;
+        snapshot::save(&self.snapshot_path(), snapshot)?;
+        wal.reset()?;
+        self.checkpointed_seq
+            .fetch_max(snapshot.seq, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The last snapshot, if any, and the log written since.
+    pub(crate) fn load(&self) -> anyhow::Result<(Option<Snapshot>, wal::WalContents)> {
+        let snapshot = snapshot::load(&self.snapshot_path())?;
+        let contents = wal::read(&self.options.dir.join("wal.log"))?;
+        Ok((snapshot, contents))
+    }
+}
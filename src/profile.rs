@@ -0,0 +1,34 @@
+//! CPU profile of a run, sampled in-process and written as a flamegraph SVG with `--profile`.
+
+use anyhow::Context;
+use pprof::{ProfilerGuard, ProfilerGuardBuilder};
+use std::fs::File;
+use std::path::PathBuf;
+
+/// Samples per second; a prime, so that sampling doesn't fall into step with periodic work.
+const FREQUENCY: i32 = 997;
+
+pub struct Profile {
+    guard: ProfilerGuard<'static>,
+    path: PathBuf,
+}
+
+impl Profile {
+    pub fn start(path: PathBuf) -> anyhow::Result<Self> {
+        let guard = ProfilerGuardBuilder::default()
+            .frequency(FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .context("starting the profiler")?;
+        Ok(Profile { guard, path })
+    }
+
+    /// Stops sampling and writes the flamegraph.
+    pub fn finish(self) -> anyhow::Result<()> {
+        let report = self.guard.report().build()?;
+        let file = File::create(&self.path)
+            .with_context(|| format!("failed to create {}", self.path.display()))?;
+        report.flamegraph(file)?;
+        Ok(())
+    }
+}
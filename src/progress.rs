@@ -0,0 +1,179 @@
+//! Machine-readable progress on stderr for orchestration UIs: with `--progress json`, a JSON
+//! object per line every second while the run processes its input, and a last one once it is
+//! done, e.g.
+//!
+//! ```text
+//! {"event":"progress","elapsed_ms":1000,"rows_read":52000,"processed":50000,"failed":12,"bytes_read":1048576,"bytes_total":4194304,"eta_secs":3.0}
+//! ```
+
+use crate::tenant::TenantRegistry;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Json,
+}
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Whether an input of unknown size, such as stdin, was opened.
+static SIZE_UNKNOWN: AtomicBool = AtomicBool::new(false);
+
+/// Count the bytes of every input opened from now on.
+pub fn track_inputs(track: bool) {
+    TRACKING.store(track, Ordering::Relaxed);
+}
+
+pub fn tracking() -> bool {
+    TRACKING.load(Ordering::Relaxed)
+}
+
+/// Counts what is read through it towards the progress of the run.
+pub struct ProgressReader<R> {
+    inner: R,
+}
+
+impl<R> ProgressReader<R> {
+    /// `size` is the length of the input, `None` for pipes and stdin.
+    pub fn new(size: Option<u64>, inner: R) -> Self {
+        if let Some(size) = size {
+            BYTES_TOTAL.fetch_add(size, Ordering::Relaxed);
+        } else {
+            SIZE_UNKNOWN.store(true, Ordering::Relaxed);
+        }
+        ProgressReader { inner }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        BYTES_READ.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EventKind {
+    Progress,
+    Done,
+}
+
+#[derive(Debug, Serialize)]
+struct ProgressEvent {
+    event: EventKind,
+    elapsed_ms: u64,
+    /// Transactions read from the input, processed or still queued.
+    rows_read: u64,
+    processed: u64,
+    failed: u64,
+    bytes_read: u64,
+    /// `None` when an input's size isn't known.
+    bytes_total: Option<u64>,
+    /// `None` until the size of the input and the rate it is read at are known.
+    eta_secs: Option<f64>,
+}
+
+/// Reports the progress of the transactions queued to `registry`. Streaming sources apply
+/// transactions themselves, so only their failures show.
+pub struct Progress {
+    registry: Arc<TenantRegistry>,
+    started: Instant,
+}
+
+impl Progress {
+    pub fn new(registry: Arc<TenantRegistry>) -> Self {
+        Progress {
+            registry,
+            started: Instant::now(),
+        }
+    }
+
+    fn event(&self, event: EventKind) -> ProgressEvent {
+        let queue = self.registry.queue();
+        let processed = queue.dequeued();
+        let bytes_read = BYTES_READ.load(Ordering::Relaxed);
+        let bytes_total =
+            (!SIZE_UNKNOWN.load(Ordering::Relaxed)).then(|| BYTES_TOTAL.load(Ordering::Relaxed));
+        let elapsed = self.started.elapsed();
+        ProgressEvent {
+            event,
+            elapsed_ms: elapsed.as_millis() as u64,
+            rows_read: processed + queue.depth() as u64,
+            processed,
+            failed: self.registry.failure_count() as u64,
+            bytes_read,
+            bytes_total,
+            eta_secs: match event {
+                EventKind::Progress => eta_secs(
+                    elapsed,
+                    bytes_read,
+                    bytes_total,
+                    queue.latest().consumer_lag_secs,
+                ),
+                EventKind::Done => Some(0.0),
+            },
+        }
+    }
+
+    fn emit(&self, event: EventKind) {
+        if let Ok(line) = serde_json::to_string(&self.event(event)) {
+            eprintln!("{line}");
+        }
+    }
+
+    /// Emits a progress event every `interval` until the process exits.
+    pub async fn report(self: Arc<Self>, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        // The first tick completes immediately, before anything was read.
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            self.emit(EventKind::Progress);
+        }
+    }
+
+    /// Emits the final event, once every transaction has been processed.
+    pub fn finish(&self) {
+        self.emit(EventKind::Done);
+    }
+}
+
+/// Time left to read the rest of the input at the rate read so far, or to work off the queue if
+/// that takes longer.
+fn eta_secs(
+    elapsed: Duration,
+    bytes_read: u64,
+    bytes_total: Option<u64>,
+    consumer_lag_secs: Option<f64>,
+) -> Option<f64> {
+    let bytes_total = bytes_total?;
+    if bytes_read == 0 {
+        return None;
+    }
+    let reading =
+        elapsed.as_secs_f64() * bytes_total.saturating_sub(bytes_read) as f64 / bytes_read as f64;
+    Some(reading.max(consumer_lag_secs.unwrap_or(0.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_from_read_rate_and_queue_lag() {
+        let elapsed = Duration::from_secs(2);
+        assert_eq!(eta_secs(elapsed, 250, Some(1_000), None), Some(6.0));
+        assert_eq!(eta_secs(elapsed, 250, Some(1_000), Some(9.5)), Some(9.5));
+        assert_eq!(eta_secs(elapsed, 1_000, Some(1_000), Some(0.5)), Some(0.5));
+        assert_eq!(eta_secs(elapsed, 0, Some(1_000), None), None);
+        assert_eq!(eta_secs(elapsed, 250, None, None), None);
+    }
+}
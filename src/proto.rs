@@ -0,0 +1,173 @@
+//! Protocol Buffers messages of `proto/walletmanager/v1/walletmanager.proto` for transactions and
+//! wallets, declared by hand like the gRPC messages so that building doesn't need protoc.
+
+use crate::transaction::{self, Amount, Client, Envelope, Tenant, Timestamp, TransactionId};
+use crate::wallet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TransactionType {
+    Unspecified = 0,
+    Deposit = 1,
+    Withdrawal = 2,
+    Dispute = 3,
+    Resolve = 4,
+    Chargeback = 5,
+    Represent = 6,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Transaction {
+    #[prost(enumeration = "TransactionType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    /// Decimal string, set for deposits and withdrawals only.
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    #[prost(int64, optional, tag = "5")]
+    pub timestamp: Option<i64>,
+    #[prost(string, optional, tag = "6")]
+    pub tenant: Option<String>,
+    #[prost(uint64, optional, tag = "7")]
+    pub seq: Option<u64>,
+}
+
+impl From<&Envelope> for Transaction {
+    fn from(envelope: &Envelope) -> Self {
+        let transaction = &envelope.transaction;
+        let r#type = match transaction {
+            transaction::Transaction::Deposit { .. } => TransactionType::Deposit,
+            transaction::Transaction::Withdrawal { .. } => TransactionType::Withdrawal,
+            transaction::Transaction::Dispute { .. } => TransactionType::Dispute,
+            transaction::Transaction::Resolve { .. } => TransactionType::Resolve,
+            transaction::Transaction::ChargeBack { .. } => TransactionType::Chargeback,
+            transaction::Transaction::Represent { .. } => TransactionType::Represent,
+        };
+        Transaction {
+            r#type: r#type.into(),
+            client: transaction.client().id().into(),
+            tx: transaction.tx_id().id(),
+            amount: transaction.amount().map(|amount| amount.to_string()),
+            timestamp: envelope.timestamp.map(|t| t.as_secs()),
+            tenant: envelope.tenant.as_ref().map(|t| t.as_str().to_string()),
+            seq: envelope.seq,
+        }
+    }
+}
+
+impl TryFrom<Transaction> for Envelope {
+    type Error = String;
+
+    fn try_from(message: Transaction) -> Result<Self, Self::Error> {
+        let client = Client::new(
+            u16::try_from(message.client)
+                .map_err(|_| format!("client {} out of range", message.client))?,
+        );
+        let tx_id = TransactionId::new(message.tx);
+        let amount = || -> Result<Amount, String> {
+            message
+                .amount
+                .as_deref()
+                .ok_or_else(|| format!("transaction {} has no amount", message.tx))?
+                .parse()
+        };
+        let transaction = match TransactionType::try_from(message.r#type) {
+            Ok(TransactionType::Deposit) => transaction::Transaction::Deposit {
+                client,
+                tx_id,
+                amount: amount()?,
+            },
+            Ok(TransactionType::Withdrawal) => transaction::Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount: amount()?,
+            },
+            Ok(TransactionType::Dispute) => transaction::Transaction::Dispute { client, tx_id },
+            Ok(TransactionType::Resolve) => transaction::Transaction::Resolve { client, tx_id },
+            Ok(TransactionType::Chargeback) => {
+                transaction::Transaction::ChargeBack { client, tx_id }
+            }
+            Ok(TransactionType::Represent) => transaction::Transaction::Represent { client, tx_id },
+            Ok(TransactionType::Unspecified) | Err(_) => {
+                return Err(format!("unknown transaction type {}", message.r#type));
+            }
+        };
+        Ok(Envelope {
+            timestamp: message.timestamp.map(Timestamp::from_secs),
+            tenant: match message.tenant {
+                Some(tenant) => {
+                    Some(Tenant::parse(&tenant).ok_or(format!("invalid tenant id {tenant:?}"))?)
+                }
+                None => None,
+            },
+            seq: message.seq,
+            ..Envelope::from(transaction)
+        })
+    }
+}
+
+/// A row of the wallet export.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Wallet {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+}
+
+impl From<&wallet::Wallet> for Wallet {
+    fn from(wallet: &wallet::Wallet) -> Self {
+        Wallet {
+            client: wallet.client().id().into(),
+            available: wallet.available().to_string(),
+            held: wallet.held().to_string(),
+            total: wallet.total().to_string(),
+            locked: wallet.is_locked(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_transaction_round_trips_with_schema_field_numbers() {
+        let envelope = Envelope {
+            timestamp: Some(Timestamp::from_secs(60)),
+            ..Envelope::from(transaction::Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(1, 5_000),
+            })
+        };
+        let bytes = Transaction::from(&envelope).encode_to_vec();
+        // type = 1 (varint), client = 2, tx = 3, amount = 4 (string), timestamp = 5.
+        assert_eq!(
+            bytes,
+            [
+                0x08, 1, 0x10, 1, 0x18, 2, 0x22, 6, b'1', b'.', b'5', b'0', b'0', b'0', 0x28, 60
+            ]
+        );
+        let decoded = Transaction::decode(bytes.as_slice()).unwrap();
+        assert_eq!(Envelope::try_from(decoded), Ok(envelope));
+
+        let dispute = Transaction {
+            r#type: TransactionType::Dispute.into(),
+            client: 70_000,
+            tx: 2,
+            ..Transaction::default()
+        };
+        assert!(Envelope::try_from(dispute).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Provenance of a run, written next to the wallet export with `--metadata-output` so that
+//! downstream consumers can tell which inputs, engine and configuration produced the numbers.
+
+use crate::input::ReadSummary;
+use crate::merkle::to_hex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static CHECKSUMS: Mutex<Vec<InputChecksum>> = Mutex::new(Vec::new());
+
+/// Checksum every input opened from now on.
+pub fn record_inputs(record: bool) {
+    RECORDING.store(record, Ordering::Relaxed);
+}
+
+pub fn recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+/// Inputs read so far, in the order they were opened.
+pub fn input_checksums() -> Vec<InputChecksum> {
+    CHECKSUMS.lock().expect("checksums lock poisoned").clone()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputChecksum {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// Hashes what is read through it, recording the checksum once dropped. Inputs are read exactly
+/// once, so this covers pipes and stdin as well as files.
+pub struct ChecksumReader<R> {
+    inner: R,
+    path: String,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R> ChecksumReader<R> {
+    pub fn new(path: &Path, inner: R) -> Self {
+        ChecksumReader {
+            inner,
+            path: path.display().to_string(),
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Drop for ChecksumReader<R> {
+    fn drop(&mut self) {
+        let checksum = InputChecksum {
+            path: std::mem::take(&mut self.path),
+            sha256: to_hex(&std::mem::take(&mut self.hasher).finalize().into()),
+            bytes: self.bytes,
+        };
+        if let Ok(mut checksums) = CHECKSUMS.lock() {
+            checksums.push(checksum);
+        }
+    }
+}
+
+/// SHA-256 of a config file, as hex.
+pub fn file_hash(path: &Path) -> io::Result<String> {
+    Ok(to_hex(&Sha256::digest(std::fs::read(path)?).into()))
+}
+
+/// The sidecar of one namespace's wallet export.
+#[derive(Debug, Serialize)]
+pub struct RunMetadata<'a> {
+    pub engine_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<&'a str>,
+    /// Command line arguments of the run, without the program name.
+    pub arguments: Vec<String>,
+    /// SHA-256 of the `--config` file.
+    pub config_hash: Option<String>,
+    pub inputs: Vec<InputChecksum>,
+    #[serde(flatten)]
+    pub rows: &'a ReadSummary,
+    pub wallets: usize,
+    /// `--state-hash` of the namespace.
+    pub state_hash: String,
+}
+
+impl RunMetadata<'_> {
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_covers_everything_read() {
+        let mut reader = ChecksumReader::new(Path::new("-"), &b"type,client,tx,amount\n"[..]);
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        drop(reader);
+
+        let checksum = input_checksums().pop().unwrap();
+        assert_eq!(checksum.path, "-");
+        assert_eq!(checksum.bytes, 22);
+        assert_eq!(
+            checksum.sha256,
+            to_hex(&Sha256::digest(read.as_bytes()).into())
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Transactions turned away by a frozen, quarantined or chargeback-locked wallet, kept with the
+//! wallet's state at the time so that they can be reviewed and re-applied once the wallet is
+//! unfrozen or unlocked. Rows start with the input columns, so the file can be passed back as
+//! input as it is.
+
+use crate::transaction::{Amount, Client, Envelope, Failure, Timestamp, TransactionId};
+use crate::wallet::Wallet;
+use serde::Serialize;
+
+/// One row of the quarantine output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuarantinedTransaction {
+    #[serde(rename = "type")]
+    pub transaction_type: &'static str,
+    pub client: Client,
+    pub tx: TransactionId,
+    pub amount: Option<Amount>,
+    pub timestamp: Option<Timestamp>,
+    /// Sequence number the transaction failed at. Not named `seq`, which input would take for a
+    /// replayed sequence number and skip the transaction as applied already.
+    pub failed_seq: u64,
+    pub status: &'static str,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub reason: String,
+}
+
+impl QuarantinedTransaction {
+    pub fn new(envelope: &Envelope, wallet: &Wallet, seq: u64, failure: &Failure) -> Self {
+        let transaction = &envelope.transaction;
+        QuarantinedTransaction {
+            transaction_type: transaction.type_name(),
+            client: transaction.client(),
+            tx: transaction.tx_id(),
+            amount: transaction.amount(),
+            timestamp: envelope.timestamp,
+            failed_seq: seq,
+            status: if wallet.is_frozen() {
+                "frozen"
+            } else if wallet.quarantined {
+                "quarantined"
+            } else {
+                "locked"
+            },
+            available: wallet.available(),
+            held: wallet.held(),
+            total: wallet.total(),
+            reason: failure.reason.clone(),
+        }
+    }
+}
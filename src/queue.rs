@@ -0,0 +1,241 @@
+//! Depth and age of the queue between the input reader and the wallet managers, so that a
+//! consumer falling behind is noticed before the queued transactions exhaust memory. The queue
+//! carries the transactions of input files, statements, brokers and `serve`; `--listen`
+//! connections apply theirs without it, each reading at most `--listen-buffer` lines ahead, so
+//! they don't show here.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Updated by the consumer on every transaction it takes off the queue and sampled periodically
+/// by `monitor`.
+#[derive(Debug, Default)]
+pub struct QueueMetrics {
+    /// Transactions that entered the queue, counted once it is `meter`ed.
+    enqueued: AtomicU64,
+    metered: AtomicBool,
+    dequeued: AtomicU64,
+    depth: AtomicUsize,
+    samples: Mutex<Samples>,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    /// Sample times with the number of transactions enqueued by then, from the sample the oldest
+    /// queued transaction was enqueued by.
+    checkpoints: VecDeque<(Instant, u64)>,
+    /// Time and dequeue count of the previous sample, to tell the consumer's rate.
+    previous: Option<(Instant, u64)>,
+    latest: QueueStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueueStats {
+    /// Transactions waiting; see `QueueMetrics::depth`.
+    pub depth: usize,
+    /// How long the oldest waiting transaction has been queued, to the sampling interval.
+    pub oldest_age_ms: u64,
+    /// Time the consumer needs to work through the queue at its rate since the previous sample;
+    /// `None` while it makes no progress.
+    pub consumer_lag_secs: Option<f64>,
+}
+
+impl QueueMetrics {
+    /// Counts the transactions entering the queue `tx_recv` takes them from, passing them on to
+    /// the returned receiver, so that the depth is known even while the consumer stalls and
+    /// reports nothing.
+    pub fn meter<T: Send + 'static>(
+        self: &Arc<Self>,
+        mut tx_recv: UnboundedReceiver<T>,
+    ) -> UnboundedReceiver<T> {
+        self.metered.store(true, Ordering::Relaxed);
+        let (forward, metered) = mpsc::unbounded_channel();
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(1024);
+            while tx_recv.recv_many(&mut batch, 1024).await > 0 {
+                metrics
+                    .enqueued
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                for item in batch.drain(..) {
+                    if forward.send(item).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        metered
+    }
+
+    /// Called by the consumer after taking a transaction off the queue, with the number of
+    /// transactions still waiting.
+    pub fn record_dequeue(&self, remaining: usize) {
+        self.dequeued.fetch_add(1, Ordering::Relaxed);
+        self.depth.store(remaining, Ordering::Relaxed);
+    }
+
+    /// Takes a sample at `now`.
+    pub fn sample(&self, now: Instant) -> QueueStats {
+        let dequeued = self.dequeued.load(Ordering::Relaxed);
+        let depth = self.depth();
+        let mut samples = self.samples.lock().expect("queue samples lock poisoned");
+        // Checkpoints whose transactions have all been taken off the queue are done with.
+        while samples
+            .checkpoints
+            .front()
+            .is_some_and(|(_, enqueued)| *enqueued <= dequeued)
+        {
+            samples.checkpoints.pop_front();
+        }
+        let oldest_age = match samples.checkpoints.front() {
+            Some((enqueued_by, _)) if depth > 0 => now.duration_since(*enqueued_by),
+            _ => Duration::ZERO,
+        };
+        let consumer_lag_secs = match samples.previous {
+            _ if depth == 0 => Some(0.0),
+            Some((at, before)) if dequeued > before => {
+                let rate = (dequeued - before) as f64 / now.duration_since(at).as_secs_f64();
+                Some(depth as f64 / rate)
+            }
+            _ => None,
+        };
+        samples
+            .checkpoints
+            .push_back((now, dequeued + depth as u64));
+        samples.previous = Some((now, dequeued));
+        samples.latest = QueueStats {
+            depth,
+            oldest_age_ms: oldest_age.as_millis() as u64,
+            consumer_lag_secs,
+        };
+        samples.latest
+    }
+
+    /// Transactions taken off the queue so far.
+    pub fn dequeued(&self) -> u64 {
+        self.dequeued.load(Ordering::Relaxed)
+    }
+
+    /// Transactions waiting: those that entered a `meter`ed queue and weren't taken off yet, or
+    /// else as many as the consumer saw as of the last one it took off.
+    pub fn depth(&self) -> usize {
+        if self.metered.load(Ordering::Relaxed) {
+            let enqueued = self.enqueued.load(Ordering::Relaxed);
+            enqueued.saturating_sub(self.dequeued.load(Ordering::Relaxed)) as usize
+        } else {
+            self.depth.load(Ordering::Relaxed)
+        }
+    }
+
+    /// The stats of the latest sample.
+    pub fn latest(&self) -> QueueStats {
+        self.samples
+            .lock()
+            .expect("queue samples lock poisoned")
+            .latest
+    }
+}
+
+/// Backlog from which `monitor` warns; nothing is reported when unset.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueAlerts {
+    pub max_depth: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl QueueAlerts {
+    fn exceeded(&self, stats: &QueueStats) -> bool {
+        self.max_depth.is_some_and(|max| stats.depth > max)
+            || self
+                .max_age
+                .is_some_and(|max| Duration::from_millis(stats.oldest_age_ms) > max)
+    }
+}
+
+/// Samples `metrics` every `interval` until the process exits, warning on stderr when the backlog
+/// exceeds `alerts` and again once it has recovered.
+pub async fn monitor(metrics: Arc<QueueMetrics>, alerts: QueueAlerts, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    let mut alerting = false;
+    loop {
+        let now = ticks.tick().await.into_std();
+        let stats = metrics.sample(now);
+        if alerts.exceeded(&stats) != alerting {
+            alerting = !alerting;
+            let state = if alerting { "backlog" } else { "recovered" };
+            eprintln!(
+                "queue {state}: {} transactions waiting, oldest for {} ms",
+                stats.depth, stats.oldest_age_ms
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_track_depth_age_and_lag() {
+        let metrics = QueueMetrics::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Ten transactions read, one taken off the queue.
+        metrics.record_dequeue(9);
+        let stats = metrics.sample(at(0));
+        assert_eq!(stats.depth, 9);
+        assert_eq!(stats.consumer_lag_secs, None);
+
+        // Ten more read; the consumer took four in two seconds.
+        for remaining in (15..=18).rev() {
+            metrics.record_dequeue(remaining);
+        }
+        let stats = metrics.sample(at(2));
+        assert_eq!(stats.oldest_age_ms, 2000);
+        assert_eq!(stats.consumer_lag_secs, Some(7.5));
+
+        // Fourteen more taken; the oldest waiting one was read by the second sample.
+        for _ in 0..14 {
+            metrics.record_dequeue(6);
+        }
+        let stats = metrics.sample(at(3));
+        assert_eq!(stats.depth, 6);
+        assert_eq!(stats.oldest_age_ms, 1000);
+        assert_eq!(metrics.latest(), stats);
+    }
+
+    #[tokio::test]
+    async fn test_metered_queue_shows_backlog_of_stalled_consumer() {
+        let metrics = Arc::new(QueueMetrics::default());
+        let (tx_send, tx_recv) = mpsc::unbounded_channel();
+        let mut tx_recv = metrics.meter(tx_recv);
+        let start = Instant::now();
+        for tx in 0..5 {
+            tx_send.send(tx).unwrap();
+        }
+        while metrics.depth() < 5 {
+            tokio::task::yield_now().await;
+        }
+        metrics.sample(start);
+
+        // Nothing was taken off the queue meanwhile.
+        let stats = metrics.sample(start + Duration::from_secs(3));
+        assert_eq!(stats.depth, 5);
+        assert_eq!(stats.oldest_age_ms, 3000);
+        assert_eq!(stats.consumer_lag_secs, None);
+        let alerts = QueueAlerts {
+            max_depth: None,
+            max_age: Some(Duration::from_secs(1)),
+        };
+        assert!(alerts.exceeded(&stats));
+
+        assert_eq!(tx_recv.recv().await, Some(0));
+        metrics.record_dequeue(tx_recv.len());
+        assert_eq!(metrics.depth(), 4);
+    }
+}
@@ -0,0 +1,268 @@
+//! A deliberately naive, single-threaded implementation of the accounting rules, kept next to the
+//! engine as an oracle: random workloads run through both have to end in the same state. It
+//! follows the engine's rules as they are, including the ones that look surprising: locked
+//! wallets turn away everything but re-presentments, which leave the wallet locked, and a charged
+//! back withdrawal can't be disputed again.
+
+use crate::transaction::{Amount, Transaction};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy)]
+enum Journaled {
+    Deposit(Amount),
+    Withdrawal(Amount),
+}
+
+/// Balances, lock and open disputes of one client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    pub disputes: BTreeMap<u32, Amount>,
+    pub charged_back: BTreeMap<u32, Amount>,
+}
+
+#[derive(Debug, Default)]
+pub struct Reference {
+    accounts: BTreeMap<u16, Account>,
+    journal: HashMap<(u16, u32), Journaled>,
+    reversed_withdrawals: HashSet<(u16, u32)>,
+}
+
+impl Reference {
+    /// Applies `transaction`, returning whether it succeeded.
+    pub fn apply(&mut self, transaction: &Transaction) -> bool {
+        let client = transaction.client().id();
+        let tx = transaction.tx_id().id();
+        let locked = self.accounts.get(&client).is_some_and(|a| a.locked);
+        if locked && !matches!(transaction, Transaction::Represent { .. }) {
+            return false;
+        }
+        match *transaction {
+            Transaction::Deposit { amount, .. } => {
+                let account = self.accounts.entry(client).or_insert_with(|| Account {
+                    available: Amount::zero(),
+                    held: Amount::zero(),
+                    total: Amount::zero(),
+                    locked: false,
+                    disputes: BTreeMap::new(),
+                    charged_back: BTreeMap::new(),
+                });
+                account.available += amount;
+                account.total += amount;
+                self.journal
+                    .insert((client, tx), Journaled::Deposit(amount));
+                true
+            }
+            Transaction::Withdrawal { amount, .. } => {
+                let Some(account) = self.accounts.get_mut(&client) else {
+                    return false;
+                };
+                if account.available < amount {
+                    return false;
+                }
+                account.available -= amount;
+                account.total -= amount;
+                self.journal
+                    .insert((client, tx), Journaled::Withdrawal(amount));
+                true
+            }
+            Transaction::Dispute { .. } => {
+                let Some(journaled) = self.journal.get(&(client, tx)).copied() else {
+                    return false;
+                };
+                if self.reversed_withdrawals.contains(&(client, tx)) {
+                    return false;
+                }
+                let Some(account) = self.accounts.get_mut(&client) else {
+                    return false;
+                };
+                if account.disputes.contains_key(&tx) {
+                    return false;
+                }
+                let amount = match journaled {
+                    Journaled::Deposit(amount) => {
+                        account.available -= amount;
+                        amount
+                    }
+                    Journaled::Withdrawal(amount) => {
+                        account.total += amount;
+                        amount
+                    }
+                };
+                account.held += amount;
+                account.disputes.insert(tx, amount);
+                true
+            }
+            Transaction::Resolve { .. } => {
+                let Some(account) = self.accounts.get_mut(&client) else {
+                    return false;
+                };
+                let Some(amount) = account.disputes.remove(&tx) else {
+                    return false;
+                };
+                account.held -= amount;
+                match self.journal[&(client, tx)] {
+                    Journaled::Deposit(_) => account.available += amount,
+                    Journaled::Withdrawal(_) => account.total -= amount,
+                }
+                true
+            }
+            Transaction::ChargeBack { .. } => {
+                let Some(account) = self.accounts.get_mut(&client) else {
+                    return false;
+                };
+                let Some(amount) = account.disputes.remove(&tx) else {
+                    return false;
+                };
+                account.held -= amount;
+                match self.journal[&(client, tx)] {
+                    Journaled::Deposit(_) => {
+                        account.total -= amount;
+                        account.locked = true;
+                        account.charged_back.insert(tx, amount);
+                    }
+                    Journaled::Withdrawal(_) => {
+                        account.available += amount;
+                        self.reversed_withdrawals.insert((client, tx));
+                    }
+                }
+                true
+            }
+            Transaction::Represent { .. } => {
+                let Some(account) = self.accounts.get_mut(&client) else {
+                    return false;
+                };
+                let Some(amount) = account.charged_back.remove(&tx) else {
+                    return false;
+                };
+                account.available += amount;
+                account.total += amount;
+                true
+            }
+        }
+    }
+
+    /// Every account by client.
+    pub fn accounts(&self) -> &BTreeMap<u16, Account> {
+        &self.accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Rng;
+    use crate::transaction::{Client, TransactionId};
+    use crate::wallet_manager::WalletManager;
+    use std::sync::Arc;
+
+    const CLIENTS: u64 = 4;
+    const TRANSACTIONS: u32 = 400;
+
+    /// Mostly deposits and withdrawals, with disputes, resolves, chargebacks and re-presentments
+    /// of earlier, later, other clients' and withdrawn transactions mixed in.
+    fn workload(seed: u64) -> Vec<Transaction> {
+        let mut rng = Rng::new(seed);
+        (1..=TRANSACTIONS)
+            .map(|next_tx| {
+                let client = Client::new(1 + rng.below(CLIENTS) as u16);
+                let amount = Amount::from_minor_units(rng.below(2_000_000) as i64);
+                let tx_id = TransactionId::new(next_tx);
+                let earlier = TransactionId::new(1 + rng.below(u64::from(next_tx) + 2) as u32);
+                match rng.below(11) {
+                    0..=3 => Transaction::Deposit {
+                        client,
+                        tx_id,
+                        amount,
+                    },
+                    4..=5 => Transaction::Withdrawal {
+                        client,
+                        tx_id,
+                        amount,
+                    },
+                    6..=7 => Transaction::Dispute {
+                        client,
+                        tx_id: earlier,
+                    },
+                    8 => Transaction::Resolve {
+                        client,
+                        tx_id: earlier,
+                    },
+                    9 => Transaction::ChargeBack {
+                        client,
+                        tx_id: earlier,
+                    },
+                    _ => Transaction::Represent {
+                        client,
+                        tx_id: earlier,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    async fn run_engine(transactions: &[Transaction]) -> (BTreeMap<u16, Account>, Vec<(u16, u32)>) {
+        let wallet_manager = Arc::new(WalletManager::init());
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, mut err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let runner = tokio::spawn({
+            let wallet_manager = wallet_manager.clone();
+            async move { wallet_manager.run(tx_receiver, err_sender).await }
+        });
+        for transaction in transactions {
+            tx_sender.send((*transaction).into()).unwrap();
+        }
+        drop(tx_sender);
+        runner.await.unwrap();
+
+        let mut failures = Vec::new();
+        while let Some(failure) = err_receiver.recv().await {
+            failures.push((failure.client.id(), failure.tx.id()));
+        }
+        let accounts = wallet_manager
+            .export_wallets()
+            .iter()
+            .map(|wallet| {
+                let disputes = wallet
+                    .open_disputes()
+                    .iter()
+                    .map(|(tx, &amount)| (tx.id(), amount))
+                    .collect();
+                let account = Account {
+                    available: wallet.available(),
+                    held: wallet.held(),
+                    total: wallet.total(),
+                    locked: wallet.is_locked(),
+                    disputes,
+                    charged_back: wallet
+                        .charged_back()
+                        .iter()
+                        .map(|(tx, &amount)| (tx.id(), amount))
+                        .collect(),
+                };
+                (wallet.client().id(), account)
+            })
+            .collect();
+        (accounts, failures)
+    }
+
+    #[tokio::test]
+    async fn test_engine_agrees_with_reference() {
+        for seed in 0..20 {
+            let transactions = workload(seed);
+            let mut reference = Reference::default();
+            let failures: Vec<_> = transactions
+                .iter()
+                .filter(|transaction| !reference.apply(transaction))
+                .map(|transaction| (transaction.client().id(), transaction.tx_id().id()))
+                .collect();
+            let (accounts, engine_failures) = run_engine(&transactions).await;
+            assert_eq!(&accounts, reference.accounts(), "seed {seed}");
+            assert_eq!(engine_failures, failures, "seed {seed}");
+            assert!(!failures.is_empty(), "seed {seed}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Report of the transactions the wallets rejected, written with `--errors`: every failure with
+//! the input row it was read from, as far as its source records one, the rows of the input that
+//! aren't transactions, and the number of rejections per failure kind. As CSV, the rows go to the
+//! given path and the counts to a `.summary.csv` file next to it; as JSON, both go into one
+//! document.
+
+use crate::export::write_csv_report;
+use crate::exposure::ReportFormat;
+use crate::transaction::{Client, Failure, FailureKind, Origin, TransactionId};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// One rejected transaction, or row that isn't one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejection {
+    pub input: Option<String>,
+    pub row: Option<u64>,
+    pub client: Option<Client>,
+    pub tx: Option<TransactionId>,
+    pub kind: FailureKind,
+    pub reason: String,
+    pub seq: Option<u64>,
+    /// The row as read, for CSV inputs.
+    pub raw: Option<String>,
+}
+
+impl From<Failure> for Rejection {
+    fn from(failure: Failure) -> Self {
+        let origin = failure.origin.map(|origin| *origin);
+        let (input, row, raw) = match origin {
+            Some(origin) => (Some(origin.input), Some(origin.row), origin.raw),
+            None => (None, None, None),
+        };
+        Rejection {
+            input,
+            row,
+            client: Some(failure.client),
+            tx: Some(failure.tx),
+            kind: failure.kind,
+            reason: failure.reason,
+            seq: failure.seq,
+            raw,
+        }
+    }
+}
+
+impl From<Origin> for Rejection {
+    /// The row at `origin`, which isn't a transaction.
+    fn from(origin: Origin) -> Self {
+        Rejection {
+            input: Some(origin.input),
+            row: Some(origin.row),
+            client: None,
+            tx: None,
+            kind: FailureKind::InvalidRow,
+            reason: "Row is not a valid transaction".to_string(),
+            seq: None,
+            raw: origin.raw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectionCount {
+    pub kind: FailureKind,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RejectionDocument<'a> {
+    counts: Vec<RejectionCount>,
+    rejections: &'a [Rejection],
+}
+
+#[derive(Debug, Default)]
+pub struct RejectionReport {
+    rejections: Vec<Rejection>,
+}
+
+impl RejectionReport {
+    pub fn record(&mut self, failure: Failure) {
+        self.rejections.push(failure.into());
+    }
+
+    pub fn record_invalid_row(&mut self, origin: Origin) {
+        self.rejections.push(origin.into());
+    }
+
+    /// Rejections per failure kind, the most frequent first.
+    pub fn counts(&self) -> Vec<RejectionCount> {
+        let mut counts: HashMap<FailureKind, u64> = HashMap::new();
+        for rejection in &self.rejections {
+            *counts.entry(rejection.kind).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts
+            .into_iter()
+            .map(|(kind, count)| RejectionCount { kind, count })
+            .collect();
+        counts.sort_by_key(|count| (Reverse(count.count), format!("{:?}", count.kind)));
+        counts
+    }
+
+    /// Writes the report: the invalid rows, then the rejections in the order the manager
+    /// numbered them.
+    pub fn write(&mut self, path: &Path, format: ReportFormat) -> anyhow::Result<()> {
+        self.rejections.sort_by_key(|rejection| rejection.seq);
+        let counts = self.counts();
+        match format {
+            ReportFormat::Csv => {
+                write_csv_report(path, &self.rejections)?;
+                write_csv_report(&summary_path(path), &counts)?;
+            }
+            ReportFormat::Json => serde_json::to_writer_pretty(
+                BufWriter::new(File::create(path)?),
+                &RejectionDocument {
+                    counts,
+                    rejections: &self.rejections,
+                },
+            )?,
+        }
+        Ok(())
+    }
+}
+
+/// Where the counts of a CSV report at `path` go, e.g. `errors.summary.csv` for `errors.csv`.
+pub fn summary_path(path: &Path) -> PathBuf {
+    path.with_extension("summary.csv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejections_with_their_rows_and_counts() {
+        let failure = |tx, seq, row: Option<u64>| Failure {
+            seq: Some(seq),
+            origin: row.map(|row| {
+                Box::new(Origin {
+                    input: "day.csv".to_string(),
+                    row,
+                    raw: Some(format!("withdrawal,1,{tx},5.0")),
+                })
+            }),
+            ..Failure::insufficient_funds(Client::new(1), TransactionId::new(tx))
+        };
+        let mut report = RejectionReport::default();
+        report.record(failure(4, 4, Some(3)));
+        report.record(failure(2, 2, Some(2)));
+        report.record(Failure {
+            seq: Some(3),
+            ..Failure::new(
+                Client::new(2),
+                TransactionId::new(3),
+                FailureKind::NoWallet,
+                "No wallet".to_string(),
+            )
+        });
+        report.record_invalid_row(Origin {
+            input: "day.csv".to_string(),
+            row: 1,
+            raw: Some("deposit,x,1,5.0".to_string()),
+        });
+
+        let path = std::env::temp_dir().join(format!("rejections-{}.csv", std::process::id()));
+        report.write(&path, ReportFormat::Csv).unwrap();
+        let rows = std::fs::read_to_string(&path).unwrap();
+        let counts = std::fs::read_to_string(summary_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(summary_path(&path)).unwrap();
+
+        assert_eq!(
+            rows,
+            "input,row,client,tx,kind,reason,seq,raw\n\
+             day.csv,1,,,InvalidRow,Row is not a valid transaction,,\"deposit,x,1,5.0\"\n\
+             day.csv,2,1,2,InsufficientFunds,Insufficient funds,2,\"withdrawal,1,2,5.0\"\n\
+             ,,2,3,NoWallet,No wallet,3,\n\
+             day.csv,3,1,4,InsufficientFunds,Insufficient funds,4,\"withdrawal,1,4,5.0\"\n"
+        );
+        assert_eq!(
+            counts,
+            "kind,count\nInsufficientFunds,2\nInvalidRow,1\nNoWallet,1\n"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Hot reload of the account rules: a long-running instance polls the config file and the
+//! per-client minimum balances for changes and swaps the rebuilt configuration into every
+//! namespace, keeping the wallets and journals in memory.
+
+use crate::config::{ConfigFile, ConfigLayers};
+use crate::tenant::TenantRegistry;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Rebuilds the configuration from `path` and applies it to `registry`. Nothing changes unless
+/// every file loads.
+pub fn reload(path: &Path, layers: &ConfigLayers, registry: &TenantRegistry) -> anyhow::Result<()> {
+    let file = ConfigFile::load(path)?;
+    let config = layers.resolve(&file)?;
+    registry.reconfigure(config, file.tenants);
+    Ok(())
+}
+
+/// Modification times of `paths`, none for a file that can't be read.
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Checks the config file at `path` and the rules files of `layers` every `interval` until the
+/// process exits, reloading when any of them changed. A change that fails to load keeps the
+/// current rules.
+pub async fn watch(
+    path: PathBuf,
+    layers: ConfigLayers,
+    registry: Arc<TenantRegistry>,
+    interval: Duration,
+) {
+    let watched: Vec<PathBuf> = std::iter::once(path.clone())
+        .chain(layers.client_min_balances.clone())
+        .collect();
+    let mut seen = modified(&watched);
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let current = modified(&watched);
+        if current == seen {
+            continue;
+        }
+        seen = current;
+        match reload(&path, &layers, &registry) {
+            Ok(()) => info!("Reloaded the rules from {}", path.display()),
+            Err(e) => warn!(
+                "Keeping the current rules, {} failed to load: {e:#}",
+                path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Settings};
+    use crate::transaction::{Amount, Client, Envelope, FailureKind, Transaction, TransactionId};
+
+    #[test]
+    fn test_reload_keeps_wallets_and_applies_new_limits() {
+        let dir = std::env::temp_dir().join(format!("reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let layers = ConfigLayers {
+            base: Config::default(),
+            env: Settings::default(),
+            flags: Settings::default(),
+            score_risk: false,
+            client_min_balances: None,
+        };
+        std::fs::write(&path, "max_deposit = \"100.0\"\n").unwrap();
+        let file = ConfigFile::load(&path).unwrap();
+        let registry = TenantRegistry::new(layers.resolve(&file).unwrap(), file.tenants);
+        let deposit = |tx| {
+            Envelope::from(Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            })
+        };
+        registry.apply(deposit(1)).unwrap();
+
+        std::fs::write(&path, "max_deposit = \"5.0\"\n").unwrap();
+        reload(&path, &layers, &registry).unwrap();
+        let failure = registry.apply(deposit(2)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AmountOverLimit);
+
+        std::fs::write(&path, "max_deposit = ").unwrap();
+        assert!(reload(&path, &layers, &registry).is_err());
+        assert!(registry.apply(deposit(3)).is_err());
+
+        let wallets = registry.default_manager().export_wallets();
+        assert_eq!(wallets[0].total(), Amount::from_major(10, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
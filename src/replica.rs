@@ -0,0 +1,178 @@
+//! Read replica of the wallet balances for query serving. It follows the wallet event stream
+//! instead of reading the wallet managers' maps, so heavy query traffic never contends with the
+//! transactions being applied; in exchange its answers may trail them by a few events.
+
+use crate::events::WalletEvent;
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Amount, Client, Tenant};
+use crate::wallet::Wallet;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+
+/// A wallet as the replica last saw it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicaWallet {
+    pub client: Client,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Sequence number of the last transaction the replica has seen applied to the wallet.
+    pub seq: u64,
+}
+
+impl From<&Wallet> for ReplicaWallet {
+    fn from(wallet: &Wallet) -> Self {
+        ReplicaWallet {
+            client: wallet.client(),
+            available: wallet.available(),
+            held: wallet.held(),
+            total: wallet.total(),
+            locked: wallet.is_locked(),
+            seq: wallet.last_seq,
+        }
+    }
+}
+
+impl From<&WalletEvent> for ReplicaWallet {
+    fn from(event: &WalletEvent) -> Self {
+        ReplicaWallet {
+            client: event.client,
+            available: event.balance.available,
+            held: event.balance.held,
+            total: event.balance.total,
+            locked: event.locked,
+            seq: event.seq,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReadReplica {
+    wallets: RwLock<HashMap<(Option<Tenant>, Client), ReplicaWallet>>,
+}
+
+impl ReadReplica {
+    pub fn wallet(&self, tenant: Option<&Tenant>, client: Client) -> Option<ReplicaWallet> {
+        self.wallets
+            .read()
+            .expect("replica lock poisoned")
+            .get(&(tenant.cloned(), client))
+            .cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.wallets.read().expect("replica lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keeps whichever of `wallet` and the replica's copy is newer.
+    fn update(&self, tenant: Option<Tenant>, wallet: ReplicaWallet) {
+        let mut wallets = self.wallets.write().expect("replica lock poisoned");
+        let copy = wallets
+            .entry((tenant, wallet.client))
+            .or_insert(wallet.clone());
+        if wallet.seq > copy.seq {
+            *copy = wallet;
+        }
+    }
+
+    /// Copies every wallet of `registry`, after events were lost.
+    fn resync(&self, registry: &TenantRegistry) {
+        let namespaces = std::iter::once((None, registry.default_manager())).chain(
+            registry
+                .tenant_managers()
+                .into_iter()
+                .map(|(tenant, manager)| (Some(tenant), manager)),
+        );
+        for (tenant, manager) in namespaces {
+            for wallet in manager.export_wallets() {
+                self.update(tenant.clone(), ReplicaWallet::from(&wallet));
+            }
+        }
+    }
+
+    /// Applies the wallet events of `updates`, subscribed to the events `registry` publishes,
+    /// until every publisher is gone. Falling too far behind loses events; the replica then
+    /// copies the registry's wallets once to catch up.
+    pub async fn follow(
+        self: Arc<Self>,
+        mut updates: Receiver<WalletEvent>,
+        registry: Arc<TenantRegistry>,
+    ) {
+        loop {
+            match updates.recv().await {
+                Ok(event) => self.update(event.tenant.clone(), ReplicaWallet::from(&event)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Read replica missed {missed} wallet events, copying every wallet");
+                    self.resync(&registry);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::events::EventHub;
+    use crate::transaction::{Transaction, TransactionId};
+
+    #[tokio::test]
+    async fn test_replica_follows_events_and_resyncs_after_lag() {
+        let events = EventHub::new(4);
+        let updates = events.subscribe();
+        let registry =
+            Arc::new(TenantRegistry::new(Config::default(), HashMap::new()).with_events(events));
+        let replica = Arc::new(ReadReplica::default());
+        let follower = tokio::spawn(replica.clone().follow(updates, registry.clone()));
+
+        let deposit = |client, tx| {
+            Transaction::Deposit {
+                client: Client::new(client),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(1, 0),
+            }
+            .into()
+        };
+        // More events than the hub holds arrive before the replica gets to run.
+        for tx in 1..=10 {
+            registry.apply(deposit(tx as u16 % 3, tx)).unwrap();
+        }
+        let caught_up = |replica: &ReadReplica| {
+            replica.wallet(None, Client::new(1)).map(|w| w.total) == Some(Amount::from_major(4, 0))
+        };
+        for _ in 0..100 {
+            if caught_up(&replica) {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert!(caught_up(&replica));
+        assert_eq!(replica.len(), 3);
+
+        registry.apply(deposit(2, 11)).unwrap();
+        for _ in 0..100 {
+            if replica.wallet(None, Client::new(2)).map(|w| w.seq) == Some(11) {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        let wallet = replica.wallet(None, Client::new(2)).unwrap();
+        assert_eq!((wallet.total, wallet.seq), (Amount::from_major(4, 0), 11));
+        assert_eq!(
+            replica.wallet(Some(&Tenant::new("acme")), Client::new(2)),
+            None
+        );
+        follower.abort();
+    }
+}
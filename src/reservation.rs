@@ -0,0 +1,29 @@
+//! Funds set aside for a pending operation of an external payment flow, e.g. a card payment
+//! between its authorization and its settlement. A reservation holds the funds like a dispute
+//! does, but outside the transaction journal: committing it withdraws them, releasing it makes
+//! them available again.
+
+use crate::transaction::{Amount, Client, TransactionId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ReservationId(u64);
+
+impl ReservationId {
+    pub fn new(id: u64) -> Self {
+        ReservationId(id)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Funds held in the wallet of `client` until the reservation is committed or released.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reservation {
+    pub client: Client,
+    /// The withdrawal the reservation settles as once committed.
+    pub tx_id: TransactionId,
+    pub amount: Amount,
+}
@@ -0,0 +1,380 @@
+//! REST endpoint for batch uploads to a running server. `POST /batches` takes a CSV file as a
+//! multipart upload and answers at once with the id of the batch, which is applied in the
+//! background; `GET /batches/{id}` reports its status and, once it finished, its summary and
+//! failures. Only the most recently finished batches are kept; older ones answer 404.
+
+use crate::input::CsvOptions;
+use crate::tenant::TenantRegistry;
+use crate::trailer::ControlTotals;
+use crate::transaction::{Envelope, Failure};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dashmap::DashMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BatchId(u64);
+
+impl BatchId {
+    pub fn new(id: u64) -> Self {
+        BatchId(id)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Queued,
+    Processing,
+    Done,
+    /// Stopped part way: the upload isn't valid CSV, its trailer doesn't match or the failure
+    /// policy aborted the run. Rows before the error stay applied.
+    Failed,
+}
+
+/// Counters of a finished batch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchSummary {
+    pub rows_read: u64,
+    pub rows_skipped: u64,
+    pub applied: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Batch {
+    pub id: BatchId,
+    pub status: BatchStatus,
+    /// Name the upload gave its file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<BatchSummary>,
+    /// Transactions of the batch the wallets rejected, in input order.
+    pub failures: Vec<Failure>,
+    /// Why a failed batch stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The batches uploaded since the server started, less the finished ones beyond the `retained`
+/// most recent.
+#[derive(Debug)]
+pub struct Batches {
+    next: AtomicU64,
+    batches: DashMap<BatchId, Batch>,
+    /// Finished batches, the oldest first.
+    finished: Mutex<VecDeque<BatchId>>,
+    retained: usize,
+}
+
+impl Batches {
+    pub fn new(retained: usize) -> Self {
+        Batches {
+            next: AtomicU64::new(0),
+            batches: DashMap::new(),
+            finished: Mutex::new(VecDeque::new()),
+            retained,
+        }
+    }
+
+    fn create(&self, file_name: Option<String>) -> Batch {
+        let batch = Batch {
+            id: BatchId::new(self.next.fetch_add(1, Ordering::Relaxed) + 1),
+            status: BatchStatus::Queued,
+            file_name,
+            summary: None,
+            failures: Vec::new(),
+            error: None,
+        };
+        self.batches.insert(batch.id, batch.clone());
+        batch
+    }
+
+    pub fn get(&self, id: BatchId) -> Option<Batch> {
+        self.batches.get(&id).map(|batch| batch.clone())
+    }
+
+    fn update(&self, id: BatchId, update: impl FnOnce(&mut Batch)) {
+        if let Some(mut batch) = self.batches.get_mut(&id) {
+            update(&mut batch);
+        }
+    }
+
+    /// Updates batch `id` for the last time, dropping the oldest finished batches beyond the
+    /// retained ones.
+    fn finish(&self, id: BatchId, update: impl FnOnce(&mut Batch)) {
+        self.update(id, update);
+        let mut finished = self.finished.lock().expect("batch list lock poisoned");
+        finished.push_back(id);
+        while finished.len() > self.retained {
+            if let Some(evicted) = finished.pop_front() {
+                self.batches.remove(&evicted);
+            }
+        }
+    }
+}
+
+struct BatchService {
+    registry: Arc<TenantRegistry>,
+    csv_options: CsvOptions,
+    batches: Batches,
+}
+
+impl BatchService {
+    /// Applies the rows of batch `id` to the registry, the failures going to the batch instead
+    /// of the failure sink.
+    fn process(&self, id: BatchId, upload: Bytes) {
+        self.batches
+            .update(id, |batch| batch.status = BatchStatus::Processing);
+        let source = PathBuf::from(format!("batch {}", id.id()));
+        let mut summary = BatchSummary::default();
+        let mut failures = Vec::new();
+        let result = (|| {
+            let (mut csv_reader, columns) =
+                self.csv_options
+                    .reader(Box::new(Cursor::new(upload)), &source, false)?;
+            let mut totals = ControlTotals::default();
+            for csv_row in csv_reader.records() {
+                let csv_row = csv_row?;
+                summary.rows_read += 1;
+                if totals.record(&csv_row, &columns) {
+                    continue;
+                }
+                let Some(envelope) = Envelope::from_csv_row(&csv_row, &columns) else {
+                    summary.rows_skipped += 1;
+                    continue;
+                };
+                match self.registry.apply(envelope) {
+                    Ok(()) => summary.applied += 1,
+                    Err(failure) => {
+                        failures.push(failure);
+                        anyhow::ensure!(
+                            !self.registry.aborted(),
+                            "aborted: too many failed transactions"
+                        );
+                    }
+                }
+            }
+            self.csv_options
+                .trailer_mismatch
+                .enforce(&source, totals.verify())
+        })();
+        summary.failed = failures.len() as u64;
+        info!(
+            "Batch {} applied {} of {} rows",
+            id.id(),
+            summary.applied,
+            summary.rows_read
+        );
+        self.batches.finish(id, |batch| {
+            batch.status = match &result {
+                Ok(()) => BatchStatus::Done,
+                Err(_) => BatchStatus::Failed,
+            };
+            batch.summary = Some(summary);
+            batch.failures = failures;
+            batch.error = result.err().map(|e| format!("{e:#}"));
+        });
+    }
+}
+
+async fn create_batch(
+    State(service): State<Arc<BatchService>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let rejected = |e: axum::extract::multipart::MultipartError| (e.status(), e.body_text());
+    let field = multipart.next_field().await.map_err(rejected)?.ok_or((
+        StatusCode::BAD_REQUEST,
+        "expected the CSV file as a multipart field".to_string(),
+    ))?;
+    let file_name = field.file_name().map(str::to_string);
+    let upload = field.bytes().await.map_err(rejected)?;
+    let batch = service.batches.create(file_name);
+    let id = batch.id;
+    tokio::task::spawn_blocking(move || service.process(id, upload));
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/batches/{}", id.id()))],
+        Json(batch),
+    ))
+}
+
+async fn batch_status(
+    State(service): State<Arc<BatchService>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Batch>, StatusCode> {
+    service
+        .batches
+        .get(BatchId::new(id))
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// The batch endpoints, accepting uploads of up to `upload_limit` bytes and keeping the
+/// `retained` most recently finished batches.
+pub fn router(
+    registry: Arc<TenantRegistry>,
+    csv_options: CsvOptions,
+    upload_limit: usize,
+    retained: usize,
+) -> Router {
+    let service = Arc::new(BatchService {
+        registry,
+        csv_options,
+        batches: Batches::new(retained),
+    });
+    Router::new()
+        .route("/batches", post(create_batch))
+        .route("/batches/{id}", get(batch_status))
+        .layer(DefaultBodyLimit::max(upload_limit))
+        .with_state(service)
+}
+
+/// Serves the batch endpoints on `listener` until the process exits.
+pub async fn serve(
+    listener: TcpListener,
+    registry: Arc<TenantRegistry>,
+    csv_options: CsvOptions,
+    upload_limit: usize,
+    retained: usize,
+) -> anyhow::Result<()> {
+    axum::serve(
+        listener,
+        router(registry, csv_options, upload_limit, retained),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, TransactionLimits};
+    use crate::locale::AmountLocale;
+    use crate::schema::Schema;
+    use crate::timeformat::TimestampFormat;
+    use crate::trailer::TrailerMismatch;
+    use crate::transaction::Amount;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Sends one HTTP/1.1 request and returns the status code and body of the response.
+    async fn request(addr: SocketAddr, head: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "{head}\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batches_are_applied_in_the_background() {
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let csv_options = CsvOptions {
+            amount_locale: AmountLocale::default(),
+            lenient_amounts: false,
+            timestamp_format: TimestampFormat::default(),
+            trailer_mismatch: TrailerMismatch::Fail,
+            schema: Schema::default(),
+            currency: None,
+            watermark: None,
+            limits: TransactionLimits::default(),
+        };
+        tokio::spawn(serve(listener, registry.clone(), csv_options, 1 << 20, 10));
+
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,2.0\n\
+                   withdrawal,1,2,5.0\n\
+                   not,a,transaction,row\n\
+                   withdrawal,1,3,0.5\n";
+        let body = format!(
+            "--boundary\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"day.csv\"\r\n\
+             Content-Type: text/csv\r\n\r\n\
+             {csv}\r\n\
+             --boundary--\r\n"
+        );
+        let (status, created) = request(
+            addr,
+            "POST /batches HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=boundary",
+            body.as_bytes(),
+        )
+        .await;
+        assert_eq!(status, 202, "{created}");
+        let created: serde_json::Value = serde_json::from_str(&created).unwrap();
+        assert_eq!(created["file_name"], "day.csv");
+        let id = created["id"].as_u64().unwrap();
+
+        let batch = loop {
+            let (status, batch) = request(addr, &format!("GET /batches/{id} HTTP/1.1"), b"").await;
+            assert_eq!(status, 200);
+            let batch: serde_json::Value = serde_json::from_str(&batch).unwrap();
+            if batch["status"] == "done" {
+                break batch;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(batch["status"], "done", "{batch}");
+        assert_eq!(batch["summary"]["rows_read"], 4);
+        assert_eq!(batch["summary"]["rows_skipped"], 1);
+        assert_eq!(batch["summary"]["applied"], 2);
+        assert_eq!(batch["failures"][0]["tx"], 2);
+        let wallets = registry.default_manager().export_wallets();
+        assert_eq!(wallets[0].balance.available, Amount::from_major(1, 5000));
+
+        let (status, _) = request(addr, "GET /batches/99 HTTP/1.1", b"").await;
+        assert_eq!(status, 404);
+        let (status, _) = request(
+            addr,
+            "POST /batches HTTP/1.1\r\nContent-Type: text/csv",
+            csv.as_bytes(),
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn test_only_the_most_recently_finished_batches_are_kept() {
+        let batches = Batches::new(2);
+        let ids: Vec<_> = (0..4).map(|_| batches.create(None).id).collect();
+        for &id in &ids[1..] {
+            batches.finish(id, |batch| batch.status = BatchStatus::Done);
+        }
+        // The first batch is still queued, so it stays.
+        assert!(batches.get(ids[0]).is_some());
+        assert!(batches.get(ids[1]).is_none());
+        assert!(batches.get(ids[2]).is_some());
+        assert!(batches.get(ids[3]).is_some());
+
+        batches.finish(ids[0], |batch| batch.status = BatchStatus::Failed);
+        assert!(batches.get(ids[2]).is_none());
+        assert_eq!(batches.get(ids[0]).unwrap().status, BatchStatus::Failed);
+    }
+}
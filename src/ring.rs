@@ -0,0 +1,258 @@
+//! Wallets spread over a changing set of shards by consistent hashing: every shard owns the
+//! arcs of a hash ring ending at its virtual nodes, so adding or removing a shard only moves the
+//! clients on the arcs that change hands, about one in N, instead of reshuffling them all.
+//! Moving clients hand their wallet and journal slice over from one shard's manager to the
+//! other's.
+
+use crate::config::Config;
+use crate::transaction::{Client, Envelope, Failure};
+use crate::wallet::Wallet;
+use crate::wallet_manager::WalletManager;
+use std::collections::BTreeMap;
+use std::io;
+
+pub type ShardId = usize;
+
+/// Virtual nodes per shard; more even out the arcs at the cost of a bigger ring.
+const VNODES: u64 = 64;
+
+/// FNV-1a with a final mix, stable across runs and platforms unlike the std hasher.
+fn stable_hash(value: u64) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in value.to_le_bytes() {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^ (hash >> 33)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRing {
+    points: BTreeMap<u64, ShardId>,
+}
+
+impl HashRing {
+    pub fn new(shards: impl IntoIterator<Item = ShardId>) -> Self {
+        let mut ring = HashRing::default();
+        for shard in shards {
+            ring.add(shard);
+        }
+        ring
+    }
+
+    pub fn add(&mut self, shard: ShardId) {
+        for vnode in 0..VNODES {
+            // Offset above every client id, so that no virtual node lands on a client's point.
+            let point = stable_hash((shard as u64 + 1) << 32 | vnode);
+            self.points.insert(point, shard);
+        }
+    }
+
+    pub fn remove(&mut self, shard: ShardId) {
+        self.points.retain(|_, owner| *owner != shard);
+    }
+
+    /// The shard owning `client`, the first virtual node at or after its hash; `None` for an
+    /// empty ring.
+    pub fn shard_of(&self, client: Client) -> Option<ShardId> {
+        let point = stable_hash(u64::from(client.id()));
+        self.points
+            .range(point..)
+            .chain(&self.points)
+            .next()
+            .map(|(_, shard)| *shard)
+    }
+}
+
+/// One wallet manager per shard of a `HashRing`, all with the same configuration.
+pub struct Cluster {
+    config: Config,
+    ring: HashRing,
+    shards: BTreeMap<ShardId, WalletManager>,
+}
+
+impl Cluster {
+    pub fn new(config: Config, shards: usize) -> Self {
+        Cluster {
+            ring: HashRing::new(0..shards),
+            shards: (0..shards)
+                .map(|shard| (shard, WalletManager::with_config(config.clone())))
+                .collect(),
+            config,
+        }
+    }
+
+    pub fn shard_ids(&self) -> Vec<ShardId> {
+        self.shards.keys().copied().collect()
+    }
+
+    pub fn shard(&self, shard: ShardId) -> Option<&WalletManager> {
+        self.shards.get(&shard)
+    }
+
+    /// The shard the wallet `client` transacts on lives on; joint owners share their wallet's.
+    pub fn shard_of(&self, client: Client) -> Option<ShardId> {
+        self.ring.shard_of(self.config.wallet_of(client))
+    }
+
+    /// Applies `envelope` on the shard owning its client.
+    ///
+    /// # Panics
+    ///
+    /// If the cluster has no shards left.
+    pub fn apply(&self, envelope: Envelope) -> Result<(), Failure> {
+        let shard = self
+            .shard_of(envelope.transaction.client())
+            .expect("cluster has no shards");
+        self.shards[&shard].apply(envelope)
+    }
+
+    /// Adds a shard, taking over the clients on its arcs; returns its id and how many wallets
+    /// moved.
+    pub fn add_shard(&mut self) -> io::Result<(ShardId, usize)> {
+        let shard = self.shards.keys().next_back().map_or(0, |last| last + 1);
+        self.shards
+            .insert(shard, WalletManager::with_config(self.config.clone()));
+        let mut ring = self.ring.clone();
+        ring.add(shard);
+        Ok((shard, self.rebalance(ring)?))
+    }
+
+    /// Removes a shard, handing its clients over to the shards that own their arcs now; returns
+    /// how many wallets moved, `None` for an unknown or the last shard.
+    pub fn remove_shard(&mut self, shard: ShardId) -> io::Result<Option<usize>> {
+        if !self.shards.contains_key(&shard) || self.shards.len() == 1 {
+            return Ok(None);
+        }
+        let mut ring = self.ring.clone();
+        ring.remove(shard);
+        let moved = self.rebalance(ring)?;
+        self.shards.remove(&shard);
+        Ok(Some(moved))
+    }
+
+    /// Switches to `ring`, handing every wallet whose shard changes over to its new shard. No
+    /// transaction may be applied meanwhile, which `&mut self` ensures.
+    ///
+    /// A journal failing midway leaves the ring unchanged, with the wallets handed over so far
+    /// on their new shard already: the failing manager is `WalletManager::aborted` and the
+    /// cluster must not be used any more.
+    fn rebalance(&mut self, ring: HashRing) -> io::Result<usize> {
+        let mut moves = Vec::new();
+        for (&shard, manager) in &self.shards {
+            for wallet in manager.export_wallets() {
+                let client = wallet.client();
+                match ring.shard_of(client) {
+                    Some(owner) if owner != shard => moves.push((client, shard, owner)),
+                    _ => {}
+                }
+            }
+        }
+        for &(client, from, to) in &moves {
+            if let Some(handoff) = self.shards[&from].hand_off(client)? {
+                self.shards[&to].take_over(handoff)?;
+            }
+        }
+        self.ring = ring;
+        Ok(moves.len())
+    }
+
+    /// The wallets of every shard.
+    pub fn export_wallets(&self) -> Vec<Wallet> {
+        self.shards
+            .values()
+            .flat_map(WalletManager::export_wallets)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Transaction, TransactionId};
+
+    #[test]
+    fn test_adding_a_shard_moves_only_its_arcs() {
+        let clients: Vec<_> = (0..=u16::MAX).step_by(7).map(Client::new).collect();
+        let ring = HashRing::new(0..4);
+        let mut grown = ring.clone();
+        grown.add(4);
+
+        let mut per_shard = [0; 5];
+        let mut moved = 0;
+        for &client in &clients {
+            let (before, after) = (ring.shard_of(client), grown.shard_of(client));
+            if before != after {
+                assert_eq!(after, Some(4), "{client:?} moved between old shards");
+                moved += 1;
+            }
+            per_shard[after.unwrap()] += 1;
+        }
+        // About a fifth of the clients move, and every shard gets a fair share.
+        let share = clients.len() / 5;
+        assert!(
+            moved > share / 2 && moved < share * 2,
+            "{moved} of {}",
+            clients.len()
+        );
+        assert!(per_shard.iter().all(|&n| n > share / 2), "{per_shard:?}");
+
+        grown.remove(4);
+        assert_eq!(grown, ring);
+    }
+
+    #[test]
+    fn test_wallets_keep_state_across_handoffs() {
+        let mut cluster = Cluster::new(Config::default(), 2);
+        for client in 1..=50 {
+            let tx_id = TransactionId::new(u32::from(client));
+            let client = Client::new(client);
+            cluster
+                .apply(Envelope::from(Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount: Amount::from_major(10, 0),
+                }))
+                .unwrap();
+            cluster
+                .apply(Envelope::from(Transaction::Dispute { client, tx_id }))
+                .unwrap();
+        }
+        let state = |cluster: &Cluster| {
+            let mut wallets = cluster.export_wallets();
+            wallets.sort_by_key(Wallet::client);
+            wallets
+        };
+        let before = state(&cluster);
+
+        let (shard, moved) = cluster.add_shard().unwrap();
+        assert!(moved > 0);
+        assert_eq!(state(&cluster), before);
+        assert_eq!(cluster.shard(shard).unwrap().wallet_count(), moved);
+        // The journal slice moved along, so disputes opened before the move still resolve.
+        for client in 1..=50 {
+            cluster
+                .apply(Envelope::from(Transaction::Resolve {
+                    client: Client::new(client),
+                    tx_id: TransactionId::new(u32::from(client)),
+                }))
+                .unwrap();
+        }
+        assert!(
+            cluster
+                .remove_shard(0)
+                .unwrap()
+                .is_some_and(|moved| moved > 0)
+        );
+        assert_eq!(cluster.shard_ids(), [1, 2]);
+        for shard in cluster.shard_ids() {
+            cluster.shard(shard).unwrap().verify_totals().unwrap();
+        }
+        assert!(
+            state(&cluster)
+                .iter()
+                .all(|w| w.available() == Amount::from_major(10, 0))
+        );
+    }
+}
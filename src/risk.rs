@@ -0,0 +1,312 @@
+//! Per-transaction risk scoring. A `RiskScorer` rates every transaction from a few features of the
+//! transaction and its wallet, and `RiskThresholds` turn the score into an action.
+
+use crate::enrich::Attributes;
+use crate::transaction::{Amount, Client, Timestamp};
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Window in which earlier transactions count towards `RiskFeatures::velocity`.
+pub const VELOCITY_WINDOW_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskFeatures {
+    /// Amount of a deposit or withdrawal, zero for disputes and their outcomes.
+    pub amount: Amount,
+    /// Transactions of the wallet in the `VELOCITY_WINDOW_SECS` before this one, by input
+    /// timestamp. Transactions without a timestamp and failed ones don't count.
+    pub velocity: usize,
+    /// Disputes raised on the wallet so far.
+    pub disputes: u64,
+    /// Deposits of the wallet in the hour before this transaction, by input timestamp.
+    pub deposits_last_hour: Amount,
+    /// Deposits of the wallet in the day before this transaction, by input timestamp.
+    pub deposits_last_day: Amount,
+    /// Attributes of the transaction, including those added by enrichment.
+    pub attributes: Attributes,
+}
+
+/// Rates a transaction; the higher the score, the riskier.
+pub trait RiskScorer: fmt::Debug + Send + Sync {
+    fn score(&self, features: &RiskFeatures) -> f32;
+}
+
+/// Adds up the features, each multiplied by its weight.
+#[derive(Debug, Clone)]
+pub struct WeightedScorer {
+    pub per_unit_amount: f32,
+    pub per_recent_transaction: f32,
+    pub per_dispute: f32,
+    /// Added for every attribute the transaction has, keyed `name=value`, e.g.
+    /// `category=gambling`.
+    pub per_attribute: HashMap<String, f32>,
+}
+
+impl Default for WeightedScorer {
+    /// A deposit of 1000 scores 1, as do ten transactions within the hour or two disputes.
+    fn default() -> Self {
+        WeightedScorer {
+            per_unit_amount: 0.001,
+            per_recent_transaction: 0.1,
+            per_dispute: 0.5,
+            per_attribute: HashMap::new(),
+        }
+    }
+}
+
+impl RiskScorer for WeightedScorer {
+    fn score(&self, features: &RiskFeatures) -> f32 {
+        features.amount.as_f32() * self.per_unit_amount
+            + features.velocity as f32 * self.per_recent_transaction
+            + features.disputes as f32 * self.per_dispute
+            + features
+                .attributes
+                .iter()
+                .filter_map(|(name, value)| self.per_attribute.get(&format!("{name}={value}")))
+                .sum::<f32>()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskAction {
+    /// Apply the transaction and report the client in the risk report.
+    Flag,
+    /// Apply the transaction, then freeze the wallet until an admin unfreezes it.
+    Hold,
+    /// Fail the transaction.
+    Reject,
+}
+
+/// Scores from which each action is taken; the most severe action reached wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RiskThresholds {
+    pub flag: Option<f32>,
+    pub hold: Option<f32>,
+    pub reject: Option<f32>,
+}
+
+impl RiskThresholds {
+    pub fn action(&self, score: f32) -> Option<RiskAction> {
+        let reached = |threshold: Option<f32>| threshold.is_some_and(|t| score >= t);
+        if reached(self.reject) {
+            Some(RiskAction::Reject)
+        } else if reached(self.hold) {
+            Some(RiskAction::Hold)
+        } else if reached(self.flag) {
+            Some(RiskAction::Flag)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.flag.is_some() || self.hold.is_some() || self.reject.is_some()
+    }
+}
+
+/// Freezes wallets whose chargebacks outnumber their deposits by more than `max_ratio` within
+/// the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargebackPolicy {
+    /// Chargebacks per deposit above which the wallet is frozen; nothing is monitored when unset.
+    pub max_ratio: Option<f32>,
+    /// How many of the wallet's latest deposits and chargebacks the ratio is taken over.
+    pub window: usize,
+    /// Chargebacks the window needs before the ratio counts, so a single early chargeback
+    /// doesn't freeze a new wallet.
+    pub min_chargebacks: usize,
+}
+
+impl Default for ChargebackPolicy {
+    fn default() -> Self {
+        ChargebackPolicy {
+            max_ratio: None,
+            window: 100,
+            min_chargebacks: 2,
+        }
+    }
+}
+
+/// A freeze decided by risk monitoring, as journaled in the risk journal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskDecision {
+    /// Sequence number of the transaction that triggered the decision.
+    pub seq: u64,
+    pub client: Client,
+    pub reason: String,
+}
+
+/// Scoring and chargeback history of one wallet.
+#[derive(Debug, Clone, Default)]
+pub struct ClientRisk {
+    recent: VecDeque<Timestamp>,
+    /// The latest deposits and chargebacks, `true` for chargebacks.
+    funding: VecDeque<bool>,
+    chargebacks: u64,
+    auto_frozen_at: Option<u64>,
+    scored: u64,
+    last_score: f32,
+    max_score: f32,
+    flagged: u64,
+    held: u64,
+    rejected: u64,
+}
+
+impl ClientRisk {
+    /// Counts the transactions within the window before `timestamp`.
+    pub fn velocity(&mut self, timestamp: Option<Timestamp>) -> usize {
+        if let Some(timestamp) = timestamp {
+            while self
+                .recent
+                .front()
+                .is_some_and(|t| timestamp.as_secs() - t.as_secs() > VELOCITY_WINDOW_SECS)
+            {
+                self.recent.pop_front();
+            }
+        }
+        self.recent.len()
+    }
+
+    /// Remembers a transaction that was applied at `timestamp`, for the velocity of later ones.
+    pub fn record_applied(&mut self, timestamp: Timestamp) {
+        self.recent.push_back(timestamp);
+    }
+
+    pub fn record(&mut self, score: f32, action: Option<RiskAction>) {
+        self.scored += 1;
+        self.last_score = score;
+        self.max_score = self.max_score.max(score);
+        match action {
+            Some(RiskAction::Flag) => self.flagged += 1,
+            Some(RiskAction::Hold) => self.held += 1,
+            Some(RiskAction::Reject) => self.rejected += 1,
+            None => {}
+        }
+    }
+
+    /// Records a deposit or a chargeback, returning the chargeback ratio if this chargeback
+    /// pushes it over the policy limit.
+    pub fn record_funding(&mut self, chargeback: bool, policy: &ChargebackPolicy) -> Option<f32> {
+        let max_ratio = policy.max_ratio?;
+        while self.funding.len() >= policy.window.max(1) {
+            self.funding.pop_front();
+        }
+        self.funding.push_back(chargeback);
+        if chargeback {
+            self.chargebacks += 1;
+        }
+        let ratio = self.chargeback_ratio();
+        let chargebacks = self.funding.iter().filter(|c| **c).count();
+        (chargeback && chargebacks >= policy.min_chargebacks && ratio > max_ratio).then_some(ratio)
+    }
+
+    /// Chargebacks per deposit within the window.
+    pub fn chargeback_ratio(&self) -> f32 {
+        let chargebacks = self.funding.iter().filter(|c| **c).count();
+        let deposits = self.funding.len() - chargebacks;
+        chargebacks as f32 / deposits.max(1) as f32
+    }
+
+    pub fn auto_freeze(&mut self, seq: u64) {
+        self.auto_frozen_at = Some(seq);
+    }
+
+    pub fn report(&self, client: Client) -> RiskScore {
+        RiskScore {
+            client,
+            scored: self.scored,
+            last_score: self.last_score,
+            max_score: self.max_score,
+            flagged: self.flagged,
+            held: self.held,
+            rejected: self.rejected,
+            chargebacks: self.chargebacks,
+            chargeback_ratio: self.chargeback_ratio(),
+            auto_frozen_at: self.auto_frozen_at,
+        }
+    }
+}
+
+/// One row of the risk report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskScore {
+    pub client: Client,
+    pub scored: u64,
+    #[serde(serialize_with = "four_decimals")]
+    pub last_score: f32,
+    #[serde(serialize_with = "four_decimals")]
+    pub max_score: f32,
+    pub flagged: u64,
+    pub held: u64,
+    pub rejected: u64,
+    pub chargebacks: u64,
+    #[serde(serialize_with = "four_decimals")]
+    pub chargeback_ratio: f32,
+    /// Sequence number of the chargeback after which the wallet was frozen automatically.
+    pub auto_frozen_at: Option<u64>,
+}
+
+/// Scores with the precision amounts are written with, rather than every float digit.
+fn four_decimals<S: Serializer>(score: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{score:.4}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_severe_threshold_wins() {
+        let thresholds = RiskThresholds {
+            flag: Some(1.0),
+            hold: Some(2.0),
+            reject: Some(3.0),
+        };
+        assert_eq!(thresholds.action(0.5), None);
+        assert_eq!(thresholds.action(1.0), Some(RiskAction::Flag));
+        assert_eq!(thresholds.action(2.5), Some(RiskAction::Hold));
+        assert_eq!(thresholds.action(9.0), Some(RiskAction::Reject));
+        let flag_only = RiskThresholds {
+            flag: Some(1.0),
+            ..RiskThresholds::default()
+        };
+        assert_eq!(flag_only.action(9.0), Some(RiskAction::Flag));
+    }
+
+    #[test]
+    fn test_velocity_counts_transactions_within_window() {
+        let mut risk = ClientRisk::default();
+        let mut velocity = |secs| {
+            let velocity = risk.velocity(Some(Timestamp::from_secs(secs)));
+            risk.record_applied(Timestamp::from_secs(secs));
+            velocity
+        };
+        assert_eq!(velocity(0), 0);
+        assert_eq!(velocity(600), 1);
+        assert_eq!(velocity(3600), 2);
+        assert_eq!(velocity(4000), 2);
+        assert_eq!(risk.velocity(None), 3);
+    }
+
+    #[test]
+    fn test_chargeback_ratio_over_window() {
+        let policy = ChargebackPolicy {
+            max_ratio: Some(0.5),
+            window: 4,
+            min_chargebacks: 2,
+        };
+        let mut risk = ClientRisk::default();
+        for _ in 0..3 {
+            assert_eq!(risk.record_funding(false, &policy), None);
+        }
+        assert_eq!(risk.record_funding(true, &policy), None);
+        // The window now holds two deposits and two chargebacks.
+        assert_eq!(risk.record_funding(true, &policy), Some(1.0));
+        assert_eq!(risk.report(Client::new(1)).chargebacks, 2);
+        assert_eq!(
+            ClientRisk::default().record_funding(true, &ChargebackPolicy::default()),
+            None
+        );
+    }
+}
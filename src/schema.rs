@@ -0,0 +1,99 @@
+//! Versions of the CSV input layout. A file names its version in a `#version: <n>` line before
+//! the header row; files without one are read in the version passed with `--schema`, so older
+//! files keep working as the layout evolves. Every version is converted to the same `Columns`.
+
+use crate::transaction::Columns;
+use clap::ValueEnum;
+use csv::StringRecord;
+use std::io::{self, BufRead};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Schema {
+    /// `type,client,tx,amount` by position, followed by any of the optional `timestamp`,
+    /// `tenant` and `seq` columns
+    #[default]
+    V1,
+    /// Every column by its header name in any order: `type`, `client`, `tx`, `amount` and
+    /// `currency` are required, `timestamp`, `tenant` and `seq` optional
+    V2,
+}
+
+impl Schema {
+    fn from_version(version: &str) -> Option<Schema> {
+        match version.trim().trim_start_matches(['v', 'V']) {
+            "1" => Some(Schema::V1),
+            "2" => Some(Schema::V2),
+            _ => None,
+        }
+    }
+
+    /// Resolves the columns of a file in this version from its header row.
+    pub fn columns(self, headers: &StringRecord) -> Result<Columns, String> {
+        match self {
+            Schema::V1 => Ok(Columns::from_headers(headers)),
+            Schema::V2 => {
+                let required = |name: &str| {
+                    headers
+                        .iter()
+                        .position(|h| h == name)
+                        .ok_or_else(|| format!("schema v2 requires a `{name}` column"))
+                };
+                Ok(Columns {
+                    transaction_type: required("type")?,
+                    client: required("client")?,
+                    tx: required("tx")?,
+                    amount: required("amount")?,
+                    currency: Some(required("currency")?),
+                    ..Columns::from_headers(headers)
+                }
+                .with_attributes(headers))
+            }
+        }
+    }
+}
+
+/// Reads the `#version:` line at the start of `input`, if there is one, leaving `input` at the
+/// header row.
+pub fn read_version(input: &mut impl BufRead) -> io::Result<Option<Schema>> {
+    const PREFIX: &[u8] = b"#version:";
+    if !input.fill_buf()?.starts_with(PREFIX) {
+        return Ok(None);
+    }
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let version = &line[PREFIX.len()..];
+    Schema::from_version(version).map(Some).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported input schema version {:?}", version.trim()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_line_selects_layout() {
+        let mut input = "#version: 2\ncurrency,amount,tx,client,type\n".as_bytes();
+        let schema = read_version(&mut input).unwrap().unwrap();
+        assert_eq!(schema, Schema::V2);
+        let headers = StringRecord::from(vec!["currency", "amount", "tx", "client", "type"]);
+        let columns = schema.columns(&headers).unwrap();
+        assert_eq!(
+            (columns.transaction_type, columns.amount, columns.currency),
+            (4, 1, Some(0))
+        );
+        assert!(
+            Schema::V2
+                .columns(&headers.iter().skip(1).collect())
+                .is_err()
+        );
+
+        let mut input = "type,client,tx,amount\n".as_bytes();
+        assert_eq!(read_version(&mut input).unwrap(), None);
+        assert_eq!(input, b"type,client,tx,amount\n");
+        assert!(read_version(&mut "#version: 3\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Client segments, e.g. VIP, retail or test clients, read from a CSV file with a header row
+//! mapping the client in its first column to the segment in its second. Reports join their
+//! wallets against the segments to break their figures down by segment; clients the file
+//! doesn't list are `unassigned`.
+
+use crate::transaction::{Amount, Client};
+use crate::wallet::Wallet;
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Segment of the clients without one.
+pub const UNASSIGNED: &str = "unassigned";
+
+#[derive(Debug, Clone, Default)]
+pub struct Segments(HashMap<Client, String>);
+
+impl Segments {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let mut segments = HashMap::new();
+        for row in csv_reader.records() {
+            let row = row?;
+            let (Some(client), Some(segment)) = (row.get(0), row.get(1)) else {
+                anyhow::bail!("{}: expected a client and a segment", path.display());
+            };
+            let client = client
+                .parse()
+                .with_context(|| format!("{}: invalid client {client}", path.display()))?;
+            segments.insert(Client::new(client), segment.to_string());
+        }
+        Ok(Segments(segments))
+    }
+
+    pub fn segment(&self, client: Client) -> &str {
+        self.0.get(&client).map_or(UNASSIGNED, String::as_str)
+    }
+
+    /// `wallets` grouped by segment, ordered by name.
+    pub fn group<'a>(&self, wallets: &'a [Wallet]) -> BTreeMap<&str, Vec<&'a Wallet>> {
+        let mut groups: BTreeMap<&str, Vec<&Wallet>> = BTreeMap::new();
+        for wallet in wallets {
+            groups
+                .entry(self.segment(wallet.client()))
+                .or_default()
+                .push(wallet);
+        }
+        groups
+    }
+}
+
+/// The wallets of one segment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentSummary {
+    pub segment: String,
+    pub clients: usize,
+    pub locked: usize,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub failures: u64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+/// Totals of `wallets` per segment, ordered by segment.
+pub fn segment_summary(wallets: &[Wallet], segments: &Segments) -> Vec<SegmentSummary> {
+    segments
+        .group(wallets)
+        .into_iter()
+        .map(|(segment, wallets)| SegmentSummary {
+            segment: segment.to_string(),
+            clients: wallets.len(),
+            locked: wallets.iter().filter(|wallet| wallet.is_locked()).count(),
+            deposits: wallets.iter().map(|wallet| wallet.stats.deposits).sum(),
+            withdrawals: wallets.iter().map(|wallet| wallet.stats.withdrawals).sum(),
+            disputes: wallets.iter().map(|wallet| wallet.stats.disputes).sum(),
+            failures: wallets.iter().map(|wallet| wallet.stats.failures).sum(),
+            available: wallets.iter().map(|wallet| wallet.available()).sum(),
+            held: wallets.iter().map(|wallet| wallet.held()).sum(),
+            total: wallets.iter().map(|wallet| wallet.total()).sum(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionId;
+
+    #[test]
+    fn test_wallets_summed_per_segment() {
+        let path = std::env::temp_dir().join(format!("segments-{}.csv", std::process::id()));
+        std::fs::write(&path, "client,segment\n1,vip\n2, retail\n3,vip\n").unwrap();
+        let segments = Segments::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let wallets: Vec<_> = (1..=4)
+            .map(|client| {
+                let mut wallet = Wallet::new(Client::new(client));
+                wallet.deposit(
+                    TransactionId::new(1),
+                    Amount::from_major(u64::from(client), 0),
+                );
+                wallet.stats.deposits = 1;
+                wallet
+            })
+            .collect();
+
+        let summary = segment_summary(&wallets, &segments);
+
+        let totals: Vec<_> = summary
+            .iter()
+            .map(|row| (row.segment.as_str(), row.clients, row.deposits, row.total))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("retail", 1, 1, Amount::from_major(2, 0)),
+                (UNASSIGNED, 1, 1, Amount::from_major(4, 0)),
+                ("vip", 2, 2, Amount::from_major(4, 0)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! HTTP ingestion server of the `serve` subcommand, for services pushing transactions instead of
+//! handing over a file. Submitted transactions go through the same channel as file input:
+//!
+//! - `POST /transactions` takes one CSV row (`type,client,tx,amount[,timestamp[,tenant]]`) or
+//!   JSON object per line, as `--listen` does
+//! - `GET /wallets/{client}` answers with the balance of a client's wallet
+//! - `GET /disputes` lists the open disputes, of one client with `?client=`
+//! - `GET /failures` streams failed transactions as server-sent events from then on
+//!
+//! A `tenant` query parameter selects another namespace than the default one. The batch
+//! endpoints of `rest` are served alongside.
+
+use crate::input::ReadSummary;
+use crate::replica::ReplicaWallet;
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Amount, Client, Envelope, Failure, Tenant, Timestamp, TransactionId};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+
+/// What `POST /transactions` made of the lines it was sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Submitted {
+    /// Transactions queued for the wallets; whether they fail shows on `GET /failures`.
+    pub accepted: u64,
+    /// Lines that aren't transactions.
+    pub skipped: u64,
+}
+
+/// An open dispute as `GET /disputes` lists it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenDispute {
+    pub client: Client,
+    pub tx: TransactionId,
+    /// Amount held for the dispute.
+    pub amount: Amount,
+    /// Whether the disputed transaction is a withdrawal.
+    pub withdrawal: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disputed_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Namespace {
+    tenant: Option<Tenant>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DisputeFilter {
+    tenant: Option<Tenant>,
+    client: Option<u16>,
+}
+
+struct Ingestion {
+    registry: Arc<TenantRegistry>,
+    tx_sender: UnboundedSender<Envelope>,
+    failures: broadcast::Sender<Failure>,
+    stopped: watch::Receiver<bool>,
+    rows_read: AtomicU64,
+    rows_skipped: AtomicU64,
+}
+
+async fn submit(
+    State(ingestion): State<Arc<Ingestion>>,
+    body: String,
+) -> Result<(StatusCode, Json<Submitted>), (StatusCode, String)> {
+    let mut submitted = Submitted::default();
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let Some(envelope) = Envelope::from_line(line) else {
+            submitted.skipped += 1;
+            continue;
+        };
+        if ingestion.tx_sender.send(envelope).is_err() {
+            // The wallet managers stopped, e.g. aborted by the failure policy.
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "no longer taking transactions".to_string(),
+            ));
+        }
+        submitted.accepted += 1;
+    }
+    ingestion
+        .rows_read
+        .fetch_add(submitted.accepted + submitted.skipped, Ordering::Relaxed);
+    ingestion
+        .rows_skipped
+        .fetch_add(submitted.skipped, Ordering::Relaxed);
+    Ok((StatusCode::ACCEPTED, Json(submitted)))
+}
+
+async fn wallet(
+    State(ingestion): State<Arc<Ingestion>>,
+    Path(client): Path<u16>,
+    Query(namespace): Query<Namespace>,
+) -> Result<Json<ReplicaWallet>, StatusCode> {
+    let tenant = namespace.tenant;
+    let client = Client::new(client);
+    let wallet = match ingestion.registry.read_replica() {
+        Some(replica) => replica.wallet(tenant.as_ref(), client),
+        None => ingestion
+            .registry
+            .existing_manager(tenant.as_ref())
+            .and_then(|manager| manager.wallet(client))
+            .as_ref()
+            .map(ReplicaWallet::from),
+    };
+    wallet.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn disputes(
+    State(ingestion): State<Arc<Ingestion>>,
+    Query(filter): Query<DisputeFilter>,
+) -> Json<Vec<OpenDispute>> {
+    let tenant = filter.tenant;
+    let wallets = match ingestion.registry.existing_manager(tenant.as_ref()) {
+        Some(manager) => match filter.client {
+            Some(client) => manager.wallet(Client::new(client)).into_iter().collect(),
+            None => manager.export_wallets(),
+        },
+        None => Vec::new(),
+    };
+    let mut disputes: Vec<_> = wallets
+        .iter()
+        .flat_map(|wallet| {
+            wallet
+                .open_disputes()
+                .iter()
+                .map(|(&tx, &amount)| OpenDispute {
+                    client: wallet.client(),
+                    tx,
+                    amount,
+                    withdrawal: wallet.is_disputed_withdrawal(tx),
+                    disputed_at: wallet.disputed_at(tx),
+                })
+        })
+        .collect();
+    disputes.sort_unstable_by_key(|dispute| (dispute.client, dispute.tx.id()));
+    Json(disputes)
+}
+
+async fn stream_failures(
+    State(ingestion): State<Arc<Ingestion>>,
+) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let mut stopped = ingestion.stopped.clone();
+    let events = BroadcastStream::new(ingestion.failures.subscribe())
+        .map(|failure| {
+            Ok(match failure {
+                Ok(failure) => Event::default()
+                    .event("failure")
+                    .json_data(failure)
+                    .expect("failures serialize"),
+                // The subscriber has to look the missed failures up elsewhere, e.g. in the log.
+                Err(BroadcastStreamRecvError::Lagged(dropped)) => {
+                    Event::default().event("lagged").data(dropped.to_string())
+                }
+            })
+        })
+        // Ends the stream on shutdown, which waits for every open response.
+        .take_until(async move {
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Serves the ingestion endpoints, and `batches` with them, on `listener` until `shutdown`
+/// completes, then lets the open requests finish and returns the counters of the submitted
+/// lines.
+pub async fn serve(
+    listener: TcpListener,
+    registry: Arc<TenantRegistry>,
+    tx_sender: UnboundedSender<Envelope>,
+    failures: broadcast::Sender<Failure>,
+    batches: Router,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<ReadSummary> {
+    let (stop, stopped) = watch::channel(false);
+    let ingestion = Arc::new(Ingestion {
+        registry,
+        tx_sender,
+        failures,
+        stopped: stopped.clone(),
+        rows_read: AtomicU64::new(0),
+        rows_skipped: AtomicU64::new(0),
+    });
+    let app = Router::new()
+        .route("/transactions", post(submit))
+        .route("/wallets/{client}", get(wallet))
+        .route("/disputes", get(disputes))
+        .route("/failures", get(stream_failures))
+        .with_state(ingestion.clone())
+        .merge(batches);
+    let mut stopping = stopped;
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        let _ = stopping.wait_for(|stopped| *stopped).await;
+    });
+    let (served, ()) = tokio::join!(server.into_future(), async {
+        shutdown.await;
+        let _ = stop.send(true);
+    });
+    served?;
+    Ok(ReadSummary {
+        rows_read: ingestion.rows_read.load(Ordering::Relaxed),
+        rows_skipped: ingestion.rows_skipped.load(Ordering::Relaxed),
+        ..ReadSummary::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
+    /// Sends one HTTP/1.1 request, leaving the response to be read.
+    async fn send(addr: SocketAddr, head: &str, body: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{head}\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn request(addr: SocketAddr, head: &str, body: &str) -> (u16, String) {
+        let mut response = String::new();
+        send(addr, head, body)
+            .await
+            .read_to_string(&mut response)
+            .await
+            .unwrap();
+        let status = response[9..12].parse().unwrap();
+        (
+            status,
+            response.split_once("\r\n\r\n").unwrap().1.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serve_ingests_over_http() {
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, mut err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let feed = broadcast::Sender::new(16);
+        let runner = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.run(tx_receiver, err_sender).await }
+        });
+        let forwarder = tokio::spawn({
+            let feed = feed.clone();
+            async move {
+                while let Some(failure) = err_receiver.recv().await {
+                    let _ = feed.send(failure);
+                }
+            }
+        });
+        let (shutdown, shutdown_recv) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            registry.clone(),
+            tx_sender,
+            feed,
+            Router::new(),
+            async {
+                let _ = shutdown_recv.await;
+            },
+        ));
+
+        let mut failures = BufReader::new(send(addr, "GET /failures HTTP/1.1", "").await);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            failures.read_line(&mut line).await.unwrap();
+        }
+        let (status, submitted) = request(
+            addr,
+            "POST /transactions HTTP/1.1",
+            "deposit,1,1,10.0\n\
+             {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 50.0}\n\
+             not a transaction\n\
+             dispute,1,1\n",
+        )
+        .await;
+        assert_eq!(status, 202);
+        let submitted: Submitted = serde_json::from_str(&submitted).unwrap();
+        assert_eq!((submitted.accepted, submitted.skipped), (3, 1));
+
+        let event = loop {
+            line.clear();
+            failures.read_line(&mut line).await.unwrap();
+            if let Some(data) = line.strip_prefix("data: ") {
+                break serde_json::from_str::<serde_json::Value>(data).unwrap();
+            }
+        };
+        assert_eq!(
+            (event["client"].as_u64(), event["tx"].as_u64()),
+            (Some(1), Some(2))
+        );
+        while registry.default_manager().last_sequence() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        let (status, wallet) = request(addr, "GET /wallets/1 HTTP/1.1", "").await;
+        assert_eq!(status, 200);
+        let wallet: ReplicaWallet = serde_json::from_str(&wallet).unwrap();
+        assert_eq!(wallet.held, Amount::from_major(10, 0));
+        let (status, _) = request(addr, "GET /wallets/1?tenant=acme HTTP/1.1", "").await;
+        assert_eq!(status, 404);
+        let (_, disputes) = request(addr, "GET /disputes?client=1 HTTP/1.1", "").await;
+        let disputes: Vec<OpenDispute> = serde_json::from_str(&disputes).unwrap();
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].tx, TransactionId::new(1));
+        assert!(!disputes[0].withdrawal);
+
+        shutdown.send(()).unwrap();
+        let summary = server.await.unwrap().unwrap();
+        assert_eq!((summary.rows_read, summary.rows_skipped), (4, 1));
+        runner.await.unwrap();
+        forwarder.await.unwrap();
+        assert!(registry.tenant_managers().is_empty());
+    }
+}
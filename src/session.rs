@@ -0,0 +1,57 @@
+//! Named processing sessions: batches of transactions, e.g. one settlement file a day, applied
+//! to the wallets all batches share but accounted for on their own. A transaction belongs to
+//! the session its `session` attribute names, taken from an input column or stamped by
+//! `Config::session`. The manager keeps the counts and balance changes of every session, and
+//! the ledger export carries the attribute as metadata of each entry.
+
+use crate::transaction::{Amount, Client};
+use crate::wallet::Balance;
+use serde::Serialize;
+
+/// Attribute naming the session of a transaction.
+pub const SESSION_ATTRIBUTE: &str = "session";
+
+/// What the transactions of one session did to one client's wallet.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionActivity {
+    pub session: String,
+    pub client: Client,
+    pub transactions: u64,
+    pub failed: u64,
+    /// Net changes of the balance, so the sessions' changes add up to the balance.
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// Sequence numbers of the session's first and last transaction of the client.
+    pub first_seq: u64,
+    pub last_seq: u64,
+}
+
+impl SessionActivity {
+    pub fn new(session: &str, client: Client, seq: u64) -> Self {
+        SessionActivity {
+            session: session.to_string(),
+            client,
+            transactions: 0,
+            failed: 0,
+            available: Amount::zero(),
+            held: Amount::zero(),
+            total: Amount::zero(),
+            first_seq: seq,
+            last_seq: seq,
+        }
+    }
+
+    /// Counts the transaction numbered `seq` and the balance change `delta` it made.
+    pub fn record(&mut self, seq: u64, ok: bool, delta: &Balance) {
+        self.transactions += 1;
+        if !ok {
+            self.failed += 1;
+        }
+        self.available += delta.available;
+        self.held += delta.held;
+        self.total += delta.total;
+        self.first_seq = self.first_seq.min(seq);
+        self.last_seq = self.last_seq.max(seq);
+    }
+}
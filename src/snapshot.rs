@@ -0,0 +1,329 @@
+//! Checkpoint snapshots of a wallet manager: every wallet with the state the CSV export and the
+//! binary wallet stream leave out, such as its dispute history and lock reasons, and the slice
+//! of the transaction journal its disputes may still refer to. A snapshot is a `WMSN` stream in
+//! the framing of `wire`: a header record with the sequence number it was taken at and the
+//! wallet count, then one record per wallet.
+
+use crate::journal::JournalEntry;
+use crate::transaction::{Amount, Client, Timestamp, Transaction, TransactionId};
+use crate::wallet::{Balance, Wallet, WalletStats};
+use crate::wallet_manager::Handoff;
+use crate::wire::{read_header, read_record, write_header, write_record};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"WMSN";
+
+/// The state of a wallet manager as of sequence number `seq`.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub seq: u64,
+    pub wallets: Vec<Handoff>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    seq: u64,
+    wallets: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotWallet {
+    client: u16,
+    available: f32,
+    held: f32,
+    total: f32,
+    locked: bool,
+    frozen: bool,
+    quarantined: bool,
+    dormant: bool,
+    last_activity: Option<i64>,
+    last_seq: u64,
+    joint_owners: Vec<u16>,
+    lock_reasons: Vec<String>,
+    /// Deposits, withdrawals, disputes and failures.
+    stats: [u64; 4],
+    open_disputes: Vec<(u32, f32)>,
+    dispute_cycles: Vec<(u32, u32)>,
+    disputed_at: Vec<(u32, i64)>,
+    charged_back: Vec<(u32, f32)>,
+    disputed_withdrawals: Vec<u32>,
+    reversed_withdrawals: Vec<(u32, f32)>,
+    /// Journaled transactions as `(tx, amount, withdrawal, seq)`.
+    journal: Vec<(u32, f32, bool, Option<u64>)>,
+}
+
+/// The entries of `map` sorted by transaction id, so equal states encode to equal bytes.
+fn sorted<V: Copy, T>(map: &HashMap<TransactionId, V>, value: impl Fn(V) -> T) -> Vec<(u32, T)> {
+    let mut entries: Vec<_> = map.iter().map(|(tx, v)| (tx.id(), value(*v))).collect();
+    entries.sort_unstable_by_key(|(tx, _)| *tx);
+    entries
+}
+
+fn signed(value: f32) -> anyhow::Result<Amount> {
+    Amount::try_from(value.abs())
+        .map(|amount| if value < 0.0 { -amount } else { amount })
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+fn unsorted<V, T>(
+    entries: Vec<(u32, T)>,
+    value: impl Fn(T) -> anyhow::Result<V>,
+) -> anyhow::Result<HashMap<TransactionId, V>> {
+    entries
+        .into_iter()
+        .map(|(tx, v)| Ok((TransactionId::new(tx), value(v)?)))
+        .collect()
+}
+
+impl SnapshotWallet {
+    fn new(handoff: &Handoff) -> Self {
+        let wallet = &handoff.wallet;
+        let mut disputed_withdrawals: Vec<_> = wallet
+            .disputed_withdrawals
+            .iter()
+            .map(|tx| tx.id())
+            .collect();
+        disputed_withdrawals.sort_unstable();
+        let mut journal: Vec<_> = handoff
+            .journal
+            .values()
+            .filter_map(|entry| match entry.transaction {
+                Transaction::Deposit { tx_id, amount, .. } => {
+                    Some((tx_id.id(), amount.as_f32(), false, entry.seq))
+                }
+                Transaction::Withdrawal { tx_id, amount, .. } => {
+                    Some((tx_id.id(), amount.as_f32(), true, entry.seq))
+                }
+                _ => None,
+            })
+            .collect();
+        journal.sort_unstable_by_key(|(tx, ..)| *tx);
+        SnapshotWallet {
+            client: wallet.client.id(),
+            available: wallet.balance.available.as_f32(),
+            held: wallet.balance.held.as_f32(),
+            total: wallet.balance.total.as_f32(),
+            locked: wallet.locked,
+            frozen: wallet.frozen,
+            quarantined: wallet.quarantined,
+            dormant: wallet.dormant,
+            last_activity: wallet.last_activity.map(|t| t.as_secs()),
+            last_seq: wallet.last_seq,
+            joint_owners: wallet.joint_owners.iter().map(|c| c.id()).collect(),
+            lock_reasons: wallet.lock_reasons.iter().map(|r| r.to_string()).collect(),
+            stats: [
+                wallet.stats.deposits,
+                wallet.stats.withdrawals,
+                wallet.stats.disputes,
+                wallet.stats.failures,
+            ],
+            open_disputes: sorted(&wallet.open_disputes, Amount::as_f32),
+            dispute_cycles: sorted(&wallet.dispute_cycles, |cycles| cycles),
+            disputed_at: sorted(&wallet.disputed_at, |t| t.as_secs()),
+            charged_back: sorted(&wallet.charged_back, Amount::as_f32),
+            disputed_withdrawals,
+            reversed_withdrawals: sorted(&wallet.reversed_withdrawals, Amount::as_f32),
+            journal,
+        }
+    }
+
+    fn into_handoff(self) -> anyhow::Result<Handoff> {
+        let client = Client::new(self.client);
+        let journal = self
+            .journal
+            .into_iter()
+            .map(|(tx, amount, withdrawal, seq)| {
+                let (tx_id, amount) = (TransactionId::new(tx), signed(amount)?);
+                let transaction = if withdrawal {
+                    Transaction::Withdrawal {
+                        client,
+                        tx_id,
+                        amount,
+                    }
+                } else {
+                    Transaction::Deposit {
+                        client,
+                        tx_id,
+                        amount,
+                    }
+                };
+                Ok((tx_id, JournalEntry { transaction, seq }))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let [deposits, withdrawals, disputes, failures] = self.stats;
+        let wallet = Wallet {
+            balance: Balance {
+                available: signed(self.available)?,
+                held: signed(self.held)?,
+                total: signed(self.total)?,
+            },
+            locked: self.locked,
+            frozen: self.frozen,
+            quarantined: self.quarantined,
+            dormant: self.dormant,
+            last_activity: self.last_activity.map(Timestamp::from_secs),
+            last_seq: self.last_seq,
+            joint_owners: self.joint_owners.into_iter().map(Client::new).collect(),
+            lock_reasons: self
+                .lock_reasons
+                .iter()
+                .map(|reason| reason.parse().map_err(|e: String| anyhow::anyhow!(e)))
+                .collect::<anyhow::Result<_>>()?,
+            stats: WalletStats {
+                deposits,
+                withdrawals,
+                disputes,
+                failures,
+            },
+            open_disputes: unsorted(self.open_disputes, signed)?,
+            dispute_cycles: unsorted(self.dispute_cycles, Ok)?,
+            disputed_at: unsorted(self.disputed_at, |secs| Ok(Timestamp::from_secs(secs)))?,
+            charged_back: unsorted(self.charged_back, signed)?,
+            disputed_withdrawals: self
+                .disputed_withdrawals
+                .into_iter()
+                .map(TransactionId::new)
+                .collect(),
+            reversed_withdrawals: unsorted(self.reversed_withdrawals, signed)?,
+            ..Wallet::new(client)
+        };
+        Ok(Handoff { wallet, journal })
+    }
+}
+
+/// Writes `snapshot` as a snapshot stream.
+pub fn write_snapshot<W: Write>(mut writer: W, snapshot: &Snapshot) -> anyhow::Result<()> {
+    write_header(&mut writer, SNAPSHOT_MAGIC)?;
+    let header = SnapshotHeader {
+        seq: snapshot.seq,
+        wallets: snapshot.wallets.len() as u64,
+    };
+    write_record(&mut writer, &header)?;
+    for handoff in &snapshot.wallets {
+        write_record(&mut writer, &SnapshotWallet::new(handoff))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_snapshot<R: io::Read>(mut reader: R) -> anyhow::Result<Snapshot> {
+    read_header(&mut reader, SNAPSHOT_MAGIC)?;
+    let header: SnapshotHeader = read_record(&mut reader)?.context("snapshot without a header")?;
+    let mut wallets = Vec::new();
+    while let Some(wallet) = read_record::<_, SnapshotWallet>(&mut reader)? {
+        wallets.push(wallet.into_handoff()?);
+    }
+    anyhow::ensure!(
+        wallets.len() as u64 == header.wallets,
+        "snapshot holds {} of its {} wallets",
+        wallets.len(),
+        header.wallets
+    );
+    Ok(Snapshot {
+        seq: header.seq,
+        wallets,
+    })
+}
+
+/// Replaces the snapshot at `path` with `snapshot`, going through a temporary file so that a
+/// crash leaves either the old or the new snapshot behind.
+pub fn save(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+    let mut writer = BufWriter::new(file);
+    write_snapshot(&mut writer, snapshot)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent() {
+        // Makes the rename itself durable; not every platform can open a directory.
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Loads the snapshot at `path`, `None` if there is none yet.
+pub fn load(path: &Path) -> anyhow::Result<Option<Snapshot>> {
+    match File::open(path) {
+        Ok(file) => read_snapshot(BufReader::new(file))
+            .with_context(|| format!("failed to read the snapshot {}", path.display()))
+            .map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to open {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::LockReason;
+
+    #[test]
+    fn test_snapshot_round_trips_what_the_export_leaves_out() {
+        let client = Client::new(4);
+        let (deposit, withdrawal) = (TransactionId::new(1), TransactionId::new(2));
+        let mut wallet = Wallet::joint(client, vec![Client::new(5)]);
+        wallet.deposit(deposit, Amount::from_major(10, 0));
+        wallet
+            .withdraw(withdrawal, Amount::from_major(3, 0))
+            .unwrap();
+        wallet
+            .dispute_withdrawal(withdrawal, Amount::from_major(3, 0), None)
+            .unwrap();
+        wallet
+            .dispute(deposit, Amount::from_major(10, 0), Some(2))
+            .unwrap();
+        wallet.charge_back(deposit).unwrap();
+        wallet
+            .disputed_at
+            .insert(withdrawal, Timestamp::from_secs(60));
+        wallet.touch(Some(Timestamp::from_secs(90)));
+        wallet.frozen = true;
+        wallet.lock_reasons.push(LockReason::Admin);
+        wallet.stats.deposits = 1;
+        wallet.last_seq = 6;
+        let journal = HashMap::from([
+            (
+                deposit,
+                JournalEntry {
+                    transaction: Transaction::Deposit {
+                        client,
+                        tx_id: deposit,
+                        amount: Amount::from_major(10, 0),
+                    },
+                    seq: Some(1),
+                },
+            ),
+            (
+                withdrawal,
+                JournalEntry {
+                    transaction: Transaction::Withdrawal {
+                        client,
+                        tx_id: withdrawal,
+                        amount: Amount::from_major(3, 0),
+                    },
+                    seq: None,
+                },
+            ),
+        ]);
+        let snapshot = Snapshot {
+            seq: 6,
+            wallets: vec![Handoff { wallet, journal }],
+        };
+
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &snapshot).unwrap();
+        let read = read_snapshot(bytes.as_slice()).unwrap();
+        assert_eq!(read.seq, 6);
+        assert_eq!(read.wallets[0].wallet, snapshot.wallets[0].wallet);
+        assert_eq!(read.wallets[0].journal, snapshot.wallets[0].journal);
+        assert!(read_snapshot(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Sources of the transactions of input files, all feeding the channel of the wallet managers:
+//! CSV, which may merge several inputs, and the record formats, i.e. the binary wire format and,
+//! behind their features, Avro, JSON Lines and Parquet.
+
+#[cfg(feature = "avro")]
+use crate::avro;
+use crate::dedupe::DedupeWindow;
+use crate::input::{CsvOptions, ReadSummary, open_input};
+#[cfg(feature = "jsonl")]
+use crate::jsonl;
+use crate::merge::SortedMerge;
+#[cfg(feature = "parquet")]
+use crate::parquet;
+use crate::trailer::ControlTotals;
+use crate::transaction::{Envelope, Origin};
+use crate::watermark::ProcessedPrefix;
+use crate::wire;
+use csv::StringRecord;
+use std::io;
+use std::path::PathBuf;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task;
+
+/// An input the transactions are read from.
+pub trait TransactionSource: Send + 'static {
+    /// Sends the transactions of the input to `tx_sender` until the input ends or the receiver
+    /// is dropped. Blocks on the input, so it runs on the blocking pool.
+    fn read_into(
+        self: Box<Self>,
+        tx_sender: &UnboundedSender<Envelope>,
+    ) -> anyhow::Result<ReadSummary>;
+}
+
+/// Reads `source` into the channel.
+pub async fn stream_into_channel(
+    source: Box<dyn TransactionSource>,
+    tx_sender: UnboundedSender<Envelope>,
+) -> anyhow::Result<ReadSummary> {
+    task::spawn_blocking(move || source.read_into(&tx_sender)).await?
+}
+
+/// CSV inputs. Several inputs are interleaved by timestamp, so each of them has to be in
+/// timestamp order.
+pub struct CsvSource {
+    pub paths: Vec<PathBuf>,
+    pub options: CsvOptions,
+    pub dedupe: Option<DedupeWindow>,
+    /// Whether transactions carry their row, see `Envelope::origin`.
+    pub origins: bool,
+}
+
+impl TransactionSource for CsvSource {
+    fn read_into(
+        self: Box<Self>,
+        tx_sender: &UnboundedSender<Envelope>,
+    ) -> anyhow::Result<ReadSummary> {
+        let CsvSource {
+            paths,
+            options,
+            mut dedupe,
+            origins,
+        } = *self;
+        let mut sources = Vec::with_capacity(paths.len());
+        // Each input is checked against its own trailer.
+        let mut totals = Vec::with_capacity(paths.len());
+        for (source, path) in paths.iter().enumerate() {
+            let (csv_reader, columns) = options.open(path, false)?;
+            totals.push((ControlTotals::default(), columns.clone()));
+            let input = origins.then(|| path.display().to_string());
+            let mut row = 0;
+            sources.push(csv_reader.into_records().map(move |csv_row| {
+                csv_row.map(|csv_row| {
+                    row += 1;
+                    let origin = input.as_ref().map(|input| Origin {
+                        input: input.clone(),
+                        row,
+                        raw: Some(raw_row(&csv_row)),
+                    });
+                    // A row that doesn't parse keeps its origin for the rejection report.
+                    let envelope = match Envelope::from_csv_row(&csv_row, &columns) {
+                        Some(envelope) => Ok(Envelope {
+                            origin: origin.map(Box::new),
+                            ..envelope
+                        }),
+                        None => Err(origin),
+                    };
+                    (source, csv_row, envelope)
+                })
+            }));
+        }
+        let rows = SortedMerge::new(sources, |row| row.as_ref().ok()?.2.as_ref().ok()?.timestamp);
+        let mut summary = ReadSummary::default();
+        let mut prefix = ProcessedPrefix::new(options.watermark);
+        let mut stopped = false;
+
+        for row in rows {
+            let (source, csv_row, envelope) = row?;
+            summary.rows_read += 1;
+            let (source_totals, columns) = &mut totals[source];
+            if source_totals.record(&csv_row, columns) {
+                continue;
+            }
+            if let Some(dedupe) = dedupe.as_mut()
+                && dedupe.is_duplicate(&csv_row)
+            {
+                continue;
+            }
+            match envelope {
+                Ok(envelope) => {
+                    if prefix.skips(&envelope) {
+                        continue;
+                    }
+                    if tx_sender.send(envelope).is_err() {
+                        // The manager stopped early, e.g. aborted by the failure policy.
+                        stopped = true;
+                        break;
+                    }
+                }
+                Err(origin) => {
+                    summary.rows_skipped += 1;
+                    summary.invalid_rows.extend(origin);
+                }
+            }
+        }
+        summary.duplicates_dropped = dedupe.map_or(0, |d| d.dropped());
+        summary.rows_before_watermark = prefix.skipped();
+        summary.next_watermark_tx = prefix.next_watermark();
+        if !stopped {
+            for (path, (totals, _)) in paths.iter().zip(&totals) {
+                options.trailer_mismatch.enforce(path, totals.verify())?;
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+/// `csv_row` as a line of CSV, without the line break.
+fn raw_row(csv_row: &StringRecord) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(csv_row).expect("writes to memory");
+    let line = writer.into_inner().expect("writes to memory");
+    String::from_utf8_lossy(&line).trim_end().to_string()
+}
+
+/// Formats holding one transaction per record, which are read as they come.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Binary,
+    #[cfg(feature = "avro")]
+    Avro,
+    #[cfg(feature = "jsonl")]
+    JsonLines,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// An input in one of the record formats.
+pub struct RecordSource {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// Whether transactions carry their record number, see `Envelope::origin`.
+    pub origins: bool,
+}
+
+impl TransactionSource for RecordSource {
+    fn read_into(
+        self: Box<Self>,
+        tx_sender: &UnboundedSender<Envelope>,
+    ) -> anyhow::Result<ReadSummary> {
+        let input = || io::Result::Ok(io::BufReader::new(open_input(&self.path)?));
+        let name = self.origins.then(|| self.path.display().to_string());
+        let name = name.as_deref();
+        match self.format {
+            RecordFormat::Binary => {
+                send_records(wire::read_transactions(input()?)?, tx_sender, name)
+            }
+            #[cfg(feature = "avro")]
+            RecordFormat::Avro => send_records(avro::read_transactions(input()?)?, tx_sender, name),
+            #[cfg(feature = "jsonl")]
+            RecordFormat::JsonLines => {
+                send_records(jsonl::read_transactions(input()?), tx_sender, name)
+            }
+            // Parquet is read from its footer, so it opens the input itself.
+            #[cfg(feature = "parquet")]
+            RecordFormat::Parquet => {
+                send_records(parquet::read_transactions(&self.path)?, tx_sender, name)
+            }
+        }
+    }
+}
+
+/// Sends `records` on, naming `input` as their origin if set.
+fn send_records(
+    records: impl Iterator<Item = anyhow::Result<Option<Envelope>>>,
+    tx_sender: &UnboundedSender<Envelope>,
+    input: Option<&str>,
+) -> anyhow::Result<ReadSummary> {
+    let mut summary = ReadSummary::default();
+    for envelope in records {
+        summary.rows_read += 1;
+        match envelope? {
+            Some(mut envelope) => {
+                if let Some(input) = input {
+                    envelope.origin = Some(Box::new(Origin {
+                        input: input.to_string(),
+                        row: summary.rows_read,
+                        raw: None,
+                    }));
+                }
+                if tx_sender.send(envelope).is_err() {
+                    break;
+                }
+            }
+            None => {
+                summary.rows_skipped += 1;
+                if let Some(input) = input {
+                    summary.invalid_rows.push(Origin {
+                        input: input.to_string(),
+                        row: summary.rows_read,
+                        raw: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TransactionLimits;
+    use crate::locale::AmountLocale;
+    use crate::schema::Schema;
+    use crate::timeformat::TimestampFormat;
+    use crate::trailer::TrailerMismatch;
+    use crate::transaction::{Amount, Client, Transaction, TransactionId};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_sources_feed_the_channel() {
+        let dir = std::env::temp_dir().join(format!("source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let deposit = |tx| {
+            Envelope::from(Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(1, 0),
+            })
+        };
+
+        let binary = dir.join("transactions.bin");
+        let mut bytes = Vec::new();
+        wire::write_transactions(&mut bytes, &[deposit(1), deposit(2)]).unwrap();
+        std::fs::write(&binary, bytes).unwrap();
+        let (tx_sender, mut tx_receiver) = mpsc::unbounded_channel();
+        let source = RecordSource {
+            path: binary,
+            format: RecordFormat::Binary,
+            origins: false,
+        };
+        let summary = stream_into_channel(Box::new(source), tx_sender)
+            .await
+            .unwrap();
+        assert_eq!((summary.rows_read, summary.rows_skipped), (2, 0));
+        assert_eq!(tx_receiver.recv().await, Some(deposit(1)));
+        assert_eq!(tx_receiver.recv().await, Some(deposit(2)));
+        assert_eq!(tx_receiver.recv().await, None);
+
+        let (early, late) = (dir.join("early.csv"), dir.join("late.csv"));
+        std::fs::write(
+            &early,
+            "type,client,tx,amount,timestamp\ndeposit,1,1,1.0,10\nbogus,1,3,1.0,30\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &late,
+            "type,client,tx,amount,timestamp\ndeposit,1,2,1.0,20\n",
+        )
+        .unwrap();
+        let (tx_sender, mut tx_receiver) = mpsc::unbounded_channel();
+        let source = CsvSource {
+            paths: vec![early, late],
+            options: CsvOptions {
+                amount_locale: AmountLocale::default(),
+                lenient_amounts: false,
+                timestamp_format: TimestampFormat::default(),
+                trailer_mismatch: TrailerMismatch::Fail,
+                schema: Schema::default(),
+                currency: None,
+                watermark: None,
+                limits: TransactionLimits::default(),
+            },
+            dedupe: None,
+            origins: true,
+        };
+        let summary = stream_into_channel(Box::new(source), tx_sender)
+            .await
+            .unwrap();
+        assert_eq!((summary.rows_read, summary.rows_skipped), (3, 1));
+        assert_eq!(
+            summary.invalid_rows,
+            [Origin {
+                input: dir.join("early.csv").display().to_string(),
+                row: 2,
+                raw: Some("bogus,1,3,1.0,30".to_string()),
+            }]
+        );
+        let mut origins = Vec::new();
+        while let Some(envelope) = tx_receiver.recv().await {
+            let origin = envelope.origin.unwrap();
+            origins.push((envelope.transaction.tx_id().id(), origin.row, origin.raw));
+        }
+        assert_eq!(
+            origins,
+            [
+                (1, 1, Some("deposit,1,1,1.0,10".to_string())),
+                (2, 1, Some("deposit,1,2,1.0,20".to_string())),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_only_lenient_amounts_may_be_padded() {
+        let dir = std::env::temp_dir().join(format!("source-lenient-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("padded.csv");
+        std::fs::write(
+            &path,
+            "type, client, tx, amount\ndeposit, 1, 1,1.5\ndeposit,1,2, 1.5 \ndeposit,1,3,.5\n",
+        )
+        .unwrap();
+        let read = async |lenient_amounts| {
+            let (tx_sender, mut tx_receiver) = mpsc::unbounded_channel();
+            let source = CsvSource {
+                paths: vec![path.clone()],
+                options: CsvOptions {
+                    amount_locale: AmountLocale::default(),
+                    lenient_amounts,
+                    timestamp_format: TimestampFormat::default(),
+                    trailer_mismatch: TrailerMismatch::Fail,
+                    schema: Schema::default(),
+                    currency: None,
+                    watermark: None,
+                    limits: TransactionLimits::default(),
+                },
+                dedupe: None,
+                origins: false,
+            };
+            let summary = stream_into_channel(Box::new(source), tx_sender)
+                .await
+                .unwrap();
+            let mut txs = Vec::new();
+            while let Some(envelope) = tx_receiver.recv().await {
+                txs.push(envelope.transaction.tx_id().id());
+            }
+            (summary.rows_skipped, txs)
+        };
+
+        assert_eq!(read(false).await, (1, vec![1, 3]));
+        assert_eq!(read(true).await, (0, vec![1, 2, 3]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Local spool for failures whose consumer went away. A dropped failure receiver used to stop
+//! `run` in its tracks; failures are spooled here instead, so settlement carries on and the
+//! failures can still be collected afterwards.
+
+use crate::transaction::Failure;
+use log::{error, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Failures that couldn't be delivered, appended as JSON lines to a file when one is given and
+/// kept in memory otherwise, or when the file can't be written.
+#[derive(Debug, Default)]
+pub struct FailureSpool {
+    path: Option<PathBuf>,
+    state: Mutex<SpoolState>,
+}
+
+#[derive(Debug, Default)]
+struct SpoolState {
+    /// Opened on the first spooled failure, so a healthy run leaves no file behind.
+    writer: Option<BufWriter<File>>,
+    kept: Vec<Failure>,
+    spooled: u64,
+}
+
+impl FailureSpool {
+    /// A spool appending to the file at `path`, or keeping failures in memory without one.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        FailureSpool {
+            path,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Sends `failure` to `err_send`, spooling it once the receiver is gone.
+    pub fn deliver(&self, err_send: &UnboundedSender<Failure>, failure: Failure) {
+        if let Err(undelivered) = err_send.send(failure) {
+            self.spool(undelivered.0);
+        }
+    }
+
+    pub fn spool(&self, failure: Failure) {
+        let mut state = self.state();
+        if state.spooled == 0 {
+            warn!("The failure consumer is gone, spooling failures locally");
+        }
+        state.spooled += 1;
+        if let Some(path) = &self.path {
+            let written = match &mut state.writer {
+                Some(writer) => append(writer, &failure),
+                None => OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|file| {
+                        let writer = state.writer.insert(BufWriter::new(file));
+                        append(writer, &failure)
+                    }),
+            };
+            match written {
+                Ok(()) => return,
+                Err(e) => error!("Failed to spool a failure to {}: {e}", path.display()),
+            }
+        }
+        state.kept.push(failure);
+    }
+
+    /// Failures spooled since the start, whether to the file or in memory.
+    pub fn spooled(&self) -> u64 {
+        self.state().spooled
+    }
+
+    /// The failures kept in memory since the last call.
+    pub fn take(&self) -> Vec<Failure> {
+        std::mem::take(&mut self.state().kept)
+    }
+
+    /// Writes out what is buffered for the spool file.
+    pub fn flush(&self) -> io::Result<()> {
+        match &mut self.state().writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SpoolState> {
+        self.state.lock().expect("failure spool lock poisoned")
+    }
+}
+
+fn append(writer: &mut BufWriter<File>, failure: &Failure) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, failure)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Client, TransactionId};
+
+    #[test]
+    fn test_spools_once_the_receiver_is_gone() {
+        let path = std::env::temp_dir().join(format!(
+            "walletmanagermock-spool-{}.jsonl",
+            std::process::id()
+        ));
+        let failure = |tx| Failure::insufficient_funds(Client::new(1), TransactionId::new(tx));
+        let (err_send, mut err_recv) = tokio::sync::mpsc::unbounded_channel();
+        let memory = FailureSpool::new(None);
+        memory.deliver(&err_send, failure(1));
+        assert_eq!(err_recv.try_recv().unwrap().tx, TransactionId::new(1));
+        drop(err_recv);
+        memory.deliver(&err_send, failure(2));
+        assert_eq!(memory.spooled(), 1);
+        assert_eq!(memory.take()[0].tx, TransactionId::new(2));
+
+        let file = FailureSpool::new(Some(path.clone()));
+        file.deliver(&err_send, failure(3));
+        file.deliver(&err_send, failure(4));
+        file.flush().unwrap();
+        assert!(file.take().is_empty());
+        let spooled = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(spooled.lines().count(), 2);
+        assert!(spooled.contains("\"tx\":4"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! ISO 20022 messages: `camt.053` bank-to-customer statements and `pain.001` customer credit
+//! transfer initiations.
+
+use super::{StatementEntry, parse_amount};
+use crate::transaction::Timestamp;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+
+/// Every `<Ntry>` of a camt.053 statement, signed by its `<CdtDbtInd>`.
+pub fn parse_camt053(input: &str) -> anyhow::Result<Vec<StatementEntry>> {
+    let mut entries = Vec::new();
+    let mut amount = None;
+    let mut debit = false;
+    let mut posted = None;
+    walk(input, |path, event| match (path, event) {
+        ([.., "Ntry"], XmlEvent::Start) => {
+            amount = None;
+            debit = false;
+            posted = None;
+        }
+        ([.., "Ntry", "Amt"], XmlEvent::Text(text)) => amount = parse_amount(text),
+        ([.., "Ntry", "CdtDbtInd"], XmlEvent::Text(text)) => debit = text == "DBIT",
+        ([.., "Ntry", "BookgDt", "Dt" | "DtTm"], XmlEvent::Text(text)) => posted = parse_date(text),
+        ([.., "Ntry"], XmlEvent::End) => {
+            if let Some(amount) = amount.take() {
+                entries.push(StatementEntry {
+                    amount: if debit { -amount } else { amount },
+                    posted: posted.take(),
+                });
+            }
+        }
+        _ => {}
+    })?;
+    Ok(entries)
+}
+
+/// Every `<CdtTrfTxInf>` of a pain.001 initiation as an outgoing payment, dated with the
+/// requested execution date of its payment information block.
+pub fn parse_pain001(input: &str) -> anyhow::Result<Vec<StatementEntry>> {
+    let mut entries = Vec::new();
+    let mut execution_date = None;
+    let mut amount = None;
+    walk(input, |path, event| match (path, event) {
+        ([.., "PmtInf", "ReqdExctnDt"], XmlEvent::Text(text))
+        | ([.., "PmtInf", "ReqdExctnDt", "Dt" | "DtTm"], XmlEvent::Text(text)) => {
+            execution_date = parse_date(text)
+        }
+        ([.., "CdtTrfTxInf", "Amt", "InstdAmt"], XmlEvent::Text(text)) => {
+            amount = parse_amount(text)
+        }
+        ([.., "CdtTrfTxInf"], XmlEvent::End) => {
+            if let Some(amount) = amount.take() {
+                entries.push(StatementEntry {
+                    amount: -amount,
+                    posted: execution_date,
+                });
+            }
+        }
+        _ => {}
+    })?;
+    Ok(entries)
+}
+
+enum XmlEvent<'a> {
+    Start,
+    Text(&'a str),
+    End,
+}
+
+/// Streams the document to `visit` along with the local names of the enclosing elements,
+/// ignoring namespaces so that every message version matches the same paths.
+fn walk(input: &str, mut visit: impl FnMut(&[&str], XmlEvent)) -> anyhow::Result<()> {
+    let mut reader = Reader::from_str(input);
+    let mut path: Vec<String> = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                path.push(start.local_name().into_inner().to_string());
+                visit(&names(&path), XmlEvent::Start);
+            }
+            Event::End(_) => {
+                visit(&names(&path), XmlEvent::End);
+                path.pop();
+            }
+            Event::Text(text) => {
+                let text = text.trim();
+                if !text.is_empty() {
+                    visit(&names(&path), XmlEvent::Text(text));
+                }
+            }
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn names(path: &[String]) -> Vec<&str> {
+    path.iter().map(String::as_str).collect()
+}
+
+/// ISO dates (`2024-01-02`) or date-times (`2024-01-02T10:30:00`, offsets ignored).
+fn parse_date(value: &str) -> Option<Timestamp> {
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let mut parts = date.split('-').map(str::parse::<u32>);
+    let year = parts.next()?.ok()?;
+    let day = Timestamp::from_date(year as i32, parts.next()?.ok()?, parts.next()?.ok()?)?;
+    let mut clock = time
+        .get(..8)
+        .unwrap_or("")
+        .split(':')
+        .map(str::parse::<i64>);
+    let seconds = clock
+        .by_ref()
+        .take(3)
+        .zip([3600, 60, 1])
+        .map(|(v, unit)| v.unwrap_or(0) * unit)
+        .sum::<i64>();
+    Some(Timestamp::from_secs(day.as_secs() + seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_camt053_entries() {
+        let camt = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08"><BkToCstmrStmt><Stmt>
+            <Ntry><Amt Ccy="EUR">100.00</Amt><CdtDbtInd>CRDT</CdtDbtInd>
+                <BookgDt><Dt>2024-01-02</Dt></BookgDt>
+                <NtryDtls><TxDtls><Amt Ccy="EUR">100.00</Amt></TxDtls></NtryDtls></Ntry>
+            <Ntry><Amt Ccy="EUR">25.50</Amt><CdtDbtInd>DBIT</CdtDbtInd>
+                <BookgDt><DtTm>2024-01-03T00:00:10</DtTm></BookgDt></Ntry>
+        </Stmt></BkToCstmrStmt></Document>"#;
+
+        assert_eq!(
+            parse_camt053(camt).unwrap(),
+            vec![
+                StatementEntry {
+                    amount: 100.0,
+                    posted: Some(Timestamp::from_secs(1_704_153_600)),
+                },
+                StatementEntry {
+                    amount: -25.5,
+                    posted: Some(Timestamp::from_secs(1_704_240_010)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_pain001_transfers() {
+        let pain = r#"<Document><CstmrCdtTrfInitn><PmtInf>
+            <ReqdExctnDt><Dt>2024-01-02</Dt></ReqdExctnDt>
+            <CdtTrfTxInf><Amt><InstdAmt Ccy="EUR">12.34</InstdAmt></Amt></CdtTrfTxInf>
+            <CdtTrfTxInf><Amt><InstdAmt Ccy="EUR">5</InstdAmt></Amt></CdtTrfTxInf>
+        </PmtInf></CstmrCdtTrfInitn></Document>"#;
+
+        let entries = parse_pain001(pain).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].amount, -12.34);
+        assert_eq!(entries[1].posted, Some(Timestamp::from_secs(1_704_153_600)));
+    }
+}
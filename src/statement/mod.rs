@@ -0,0 +1,50 @@
+//! Converters from personal-finance bank statements into the engine's transaction stream.
+//!
+//! Statements describe a single account, so every entry is booked on one client; credits become
+//! deposits and debits become withdrawals, numbered with consecutive transaction ids.
+
+use crate::transaction::{Amount, Client, Envelope, Timestamp, Transaction, TransactionId};
+
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+pub mod ofx;
+pub mod qif;
+
+/// A signed statement line: positive amounts are credits, negative ones debits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementEntry {
+    pub amount: f32,
+    pub posted: Option<Timestamp>,
+}
+
+impl StatementEntry {
+    pub fn into_envelope(self, client: Client, tx_id: TransactionId) -> Option<Envelope> {
+        let amount = Amount::try_from(self.amount.abs()).ok()?;
+        let transaction = if self.amount < 0.0 {
+            Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount,
+            }
+        } else {
+            Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            }
+        };
+        Some(Envelope {
+            timestamp: self.posted,
+            ..Envelope::from(transaction)
+        })
+    }
+}
+
+/// Parses an amount as written on statements, tolerating thousands separators and a leading `+`.
+fn parse_amount(s: &str) -> Option<f32> {
+    s.trim()
+        .trim_start_matches('+')
+        .replace(',', "")
+        .parse()
+        .ok()
+}
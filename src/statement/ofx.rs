@@ -0,0 +1,63 @@
+use super::{StatementEntry, parse_amount};
+use crate::transaction::Timestamp;
+
+/// Extracts the `<STMTTRN>` records of an OFX statement. Both the SGML (1.x) and XML (2.x)
+/// flavours work since values are read up to the next tag.
+pub fn parse(input: &str) -> Vec<StatementEntry> {
+    input
+        .split("<STMTTRN>")
+        .skip(1)
+        .filter_map(|block| {
+            let block = block.split("</STMTTRN>").next().unwrap_or(block);
+            let amount = parse_amount(tag_value(block, "TRNAMT")?)?;
+            let posted = tag_value(block, "DTPOSTED").and_then(parse_date);
+            Some(StatementEntry { amount, posted })
+        })
+        .collect()
+}
+
+fn tag_value<'a>(block: &'a str, tag: &str) -> Option<&'a str> {
+    let start = block.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let rest = &block[start..];
+    let value = rest[..rest.find('<').unwrap_or(rest.len())].trim();
+    (!value.is_empty()).then_some(value)
+}
+
+/// OFX dates are `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]`; the time zone suffix is ignored.
+fn parse_date(value: &str) -> Option<Timestamp> {
+    let digits = |range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    let date = Timestamp::from_date(digits(0..4)? as i32, digits(4..6)?, digits(6..8)?)?;
+    let seconds = digits(8..10).unwrap_or(0) * 3600
+        + digits(10..12).unwrap_or(0) * 60
+        + digits(12..14).unwrap_or(0);
+    Some(Timestamp::from_secs(date.as_secs() + seconds as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sgml_statement() {
+        let ofx = "OFXHEADER:100\n<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240102120000<TRNAMT>1,250.50<FITID>A1\n\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240103<TRNAMT>-20.00<FITID>A2\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+
+        let entries = parse(ofx);
+
+        assert_eq!(
+            entries,
+            vec![
+                StatementEntry {
+                    amount: 1250.5,
+                    posted: Some(Timestamp::from_secs(1_704_196_800)),
+                },
+                StatementEntry {
+                    amount: -20.0,
+                    posted: Some(Timestamp::from_secs(1_704_240_000)),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use super::{StatementEntry, parse_amount};
+use crate::transaction::Timestamp;
+
+/// Parses the records of a QIF bank statement (`!Type:Bank`), each terminated by a `^` line.
+pub fn parse(input: &str) -> Vec<StatementEntry> {
+    let mut entries = Vec::new();
+    let mut amount = None;
+    let mut posted = None;
+    for line in input.lines().map(str::trim) {
+        let (code, value) = line.split_at(line.len().min(1));
+        match code {
+            "T" | "U" if amount.is_none() => amount = parse_amount(value),
+            "D" => posted = parse_date(value),
+            "^" => {
+                if let Some(amount) = amount.take() {
+                    entries.push(StatementEntry {
+                        amount,
+                        posted: posted.take(),
+                    });
+                }
+                posted = None;
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// QIF dates are US style `MM/DD/YYYY`, with `'` often separating a two digit year (`1/2'24`).
+fn parse_date(value: &str) -> Option<Timestamp> {
+    let mut parts = value
+        .split(['/', '\'', '-'])
+        .map(|p| p.trim().parse::<u32>());
+    let month = parts.next()?.ok()?;
+    let day = parts.next()?.ok()?;
+    let year = match parts.next()?.ok()? {
+        year if year < 100 => 2000 + year,
+        year => year,
+    };
+    Timestamp::from_date(year as i32, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bank_statement() {
+        let qif = "!Type:Bank\nD01/02/2024\nT1,250.50\nPEmployer\n^\nD1/3'24\nT-20.00\n^\n";
+
+        let entries = parse(qif);
+
+        assert_eq!(
+            entries,
+            vec![
+                StatementEntry {
+                    amount: 1250.5,
+                    posted: Some(Timestamp::from_secs(1_704_153_600)),
+                },
+                StatementEntry {
+                    amount: -20.0,
+                    posted: Some(Timestamp::from_secs(1_704_240_000)),
+                },
+            ]
+        );
+    }
+}
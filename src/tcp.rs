@@ -0,0 +1,371 @@
+//! Line-protocol TCP source for legacy systems that can only push over sockets. Every line is one
+//! transaction, either a CSV row (`type,client,tx,amount[,timestamp[,tenant]]`) or a JSON object.
+//! Peers that can produce it send the binary stream of `wire` instead.
+
+use crate::input::ReadSummary;
+use crate::tenant::TenantRegistry;
+use crate::transaction::{Envelope, Failure};
+use crate::wallet_manager::RunReport;
+use crate::wire;
+use log::{info, warn};
+use std::io;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::{JoinError, JoinSet};
+
+/// How connections delimit transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Lines,
+    /// A `wire` transaction stream, header included.
+    Binary,
+}
+
+/// Accepts connections until `shutdown` completes, then stops reading from the open connections,
+/// lets them apply what they already read and returns the counters of all connections.
+///
+/// Each connection reads at most `buffer` lines ahead of processing; once that many are queued
+/// the socket is no longer read, so a fast sender is slowed down by TCP flow control instead of
+/// growing memory.
+pub async fn serve(
+    listener: TcpListener,
+    registry: Arc<TenantRegistry>,
+    err_send: UnboundedSender<Failure>,
+    buffer: NonZeroUsize,
+    framing: Framing,
+    shutdown: impl Future<Output = ()>,
+) -> ReadSummary {
+    let (stop, stopped) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    let mut summary = ReadSummary::default();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            biased;
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    info!("Accepted connection from {peer}");
+                    connections.spawn(handle_connection(
+                        stream,
+                        registry.clone(),
+                        err_send.clone(),
+                        buffer,
+                        framing,
+                        stopped.clone(),
+                    ));
+                }
+                Err(e) => warn!("Failed to accept connection: {e}"),
+            },
+            Some(finished) = connections.join_next(), if !connections.is_empty() => {
+                add_connection(&mut summary, finished);
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    let _ = stop.send(true);
+    while let Some(finished) = connections.join_next().await {
+        add_connection(&mut summary, finished);
+    }
+    summary
+}
+
+fn add_connection(summary: &mut ReadSummary, finished: Result<ReadSummary, JoinError>) {
+    match finished {
+        Ok(connection) => {
+            summary.rows_read += connection.rows_read;
+            summary.rows_skipped += connection.rows_skipped;
+            summary.applied.merge(connection.applied);
+        }
+        Err(e) => warn!("Connection task failed: {e}"),
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    registry: Arc<TenantRegistry>,
+    err_send: UnboundedSender<Failure>,
+    buffer: NonZeroUsize,
+    framing: Framing,
+    mut stopped: watch::Receiver<bool>,
+) -> ReadSummary {
+    let (queue, mut pending) = mpsc::channel::<Envelope>(buffer.get());
+    let worker = async move {
+        let mut applied = RunReport::default();
+        while let Some(envelope) = pending.recv().await {
+            let transaction = envelope.transaction;
+            let res = registry.apply(envelope);
+            applied.record(&transaction, res.is_ok());
+            if let Err(e) = res {
+                registry.deliver_failure(&err_send, e);
+                if registry.aborted() {
+                    applied.stopped_early = true;
+                    break;
+                }
+            }
+        }
+        applied
+    };
+    let reader = async move {
+        let mut summary = ReadSummary::default();
+        let mut stream = BufReader::new(stream);
+        if framing == Framing::Binary
+            && let Err(e) = read_binary_header(&mut stream).await
+        {
+            warn!("Rejecting connection: {e}");
+            return summary;
+        }
+        let mut lines = stream.lines();
+        loop {
+            let record = tokio::select! {
+                biased;
+                record = next_record(&mut lines, framing) => record,
+                _ = stopped.wait_for(|stopped| *stopped) => break,
+            };
+            let record = match record {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read from connection: {e}");
+                    break;
+                }
+            };
+            let envelope = match record {
+                Record::Line(line) if line.trim().is_empty() => continue,
+                Record::Line(line) => Envelope::from_line(&line),
+                Record::Binary(bytes) => wire::decode_transaction(&bytes),
+            };
+            summary.rows_read += 1;
+            match envelope {
+                Some(envelope) => {
+                    if queue.send(envelope).await.is_err() {
+                        break;
+                    }
+                }
+                None => summary.rows_skipped += 1,
+            }
+        }
+        summary
+    };
+    let (mut summary, applied) = tokio::join!(reader, worker);
+    summary.applied = applied;
+    summary
+}
+
+enum Record {
+    Line(String),
+    Binary(Vec<u8>),
+}
+
+async fn read_binary_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<()> {
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).await?;
+    if &header[..4] != wire::TRANSACTIONS_MAGIC || header[4] != wire::VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a binary transaction stream of a supported version",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the next line or length-prefixed binary record, `None` once the peer is done.
+async fn next_record<R: AsyncRead + Unpin>(
+    lines: &mut tokio::io::Lines<BufReader<R>>,
+    framing: Framing,
+) -> io::Result<Option<Record>> {
+    if framing == Framing::Lines {
+        return Ok(lines.next_line().await?.map(Record::Line));
+    }
+    let stream = lines.get_mut();
+    let len = match stream.read_u32_le().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len > wire::MAX_RECORD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("binary record of {len} bytes"),
+        ));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes).await?;
+    Ok(Some(Record::Binary(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::enrich::Attributes;
+    use crate::transaction::{Amount, Client, Tenant, Timestamp, Transaction, TransactionId};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_from_line() {
+        let deposit = Transaction::Deposit {
+            client: Client::new(1),
+            tx_id: TransactionId::new(2),
+            amount: Amount::from_major(1, 5000),
+        };
+        assert_eq!(
+            Envelope::from_line("deposit, 1, 2, 1.5"),
+            Some(Envelope::from(deposit))
+        );
+        assert_eq!(
+            Envelope::from_line("deposit,1,2,1.5,86400,acme"),
+            Some(Envelope {
+                transaction: deposit,
+                timestamp: Some(Timestamp::from_secs(86400)),
+                tenant: Some(Tenant::new("acme")),
+                seq: None,
+                attributes: Attributes::default(),
+                origin: None,
+            })
+        );
+        assert_eq!(
+            Envelope::from_line(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1.5}"#),
+            Some(Envelope::from(deposit))
+        );
+        assert_eq!(Envelope::from_line("deposit,1"), None);
+        assert_eq!(Envelope::from_line(r#"{"type": "deposit"}"#), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_applies_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let (err_send, mut err_recv) = mpsc::unbounded_channel();
+        let (shutdown, shutdown_recv) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            registry.clone(),
+            err_send,
+            NonZeroUsize::MIN,
+            Framing::Lines,
+            async {
+                let _ = shutdown_recv.await;
+            },
+        ));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"deposit,1,1,2.0\n\
+                  {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 0.5}\n\
+                  not a transaction\n\
+                  withdrawal,1,3,10.0\n",
+            )
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        // The server closes its side once it has read every line.
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+        shutdown.send(()).unwrap();
+        let summary = server.await.unwrap();
+
+        assert_eq!(summary.rows_read, 4);
+        assert_eq!(summary.rows_skipped, 1);
+        assert_eq!(summary.applied.processed, 3);
+        assert_eq!(summary.applied.failed, 1);
+        assert_eq!(summary.applied.deposits, 1);
+        assert_eq!(summary.applied.withdrawals, 2);
+        assert!(!summary.applied.stopped_early);
+        let wallets = registry.default_manager().export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].balance.available, Amount::from_major(1, 5000));
+        assert_eq!(
+            err_recv.recv().await.map(|failure| failure.tx),
+            Some(TransactionId::new(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_spools_failures_once_their_receiver_is_gone() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let (err_send, err_recv) = mpsc::unbounded_channel();
+        drop(err_recv);
+        let (shutdown, shutdown_recv) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            registry.clone(),
+            err_send,
+            NonZeroUsize::MIN,
+            Framing::Lines,
+            async {
+                let _ = shutdown_recv.await;
+            },
+        ));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"withdrawal,1,1,1.0\ndeposit,1,2,2.0\nwithdrawal,1,3,10.0\ndeposit,1,4,1.0\n",
+            )
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+        shutdown.send(()).unwrap();
+        let summary = server.await.unwrap();
+
+        assert_eq!(summary.applied.processed, 4);
+        assert!(!summary.applied.stopped_early);
+        let manager = registry.default_manager();
+        let spooled = manager.failure_spool().take();
+        let spooled: Vec<_> = spooled.iter().map(|failure| failure.tx).collect();
+        assert_eq!(spooled, [TransactionId::new(1), TransactionId::new(3)]);
+        let wallets = manager.export_wallets();
+        assert_eq!(wallets[0].balance.available, Amount::from_major(3, 0));
+    }
+
+    #[tokio::test]
+    async fn test_serve_applies_binary_records() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let registry = Arc::new(TenantRegistry::new(Config::default(), HashMap::new()));
+        let (err_send, _err_recv) = mpsc::unbounded_channel();
+        let (shutdown, shutdown_recv) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            registry.clone(),
+            err_send,
+            NonZeroUsize::MIN,
+            Framing::Binary,
+            async {
+                let _ = shutdown_recv.await;
+            },
+        ));
+
+        let deposit = |tx| {
+            Envelope::from(Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(2, 0),
+            })
+        };
+        let mut bytes = Vec::new();
+        wire::write_transactions(&mut bytes, &[deposit(1), deposit(2)]).unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&bytes).await.unwrap();
+        client.shutdown().await.unwrap();
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+        shutdown.send(()).unwrap();
+        let summary = server.await.unwrap();
+
+        assert_eq!(summary.rows_read, 2);
+        let wallets = registry.default_manager().export_wallets();
+        assert_eq!(wallets[0].balance.available, Amount::from_major(4, 0));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::batching::BatchPolicy;
+use crate::config::{Config, Settings};
+use crate::events::EventHub;
+use crate::fairness::FairQueue;
+use crate::persistence::Recovery;
+use crate::queue::QueueMetrics;
+use crate::replica::ReadReplica;
+use crate::transaction::{Envelope, Failure, Tenant};
+use crate::wallet_manager::{RunReport, WalletManager};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Routes transactions to one `WalletManager` per tenant so that institutions sharing an engine
+/// never see each other's wallets. Rows without a tenant go to the default namespace.
+pub struct TenantRegistry {
+    default: Arc<WalletManager>,
+    tenants: DashMap<Tenant, Arc<WalletManager>>,
+    /// Base configuration and tenant overrides, replaced together by `reconfigure`.
+    config: RwLock<(Config, HashMap<Tenant, Settings>)>,
+    events: Option<EventHub>,
+    queue: Arc<QueueMetrics>,
+    /// Transactions `run` takes ahead to serve clients in turn, if it does.
+    fair_window: Option<usize>,
+    batching: BatchPolicy,
+    /// Answers wallet queries in place of the managers, if there is one.
+    replica: Option<Arc<ReadReplica>>,
+}
+
+impl TenantRegistry {
+    /// # Panics
+    ///
+    /// If the default namespace's manager can't be created; see `try_new`.
+    pub fn new(config: Config, overrides: HashMap<Tenant, Settings>) -> Self {
+        Self::try_new(config, overrides).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// A registry for `config`, failing if the default namespace's manager can't be created, see
+    /// `WalletManager::try_with_config`.
+    pub fn try_new(config: Config, overrides: HashMap<Tenant, Settings>) -> io::Result<Self> {
+        Ok(TenantRegistry {
+            default: Arc::new(WalletManager::try_with_config(config.clone())?),
+            tenants: DashMap::new(),
+            config: RwLock::new((config, overrides)),
+            events: None,
+            queue: Arc::default(),
+            fair_window: None,
+            batching: BatchPolicy::default(),
+            replica: None,
+        })
+    }
+
+    /// Bounds of the batches `run` takes off the queue.
+    pub fn with_batching(mut self, batching: BatchPolicy) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Lets `run` serve the clients with queued transactions in turn instead of in arrival order,
+    /// taking up to `window` transactions off the queue ahead of processing.
+    pub fn with_fair_scheduling(mut self, window: usize) -> Self {
+        self.fair_window = Some(window);
+        self
+    }
+
+    /// Serves wallet queries from `replica`, which has to follow the events of this registry.
+    pub fn with_read_replica(mut self, replica: Arc<ReadReplica>) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    pub fn read_replica(&self) -> Option<&Arc<ReadReplica>> {
+        self.replica.as_ref()
+    }
+
+    /// Publishes the wallet events of every namespace to `events`, tagged with their tenant.
+    pub fn with_events(mut self, events: EventHub) -> Self {
+        let default = Arc::into_inner(self.default)
+            .expect("the default manager isn't shared before the registry is built");
+        self.default = Arc::new(default.with_events(events.for_tenant(None)));
+        self.events = Some(events);
+        self
+    }
+
+    pub async fn run(
+        &self,
+        mut tx_recv: UnboundedReceiver<Envelope>,
+        err_send: UnboundedSender<Failure>,
+    ) -> RunReport {
+        let started = Instant::now();
+        let mut report = RunReport::default();
+        let mut fair = self.fair_window.map(FairQueue::new);
+        let mut batch = Vec::new();
+        let mut size = self.batching.next_size(self.batching.min, 0);
+        'run: loop {
+            let taken = match &mut fair {
+                Some(fair) => fair.next_batch(&mut tx_recv, &mut batch, size).await,
+                None => tx_recv.recv_many(&mut batch, size).await,
+            };
+            if taken == 0 {
+                break;
+            }
+            let mut waiting = tx_recv.len() + fair.as_ref().map_or(0, FairQueue::len);
+            size = self.batching.next_size(size, waiting);
+            waiting += batch.len();
+            for envelope in batch.drain(..) {
+                waiting -= 1;
+                self.queue.record_dequeue(waiting);
+                let transaction = envelope.transaction;
+                let res = self.apply(envelope);
+                report.record(&transaction, res.is_ok());
+                if let Err(e) = res {
+                    self.deliver_failure(&err_send, e);
+                    if self.aborted() {
+                        report.stopped_early = true;
+                        break 'run;
+                    }
+                }
+                if !self.forward_deferred_failures(&err_send, &mut report) {
+                    report.stopped_early = true;
+                    break 'run;
+                }
+            }
+        }
+        if !report.stopped_early {
+            self.expire_pending_disputes();
+            self.forward_deferred_failures(&err_send, &mut report);
+        }
+        report.duration = started.elapsed();
+        report
+    }
+
+    /// Sends `failure` to `err_send`, spooling it with the default namespace's failures if the
+    /// receiver was dropped.
+    pub fn deliver_failure(&self, err_send: &UnboundedSender<Failure>, failure: Failure) {
+        self.default.deliver_failure(err_send, failure);
+    }
+
+    /// Sends the failures of deferred disputes in every namespace on to `err_send`, counting
+    /// them in `report`, and spooling them if it's gone. Returns `false` once processing should
+    /// stop.
+    pub fn forward_deferred_failures(
+        &self,
+        err_send: &UnboundedSender<Failure>,
+        report: &mut RunReport,
+    ) -> bool {
+        let deferral = self
+            .config
+            .read()
+            .expect("config lock poisoned")
+            .0
+            .dispute_deferral;
+        if deferral.is_none() {
+            return true;
+        }
+        let managers = std::iter::once(self.default.clone())
+            .chain(self.tenants.iter().map(|r| r.value().clone()));
+        for failure in managers.flat_map(|manager| manager.take_deferred_failures()) {
+            report.failed += 1;
+            self.deliver_failure(err_send, failure);
+        }
+        !self.aborted()
+    }
+
+    /// Fails the disputes every namespace still holds back, once the input has ended.
+    pub fn expire_pending_disputes(&self) {
+        self.default.expire_pending_disputes();
+        for manager in self.tenants.iter() {
+            manager.value().expire_pending_disputes();
+        }
+    }
+
+    /// Depth and age of the queue `run` consumes.
+    pub fn queue(&self) -> &Arc<QueueMetrics> {
+        &self.queue
+    }
+
+    /// Failed transactions across all namespaces.
+    pub fn failure_count(&self) -> usize {
+        self.default.failure_count()
+            + self
+                .tenants
+                .iter()
+                .map(|r| r.value().failure_count())
+                .sum::<usize>()
+    }
+
+    /// Whether the failure policy of any namespace asks to stop processing.
+    pub fn aborted(&self) -> bool {
+        self.default.aborted() || self.tenants.iter().any(|r| r.value().aborted())
+    }
+
+    pub fn apply(&self, envelope: Envelope) -> Result<(), Failure> {
+        self.manager(envelope.tenant.as_ref()).apply(envelope)
+    }
+
+    pub fn manager(&self, tenant: Option<&Tenant>) -> Arc<WalletManager> {
+        let Some(tenant) = tenant else {
+            return self.default.clone();
+        };
+        if let Some(manager) = self.tenants.get(tenant) {
+            return manager.clone();
+        }
+        self.tenants
+            .entry(tenant.clone())
+            .or_insert_with(|| {
+                let manager = WalletManager::with_config(self.config_for(tenant));
+                Arc::new(match &self.events {
+                    Some(events) => manager.with_events(events.for_tenant(Some(tenant.clone()))),
+                    None => manager,
+                })
+            })
+            .clone()
+    }
+
+    /// The configuration of `tenant`'s namespace, persisted in the `tenants/<id>` subdirectory of
+    /// the default namespace's persistence directory.
+    pub fn config_for(&self, tenant: &Tenant) -> Config {
+        let (base, overrides) = &*self.config.read().expect("config lock poisoned");
+        let mut config = base.clone();
+        if let Some(settings) = overrides.get(tenant) {
+            settings.apply_to(&mut config);
+        }
+        if let Some(persistence) = &mut config.persistence {
+            persistence.dir = persistence.dir.join("tenants").join(tenant.as_str());
+        }
+        config
+    }
+
+    /// Recovers every namespace a crashed run left state for in the persistence directory, see
+    /// `WalletManager::recover`.
+    pub fn recover(&self) -> anyhow::Result<Vec<(Option<Tenant>, Recovery)>> {
+        let Some(persistence) = self.base_config().persistence else {
+            anyhow::bail!("recovery needs Config::persistence");
+        };
+        let mut recovered = vec![(None, self.default.recover()?)];
+        let tenants_dir = persistence.dir.join("tenants");
+        if tenants_dir.is_dir() {
+            let mut tenants = Vec::new();
+            for entry in std::fs::read_dir(&tenants_dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir()
+                    && let Some(tenant) = Tenant::parse(&entry.file_name().to_string_lossy())
+                {
+                    tenants.push(tenant);
+                }
+            }
+            tenants.sort();
+            for tenant in tenants {
+                let recovery = self.manager(Some(&tenant)).recover()?;
+                recovered.push((Some(tenant), recovery));
+            }
+        }
+        Ok(recovered)
+    }
+
+    /// Checkpoints every namespace, e.g. once the input is done.
+    pub fn checkpoint(&self) -> anyhow::Result<()> {
+        self.default.checkpoint()?;
+        for manager in self.tenants.iter() {
+            manager.value().checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn base_config(&self) -> Config {
+        self.config.read().expect("config lock poisoned").0.clone()
+    }
+
+    /// Replaces the base configuration and the tenant overrides of every namespace, existing
+    /// and future, keeping their wallets.
+    pub fn reconfigure(&self, config: Config, overrides: HashMap<Tenant, Settings>) {
+        *self.config.write().expect("config lock poisoned") = (config, overrides);
+        self.default.reconfigure(self.base_config());
+        for manager in self.tenants.iter() {
+            manager.value().reconfigure(self.config_for(manager.key()));
+        }
+    }
+
+    pub fn default_manager(&self) -> Arc<WalletManager> {
+        self.default.clone()
+    }
+
+    /// The manager of `tenant` if it was seen already, unlike `manager` never opening one.
+    pub fn existing_manager(&self, tenant: Option<&Tenant>) -> Option<Arc<WalletManager>> {
+        match tenant {
+            None => Some(self.default.clone()),
+            Some(tenant) => self.tenants.get(tenant).map(|r| r.value().clone()),
+        }
+    }
+
+    /// Managers of every tenant seen so far, sorted by tenant id.
+    pub fn tenant_managers(&self) -> Vec<(Tenant, Arc<WalletManager>)> {
+        let mut managers: Vec<_> = self
+            .tenants
+            .iter()
+            .map(|r| (r.key().clone(), r.value().clone()))
+            .collect();
+        managers.sort_by(|a, b| a.0.cmp(&b.0));
+        managers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, FailureKind, Transaction, TransactionId};
+
+    #[test]
+    fn test_tenants_are_isolated_and_use_overrides() {
+        let overrides = HashMap::from([(
+            Tenant::new("acme"),
+            Settings {
+                max_deposit: Some(Amount::from_major(5, 0)),
+                ..Settings::default()
+            },
+        )]);
+        let registry = TenantRegistry::new(Config::default(), overrides);
+        let deposit = |tenant: &str| Envelope {
+            tenant: Some(Tenant::new(tenant)),
+            ..Envelope::from(Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(1),
+                amount: Amount::from_major(10, 0),
+            })
+        };
+
+        let acme = registry.manager(Some(&Tenant::new("acme")));
+        let failure = acme.apply(deposit("acme")).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AmountOverLimit);
+
+        let globex = registry.manager(Some(&Tenant::new("globex")));
+        assert!(globex.apply(deposit("globex")).is_ok());
+
+        assert!(acme.export_wallets().is_empty());
+        assert_eq!(globex.export_wallets().len(), 1);
+        assert!(registry.default_manager().export_wallets().is_empty());
+    }
+}
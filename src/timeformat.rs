@@ -0,0 +1,167 @@
+use crate::transaction::Timestamp;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// How the `timestamp` input column is written. Every format is normalized to UTC seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `1704164645`, seconds since the Unix epoch
+    #[default]
+    EpochSecs,
+    /// `1704164645000`, milliseconds since the Unix epoch
+    EpochMillis,
+    /// `2024-01-02T03:04:05Z` or `2024-01-02T05:04:05.250+02:00`
+    Rfc3339,
+    /// A strftime pattern using `%Y %m %d %H %M %S %z %%`, e.g. `%d/%m/%Y %H:%M`. Times without
+    /// `%z` are taken as UTC.
+    Custom(String),
+}
+
+impl TimestampFormat {
+    pub fn parse(&self, s: &str) -> Option<Timestamp> {
+        match self {
+            TimestampFormat::EpochSecs => s.parse().ok().map(Timestamp::from_secs),
+            TimestampFormat::EpochMillis => s
+                .parse::<i64>()
+                .ok()
+                .map(|millis| Timestamp::from_secs(millis.div_euclid(1000))),
+            TimestampFormat::Rfc3339 => {
+                // Sub-second precision is dropped, timestamps are kept in whole seconds.
+                let (date, time) = s.split_once(['T', 't', ' '])?;
+                let time = match time.split_once('.') {
+                    Some((seconds, rest)) => {
+                        let zone = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+                        format!("{seconds}{zone}")
+                    }
+                    None => time.to_string(),
+                };
+                parse_pattern("%Y-%m-%dT%H:%M:%S%z", &format!("{date}T{time}"))
+            }
+            TimestampFormat::Custom(pattern) => parse_pattern(pattern, s),
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secs" => Ok(TimestampFormat::EpochSecs),
+            "millis" => Ok(TimestampFormat::EpochMillis),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            pattern if pattern.contains('%') => {
+                let mut chars = pattern.chars();
+                while let Some(c) = chars.next() {
+                    if c == '%' && !chars.next().is_some_and(|d| "YmdHMSz%".contains(d)) {
+                        return Err(format!("unsupported directive in {pattern:?}"));
+                    }
+                }
+                Ok(TimestampFormat::Custom(pattern.to_string()))
+            }
+            _ => Err(format!(
+                "invalid timestamp format {s:?}, expected `secs`, `millis`, `rfc3339` or a strftime pattern"
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimestampFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Reads `s` according to a strftime `pattern`; every other pattern character has to match
+/// literally.
+fn parse_pattern(pattern: &str, s: &str) -> Option<Timestamp> {
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second, mut offset) = (0, 0, 0, 0);
+    let mut input = s;
+    let mut pattern = pattern.chars();
+    while let Some(c) = pattern.next() {
+        if c != '%' {
+            input = input.strip_prefix(c)?;
+            continue;
+        }
+        match pattern.next()? {
+            'Y' => year = take_number(&mut input, 4)?,
+            'm' => month = take_number(&mut input, 2)?,
+            'd' => day = take_number(&mut input, 2)?,
+            'H' => hour = take_number(&mut input, 2)?,
+            'M' => minute = take_number(&mut input, 2)?,
+            'S' => second = take_number(&mut input, 2)?,
+            'z' => (offset, input) = parse_offset(input)?,
+            '%' => input = input.strip_prefix('%')?,
+            _ => return None,
+        }
+    }
+    if !input.is_empty() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let midnight = Timestamp::from_date(year as i32, month as u32, day as u32)?;
+    Some(Timestamp::from_secs(
+        midnight.as_secs() + hour * 3600 + minute * 60 + second - offset,
+    ))
+}
+
+/// Up to `max_digits` leading digits of `input`, advancing past them.
+fn take_number(input: &mut &str, max_digits: usize) -> Option<i64> {
+    let digits = input
+        .bytes()
+        .take(max_digits)
+        .take_while(u8::is_ascii_digit)
+        .count();
+    let (number, rest) = input.split_at(digits);
+    *input = rest;
+    number.parse().ok()
+}
+
+/// `Z`, `+HH:MM` or `+HHMM` as seconds east of UTC, and the rest of the input.
+fn parse_offset(s: &str) -> Option<(i64, &str)> {
+    if let Some(rest) = s.strip_prefix(['Z', 'z']) {
+        return Some((0, rest));
+    }
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let mut rest = &s[1..];
+    let hours = take_number(&mut rest, 2)?;
+    rest = rest.strip_prefix(':').unwrap_or(rest);
+    let minutes = take_number(&mut rest, 2)?;
+    Some((sign * (hours * 3600 + minutes * 60), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_normalize_to_utc_seconds() {
+        let expected = Some(Timestamp::from_secs(1_704_164_645));
+        let rfc3339 = TimestampFormat::Rfc3339;
+        assert_eq!(rfc3339.parse("2024-01-02T03:04:05Z"), expected);
+        assert_eq!(rfc3339.parse("2024-01-02T05:04:05.250+02:00"), expected);
+        assert_eq!(rfc3339.parse("2024-01-01 22:04:05-05:00"), expected);
+        assert_eq!(rfc3339.parse("2024-01-02T03:04:05"), None);
+        assert_eq!(
+            TimestampFormat::EpochMillis.parse("1704164645999"),
+            expected
+        );
+        assert_eq!(TimestampFormat::EpochSecs.parse("1704164645"), expected);
+
+        let custom: TimestampFormat = "%d/%m/%Y %H:%M:%S %z".parse().unwrap();
+        assert_eq!(custom.parse("02/01/2024 04:04:05 +0100"), expected);
+        assert_eq!(custom.parse("02/01/2024 04:04:05"), None);
+        let utc: TimestampFormat = "%Y%m%d%H%M%S".parse().unwrap();
+        assert_eq!(utc.parse("20240102030405"), expected);
+        assert!("%Y-%j".parse::<TimestampFormat>().is_err());
+        assert!("iso".parse::<TimestampFormat>().is_err());
+    }
+}
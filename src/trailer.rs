@@ -0,0 +1,147 @@
+//! Control totals of a batch file: an optional last row `trailer,,<records>,<sum>` gives the
+//! number of data rows and the sum of their amounts, so that a truncated or corrupted file is
+//! noticed after it was read.
+
+use crate::transaction::{Columns, field};
+use clap::ValueEnum;
+use csv::StringRecord;
+use log::warn;
+use std::path::Path;
+
+/// What happens when the rows read don't match the trailer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TrailerMismatch {
+    /// Log the mismatch and keep the results
+    Warn,
+    /// Fail the run before any output is written
+    #[default]
+    Fail,
+}
+
+impl TrailerMismatch {
+    /// Applies the policy to the outcome of `ControlTotals::verify` for `source`.
+    pub fn enforce(self, source: &Path, verified: Result<(), String>) -> anyhow::Result<()> {
+        let Err(mismatch) = verified else {
+            return Ok(());
+        };
+        let message = format!("{}: {mismatch}", source.display());
+        match self {
+            TrailerMismatch::Warn => {
+                warn!("{message}");
+                Ok(())
+            }
+            TrailerMismatch::Fail => Err(anyhow::anyhow!(message)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Trailer {
+    records: u64,
+    amount_units: i128,
+}
+
+/// Counts the data rows of one input and picks up its trailer.
+#[derive(Debug, Default)]
+pub struct ControlTotals {
+    records: u64,
+    /// Sum of the amount column in ten-thousandths, exact unlike the `f32` amounts.
+    amount_units: i128,
+    /// The trailer, `Err` with its raw fields if they don't parse.
+    trailer: Option<Result<Trailer, String>>,
+    rows_after_trailer: u64,
+}
+
+impl ControlTotals {
+    /// Counts `row`, returning whether it is the trailer rather than data.
+    pub fn record(&mut self, row: &StringRecord, columns: &Columns) -> bool {
+        let amount_units = |row: &StringRecord| {
+            row.get(columns.amount)
+                .and_then(|s| columns.parse_amount_units(s))
+        };
+        if field(row, columns.transaction_type) == Some("trailer") {
+            let records = field(row, columns.tx).and_then(|s| s.parse().ok());
+            self.trailer = Some(match (records, amount_units(row)) {
+                (Some(records), Some(amount_units)) => Ok(Trailer {
+                    records,
+                    amount_units,
+                }),
+                _ => Err(row.iter().collect::<Vec<_>>().join(",")),
+            });
+            return true;
+        }
+        if self.trailer.is_some() {
+            self.rows_after_trailer += 1;
+        }
+        self.records += 1;
+        self.amount_units += amount_units(row).unwrap_or_default();
+        false
+    }
+
+    /// Compares the rows read with the trailer. Inputs without a trailer always pass.
+    pub fn verify(&self) -> Result<(), String> {
+        let trailer = match &self.trailer {
+            None => return Ok(()),
+            Some(Err(raw)) => return Err(format!("malformed trailer `{raw}`")),
+            Some(Ok(trailer)) => trailer,
+        };
+        if self.rows_after_trailer > 0 {
+            return Err(format!(
+                "{} rows after the trailer, which has to be the last row",
+                self.rows_after_trailer
+            ));
+        }
+        if trailer.records != self.records || trailer.amount_units != self.amount_units {
+            return Err(format!(
+                "trailer expects {} records summing to {}, read {} summing to {}",
+                trailer.records,
+                format_units(trailer.amount_units),
+                self.records,
+                format_units(self.amount_units)
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn format_units(units: i128) -> String {
+    let sign = if units < 0 { "-" } else { "" };
+    let units = units.unsigned_abs();
+    format!("{sign}{}.{:04}", units / 10_000, units % 10_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(rows: &[&str]) -> Result<(), String> {
+        let mut totals = ControlTotals::default();
+        for row in rows {
+            let row: StringRecord = row.split(',').collect();
+            totals.record(&row, &Columns::default());
+        }
+        totals.verify()
+    }
+
+    #[test]
+    fn test_trailer_matches_rows_read() {
+        let rows = [
+            "deposit,1,1,1.1",
+            "deposit,1,2,2.2",
+            "dispute,1,1,",
+            "trailer,,3,3.3",
+        ];
+        assert_eq!(read(&rows), Ok(()));
+        assert_eq!(read(&rows[..2]), Ok(()));
+        assert_eq!(
+            read(&[rows[0], rows[1], "trailer,,3,3.3"]),
+            Err("trailer expects 3 records summing to 3.3000, read 2 summing to 3.3000".into())
+        );
+        assert!(read(&[rows[0], "trailer,,1,1.2"]).is_err());
+        assert!(read(&[rows[0], "trailer,,1,1.1", rows[1]]).is_err());
+        assert_eq!(
+            read(&["trailer,,x,1"]),
+            Err("malformed trailer `trailer,,x,1`".into())
+        );
+    }
+}
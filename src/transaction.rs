@@ -1,7 +1,15 @@
+use crate::config::TransactionLimits;
+use crate::enrich::Attributes;
+use crate::locale::AmountLocale;
+use crate::timeformat::TimestampFormat;
 use csv::StringRecord;
 use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
 use std::iter::Sum;
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum Transaction {
@@ -27,15 +35,97 @@ pub enum Transaction {
         client: Client,
         tx_id: TransactionId,
     },
+    /// The merchant won the reversal of a chargeback, crediting its amount back.
+    Represent {
+        client: Client,
+        tx_id: TransactionId,
+    },
 }
 
 impl Transaction {
-    pub fn from_csv_row(csv_row: &StringRecord) -> Option<Transaction> {
-        let transaction_type = csv_row.get(0)?;
-        let client: u16 = csv_row.get(1).and_then(|s| s.parse().ok())?;
-        let tx: u32 = csv_row.get(2).and_then(|s| s.parse().ok())?;
-        let amount: Option<f32> = csv_row.get(3).and_then(|s| s.parse().ok());
+    pub fn client(&self) -> Client {
+        match self {
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::ChargeBack { client, .. }
+            | Transaction::Represent { client, .. } => *client,
+        }
+    }
+
+    pub fn tx_id(&self) -> TransactionId {
+        match self {
+            Transaction::Deposit { tx_id, .. }
+            | Transaction::Withdrawal { tx_id, .. }
+            | Transaction::Dispute { tx_id, .. }
+            | Transaction::Resolve { tx_id, .. }
+            | Transaction::ChargeBack { tx_id, .. }
+            | Transaction::Represent { tx_id, .. } => *tx_id,
+        }
+    }
+
+    /// The `type` column value of the transaction.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit { .. } => "deposit",
+            Transaction::Withdrawal { .. } => "withdrawal",
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolve { .. } => "resolve",
+            Transaction::ChargeBack { .. } => "chargeback",
+            Transaction::Represent { .. } => "represent",
+        }
+    }
+
+    /// The amount moved by a deposit or withdrawal; disputes and their outcomes refer to the
+    /// amount of an earlier transaction instead.
+    pub fn amount(&self) -> Option<Amount> {
+        match self {
+            Transaction::Deposit { amount, .. } | Transaction::Withdrawal { amount, .. } => {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
 
+    /// The same transaction issued on behalf of another client.
+    pub fn with_client(self, client: Client) -> Transaction {
+        match self {
+            Transaction::Deposit { tx_id, amount, .. } => Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            },
+            Transaction::Withdrawal { tx_id, amount, .. } => Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount,
+            },
+            Transaction::Dispute { tx_id, .. } => Transaction::Dispute { client, tx_id },
+            Transaction::Resolve { tx_id, .. } => Transaction::Resolve { client, tx_id },
+            Transaction::ChargeBack { tx_id, .. } => Transaction::ChargeBack { client, tx_id },
+            Transaction::Represent { tx_id, .. } => Transaction::Represent { client, tx_id },
+        }
+    }
+
+    pub fn from_csv_row(csv_row: &StringRecord, columns: &Columns) -> Option<Transaction> {
+        let transaction_type = field(csv_row, columns.transaction_type)?;
+        let client: u16 = field(csv_row, columns.client).and_then(|s| s.parse().ok())?;
+        let tx: u32 = field(csv_row, columns.tx).and_then(|s| s.parse().ok())?;
+        let amount: Option<f32> = csv_row
+            .get(columns.amount)
+            .and_then(|s| columns.parse_amount(s));
+
+        Transaction::from_parts(transaction_type, client, tx, amount)
+    }
+
+    /// Builds a transaction from the raw input fields shared by every input format.
+    pub fn from_parts(
+        transaction_type: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<f32>,
+    ) -> Option<Transaction> {
         let tx_id = TransactionId(tx);
         let client = Client(client);
 
@@ -53,6 +143,7 @@ impl Transaction {
             "dispute" => Some(Transaction::Dispute { client, tx_id }),
             "resolve" => Some(Transaction::Resolve { client, tx_id }),
             "chargeback" => Some(Transaction::ChargeBack { client, tx_id }),
+            "represent" => Some(Transaction::Represent { client, tx_id }),
             _ => None,
         }
     }
@@ -61,14 +152,78 @@ impl Transaction {
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct Amount(f32);
 
+/// Ten-thousandths, the precision amounts are exported with.
+const MINOR_UNITS_PER_MAJOR: u32 = 10_000;
+
 impl Amount {
+    #[deprecated(note = "use `Amount::from_major` or `Amount::from_minor_units`")]
     pub fn unsafe_new(value: f32) -> Self {
         Amount(value)
     }
 
+    /// `major` whole units plus `fraction` ten-thousandths, e.g. `from_major(1, 2_500)` is 1.25.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` is a whole unit or more, which is a bug of the caller.
+    pub fn from_major(major: u64, fraction: u32) -> Self {
+        assert!(
+            fraction < MINOR_UNITS_PER_MAJOR,
+            "fraction of {fraction} ten-thousandths is a whole unit or more"
+        );
+        Amount(major as f32 + fraction as f32 / MINOR_UNITS_PER_MAJOR as f32)
+    }
+
+    /// An amount counted in ten-thousandths. Debits such as negative balance deltas are negated
+    /// amounts, e.g. `-Amount::from_minor_units(5_000)`.
+    ///
+    /// # Panics
+    ///
+    /// If `units` is negative, which is a bug of the caller.
+    pub fn from_minor_units(units: i64) -> Self {
+        assert!(units >= 0, "{units} ten-thousandths is a negative amount");
+        Amount(units as f32 / MINOR_UNITS_PER_MAJOR as f32)
+    }
+
     pub fn zero() -> Self {
         Amount(0.0)
     }
+
+    /// The amount as a float, for scoring and statistics rather than bookkeeping.
+    pub fn as_f32(self) -> f32 {
+        self.0
+    }
+
+    /// Whether both amounts agree at the four decimals amounts are exported with.
+    pub fn same_to_precision(self, other: Amount) -> bool {
+        (self.0 - other.0).abs() < 0.0001
+    }
+
+    /// Whether two sums over many amounts agree as far as `f32` can tell: to four decimals while
+    /// it keeps them, and to the hundred-thousandth of the larger one beyond, leaving room for
+    /// the rounding every wallet accumulates over its transactions.
+    pub fn same_sum(self, other: Amount) -> bool {
+        let magnitude = self.0.abs().max(other.0.abs());
+        (self.0 - other.0).abs() < (magnitude * 1e-5).max(0.0001)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4}", self.0)
+    }
+}
+
+/// Parses a plain decimal such as `1.5`; see `AmountLocale` for other notations.
+impl FromStr for Amount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = AmountLocale::Plain
+            .parse(s, false)
+            .ok_or_else(|| format!("invalid amount {s:?}"))?;
+        Amount::try_from(value)
+    }
 }
 
 impl TryFrom<f32> for Amount {
@@ -88,9 +243,8 @@ impl<'de> Deserialize<'de> for Amount {
     where
         D: serde::Deserializer<'de>,
     {
-        let s: &str = serde::Deserialize::deserialize(deserializer)?;
-        let value: f32 = s.parse().map_err(serde::de::Error::custom)?;
-        Amount::try_from(value).map_err(|e| serde::de::Error::custom(e))
+        let s: String = serde::Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -99,8 +253,7 @@ impl Serialize for Amount {
     where
         S: Serializer,
     {
-        let s = format!("{:.4}", self.0);
-        serializer.serialize_str(s.as_str())
+        serializer.serialize_str(self.to_string().as_str())
     }
 }
 
@@ -112,9 +265,10 @@ impl Add for Amount {
     }
 }
 
+/// Sums in double precision, so long sums don't accumulate the rounding of every addition.
 impl Sum for Amount {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        Amount(iter.map(|t| t.0).sum())
+        Amount(iter.map(|t| f64::from(t.0)).sum::<f64>() as f32)
     }
 }
 
@@ -130,6 +284,14 @@ impl SubAssign for Amount {
     }
 }
 
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Self::Output {
+        Amount(-self.0)
+    }
+}
+
 impl Sub for Amount {
     type Output = Amount;
 
@@ -138,13 +300,17 @@ impl Sub for Amount {
     }
 }
 
-#[derive(Hash, Eq, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[derive(Hash, Eq, Ord, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Client(u16);
 
 impl Client {
     pub fn new(id: u16) -> Self {
         Client(id)
     }
+
+    pub fn id(&self) -> u16 {
+        self.0
+    }
 }
 
 #[derive(Hash, Eq, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
@@ -154,25 +320,529 @@ impl TransactionId {
     pub fn new(id: u32) -> Self {
         TransactionId(id)
     }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Seconds since the Unix epoch, as carried by the optional `timestamp` input column.
+#[derive(Hash, Eq, Ord, Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const SECONDS_PER_DAY: i64 = 86_400;
+
+    pub fn from_secs(secs: i64) -> Self {
+        Timestamp(secs)
+    }
+
+    /// Midnight UTC of the given calendar day, or `None` for an invalid date.
+    pub fn from_date(year: i32, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        // Days since the epoch in the proleptic Gregorian calendar (H. Hinnant's algorithm).
+        let year = if month <= 2 { year - 1 } else { year } as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        Some(Timestamp(days * Self::SECONDS_PER_DAY))
+    }
+
+    pub fn as_secs(&self) -> i64 {
+        self.0
+    }
+
+    /// The UTC calendar day as `(year, month, day)`.
+    pub fn to_date(self) -> (i32, u32, u32) {
+        let days = self.0.div_euclid(Self::SECONDS_PER_DAY) + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        (year, month, day)
+    }
+
+    pub fn days_since(&self, earlier: Timestamp) -> i64 {
+        (self.0 - earlier.0) / Self::SECONDS_PER_DAY
+    }
+}
+
+/// Institution owning a transaction; every tenant has its own isolated set of wallets. Tenant
+/// ids name the reports and directories of their namespace, so they consist of ASCII letters,
+/// digits, `_` and `-` only.
+#[derive(Hash, Eq, Ord, Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Tenant(String);
+
+impl Tenant {
+    /// # Panics
+    ///
+    /// If `id` is not a valid tenant id; input is read with `parse`.
+    pub fn new(id: impl Into<String>) -> Self {
+        let id = id.into();
+        Tenant::parse(&id).unwrap_or_else(|| panic!("invalid tenant id {id:?}"))
+    }
+
+    /// `id` as a tenant id, `None` if it is empty or has other characters than ASCII letters,
+    /// digits, `_` and `-`.
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+        valid.then(|| Tenant(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A transaction together with the row metadata that is not part of the accounting rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub transaction: Transaction,
+    pub timestamp: Option<Timestamp>,
+    pub tenant: Option<Tenant>,
+    /// Sequence number from a journal being replayed; the manager numbers the transaction itself
+    /// when there is none.
+    pub seq: Option<u64>,
+    /// Further input columns and the fields added by enrichment.
+    pub attributes: Attributes,
+    /// Where the input had the transaction, if the rejection report asks for it.
+    pub origin: Option<Box<Origin>>,
+}
+
+/// Where in its input a transaction was read, to report its rejection with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Origin {
+    pub input: String,
+    /// Data row of a CSV input, after the header row, or record of the other formats, counting
+    /// from 1.
+    pub row: u64,
+    /// The row as read, for CSV inputs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl TryFrom<String> for Tenant {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Tenant::parse(&id).ok_or_else(|| format!("invalid tenant id {id:?}"))
+    }
+}
+
+impl Envelope {
+    pub fn from_csv_row(csv_row: &StringRecord, columns: &Columns) -> Option<Envelope> {
+        if let (Some(idx), Some(expected)) = (columns.currency, &columns.expected_currency)
+            && field(csv_row, idx) != Some(expected.as_str())
+        {
+            return None;
+        }
+        let transaction = Transaction::from_csv_row(csv_row, columns)?;
+        if columns.limits.check(&transaction).is_err() {
+            return None;
+        }
+        let timestamp = match columns.timestamp {
+            Some(idx) => match field(csv_row, idx) {
+                Some("") | None => None,
+                Some(s) => Some(columns.timestamp_format.parse(s)?),
+            },
+            None => None,
+        };
+        let tenant = match columns.tenant.and_then(|idx| field(csv_row, idx)) {
+            Some("") | None => None,
+            Some(s) => Some(Tenant::parse(s)?),
+        };
+        let seq = match columns.seq.and_then(|idx| field(csv_row, idx)) {
+            Some("") | None => None,
+            Some(s) => Some(s.parse().ok()?),
+        };
+        let attributes = columns
+            .attributes
+            .iter()
+            .filter_map(|(name, idx)| {
+                let value = field(csv_row, *idx).filter(|s| !s.is_empty())?;
+                Some((name.clone(), value.to_string()))
+            })
+            .collect();
+        Some(Envelope {
+            transaction,
+            timestamp,
+            tenant,
+            seq,
+            attributes,
+            origin: None,
+        })
+    }
+
+    /// Parses one line of the line protocol shared by the streaming sources: a CSV row
+    /// (`type,client,tx,amount[,timestamp[,tenant]]`) or a JSON object.
+    pub fn from_line(line: &str) -> Option<Envelope> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            return Envelope::from_json(line);
+        }
+        let record: StringRecord = line.split(',').map(str::trim).collect();
+        Envelope::from_csv_row(
+            &record,
+            &Columns {
+                timestamp: Some(4),
+                tenant: Some(5),
+                ..Columns::default()
+            },
+        )
+    }
+
+    /// Parses a JSON object with the CSV column names as keys, e.g.
+    /// `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}`.
+    pub fn from_json(json: &str) -> Option<Envelope> {
+        Envelope::from_json_value(serde_json::from_str(json).ok()?)
+    }
+
+    /// Like `from_json`, for an object decoded already, e.g. converted from a Parquet row.
+    pub fn from_json_value(value: serde_json::Value) -> Option<Envelope> {
+        let record: JsonRecord = serde_json::from_value(value).ok()?;
+        let transaction = Transaction::from_parts(
+            &record.transaction_type,
+            record.client,
+            record.tx,
+            record.amount,
+        )?;
+        Some(Envelope {
+            transaction,
+            timestamp: record.timestamp.map(Timestamp::from_secs),
+            tenant: record.tenant,
+            seq: record.seq,
+            attributes: record
+                .attributes
+                .into_iter()
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(value) => (name, value),
+                    value => (name, value.to_string()),
+                })
+                .collect(),
+            origin: None,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRecord {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: u16,
+    tx: u32,
+    amount: Option<f32>,
+    timestamp: Option<i64>,
+    tenant: Option<Tenant>,
+    seq: Option<u64>,
+    #[serde(flatten)]
+    attributes: BTreeMap<String, serde_json::Value>,
+}
+
+impl From<Transaction> for Envelope {
+    fn from(transaction: Transaction) -> Self {
+        Envelope {
+            transaction,
+            timestamp: None,
+            tenant: None,
+            seq: None,
+            attributes: Attributes::default(),
+            origin: None,
+        }
+    }
+}
+
+/// Field `idx` of a CSV row without surrounding whitespace. Inputs are read untrimmed so that
+/// strict amount parsing can reject padded amounts; every other field is trimmed here.
+pub(crate) fn field(csv_row: &StringRecord, idx: usize) -> Option<&str> {
+    csv_row.get(idx).map(str::trim)
 }
 
+/// Positions of the input columns, resolved from the CSV header row according to the input's
+/// `Schema`, and how the amount column is formatted.
 #[derive(Debug, Clone)]
+pub struct Columns {
+    pub transaction_type: usize,
+    pub client: usize,
+    pub tx: usize,
+    pub amount: usize,
+    pub currency: Option<usize>,
+    pub timestamp: Option<usize>,
+    pub tenant: Option<usize>,
+    pub seq: Option<usize>,
+    /// Columns without a meaning of their own, carried as attributes under their header name.
+    pub attributes: Vec<(String, usize)>,
+    pub amount_locale: AmountLocale,
+    /// Whether amounts may be padded or in scientific notation, see `AmountLocale::parse`.
+    pub lenient_amounts: bool,
+    pub timestamp_format: TimestampFormat,
+    /// Rows with a currency column in another currency are skipped.
+    pub expected_currency: Option<String>,
+    /// Deposits and withdrawals over these are not valid rows.
+    pub limits: TransactionLimits,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Columns {
+            transaction_type: 0,
+            client: 1,
+            tx: 2,
+            amount: 3,
+            currency: None,
+            timestamp: None,
+            tenant: None,
+            seq: None,
+            attributes: Vec::new(),
+            amount_locale: AmountLocale::default(),
+            lenient_amounts: false,
+            timestamp_format: TimestampFormat::default(),
+            expected_currency: None,
+            limits: TransactionLimits::default(),
+        }
+    }
+}
+
+impl Columns {
+    /// The amount in the amount column `s`.
+    pub fn parse_amount(&self, s: &str) -> Option<f32> {
+        self.amount_locale.parse(s, self.lenient_amounts)
+    }
+
+    /// The amount in the amount column `s` in exact ten-thousandths, see
+    /// `AmountLocale::parse_minor_units`.
+    pub fn parse_amount_units(&self, s: &str) -> Option<i128> {
+        self.amount_locale
+            .parse_minor_units(s, self.lenient_amounts)
+    }
+
+    /// The `type,client,tx,amount` layout, with the optional columns found by name.
+    pub fn from_headers(headers: &StringRecord) -> Self {
+        Columns {
+            timestamp: headers.iter().position(|h| h == "timestamp"),
+            tenant: headers.iter().position(|h| h == "tenant"),
+            seq: headers.iter().position(|h| h == "seq"),
+            ..Columns::default()
+        }
+        .with_attributes(headers)
+    }
+
+    /// Carries every column not read otherwise as an attribute. The currency column is both.
+    pub fn with_attributes(self, headers: &StringRecord) -> Self {
+        let read = [
+            Some(self.transaction_type),
+            Some(self.client),
+            Some(self.tx),
+            Some(self.amount),
+            self.timestamp,
+            self.tenant,
+            self.seq,
+        ];
+        let attributes = headers
+            .iter()
+            .enumerate()
+            .filter(|(idx, name)| !name.is_empty() && !read.contains(&Some(*idx)))
+            .map(|(idx, name)| (name.to_string(), idx))
+            .collect();
+        Columns { attributes, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum FailureKind {
+    InsufficientFunds,
+    BelowMinimumBalance,
+    AmountOverLimit,
+    NoWallet,
+    TransactionNotFound,
+    DisputeNotFound,
+    InvalidDispute,
+    DisputeLimitReached,
+    ChargeBackNotFound,
+    Quarantined,
+    Frozen,
+    AccountLocked,
+    RiskRejected,
+    DuplicateTransaction,
+    ClientMismatch,
+    JournalUnavailable,
+    WalletClosed,
+    /// A row of the input that isn't a transaction, reported by `--errors` only.
+    InvalidRow,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Failure {
     pub client: Client,
     pub tx: TransactionId,
+    pub kind: FailureKind,
     pub reason: String,
+    /// Sequence number the manager gave the failed transaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Where the input had the failed transaction, see `Envelope::origin`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Box<Origin>>,
 }
 
 impl Failure {
-    pub fn new(client: Client, tx: TransactionId, reason: String) -> Self {
-        Failure { client, tx, reason }
+    pub fn new(client: Client, tx: TransactionId, kind: FailureKind, reason: String) -> Self {
+        Failure {
+            client,
+            tx,
+            kind,
+            reason,
+            seq: None,
+            origin: None,
+        }
     }
 
     pub fn insufficient_funds(client: Client, tx: TransactionId) -> Self {
         Failure {
             client,
             tx,
+            kind: FailureKind::InsufficientFunds,
             reason: "Insufficient funds".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn below_minimum_balance(client: Client, tx: TransactionId, minimum: Amount) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::BelowMinimumBalance,
+            reason: format!(
+                "Withdrawal would leave less than the minimum balance of {:.4}",
+                minimum.0
+            ),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn amount_over_limit(client: Client, tx: TransactionId, limit: Amount) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::AmountOverLimit,
+            reason: format!("Amount exceeds the per-transaction limit of {:.4}", limit.0),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn quarantined(client: Client, tx: TransactionId) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::Quarantined,
+            reason: "Wallet is quarantined pending review".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn frozen(client: Client, tx: TransactionId) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::Frozen,
+            reason: "Wallet is frozen".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn account_locked(client: Client, tx: TransactionId) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::AccountLocked,
+            reason: "Account is locked after a chargeback".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn risk_rejected(client: Client, tx: TransactionId, score: f32) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::RiskRejected,
+            reason: format!("Risk score {score:.2} reached the rejection threshold"),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn dispute_limit_reached(client: Client, tx: TransactionId, max_cycles: u32) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::DisputeLimitReached,
+            reason: format!("Transaction was disputed {max_cycles} times already"),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn duplicate_transaction(client: Client, tx: TransactionId) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::DuplicateTransaction,
+            reason: "Transaction id was already used".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn client_mismatch(client: Client, tx: TransactionId, owner: Client) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::ClientMismatch,
+            reason: format!("Transaction belongs to client {}", owner.id()),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn wallet_closed(client: Client, tx: TransactionId) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::WalletClosed,
+            reason: "Wallet was closed earlier in the input".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+
+    pub fn journal_unavailable(client: Client, tx: TransactionId, error: &io::Error) -> Self {
+        Failure {
+            client,
+            tx,
+            kind: FailureKind::JournalUnavailable,
+            reason: format!("Transaction journal failed: {error}"),
+            seq: None,
+            origin: None,
         }
     }
 
@@ -180,7 +850,89 @@ impl Failure {
         Failure {
             client,
             tx,
+            kind: FailureKind::NoWallet,
             reason: "No wallet found for client".to_string(),
+            seq: None,
+            origin: None,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} tx {}: {} ({:?})",
+            self.client.0, self.tx.0, self.reason, self.kind
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_display_round_trips_through_from_str() {
+        let amount: Amount = "2.5".parse().unwrap();
+        assert_eq!(amount, Amount::from_major(2, 5000));
+        assert_eq!(amount.to_string(), "2.5000");
+        assert_eq!(amount.to_string().parse::<Amount>(), Ok(amount));
+        assert!("-1".parse::<Amount>().is_err());
+        assert!("abc".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "whole unit or more")]
+    fn test_from_major_rejects_whole_unit_fractions() {
+        Amount::from_major(1, 10_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "negative amount")]
+    fn test_from_minor_units_rejects_negative_units() {
+        Amount::from_minor_units(-1);
+    }
+
+    #[test]
+    fn test_rows_over_the_limits_are_rejected() {
+        let columns = Columns {
+            limits: TransactionLimits {
+                max_deposit: Some(Amount::from_major(1000, 0)),
+                max_withdrawal: None,
+            },
+            ..Columns::default()
+        };
+        let row = |kind, amount| StringRecord::from(vec![kind, "1", "1", amount]);
+        assert!(Envelope::from_csv_row(&row("deposit", "1000.0"), &columns).is_some());
+        assert_eq!(
+            Envelope::from_csv_row(&row("deposit", "1000.0001"), &columns),
+            None
+        );
+        assert!(Envelope::from_csv_row(&row("withdrawal", "5000.0"), &columns).is_some());
+    }
+
+    #[test]
+    fn test_rows_with_invalid_tenant_ids_are_rejected() {
+        let columns = Columns {
+            tenant: Some(4),
+            ..Columns::default()
+        };
+        let row = |tenant| StringRecord::from(vec!["deposit", "1", "1", "1.0", tenant]);
+        let envelope = Envelope::from_csv_row(&row("acme_eu-1"), &columns).unwrap();
+        assert_eq!(envelope.tenant, Some(Tenant::new("acme_eu-1")));
+        assert_eq!(
+            Envelope::from_csv_row(&row(""), &columns).unwrap().tenant,
+            None
+        );
+        for tenant in ["../x", "a/b", "acme eu", "."] {
+            assert_eq!(Envelope::from_csv_row(&row(tenant), &columns), None);
         }
+        assert!(
+            Envelope::from_json(
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.0, "tenant": "../x"}"#
+            )
+            .is_none()
+        );
     }
 }
@@ -0,0 +1,53 @@
+//! Operations spanning several wallets, such as transfers. They are applied in two phases so that
+//! no wallet is debited without the others being credited: every leg is checked and its debit
+//! reserved first, and only once all legs are prepared are they committed.
+
+use crate::transaction::{Amount, Client, Transaction, TransactionId};
+
+/// One wallet's part of a multi-wallet operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Leg {
+    Debit { client: Client, amount: Amount },
+    Credit { client: Client, amount: Amount },
+}
+
+impl Leg {
+    pub fn client(&self) -> Client {
+        match self {
+            Leg::Debit { client, .. } | Leg::Credit { client, .. } => *client,
+        }
+    }
+
+    /// The amount the leg adds to its wallet, negative for debits.
+    pub fn signed_amount(&self) -> Amount {
+        match self {
+            Leg::Debit { amount, .. } => -*amount,
+            Leg::Credit { amount, .. } => *amount,
+        }
+    }
+
+    /// The leg as the withdrawal or deposit of the operation `tx_id` it is logged as.
+    pub(crate) fn transaction(&self, tx_id: TransactionId) -> Transaction {
+        match *self {
+            Leg::Debit { client, amount } => Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount,
+            },
+            Leg::Credit { client, amount } => Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            },
+        }
+    }
+
+    /// The leg a logged withdrawal or deposit stands for.
+    pub(crate) fn of_transaction(transaction: &Transaction) -> Option<Leg> {
+        match *transaction {
+            Transaction::Withdrawal { client, amount, .. } => Some(Leg::Debit { client, amount }),
+            Transaction::Deposit { client, amount, .. } => Some(Leg::Credit { client, amount }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Write-ahead log of the transactions a wallet manager applies, for recovering its state after
+//! a crash. Every frame holds one transaction with the sequence number it was applied under, in
+//! the binary wire encoding: the body length as a little-endian `u32`, an FNV-1a checksum of the
+//! body as a little-endian `u32`, then the body. A crash in the middle of an append leaves a torn
+//! last frame, which reading stops at. The legs of a multi-wallet operation are logged as
+//! withdrawals and deposits sharing one sequence number.
+
+use crate::durability::{FsyncPolicy, JournalFile};
+use crate::transaction::Envelope;
+use crate::wire::{self, MAX_RECORD_LEN};
+use anyhow::Context;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+/// Bytes of a frame before its body.
+const FRAME_HEADER_LEN: usize = 8;
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// The appending end of the log.
+#[derive(Debug)]
+pub struct Wal {
+    journal: JournalFile,
+}
+
+impl Wal {
+    /// Opens the log at `path` for appending, keeping what it holds.
+    pub fn open(path: &Path, fsync: FsyncPolicy) -> io::Result<Self> {
+        Ok(Wal {
+            journal: JournalFile::open(path, fsync)?,
+        })
+    }
+
+    /// Appends `envelope`, which has to carry the sequence number it is applied under.
+    pub fn append(&mut self, envelope: &Envelope) -> io::Result<()> {
+        let body = wire::encode_transaction(envelope);
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&checksum(&body).to_le_bytes());
+        frame.extend_from_slice(&body);
+        self.journal.append(&frame)
+    }
+
+    /// Empties the log, once a checkpoint holds everything it did.
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.journal.file().set_len(0)?;
+        self.journal.file().sync_all()
+    }
+
+    /// Syncs what the fsync policy left unsynced.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.journal.sync()
+    }
+}
+
+/// What reading a log found.
+#[derive(Debug, Default)]
+pub struct WalContents {
+    pub envelopes: Vec<Envelope>,
+    /// Whether the log ended in a torn or corrupt frame, which was dropped with whatever followed.
+    pub torn: bool,
+}
+
+/// Reads the log at `path`, which may not exist yet.
+pub fn read(path: &Path) -> anyhow::Result<WalContents> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(WalContents::default()),
+        Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+    };
+    let mut reader = BufReader::new(file);
+    let mut contents = WalContents::default();
+    loop {
+        let mut header = [0; FRAME_HEADER_LEN];
+        match read_full(&mut reader, &mut header)? {
+            0 => break,
+            FRAME_HEADER_LEN => {}
+            _ => {
+                contents.torn = true;
+                break;
+            }
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let sum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if len > MAX_RECORD_LEN {
+            contents.torn = true;
+            break;
+        }
+        let mut body = vec![0; len];
+        let envelope = (read_full(&mut reader, &mut body)? == len && checksum(&body) == sum)
+            .then(|| wire::decode_transaction(&body))
+            .flatten();
+        match envelope {
+            Some(envelope) if envelope.seq.is_some() => contents.envelopes.push(envelope),
+            _ => {
+                contents.torn = true;
+                break;
+            }
+        }
+    }
+    Ok(contents)
+}
+
+/// Reads until `buf` is full or the input ends, returning how many bytes were read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, Transaction, TransactionId};
+    use std::io::Write;
+
+    #[test]
+    fn test_reading_stops_at_a_torn_frame() {
+        let path = std::env::temp_dir().join(format!("wal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let envelopes: Vec<Envelope> = (1..=3)
+            .map(|tx| Envelope {
+                seq: Some(u64::from(tx)),
+                ..Envelope::from(Transaction::Deposit {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(u64::from(tx), 0),
+                })
+            })
+            .collect();
+        let mut wal = Wal::open(&path, FsyncPolicy::Never).unwrap();
+        for envelope in &envelopes {
+            wal.append(envelope).unwrap();
+        }
+        let contents = read(&path).unwrap();
+        assert_eq!(contents.envelopes, envelopes);
+        assert!(!contents.torn);
+
+        // A crash half way through the next append.
+        let full = std::fs::read(&path).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&full[..FRAME_HEADER_LEN + 1]).unwrap();
+        let contents = read(&path).unwrap();
+        assert_eq!(contents.envelopes, envelopes);
+        assert!(contents.torn);
+
+        wal.reset().unwrap();
+        assert!(read(&path).unwrap().envelopes.is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(read(&path).unwrap().envelopes.is_empty());
+    }
+}
@@ -1,7 +1,10 @@
-use crate::transaction::{Amount, Client, Failure, TransactionId};
+use crate::lifecycle::{LifecycleChange, LockReason};
+use crate::transaction::{
+    Amount, Client, Failure, FailureKind, Timestamp, Transaction, TransactionId,
+};
 use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Balance {
@@ -11,21 +14,76 @@ pub struct Balance {
 }
 
 impl Balance {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Balance {
-            available: Amount::unsafe_new(0.0),
-            held: Amount::unsafe_new(0.0),
-            total: Amount::unsafe_new(0.0),
+            available: Amount::zero(),
+            held: Amount::zero(),
+            total: Amount::zero(),
         }
     }
+
+    /// Per-field change from `before` to this balance.
+    pub fn delta_since(&self, before: &Balance) -> Balance {
+        Balance {
+            available: self.available - before.available,
+            held: self.held - before.held,
+            total: self.total - before.total,
+        }
+    }
+}
+
+/// Counters of the transactions processed for one wallet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WalletStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub failures: u64,
+}
+
+impl WalletStats {
+    pub fn record(&mut self, transaction: &Transaction, applied: bool) {
+        let counter = match transaction {
+            _ if !applied => &mut self.failures,
+            Transaction::Deposit { .. } => &mut self.deposits,
+            Transaction::Withdrawal { .. } => &mut self.withdrawals,
+            Transaction::Dispute { .. } => &mut self.disputes,
+            Transaction::Resolve { .. }
+            | Transaction::ChargeBack { .. }
+            | Transaction::Represent { .. } => return,
+        };
+        *counter += 1;
+    }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Wallet {
     pub(super) client: Client,
     pub(super) balance: Balance,
     pub(super) locked: bool,
     pub(super) open_disputes: HashMap<TransactionId, Amount>,
+    /// Disputes opened per transaction, counting the resolved ones.
+    pub(super) dispute_cycles: HashMap<TransactionId, u32>,
+    /// When the open disputes were opened, as far as the input tells.
+    pub(super) disputed_at: HashMap<TransactionId, Timestamp>,
+    /// Charged back transactions with their amount, until they are re-presented.
+    pub(super) charged_back: HashMap<TransactionId, Amount>,
+    /// Open disputes of withdrawals rather than deposits, whose held funds were credited back.
+    pub(super) disputed_withdrawals: HashSet<TransactionId>,
+    /// Charged back withdrawals with their amount, returned to the wallet for good.
+    pub(super) reversed_withdrawals: HashMap<TransactionId, Amount>,
+    pub(super) last_activity: Option<Timestamp>,
+    pub(super) dormant: bool,
+    pub(super) joint_owners: Vec<Client>,
+    /// Frozen after a failure under `FailurePolicy::Quarantine`.
+    pub(super) quarantined: bool,
+    /// Frozen by an administrator. Unlike a chargeback lock, unfreezing lifts it again.
+    pub(super) frozen: bool,
+    /// Why the wallet was locked, frozen or quarantined, oldest first.
+    pub(super) lock_reasons: Vec<LockReason>,
+    pub(super) stats: WalletStats,
+    /// Sequence number of the last transaction applied to, or failed on, this wallet.
+    pub(super) last_seq: u64,
 }
 
 impl Wallet {
@@ -35,13 +93,195 @@ impl Wallet {
             balance: Balance::new(),
             locked: false,
             open_disputes: HashMap::new(),
+            dispute_cycles: HashMap::new(),
+            disputed_at: HashMap::new(),
+            charged_back: HashMap::new(),
+            disputed_withdrawals: HashSet::new(),
+            reversed_withdrawals: HashMap::new(),
+            last_activity: None,
+            dormant: false,
+            joint_owners: Vec::new(),
+            quarantined: false,
+            frozen: false,
+            lock_reasons: Vec::new(),
+            stats: WalletStats::default(),
+            last_seq: 0,
+        }
+    }
+
+    /// A wallet that `joint_owners` may operate on in addition to `client`.
+    pub fn joint(client: Client, joint_owners: Vec<Client>) -> Self {
+        Wallet {
+            joint_owners,
+            ..Wallet::new(client)
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        self.client
+    }
+
+    pub fn balance(&self) -> &Balance {
+        &self.balance
+    }
+
+    pub fn available(&self) -> Amount {
+        self.balance.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.balance.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.balance.total
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// `frozen`, `locked` or `active`. A frozen wallet shows as frozen even if it is also locked,
+    /// which the `locked` column tells apart.
+    pub fn status(&self) -> &'static str {
+        if self.frozen {
+            "frozen"
+        } else if self.locked {
+            "locked"
+        } else {
+            "active"
         }
     }
 
-    pub fn dispute(&mut self, tx: TransactionId, amount: Amount) {
+    /// Every reason the wallet was locked, frozen or quarantined for, oldest first, including
+    /// those of restrictions lifted since.
+    pub fn lock_history(&self) -> &[LockReason] {
+        &self.lock_reasons
+    }
+
+    /// The reason of the latest restriction still in effect, if any.
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        self.lock_reasons
+            .iter()
+            .rev()
+            .copied()
+            .find(|reason| match reason.change() {
+                LifecycleChange::Locked => self.locked,
+                LifecycleChange::Frozen => self.frozen,
+                _ => self.quarantined,
+            })
+    }
+
+    /// Amounts held for each disputed transaction.
+    pub fn open_disputes(&self) -> &HashMap<TransactionId, Amount> {
+        &self.open_disputes
+    }
+
+    pub fn disputed_at(&self, tx: TransactionId) -> Option<Timestamp> {
+        self.disputed_at.get(&tx).copied()
+    }
+
+    pub fn charged_back(&self) -> &HashMap<TransactionId, Amount> {
+        &self.charged_back
+    }
+
+    /// Whether the open dispute of `tx` is one of a withdrawal.
+    pub fn is_disputed_withdrawal(&self, tx: TransactionId) -> bool {
+        self.disputed_withdrawals.contains(&tx)
+    }
+
+    pub fn reversed_withdrawals(&self) -> &HashMap<TransactionId, Amount> {
+        &self.reversed_withdrawals
+    }
+
+    pub fn owners(&self) -> impl Iterator<Item = Client> + '_ {
+        std::iter::once(self.client).chain(self.joint_owners.iter().copied())
+    }
+
+    pub fn touch(&mut self, timestamp: Option<Timestamp>) {
+        if let Some(timestamp) = timestamp {
+            self.last_activity = Some(self.last_activity.map_or(timestamp, |t| t.max(timestamp)));
+        }
+    }
+
+    /// Flags the wallet as dormant and deducts `fee` from the available funds, never taking the
+    /// balance below zero. Returns the amount actually charged.
+    pub fn mark_dormant(&mut self, fee: Option<Amount>) -> Amount {
+        self.dormant = true;
+        let charged = match fee {
+            Some(fee) if fee <= self.balance.available => fee,
+            Some(_) if self.balance.available > Amount::zero() => self.balance.available,
+            _ => Amount::zero(),
+        };
+        self.balance.available -= charged;
+        self.balance.total -= charged;
+        charged
+    }
+
+    /// Holds `amount` of a deposit under dispute. A transaction can be disputed again once its
+    /// dispute is resolved, up to `max_cycles` disputes in all.
+    pub fn dispute(
+        &mut self,
+        tx: TransactionId,
+        amount: Amount,
+        max_cycles: Option<u32>,
+    ) -> Result<(), Failure> {
+        self.open_dispute(tx, amount, max_cycles)?;
         self.balance.available -= amount;
         self.balance.held += amount;
+        Ok(())
+    }
+
+    /// Credits `amount` of a withdrawal under dispute back to the wallet, held until the dispute
+    /// ends: resolving it lets the withdrawal stand, charging it back returns the funds for good.
+    pub fn dispute_withdrawal(
+        &mut self,
+        tx: TransactionId,
+        amount: Amount,
+        max_cycles: Option<u32>,
+    ) -> Result<(), Failure> {
+        if self.reversed_withdrawals.contains_key(&tx) {
+            return Err(Failure::new(
+                self.client,
+                tx,
+                FailureKind::InvalidDispute,
+                "Withdrawal was already charged back!".to_string(),
+            ));
+        }
+        self.open_dispute(tx, amount, max_cycles)?;
+        self.balance.held += amount;
+        self.balance.total += amount;
+        self.disputed_withdrawals.insert(tx);
+        Ok(())
+    }
+
+    fn open_dispute(
+        &mut self,
+        tx: TransactionId,
+        amount: Amount,
+        max_cycles: Option<u32>,
+    ) -> Result<(), Failure> {
+        if self.open_disputes.contains_key(&tx) {
+            return Err(Failure::new(
+                self.client,
+                tx,
+                FailureKind::InvalidDispute,
+                "Transaction is already disputed!".to_string(),
+            ));
+        }
+        let cycles = self.dispute_cycles.entry(tx).or_default();
+        if let Some(max) = max_cycles
+            && *cycles >= max
+        {
+            return Err(Failure::dispute_limit_reached(self.client, tx, max));
+        }
+        *cycles += 1;
         self.open_disputes.insert(tx, amount);
+        Ok(())
     }
 
     pub fn deposit(&mut self, _tx: TransactionId, amount: Amount) {
@@ -49,42 +289,124 @@ impl Wallet {
         self.balance.total += amount;
     }
 
-    pub fn settle_dispute(&mut self, tx: TransactionId) -> Result<(), Failure> {
-        if let Some(disputed_amount) = self.open_disputes.get(&tx) {
-            self.balance.held -= *disputed_amount;
-            self.balance.available += *disputed_amount;
-            Ok(())
+    /// Releases held funds of a disputed transaction and closes its dispute, returning the
+    /// released amount. The funds of a disputed withdrawal leave the wallet again instead.
+    pub fn settle_dispute(&mut self, tx: TransactionId) -> Result<Amount, Failure> {
+        if let Some(disputed_amount) = self.open_disputes.remove(&tx) {
+            self.disputed_at.remove(&tx);
+            self.balance.held -= disputed_amount;
+            if self.disputed_withdrawals.remove(&tx) {
+                self.balance.total -= disputed_amount;
+            } else {
+                self.balance.available += disputed_amount;
+            }
+            Ok(disputed_amount)
         } else {
             Err(Failure::new(
                 self.client,
                 tx,
+                FailureKind::DisputeNotFound,
                 "Disputed transaction not found for settlement!".to_string(),
             ))
         }
     }
 
-    pub fn charge_back(&mut self, tx: TransactionId) -> Result<(), Failure> {
-        if let Some(disputed_amount) = self.open_disputes.get(&tx) {
-            self.balance.held -= *disputed_amount;
-            self.balance.total -= *disputed_amount;
+    /// Reverses a disputed transaction, closing its dispute, and locks the wallet, returning the
+    /// charged back amount. A reversed withdrawal makes its held funds available instead, and
+    /// leaves the wallet unlocked: the client was the one wronged.
+    pub fn charge_back(&mut self, tx: TransactionId) -> Result<Amount, Failure> {
+        if let Some(disputed_amount) = self.open_disputes.remove(&tx) {
+            self.disputed_at.remove(&tx);
+            self.balance.held -= disputed_amount;
+            if self.disputed_withdrawals.remove(&tx) {
+                self.balance.available += disputed_amount;
+                self.reversed_withdrawals.insert(tx, disputed_amount);
+                return Ok(disputed_amount);
+            }
+            self.balance.total -= disputed_amount;
             self.locked = true;
-            Ok(())
+            self.lock_reasons.push(LockReason::ChargeBack(tx));
+            self.charged_back.insert(tx, disputed_amount);
+            Ok(disputed_amount)
         } else {
             Err(Failure::new(
                 self.client,
                 tx,
+                FailureKind::DisputeNotFound,
                 "Disputed transaction not found for charge back!".to_string(),
             ))
         }
     }
 
+    /// Credits a charged back transaction again after the merchant won its reversal, unlocking
+    /// the wallet if `unlock` is set. Returns the re-credited amount.
+    pub fn represent(&mut self, tx: TransactionId, unlock: bool) -> Result<Amount, Failure> {
+        let Some(amount) = self.charged_back.remove(&tx) else {
+            return Err(Failure::new(
+                self.client,
+                tx,
+                FailureKind::ChargeBackNotFound,
+                "Charged back transaction not found for re-presentment!".to_string(),
+            ));
+        };
+        self.balance.available += amount;
+        self.balance.total += amount;
+        if unlock {
+            self.locked = false;
+        }
+        Ok(amount)
+    }
+
+    /// Sets `amount` aside as held for a multi-wallet operation, if at least `minimum` stays
+    /// available afterwards. The operation then either commits or releases it.
+    pub fn reserve(
+        &mut self,
+        tx: TransactionId,
+        amount: Amount,
+        minimum: Amount,
+    ) -> Result<(), Failure> {
+        if self.balance.available < amount {
+            Err(Failure::insufficient_funds(self.client, tx))
+        } else if self.balance.available - amount < minimum {
+            Err(Failure::below_minimum_balance(self.client, tx, minimum))
+        } else {
+            self.balance.available -= amount;
+            self.balance.held += amount;
+            Ok(())
+        }
+    }
+
+    /// Makes available again funds reserved for an operation that was aborted.
+    pub fn release(&mut self, amount: Amount) {
+        self.balance.held -= amount;
+        self.balance.available += amount;
+    }
+
+    /// Takes reserved funds out of the wallet once their operation commits.
+    pub fn commit_reserved(&mut self, amount: Amount) {
+        self.balance.held -= amount;
+        self.balance.total -= amount;
+    }
+
     pub fn withdraw(&mut self, tx: TransactionId, amount: Amount) -> Result<(), Failure> {
-        if self.balance.available >= amount {
+        self.withdraw_keeping(tx, amount, Amount::zero())
+    }
+
+    /// Withdraws `amount` only if at least `minimum` stays available afterwards.
+    pub fn withdraw_keeping(
+        &mut self,
+        tx: TransactionId,
+        amount: Amount,
+        minimum: Amount,
+    ) -> Result<(), Failure> {
+        if self.balance.available < amount {
+            Err(Failure::insufficient_funds(self.client, tx))
+        } else if self.balance.available - amount < minimum {
+            Err(Failure::below_minimum_balance(self.client, tx, minimum))
+        } else {
             self.balance.available -= amount;
             self.balance.total -= amount;
             Ok(())
-        } else {
-            Err(Failure::insufficient_funds(self.client, tx))
         }
     }
 }
@@ -104,6 +426,63 @@ impl Serialize for Wallet {
     }
 }
 
+/// Reads the columns written by `Serialize`, ignoring any optional export columns.
+impl<'de> Deserialize<'de> for Wallet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Row {
+            client: Client,
+            #[serde(deserialize_with = "signed_amount")]
+            available: Amount,
+            #[serde(deserialize_with = "signed_amount")]
+            held: Amount,
+            #[serde(deserialize_with = "signed_amount")]
+            total: Amount,
+            locked: bool,
+            #[serde(default)]
+            seq: u64,
+            #[serde(default)]
+            status: Option<String>,
+            #[serde(default)]
+            lock_reason: Option<String>,
+        }
+
+        let row = Row::deserialize(deserializer)?;
+        let lock_reasons = match row.lock_reason.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(reason) => vec![reason.parse().map_err(serde::de::Error::custom)?],
+        };
+        Ok(Wallet {
+            balance: Balance {
+                available: row.available,
+                held: row.held,
+                total: row.total,
+            },
+            locked: row.locked,
+            last_seq: row.seq,
+            frozen: row.status.as_deref() == Some("frozen"),
+            lock_reasons,
+            ..Wallet::new(row.client)
+        })
+    }
+}
+
+/// Balances, unlike transaction amounts, go negative when a dispute holds funds already withdrawn.
+fn signed_amount<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    match s.strip_prefix('-') {
+        Some(magnitude) => magnitude.parse().map(|amount: Amount| -amount),
+        None => s.parse(),
+    }
+    .map_err(serde::de::Error::custom)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,7 +492,7 @@ mod tests {
         let client = Client::new(1);
         let mut wallet = Wallet::new(client);
         let tx_id = TransactionId::new(1001);
-        let amount = Amount::unsafe_new(150.0);
+        let amount = Amount::from_major(150, 0);
 
         wallet.deposit(tx_id, amount);
 
@@ -126,34 +505,81 @@ mod tests {
         let client = Client::new(1);
         let mut wallet = Wallet::new(client);
         let tx_id = TransactionId::new(1001);
-        let deposit_amount = Amount::unsafe_new(200.0);
-        let withdraw_amount = Amount::unsafe_new(50.0);
+        let deposit_amount = Amount::from_major(200, 0);
+        let withdraw_amount = Amount::from_major(50, 0);
 
         wallet.deposit(tx_id, deposit_amount);
         let result = wallet.withdraw(tx_id, withdraw_amount);
 
         assert!(result.is_ok());
-        assert_eq!(wallet.balance.available, Amount::unsafe_new(150.0));
+        assert_eq!(wallet.balance.available, Amount::from_major(150, 0));
         assert_eq!(wallet.balance.total, deposit_amount - withdraw_amount);
     }
 
+    #[test]
+    fn test_wallet_withdraw_keeping_minimum_balance() {
+        let mut wallet = Wallet::new(Client::new(1));
+        wallet.deposit(TransactionId::new(1001), Amount::from_major(100, 0));
+        let minimum = Amount::from_major(25, 0);
+
+        let breach =
+            wallet.withdraw_keeping(TransactionId::new(1002), Amount::from_major(80, 0), minimum);
+        assert_eq!(breach.unwrap_err().kind, FailureKind::BelowMinimumBalance);
+        assert_eq!(wallet.balance.available, Amount::from_major(100, 0));
+
+        let overdraw = wallet.withdraw_keeping(
+            TransactionId::new(1003),
+            Amount::from_major(120, 0),
+            minimum,
+        );
+        assert_eq!(overdraw.unwrap_err().kind, FailureKind::InsufficientFunds);
+
+        assert!(
+            wallet
+                .withdraw_keeping(TransactionId::new(1004), Amount::from_major(75, 0), minimum)
+                .is_ok()
+        );
+        assert_eq!(wallet.balance.available, minimum);
+    }
+
     #[test]
     fn test_wallet_dispute_and_settle() {
         let client = Client::new(1);
         let mut wallet = Wallet::new(client);
         let tx_id = TransactionId::new(1001);
-        let deposit_amount = Amount::unsafe_new(300.0);
-        let dispute_amount = Amount::unsafe_new(100.0);
+        let deposit_amount = Amount::from_major(300, 0);
+        let dispute_amount = Amount::from_major(100, 0);
 
         wallet.deposit(tx_id, deposit_amount);
-        wallet.dispute(tx_id, dispute_amount);
+        wallet.dispute(tx_id, dispute_amount, None).unwrap();
 
-        assert_eq!(wallet.balance.available, Amount::unsafe_new(200.0));
+        assert_eq!(wallet.balance.available, Amount::from_major(200, 0));
         assert_eq!(wallet.balance.held, dispute_amount);
 
         let settle_result = wallet.settle_dispute(tx_id);
         assert!(settle_result.is_ok());
-        assert_eq!(wallet.balance.available, Amount::unsafe_new(300.0));
+        assert_eq!(wallet.balance.available, Amount::from_major(300, 0));
+        assert_eq!(wallet.balance.held, Amount::zero());
+        assert!(wallet.settle_dispute(tx_id).is_err());
+    }
+
+    #[test]
+    fn test_wallet_dispute_cycles_are_limited() {
+        let mut wallet = Wallet::new(Client::new(1));
+        let tx_id = TransactionId::new(1001);
+        let amount = Amount::from_major(100, 0);
+        wallet.deposit(tx_id, amount);
+
+        wallet.dispute(tx_id, amount, Some(2)).unwrap();
+        let again = wallet.dispute(tx_id, amount, Some(2));
+        assert_eq!(again.unwrap_err().kind, FailureKind::InvalidDispute);
+        wallet.settle_dispute(tx_id).unwrap();
+        wallet.dispute(tx_id, amount, Some(2)).unwrap();
+        wallet.settle_dispute(tx_id).unwrap();
+
+        let third = wallet.dispute(tx_id, amount, Some(2));
+        assert_eq!(third.unwrap_err().kind, FailureKind::DisputeLimitReached);
+        assert_eq!(wallet.balance.available, amount);
         assert_eq!(wallet.balance.held, Amount::zero());
     }
 
@@ -162,19 +588,104 @@ mod tests {
         let client = Client::new(1);
         let mut wallet = Wallet::new(client);
         let tx_id = TransactionId::new(1001);
-        let deposit_amount = Amount::unsafe_new(400.0);
-        let dispute_amount = Amount::unsafe_new(150.0);
+        let deposit_amount = Amount::from_major(400, 0);
+        let dispute_amount = Amount::from_major(150, 0);
 
         wallet.deposit(tx_id, deposit_amount);
-        wallet.dispute(tx_id, dispute_amount);
+        wallet.dispute(tx_id, dispute_amount, None).unwrap();
 
-        assert_eq!(wallet.balance.available, Amount::unsafe_new(250.0));
+        assert_eq!(wallet.balance.available, Amount::from_major(250, 0));
         assert_eq!(wallet.balance.held, dispute_amount);
 
         let charge_back_result = wallet.charge_back(tx_id);
         assert!(charge_back_result.is_ok());
-        assert_eq!(wallet.balance.total, Amount::unsafe_new(250.0));
+        assert_eq!(wallet.balance.total, Amount::from_major(250, 0));
         assert_eq!(wallet.balance.held, Amount::zero());
         assert!(wallet.locked);
     }
+
+    #[test]
+    fn test_wallet_represent() {
+        let mut wallet = Wallet::new(Client::new(1));
+        let tx_id = TransactionId::new(1001);
+        let deposit_amount = Amount::from_major(400, 0);
+        let dispute_amount = Amount::from_major(150, 0);
+        wallet.deposit(tx_id, deposit_amount);
+        wallet.dispute(tx_id, dispute_amount, None).unwrap();
+        wallet.charge_back(tx_id).unwrap();
+        assert!(wallet.charge_back(tx_id).is_err());
+
+        assert_eq!(wallet.represent(tx_id, true).unwrap(), dispute_amount);
+        assert_eq!(wallet.balance.available, deposit_amount);
+        assert_eq!(wallet.balance.total, deposit_amount);
+        assert!(!wallet.locked);
+        let again = wallet.represent(tx_id, true);
+        assert_eq!(again.unwrap_err().kind, FailureKind::ChargeBackNotFound);
+    }
+
+    #[test]
+    fn test_wallet_lock_reasons() {
+        let mut wallet = Wallet::new(Client::new(1));
+        let tx_id = TransactionId::new(1001);
+        wallet.deposit(tx_id, Amount::from_major(400, 0));
+        wallet
+            .dispute(tx_id, Amount::from_major(150, 0), None)
+            .unwrap();
+        assert_eq!(wallet.lock_reason(), None);
+
+        wallet.charge_back(tx_id).unwrap();
+        assert_eq!(wallet.lock_reason(), Some(LockReason::ChargeBack(tx_id)));
+        wallet.represent(tx_id, true).unwrap();
+        assert_eq!(wallet.lock_reason(), None);
+        assert_eq!(wallet.lock_history(), [LockReason::ChargeBack(tx_id)]);
+    }
+
+    #[test]
+    fn test_wallet_withdrawal_dispute_resolve_and_charge_back() {
+        let mut wallet = Wallet::new(Client::new(1));
+        let (deposit, withdrawal) = (TransactionId::new(1), TransactionId::new(2));
+        wallet.deposit(deposit, Amount::from_major(100, 0));
+        wallet
+            .withdraw(withdrawal, Amount::from_major(40, 0))
+            .unwrap();
+
+        let amount = Amount::from_major(40, 0);
+        wallet.dispute_withdrawal(withdrawal, amount, None).unwrap();
+        assert!(wallet.is_disputed_withdrawal(withdrawal));
+        assert_eq!(wallet.balance.available, Amount::from_major(60, 0));
+        assert_eq!(wallet.balance.held, amount);
+        assert_eq!(wallet.balance.total, Amount::from_major(100, 0));
+
+        // Resolving lets the withdrawal stand.
+        assert_eq!(wallet.settle_dispute(withdrawal).unwrap(), amount);
+        assert!(!wallet.is_disputed_withdrawal(withdrawal));
+        assert_eq!(wallet.balance.available, Amount::from_major(60, 0));
+        assert_eq!(wallet.balance.held, Amount::zero());
+        assert_eq!(wallet.balance.total, Amount::from_major(60, 0));
+
+        // Charging back returns the funds, and the withdrawal can't be disputed again.
+        wallet.dispute_withdrawal(withdrawal, amount, None).unwrap();
+        assert_eq!(wallet.charge_back(withdrawal).unwrap(), amount);
+        assert_eq!(wallet.balance.available, Amount::from_major(100, 0));
+        assert_eq!(wallet.balance.held, Amount::zero());
+        assert_eq!(wallet.balance.total, Amount::from_major(100, 0));
+        assert!(!wallet.locked);
+        assert!(wallet.charged_back.is_empty());
+        assert_eq!(wallet.reversed_withdrawals()[&withdrawal], amount);
+        let again = wallet.dispute_withdrawal(withdrawal, amount, None);
+        assert_eq!(again.unwrap_err().kind, FailureKind::InvalidDispute);
+    }
+
+    #[test]
+    fn test_wallet_mark_dormant_caps_fee_at_available() {
+        let mut wallet = Wallet::new(Client::new(1));
+        wallet.deposit(TransactionId::new(1001), Amount::from_major(3, 0));
+
+        let charged = wallet.mark_dormant(Some(Amount::from_major(5, 0)));
+
+        assert!(wallet.dormant);
+        assert_eq!(charged, Amount::from_major(3, 0));
+        assert_eq!(wallet.balance.available, Amount::zero());
+        assert_eq!(wallet.balance.total, Amount::zero());
+    }
 }
@@ -1,269 +1,4526 @@
-use crate::transaction::{Client, Failure, Transaction, TransactionId};
-use crate::wallet::Wallet;
+use crate::analytics::{DepositVolume, DepositWindows};
+use crate::config::{Config, FailurePolicy, MissingWallet};
+use crate::deferred::PendingDisputes;
+use crate::dormancy::{DormancyPolicy, DormantWallet};
+use crate::enrich::Attributes;
+use crate::events::{EventHub, WalletEvent};
+use crate::expiry::{ExpiringHolds, Hold};
+use crate::house::HouseAccounts;
+use crate::journal::{DiskJournal, Journal, JournalEntry, MemoryJournal, funds_of};
+use crate::ledger::{LedgerEntry, Movement};
+use crate::lifecycle::{LifecycleChange, LifecycleEvent, LifecycleState, LockReason};
+use crate::merkle::to_hex;
+use crate::persistence::{Persistence, Recovery};
+use crate::quarantine::QuarantinedTransaction;
+use crate::reservation::{Reservation, ReservationId};
+use crate::risk::{ClientRisk, RiskAction, RiskDecision, RiskFeatures, RiskScore};
+use crate::session::{SESSION_ATTRIBUTE, SessionActivity};
+use crate::snapshot::Snapshot;
+use crate::spool::FailureSpool;
+use crate::transaction::{
+    Amount, Client, Envelope, Failure, FailureKind, Timestamp, Transaction, TransactionId,
+};
+use crate::transfer::Leg;
+use crate::wallet::{Balance, Wallet};
+use anyhow::Context;
 use dashmap::DashMap;
+use dashmap::mapref::one::RefMut;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
 
+/// What a `run` worked through, returned once its input is closed or it stops early.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RunReport {
+    pub processed: u64,
+    pub failed: u64,
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub duration: Duration,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub representments: u64,
+    /// Whether the run stopped before its input was closed, e.g. aborted by the failure policy.
+    pub stopped_early: bool,
+}
+
+impl RunReport {
+    pub fn record(&mut self, transaction: &Transaction, ok: bool) {
+        self.processed += 1;
+        if !ok {
+            self.failed += 1;
+        }
+        match transaction {
+            Transaction::Deposit { .. } => self.deposits += 1,
+            Transaction::Withdrawal { .. } => self.withdrawals += 1,
+            Transaction::Dispute { .. } => self.disputes += 1,
+            Transaction::Resolve { .. } => self.resolves += 1,
+            Transaction::ChargeBack { .. } => self.chargebacks += 1,
+            Transaction::Represent { .. } => self.representments += 1,
+        }
+    }
+
+    /// Adds the counts of `other`, e.g. of another worker of the same run.
+    pub fn merge(&mut self, other: RunReport) {
+        self.processed += other.processed;
+        self.failed += other.failed;
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.representments += other.representments;
+        self.stopped_early |= other.stopped_early;
+    }
+}
+
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// State of a `WalletManager` to roll back to, e.g. when a batch turns out not to add up.
+#[derive(Debug, Clone)]
+pub struct Savepoint {
+    wallets: DashMap<Client, Wallet>,
+    latest_timestamp: i64,
+    house: HouseAccounts,
+    ledger_len: usize,
+    quarantine_len: usize,
+    lifecycle_len: usize,
+    failures: usize,
+    sequence: u64,
+    risk: DashMap<Client, ClientRisk>,
+    risk_journal_len: usize,
+    deposit_windows: DashMap<Client, DepositWindows>,
+    pending_disputes: PendingDisputes,
+    expiring_holds: ExpiringHolds,
+    reservations: DashMap<ReservationId, Reservation>,
+    sessions: DashMap<(String, Client), SessionActivity>,
+}
+
+/// A wallet's balance before and after `WalletManager::rebuild`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rebuilt {
+    pub client: Client,
+    pub before: Balance,
+    pub after: Balance,
+}
+
+/// The state `WalletManager::simulate` projects a wallet to be in after a transaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectedBalance {
+    pub client: Client,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// A client's wallet with its slice of the transaction journal, moving from one manager to
+/// another, e.g. between the shards of a `Cluster`.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    pub(crate) wallet: Wallet,
+    pub(crate) journal: HashMap<TransactionId, JournalEntry>,
+}
+
+impl Handoff {
+    /// The part of the wallet's total its journal slice doesn't account for, which moves
+    /// between the managers' opening balances.
+    fn carried(&self) -> Amount {
+        self.wallet.total() - funds_of(self.journal.values())
+    }
+}
+
 pub struct WalletManager {
     wallets: DashMap<Client, Wallet>,
-    transaction_journal: DashMap<Client, HashMap<TransactionId, Transaction>>, // For big sets would require a more memory efficient struct
+    /// Deposits and withdrawals for disputes to refer to, in memory or on disk as
+    /// `Config::journal_dir` says.
+    transaction_journal: Box<dyn Journal>,
+    /// Set once the transaction journal failed, which stops the run: disputes of the
+    /// transactions it missed would be rejected.
+    journal_failed: AtomicBool,
+    latest_timestamp: AtomicI64,
+    house: Mutex<HouseAccounts>,
+    ledger: Option<Mutex<Vec<LedgerEntry>>>,
+    quarantine: Option<Mutex<Vec<QuarantinedTransaction>>>,
+    lifecycle: Option<Mutex<Vec<LifecycleEvent>>>,
+    events: Option<EventHub>,
+    failures: AtomicUsize,
+    /// Last sequence number handed out or replayed; transactions are numbered from 1 in the order
+    /// they reach `apply`.
+    sequence: AtomicU64,
+    risk: DashMap<Client, ClientRisk>,
+    risk_journal: Mutex<Vec<RiskDecision>>,
+    deposit_windows: DashMap<Client, DepositWindows>,
+    pending_disputes: Mutex<PendingDisputes>,
+    /// Dispute holds to release once `Config::dispute_expiry_secs` passed.
+    expiring_holds: Mutex<ExpiringHolds>,
+    /// Failures of deferred disputes, which don't belong to the transaction being applied.
+    deferred_failures: Mutex<Vec<Failure>>,
+    /// Failures `run` couldn't deliver because their receiver was dropped.
+    failure_spool: FailureSpool,
+    /// Write-ahead log and checkpoints, as `Config::persistence` says.
+    persistence: Option<Persistence>,
+    /// Funds held for pending operations until they are committed or released.
+    reservations: DashMap<ReservationId, Reservation>,
+    /// Activity of every session in every wallet.
+    sessions: DashMap<(String, Client), SessionActivity>,
+    /// Last reservation id handed out.
+    next_reservation: AtomicU64,
+    /// Swapped as a whole by `reconfigure`, so a transaction sees either the old or the new one.
+    config: RwLock<Arc<Config>>,
+}
+
+pub(crate) fn sharded_map<K: Eq + Hash, V>(shards: Option<usize>) -> DashMap<K, V> {
+    match shards {
+        Some(shards) => DashMap::with_shard_amount(shards),
+        None => DashMap::new(),
+    }
 }
 
-impl WalletManager {
-    pub fn init() -> Self {
-        WalletManager {
-            wallets: DashMap::new(),
-            transaction_journal: DashMap::new(),
+impl WalletManager {
+    pub fn init() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// # Panics
+    ///
+    /// If the journal file of `Config::journal_dir` can't be created or the write-ahead log of
+    /// `Config::persistence` can't be opened; see `try_with_config`.
+    pub fn with_config(config: Config) -> Self {
+        Self::try_with_config(config).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// A manager for `config`, failing if the journal file of `Config::journal_dir` can't be
+    /// created or the write-ahead log of `Config::persistence` can't be opened.
+    pub fn try_with_config(config: Config) -> io::Result<Self> {
+        Ok(WalletManager {
+            wallets: sharded_map(config.map_shards),
+            transaction_journal: match &config.journal_dir {
+                Some(dir) => Box::new(DiskJournal::create_in(dir).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("failed to create a journal file in {}: {e}", dir.display()),
+                    )
+                })?),
+                None => Box::new(MemoryJournal::new(config.map_shards)),
+            },
+            journal_failed: AtomicBool::new(false),
+            latest_timestamp: AtomicI64::new(i64::MIN),
+            house: Mutex::new(HouseAccounts::new()),
+            ledger: config.keep_ledger.then(|| Mutex::new(Vec::new())),
+            quarantine: config.keep_quarantine.then(|| Mutex::new(Vec::new())),
+            lifecycle: config.keep_lifecycle.then(|| Mutex::new(Vec::new())),
+            events: None,
+            failures: AtomicUsize::new(0),
+            sequence: AtomicU64::new(0),
+            risk: DashMap::new(),
+            risk_journal: Mutex::new(Vec::new()),
+            deposit_windows: DashMap::new(),
+            pending_disputes: Mutex::new(PendingDisputes::default()),
+            expiring_holds: Mutex::new(ExpiringHolds::default()),
+            deferred_failures: Mutex::new(Vec::new()),
+            failure_spool: FailureSpool::new(config.undelivered_failures.clone()),
+            persistence: config
+                .persistence
+                .clone()
+                .map(|options| {
+                    let dir = options.dir.clone();
+                    Persistence::open(options).map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!(
+                                "failed to open the write-ahead log in {}: {e}",
+                                dir.display()
+                            ),
+                        )
+                    })
+                })
+                .transpose()?,
+            reservations: DashMap::new(),
+            sessions: DashMap::new(),
+            next_reservation: AtomicU64::new(0),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().expect("config lock poisoned").clone()
+    }
+
+    /// Applies `config` to the transactions from now on, keeping every wallet and journal. What
+    /// the manager records (`keep_ledger` and the like) is fixed at construction.
+    pub fn reconfigure(&self, config: Config) {
+        *self.config.write().expect("config lock poisoned") = Arc::new(config);
+    }
+
+    /// Publishes a `WalletEvent` to `events` for every transaction applied from now on.
+    pub fn with_events(mut self, events: EventHub) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Applies the transactions of `tx_recv` until it closes, or until the failure policy
+    /// aborts the run.
+    pub async fn run(
+        &self,
+        mut tx_recv: UnboundedReceiver<Envelope>,
+        err_send: UnboundedSender<Failure>,
+    ) -> RunReport {
+        let started = Instant::now();
+        let mut report = RunReport::default();
+        while let Some(envelope) = tx_recv.recv().await {
+            let transaction = envelope.transaction;
+            let res = self.apply(envelope);
+            report.record(&transaction, res.is_ok());
+            if let Err(e) = res {
+                self.failure_spool.deliver(&err_send, e);
+                if self.aborted() {
+                    report.stopped_early = true;
+                    break;
+                }
+            }
+        }
+        report.duration = started.elapsed();
+        report
+    }
+
+    /// Like `run`, applying the transactions of different wallets in parallel on `workers`
+    /// tasks. Transactions are routed by wallet, so every wallet sees its own in input order;
+    /// how those of different wallets interleave, and so the sequence numbers they get, varies
+    /// from run to run. Envelopes replayed with their sequence number need `run`'s single order.
+    pub async fn run_sharded(
+        self: Arc<Self>,
+        mut tx_recv: UnboundedReceiver<Envelope>,
+        err_send: UnboundedSender<Failure>,
+        workers: usize,
+    ) -> RunReport {
+        let started = Instant::now();
+        let (shards, handles): (Vec<_>, Vec<_>) = (0..workers.max(1))
+            .map(|_| {
+                let (shard_send, mut shard_recv) =
+                    tokio::sync::mpsc::unbounded_channel::<Envelope>();
+                let manager = self.clone();
+                let err_send = err_send.clone();
+                let handle = tokio::spawn(async move {
+                    let mut report = RunReport::default();
+                    while let Some(envelope) = shard_recv.recv().await {
+                        let transaction = envelope.transaction;
+                        let res = manager.apply(envelope);
+                        report.record(&transaction, res.is_ok());
+                        if let Err(e) = res {
+                            manager.failure_spool.deliver(&err_send, e);
+                            if manager.aborted() {
+                                report.stopped_early = true;
+                                break;
+                            }
+                        }
+                    }
+                    report
+                });
+                (shard_send, handle)
+            })
+            .unzip();
+        drop(err_send);
+        let mut report = RunReport::default();
+        while let Some(envelope) = tx_recv.recv().await {
+            if self.aborted() {
+                report.stopped_early = true;
+                break;
+            }
+            let wallet = self.config().wallet_of(envelope.transaction.client());
+            let shard = &shards[usize::from(wallet.id()) % shards.len()];
+            if shard.send(envelope).is_err() {
+                break;
+            }
+        }
+        drop(shards);
+        for handle in handles {
+            report.merge(handle.await.expect("shard worker panicked"));
+        }
+        report.duration = started.elapsed();
+        report
+    }
+
+    /// Applies `envelope`. Envelopes replayed from a journal carry their sequence number, and
+    /// those at or below the last applied one are skipped, so replaying an overlapping journal
+    /// over a snapshot doesn't apply anything twice.
+    ///
+    /// With `Config::persistence`, the transaction is logged before it is applied, and a
+    /// checkpoint is taken once one is due.
+    pub fn apply(&self, envelope: Envelope) -> Result<(), Failure> {
+        let Some(persistence) = &self.persistence else {
+            return self.apply_logged(envelope, true);
+        };
+        let res = {
+            let _applying = persistence.applying();
+            self.apply_logged(envelope, true)
+        };
+        self.checkpoint_if_due(persistence);
+        res
+    }
+
+    fn checkpoint_if_due(&self, persistence: &Persistence) {
+        if persistence.checkpoint_due(self.last_sequence())
+            && let Err(e) = self.checkpoint()
+        {
+            error!("Failed to checkpoint the wallets: {e:#}");
+        }
+    }
+
+    /// `apply`, appending the transaction to the write-ahead log first if `log` is set.
+    fn apply_logged(&self, mut envelope: Envelope, log: bool) -> Result<(), Failure> {
+        let config = self.config();
+        if let Some(enricher) = &config.enricher {
+            enricher.enrich(&mut envelope);
+        }
+        let transaction = envelope.transaction;
+        let timestamp = envelope.timestamp;
+        let client = config.wallet_of(transaction.client());
+        let lifecycle_before = self
+            .lifecycle
+            .as_ref()
+            .map(|_| self.lifecycle_state(client));
+        let next_seq = || match envelope.seq {
+            Some(seq) if seq <= self.last_sequence() => None,
+            Some(seq) => {
+                self.sequence.fetch_max(seq, Ordering::Relaxed);
+                Some(seq)
+            }
+            None => Some(self.sequence.fetch_add(1, Ordering::Relaxed) + 1),
+        };
+        let seq = match &self.persistence {
+            Some(persistence) if log => persistence.log(std::slice::from_ref(&envelope), next_seq),
+            _ => next_seq(),
+        };
+        let Some(seq) = seq else {
+            return Ok(());
+        };
+        if let Some(session) = &config.session
+            && envelope.attributes.get(SESSION_ATTRIBUTE).is_none()
+        {
+            envelope
+                .attributes
+                .insert(SESSION_ATTRIBUTE, session.as_str());
+        }
+        let session = envelope
+            .attributes
+            .get(SESSION_ATTRIBUTE)
+            .map(|session| (session.to_string(), self.balance_of(client)));
+        let origin = envelope.origin.take();
+        // Joint owners act on the wallet keyed by another client; failures name who acted.
+        let res = self.apply_envelope(envelope, seq).map_err(|mut failure| {
+            failure.client = transaction.client();
+            failure.seq = Some(seq);
+            failure.origin = origin;
+            failure
+        });
+        if let Some(mut wallet) = self.wallets.get_mut(&client) {
+            wallet.stats.record(&transaction, res.is_ok());
+            wallet.last_seq = seq;
+        }
+        if let Some((session, before)) = session {
+            let delta = self.balance_of(client).delta_since(&before);
+            self.sessions
+                .entry((session.clone(), client))
+                .or_insert_with(|| SessionActivity::new(&session, client, seq))
+                .record(seq, res.is_ok(), &delta);
+        }
+        if let Err(failure) = &res {
+            self.count_failure(failure);
+        }
+        if let Some(before) = lifecycle_before {
+            let tx = Some(transaction.tx_id());
+            self.record_lifecycle(client, before, seq, tx, timestamp);
+        }
+        if let Some(limit) = config.dispute_deferral {
+            if res.is_ok() && transaction.amount().is_some() {
+                self.apply_pending_dispute(client, transaction.tx_id());
+            }
+            let expired = self.pending_disputes().expire(seq, limit);
+            self.fail_pending_disputes(expired);
+        }
+        if config.dispute_expiry_secs.is_some() {
+            self.expire_disputes();
+        }
+        res
+    }
+
+    /// Numbers a transaction applied outside of `apply`, or the legs of a multi-wallet
+    /// operation, appending it to the write-ahead log first like `apply` does.
+    fn log_next(&self, envelopes: &[Envelope]) -> u64 {
+        let next = || Some(self.sequence.fetch_add(1, Ordering::Relaxed) + 1);
+        match &self.persistence {
+            Some(persistence) => persistence.log(envelopes, next),
+            None => next(),
+        }
+        .expect("fresh sequence numbers are always handed out")
+    }
+
+    /// Remembers a deposit or withdrawal, numbered `seq`, for the disputes that may follow.
+    fn journal(
+        &self,
+        config: &Config,
+        transaction: Transaction,
+        seq: Option<u64>,
+    ) -> Result<(), Failure> {
+        if config.skip_journal {
+            return Ok(());
+        }
+        self.transaction_journal
+            .record(JournalEntry { transaction, seq })
+            .map_err(|e| self.journal_failure(transaction.client(), transaction.tx_id(), e))
+    }
+
+    /// Fails the transaction `tx_id` of `client` over a journal I/O error, and stops the run.
+    fn journal_failure(&self, client: Client, tx_id: TransactionId, error: io::Error) -> Failure {
+        self.journal_failed.store(true, Ordering::Relaxed);
+        Failure::journal_unavailable(client, tx_id, &error)
+    }
+
+    /// Passes on the outcome of a journal operation, stopping the run if it failed.
+    fn journaled<T>(&self, res: io::Result<T>) -> io::Result<T> {
+        if res.is_err() {
+            self.journal_failed.store(true, Ordering::Relaxed);
+        }
+        res
+    }
+
+    /// The balance of the wallet `client`, zero without one.
+    fn balance_of(&self, client: Client) -> Balance {
+        self.wallets
+            .get(&client)
+            .map_or_else(Balance::new, |w| w.balance.clone())
+    }
+
+    fn lifecycle_state(&self, client: Client) -> Option<LifecycleState> {
+        self.wallets.get(&client).map(|w| LifecycleState::of(&w))
+    }
+
+    /// Records the transitions of the wallet `client` since it was in state `before`.
+    fn record_lifecycle(
+        &self,
+        client: Client,
+        before: Option<LifecycleState>,
+        seq: u64,
+        tx: Option<TransactionId>,
+        timestamp: Option<Timestamp>,
+    ) {
+        let Some(lifecycle) = &self.lifecycle else {
+            return;
+        };
+        let changes = LifecycleChange::between(before, self.lifecycle_state(client));
+        if changes.is_empty() {
+            return;
+        }
+        let lock_reasons = self
+            .wallets
+            .get(&client)
+            .map(|wallet| wallet.lock_reasons.clone())
+            .unwrap_or_default();
+        let mut lifecycle = lifecycle.lock().expect("lifecycle lock poisoned");
+        for change in changes {
+            lifecycle.push(LifecycleEvent {
+                seq,
+                client,
+                change,
+                tx,
+                timestamp,
+                reason: lock_reasons
+                    .iter()
+                    .rev()
+                    .copied()
+                    .find(|reason| reason.change() == change),
+            });
+        }
+    }
+
+    fn count_failure(&self, failure: &Failure) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        if self.config().failure_policy == FailurePolicy::Quarantine
+            && let Some(mut wallet) = self
+                .wallets
+                .get_mut(&self.config().wallet_of(failure.client))
+            && !wallet.quarantined
+        {
+            wallet.quarantined = true;
+            wallet.lock_reasons.push(LockReason::Quarantine(failure.tx));
+        }
+    }
+
+    fn pending_disputes(&self) -> MutexGuard<'_, PendingDisputes> {
+        self.pending_disputes
+            .lock()
+            .expect("pending disputes lock poisoned")
+    }
+
+    /// Parks a dispute of a transaction that hasn't arrived yet when disputes are deferred,
+    /// returning whether it was parked.
+    fn park_unmatched_dispute(&self, transaction: &Transaction, seq: u64) -> Result<bool, Failure> {
+        let Transaction::Dispute { client, tx_id } = *transaction else {
+            return Ok(false);
+        };
+        let owner = self
+            .transaction_journal
+            .owner(tx_id)
+            .map_err(|e| self.journal_failure(client, tx_id, e))?;
+        Ok(owner != Some(client)
+            && self.config().dispute_deferral.is_some()
+            && self.pending_disputes().park(client, tx_id, seq))
+    }
+
+    /// Applies the dispute parked for a transaction that just arrived, if any.
+    fn apply_pending_dispute(&self, client: Client, tx_id: TransactionId) {
+        if !self.pending_disputes().take(client, tx_id) {
+            return;
+        }
+        // Not logged: replaying the transaction it waited for applies it again.
+        if let Err(failure) =
+            self.apply_logged(Transaction::Dispute { client, tx_id }.into(), false)
+        {
+            self.deferred_failures().push(failure);
+        }
+    }
+
+    fn fail_pending_disputes(&self, expired: Vec<(Client, TransactionId, u64)>) {
+        for (client, tx_id, seq) in expired {
+            let mut failure = Failure::new(
+                client,
+                tx_id,
+                FailureKind::TransactionNotFound,
+                "Transaction to dispute didn't arrive in time!".to_string(),
+            );
+            failure.seq = Some(seq);
+            self.count_failure(&failure);
+            self.deferred_failures().push(failure);
+        }
+    }
+
+    fn expiring_holds(&self) -> MutexGuard<'_, ExpiringHolds> {
+        self.expiring_holds
+            .lock()
+            .expect("expiring holds lock poisoned")
+    }
+
+    /// Dates a dispute just opened by its timestamp or the latest one seen, and starts the expiry
+    /// of its hold. Disputes in input without timestamps stay undated and never expire.
+    fn date_dispute(&self, client: Client, tx_id: TransactionId, timestamp: Option<Timestamp>) {
+        let Some(opened) = timestamp.or_else(|| self.latest_timestamp()) else {
+            return;
+        };
+        let Some(cycle) = self.wallets.get_mut(&client).and_then(|mut wallet| {
+            wallet.disputed_at.insert(tx_id, opened);
+            wallet.dispute_cycles.get(&tx_id).copied()
+        }) else {
+            return;
+        };
+        if self.config().dispute_expiry_secs.is_none() {
+            return;
+        }
+        self.expiring_holds().place(Hold {
+            opened,
+            client,
+            tx_id,
+            cycle,
+        });
+    }
+
+    /// Releases the holds of disputes open for longer than `Config::dispute_expiry_secs` as of
+    /// the latest timestamp seen, recording each release in the ledger. Returns how many were
+    /// released.
+    pub fn expire_disputes(&self) -> usize {
+        let (Some(ttl), Some(now)) = (self.config().dispute_expiry_secs, self.latest_timestamp())
+        else {
+            return 0;
+        };
+        let due = self.expiring_holds().expire(now, ttl);
+        let mut released = 0;
+        for hold in due {
+            let (amount, movement) = {
+                let Some(mut wallet) = self.wallets.get_mut(&hold.client) else {
+                    continue;
+                };
+                // Resolved, charged back or disputed again since.
+                if wallet.dispute_cycles.get(&hold.tx_id) != Some(&hold.cycle) {
+                    continue;
+                }
+                let withdrawal = wallet.is_disputed_withdrawal(hold.tx_id);
+                let Ok(amount) = wallet.settle_dispute(hold.tx_id) else {
+                    continue;
+                };
+                if withdrawal {
+                    self.house().settle_withdrawal_dispute(amount);
+                    (amount, Movement::WithdrawalRelease)
+                } else {
+                    (amount, Movement::Release)
+                }
+            };
+            info!(
+                "Released the expired hold of tx {} of client {:?}",
+                hold.tx_id.id(),
+                hold.client
+            );
+            released += 1;
+            if let Some(ledger) = &self.ledger {
+                ledger
+                    .lock()
+                    .expect("ledger lock poisoned")
+                    .push(LedgerEntry {
+                        timestamp: Some(now),
+                        seq: None,
+                        client: hold.client,
+                        tx_id: Some(hold.tx_id),
+                        movement,
+                        amount,
+                        attributes: Attributes::default(),
+                    });
+            }
+        }
+        released
+    }
+
+    fn deferred_failures(&self) -> MutexGuard<'_, Vec<Failure>> {
+        self.deferred_failures
+            .lock()
+            .expect("deferred failures lock poisoned")
+    }
+
+    /// Failures of deferred disputes since the last call: disputes that failed once their
+    /// transaction arrived and disputes whose transaction never did.
+    pub fn take_deferred_failures(&self) -> Vec<Failure> {
+        std::mem::take(&mut *self.deferred_failures())
+    }
+
+    /// Sends `failure` to `err_send`, spooling it if the receiver was dropped, so that a dead
+    /// failure consumer doesn't stop processing.
+    pub fn deliver_failure(&self, err_send: &UnboundedSender<Failure>, failure: Failure) {
+        self.failure_spool.deliver(err_send, failure);
+    }
+
+    /// Failures spooled because their receiver was dropped; see `Config::undelivered_failures`.
+    pub fn failure_spool(&self) -> &FailureSpool {
+        &self.failure_spool
+    }
+
+    /// Fails every dispute still waiting for its transaction, once no more input is expected.
+    pub fn expire_pending_disputes(&self) {
+        let expired = self.pending_disputes().drain();
+        self.fail_pending_disputes(expired);
+    }
+
+    /// Whether `FailurePolicy::Abort` asks to stop processing, or the transaction journal failed.
+    pub fn aborted(&self) -> bool {
+        let config = self.config();
+        self.journal_failed.load(Ordering::Relaxed)
+            || config.failure_policy == FailurePolicy::Abort
+                && self.failure_count() > config.max_failures
+    }
+
+    pub fn failure_policy(&self) -> FailurePolicy {
+        self.config().failure_policy
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Sequence number of the last transaction that reached the manager, 0 before the first.
+    pub fn last_sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    fn apply_envelope(&self, envelope: Envelope, seq: u64) -> Result<(), Failure> {
+        let client = self.config().wallet_of(envelope.transaction.client());
+        let transaction = envelope.transaction.with_client(client);
+        if let Some(wallet) = self.wallets.get(&client) {
+            let failure = if wallet.frozen {
+                Some(Failure::frozen(client, transaction.tx_id()))
+            } else if wallet.quarantined {
+                Some(Failure::quarantined(client, transaction.tx_id()))
+            } else if wallet.locked && !matches!(transaction, Transaction::Represent { .. }) {
+                // Only a re-presentment of the chargeback may still unlock the wallet.
+                Some(Failure::account_locked(client, transaction.tx_id()))
+            } else {
+                None
+            };
+            if let Some(failure) = failure {
+                if let Some(quarantine) = &self.quarantine {
+                    quarantine.lock().expect("quarantine lock poisoned").push(
+                        QuarantinedTransaction::new(&envelope, &wallet, seq, &failure),
+                    );
+                }
+                return Err(failure);
+            }
+        }
+        self.check_tx_id(&transaction)?;
+        if self.park_unmatched_dispute(&transaction, seq)? {
+            return Ok(());
+        }
+        self.config().limits.check(&transaction)?;
+        let risk = self.assess_risk(&transaction, &envelope);
+        if let Some((score, RiskAction::Reject)) = risk {
+            return Err(Failure::risk_rejected(client, transaction.tx_id(), score));
+        }
+        let watched = self.events.as_ref().filter(|events| events.is_watched());
+        let before = watched.map(|_| self.balance_of(client));
+        let movement = self.movement_of(&transaction)?;
+        let res = self.apply_transaction(transaction, seq);
+        if let (Ok(amount), Some(ledger)) = (&res, &self.ledger) {
+            ledger
+                .lock()
+                .expect("ledger lock poisoned")
+                .push(LedgerEntry {
+                    timestamp: envelope.timestamp,
+                    seq: Some(seq),
+                    client,
+                    tx_id: Some(transaction.tx_id()),
+                    movement,
+                    amount: *amount,
+                    attributes: envelope.attributes.clone(),
+                });
+        }
+        if let (Ok(_), Some(events), Some(before)) = (&res, watched, before)
+            && let Some(wallet) = self.wallets.get(&client)
+        {
+            events.publish(WalletEvent {
+                tenant: events.tenant().cloned(),
+                seq,
+                client,
+                tx_id: transaction.tx_id(),
+                movement,
+                delta: wallet.balance.delta_since(&before),
+                balance: wallet.balance.clone(),
+                locked: wallet.locked,
+            });
+        }
+        match (&res, risk) {
+            (Ok(_), Some((score, RiskAction::Hold))) => self.freeze_for_risk(
+                RiskDecision {
+                    seq,
+                    client,
+                    reason: format!("risk score {score:.2} reached the hold threshold"),
+                },
+                LockReason::RiskScore,
+            ),
+            (Ok(_), Some((score, RiskAction::Flag))) => {
+                warn!("Flagged wallet {client:?} for a transaction scoring {score:.2}");
+            }
+            _ => {}
+        }
+        if res.is_ok() {
+            self.monitor_chargebacks(client, movement, seq);
+        }
+        if res.is_ok()
+            && self.config().risk_scorer.is_some()
+            && let Some(timestamp) = envelope.timestamp
+        {
+            self.risk
+                .entry(client)
+                .or_default()
+                .record_applied(timestamp);
+        }
+        if let (Ok(_), Transaction::Deposit { amount, .. }, Some(timestamp)) =
+            (&res, transaction, envelope.timestamp)
+        {
+            self.deposit_windows
+                .entry(client)
+                .or_default()
+                .record(timestamp, amount);
+        }
+        if let (Ok(_), Transaction::Dispute { tx_id, .. }) = (&res, transaction) {
+            self.date_dispute(client, tx_id, envelope.timestamp);
+        }
+        if let Some(timestamp) = envelope.timestamp {
+            self.latest_timestamp
+                .fetch_max(timestamp.as_secs(), Ordering::Relaxed);
+            if let Some(mut wallet) = self.wallets.get_mut(&client) {
+                wallet.touch(Some(timestamp));
+            }
+        }
+        res.map(|_| ())
+    }
+
+    /// Scores `transaction` if a risk scorer is configured, returning the score and the action
+    /// it calls for, if any.
+    fn assess_risk(
+        &self,
+        transaction: &Transaction,
+        envelope: &Envelope,
+    ) -> Option<(f32, RiskAction)> {
+        let config = self.config();
+        let scorer = config.risk_scorer.as_ref()?;
+        let timestamp = envelope.timestamp;
+        let client = transaction.client();
+        let volume = self.deposit_volume_at(client, timestamp);
+        let mut risk = self.risk.entry(client).or_default();
+        let features = RiskFeatures {
+            amount: transaction.amount().unwrap_or_else(Amount::zero),
+            velocity: risk.velocity(timestamp),
+            disputes: self.wallets.get(&client).map_or(0, |w| w.stats.disputes),
+            deposits_last_hour: volume.last_hour,
+            deposits_last_day: volume.last_day,
+            attributes: envelope.attributes.clone(),
+        };
+        let score = scorer.score(&features);
+        let action = config.risk_thresholds.action(score);
+        risk.record(score, action);
+        action.map(|action| (score, action))
+    }
+
+    /// Deposit volume of `client` in the windows ending at `now`, or as of its latest deposit
+    /// without a timestamp.
+    fn deposit_volume_at(&self, client: Client, now: Option<Timestamp>) -> DepositVolume {
+        match self.deposit_windows.get_mut(&client) {
+            Some(mut windows) => {
+                if let Some(now) = now {
+                    windows.advance(now);
+                }
+                windows.volume(client)
+            }
+            None => DepositWindows::default().volume(client),
+        }
+    }
+
+    /// Feeds deposits and chargebacks of deposits to the chargeback policy, freezing the wallet
+    /// once its chargeback ratio gets too high.
+    fn monitor_chargebacks(&self, client: Client, movement: Movement, seq: u64) {
+        let chargeback = match movement {
+            Movement::Deposit => false,
+            Movement::ChargeBack => true,
+            _ => return,
+        };
+        let config = self.config();
+        let policy = &config.chargeback_policy;
+        if policy.max_ratio.is_none() {
+            return;
+        }
+        let ratio = {
+            let mut risk = self.risk.entry(client).or_default();
+            let Some(ratio) = risk.record_funding(chargeback, policy) else {
+                return;
+            };
+            risk.auto_freeze(seq);
+            ratio
+        };
+        self.freeze_for_risk(
+            RiskDecision {
+                seq,
+                client,
+                reason: format!("chargeback ratio {ratio:.2} exceeds the limit"),
+            },
+            LockReason::ChargeBackRatio,
+        );
+    }
+
+    fn freeze_for_risk(&self, decision: RiskDecision, reason: LockReason) {
+        warn!("Freezing wallet {:?}: {}", decision.client, decision.reason);
+        if let Some(mut wallet) = self.wallets.get_mut(&decision.client)
+            && !wallet.frozen
+        {
+            wallet.frozen = true;
+            wallet.lock_reasons.push(reason);
+        }
+        self.risk_journal
+            .lock()
+            .expect("risk journal lock poisoned")
+            .push(decision);
+    }
+
+    /// Rejects a deposit or withdrawal reusing the id of a journaled transaction, whichever client
+    /// it belongs to, and a dispute, resolve, chargeback or re-presentment referring to another
+    /// client's transaction. Without the journal (`Config::skip_journal`) nothing is checked.
+    fn check_tx_id(&self, transaction: &Transaction) -> Result<(), Failure> {
+        let (client, tx_id) = (transaction.client(), transaction.tx_id());
+        let owner = self
+            .transaction_journal
+            .owner(tx_id)
+            .map_err(|e| self.journal_failure(client, tx_id, e))?;
+        let Some(owner) = owner else {
+            return Ok(());
+        };
+        match transaction {
+            Transaction::Deposit { .. } | Transaction::Withdrawal { .. } => {
+                Err(Failure::duplicate_transaction(client, tx_id))
+            }
+            _ if owner != client => Err(Failure::client_mismatch(client, tx_id, owner)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The movement applying `transaction` makes, telling disputes of withdrawals apart.
+    fn movement_of(&self, transaction: &Transaction) -> Result<Movement, Failure> {
+        let withdrawal = match *transaction {
+            Transaction::Dispute { client, tx_id } => matches!(
+                self.transaction_journal
+                    .get(tx_id)
+                    .map_err(|e| self.journal_failure(client, tx_id, e))?
+                    .map(|entry| entry.transaction),
+                Some(Transaction::Withdrawal { client: owner, .. }) if owner == client
+            ),
+            Transaction::Resolve { client, tx_id } | Transaction::ChargeBack { client, tx_id } => {
+                self.wallets
+                    .get(&client)
+                    .is_some_and(|wallet| wallet.is_disputed_withdrawal(tx_id))
+            }
+            _ => false,
+        };
+        let movement = Movement::of(transaction);
+        Ok(if withdrawal {
+            movement.on_withdrawal()
+        } else {
+            movement
+        })
+    }
+
+    /// Applies `transaction`, numbered `seq`, to its wallet, returning the amount of funds it
+    /// moved.
+    fn apply_transaction(&self, transaction: Transaction, seq: u64) -> Result<Amount, Failure> {
+        let config = self.config();
+        match transaction {
+            Transaction::Deposit {
+                client,
+                tx_id,
+                amount,
+            } => {
+                self.wallets
+                    .entry(client)
+                    .or_insert_with(|| self.new_wallet(client))
+                    .deposit(tx_id, amount);
+                self.house().deposit(amount);
+                self.journal(&config, transaction, Some(seq))?;
+                Ok(amount)
+            }
+            Transaction::Withdrawal {
+                client,
+                tx_id,
+                amount,
+            } => {
+                if let Some(mut wallet) = self.wallet_mut(client, config.missing_wallet) {
+                    let minimum = config.minimum_balance_for(client);
+                    wallet.withdraw_keeping(tx_id, amount, minimum)?;
+                    self.house().withdrawal(amount);
+                    self.journal(&config, transaction, Some(seq))?;
+                    Ok(amount)
+                } else {
+                    Err(Failure::no_wallet(client, tx_id))
+                }
+            }
+            Transaction::Dispute { client, tx_id } => {
+                let wallet = self.wallet_mut(client, config.missing_wallet_on_dispute);
+                let tx = self
+                    .transaction_journal
+                    .get(tx_id)
+                    .map_err(|e| self.journal_failure(client, tx_id, e))?
+                    .map(|entry| entry.transaction)
+                    .filter(|tx| tx.client() == client);
+
+                match tx {
+                    Some(Transaction::Deposit { amount, .. }) => {
+                        if let Some(mut wallet) = wallet {
+                            wallet.dispute(tx_id, amount, config.max_dispute_cycles)?;
+                            Ok(amount)
+                        } else {
+                            Err(Failure::no_wallet(client, tx_id))
+                        }
+                    }
+                    Some(Transaction::Withdrawal { amount, .. }) => {
+                        if let Some(mut wallet) = wallet {
+                            wallet.dispute_withdrawal(tx_id, amount, config.max_dispute_cycles)?;
+                            self.house().dispute_withdrawal(amount);
+                            Ok(amount)
+                        } else {
+                            Err(Failure::no_wallet(client, tx_id))
+                        }
+                    }
+                    _ => Err(Failure::new(
+                        client,
+                        tx_id,
+                        FailureKind::TransactionNotFound,
+                        "Transaction to dispute was not found!".to_string(),
+                    )),
+                }
+            }
+            Transaction::Resolve { client, tx_id } => {
+                let policy = config.missing_wallet_on_dispute;
+                if let Some(mut wallet) = self.wallet_mut(client, policy) {
+                    let withdrawal = wallet.is_disputed_withdrawal(tx_id);
+                    let amount = wallet.settle_dispute(tx_id)?;
+                    if withdrawal {
+                        self.house().settle_withdrawal_dispute(amount);
+                    }
+                    Ok(amount)
+                } else {
+                    Err(Failure::no_wallet(client, tx_id))
+                }
+            }
+            Transaction::ChargeBack { client, tx_id } => {
+                let policy = config.missing_wallet_on_dispute;
+                if let Some(mut wallet) = self.wallet_mut(client, policy) {
+                    let withdrawal = wallet.is_disputed_withdrawal(tx_id);
+                    let amount = wallet.charge_back(tx_id)?;
+                    // A reversed withdrawal stays owed to the client, now by the payee.
+                    if !withdrawal {
+                        self.house().charge_back(amount);
+                    }
+                    Ok(amount)
+                } else {
+                    Err(Failure::no_wallet(client, tx_id))
+                }
+            }
+            Transaction::Represent { client, tx_id } => {
+                let policy = config.missing_wallet_on_dispute;
+                if let Some(mut wallet) = self.wallet_mut(client, policy) {
+                    let amount = wallet.represent(tx_id, config.unlock_on_representment)?;
+                    self.house().representment(amount);
+                    Ok(amount)
+                } else {
+                    Err(Failure::no_wallet(client, tx_id))
+                }
+            }
+        }
+    }
+
+    /// The wallet of `client`, opened empty if it has none and `missing` says so.
+    fn wallet_mut(
+        &self,
+        client: Client,
+        missing: MissingWallet,
+    ) -> Option<RefMut<'_, Client, Wallet>> {
+        match missing {
+            MissingWallet::Reject => self.wallets.get_mut(&client),
+            MissingWallet::Create => Some(
+                self.wallets
+                    .entry(client)
+                    .or_insert_with(|| self.new_wallet(client)),
+            ),
+        }
+    }
+
+    fn new_wallet(&self, client: Client) -> Wallet {
+        let mut owners = self.config().owners_of(client);
+        owners.remove(0);
+        if owners.is_empty() {
+            Wallet::new(client)
+        } else {
+            Wallet::joint(client, owners)
+        }
+    }
+
+    /// The most recent timestamp seen in the input, used as "now" for time based reports.
+    pub fn latest_timestamp(&self) -> Option<Timestamp> {
+        match self.latest_timestamp.load(Ordering::Relaxed) {
+            i64::MIN => None,
+            secs => Some(Timestamp::from_secs(secs)),
+        }
+    }
+
+    /// Flags every wallet without activity for at least `policy.inactive_days` (measured against
+    /// the latest input timestamp) as dormant, charging the policy fee if configured. Wallets
+    /// that never saw a timestamped transaction are left alone.
+    pub fn apply_dormancy(&self, policy: &DormancyPolicy) -> Vec<DormantWallet> {
+        let Some(as_of) = self.latest_timestamp() else {
+            return Vec::new();
+        };
+        let mut dormant = Vec::new();
+        for mut wallet in self.wallets.iter_mut() {
+            let Some(last_activity) = wallet.last_activity else {
+                continue;
+            };
+            let days_inactive = as_of.days_since(last_activity);
+            if days_inactive >= policy.inactive_days {
+                let fee_charged = wallet.mark_dormant(policy.fee);
+                self.house().fee(fee_charged);
+                if let Some(ledger) = &self.ledger
+                    && fee_charged > Amount::zero()
+                {
+                    ledger
+                        .lock()
+                        .expect("ledger lock poisoned")
+                        .push(LedgerEntry {
+                            timestamp: Some(as_of),
+                            seq: None,
+                            client: *wallet.key(),
+                            tx_id: None,
+                            movement: Movement::Fee,
+                            amount: fee_charged,
+                            attributes: Attributes::default(),
+                        });
+                }
+                dormant.push(DormantWallet {
+                    client: *wallet.key(),
+                    last_activity,
+                    days_inactive,
+                    fee_charged,
+                });
+            }
+        }
+        dormant
+    }
+
+    fn house(&self) -> MutexGuard<'_, HouseAccounts> {
+        self.house.lock().expect("house accounts lock poisoned")
+    }
+
+    pub fn house_accounts(&self) -> HouseAccounts {
+        self.house().clone()
+    }
+
+    pub fn wallet_totals(&self) -> Amount {
+        self.wallets.iter().map(|w| w.balance.total).sum()
+    }
+
+    /// SHA-256 over the balances and open disputes of every wallet, as hex, to compare runs and
+    /// implementations with one value. The hashed text has one line per wallet in client order,
+    /// `client,available,held,total,locked` followed by `,tx:amount` for each open dispute in
+    /// transaction order, amounts with four decimals and `locked` as `true` or `false`.
+    pub fn state_hash(&self) -> String {
+        let mut wallets = self.export_wallets();
+        wallets.sort_by_key(|w| w.client());
+        let mut hasher = Sha256::new();
+        for wallet in &wallets {
+            let mut line = format!(
+                "{},{},{},{},{}",
+                wallet.client().id(),
+                wallet.available(),
+                wallet.held(),
+                wallet.total(),
+                wallet.is_locked()
+            );
+            let mut disputes: Vec<_> = wallet.open_disputes().iter().collect();
+            disputes.sort_by_key(|(tx_id, _)| tx_id.id());
+            for (tx_id, amount) in disputes {
+                line.push_str(&format!(",{}:{amount}", tx_id.id()));
+            }
+            line.push('\n');
+            hasher.update(line);
+        }
+        to_hex(&hasher.finalize().into())
+    }
+
+    /// Double-checks the wallets against the journal: opening balances plus deposits minus
+    /// withdrawals, chargebacks and fees, plus disputed and reversed withdrawals, have to add up
+    /// to the sum of the wallet totals, as far as `Amount::same_sum` can tell.
+    pub fn verify_totals(&self) -> anyhow::Result<()> {
+        let journaled = self.transaction_journal.funds();
+        let house = self.house_accounts();
+        let expected = house.opening_balances + journaled + house.withdrawal_receivables
+            - house.chargeback_losses
+            - house.fee_income;
+        let actual = self.wallet_totals();
+        anyhow::ensure!(
+            expected.same_sum(actual),
+            "wallet totals sum to {actual} but the journal accounts for {expected}"
+        );
+        Ok(())
+    }
+
+    /// Captures the current state, so that what is applied from now on can be undone with
+    /// `rollback_to_savepoint`. Copies every wallet and, unless it's on disk, the journal, so it's
+    /// meant for batch boundaries rather than single transactions. Only the latest savepoint can
+    /// be rolled back to.
+    pub fn savepoint(&self) -> Savepoint {
+        self.transaction_journal.savepoint();
+        Savepoint {
+            wallets: self.wallets.clone(),
+            latest_timestamp: self.latest_timestamp.load(Ordering::Relaxed),
+            house: self.house_accounts(),
+            ledger_len: self.ledger_entries_len(),
+            quarantine_len: self
+                .quarantine
+                .as_ref()
+                .map_or(0, |q| q.lock().expect("quarantine lock poisoned").len()),
+            lifecycle_len: self
+                .lifecycle
+                .as_ref()
+                .map_or(0, |l| l.lock().expect("lifecycle lock poisoned").len()),
+            failures: self.failure_count(),
+            sequence: self.last_sequence(),
+            risk: self.risk.clone(),
+            risk_journal_len: self
+                .risk_journal
+                .lock()
+                .expect("risk journal lock poisoned")
+                .len(),
+            deposit_windows: self.deposit_windows.clone(),
+            pending_disputes: self.pending_disputes().clone(),
+            expiring_holds: self.expiring_holds().clone(),
+            reservations: self.reservations.clone(),
+            sessions: self.sessions.clone(),
+        }
+    }
+
+    /// Undoes everything applied since `savepoint` was taken. Wallet events already published
+    /// and failures already reported stay out, and transactions applied meanwhile must not
+    /// be running concurrently. Fails if the journal on disk can't be rolled back, which stops
+    /// the run; everything else is rolled back regardless.
+    pub fn rollback_to_savepoint(&self, savepoint: Savepoint) -> io::Result<()> {
+        fn restore<K: Eq + std::hash::Hash + Clone, V: Clone>(
+            map: &DashMap<K, V>,
+            saved: DashMap<K, V>,
+        ) {
+            map.clear();
+            for (key, value) in saved {
+                map.insert(key, value);
+            }
+        }
+        restore(&self.wallets, savepoint.wallets);
+        restore(&self.risk, savepoint.risk);
+        restore(&self.deposit_windows, savepoint.deposit_windows);
+        restore(&self.reservations, savepoint.reservations);
+        restore(&self.sessions, savepoint.sessions);
+        let journal = self.journaled(self.transaction_journal.rollback());
+        self.latest_timestamp
+            .store(savepoint.latest_timestamp, Ordering::Relaxed);
+        *self.house() = savepoint.house;
+        if let Some(ledger) = &self.ledger {
+            ledger
+                .lock()
+                .expect("ledger lock poisoned")
+                .truncate(savepoint.ledger_len);
+        }
+        if let Some(quarantine) = &self.quarantine {
+            quarantine
+                .lock()
+                .expect("quarantine lock poisoned")
+                .truncate(savepoint.quarantine_len);
+        }
+        if let Some(lifecycle) = &self.lifecycle {
+            lifecycle
+                .lock()
+                .expect("lifecycle lock poisoned")
+                .truncate(savepoint.lifecycle_len);
+        }
+        self.failures.store(savepoint.failures, Ordering::Relaxed);
+        self.sequence.store(savepoint.sequence, Ordering::Relaxed);
+        self.risk_journal
+            .lock()
+            .expect("risk journal lock poisoned")
+            .truncate(savepoint.risk_journal_len);
+        *self.pending_disputes() = savepoint.pending_disputes;
+        *self.expiring_holds() = savepoint.expiring_holds;
+        self.deferred_failures().clear();
+        journal
+    }
+
+    fn ledger_entries_len(&self) -> usize {
+        self.ledger
+            .as_ref()
+            .map_or(0, |l| l.lock().expect("ledger lock poisoned").len())
+    }
+
+    /// Every recorded balance movement, in application order. Empty unless
+    /// `Config::keep_ledger` is set.
+    pub fn ledger_entries(&self) -> Vec<LedgerEntry> {
+        self.ledger
+            .as_ref()
+            .map(|l| l.lock().expect("ledger lock poisoned").clone())
+            .unwrap_or_default()
+    }
+
+    /// Transactions turned away by frozen, quarantined or locked wallets, in application order.
+    /// Empty unless `Config::keep_quarantine` is set.
+    pub fn quarantined_transactions(&self) -> Vec<QuarantinedTransaction> {
+        self.quarantine
+            .as_ref()
+            .map(|q| q.lock().expect("quarantine lock poisoned").clone())
+            .unwrap_or_default()
+    }
+
+    /// Current state of the wallet `client` transacts on, following joint ownership.
+    pub fn wallet(&self, client: Client) -> Option<Wallet> {
+        self.wallets
+            .get(&self.config().wallet_of(client))
+            .map(|r| r.value().clone())
+    }
+
+    /// What applying `transaction` would do as of now, without applying it: the balance it would
+    /// leave its wallet with, or the failure it would meet, e.g. to pre-validate a withdrawal. It
+    /// is applied to a scratch manager holding a copy of the wallet with its journal and risk
+    /// state, so nothing is recorded, counted or published.
+    pub fn simulate(&self, transaction: Transaction) -> Result<ProjectedBalance, Failure> {
+        let config = self.config();
+        let client = config.wallet_of(transaction.client());
+        self.check_tx_id(&transaction.with_client(client))
+            .map_err(|failure| Failure {
+                client: transaction.client(),
+                ..failure
+            })?;
+        let scratch = WalletManager::with_config(Config {
+            keep_ledger: false,
+            keep_quarantine: false,
+            keep_lifecycle: false,
+            journal_dir: None,
+            persistence: None,
+            ..(*config).clone()
+        });
+        if let Some(wallet) = self.wallets.get(&client) {
+            scratch.wallets.insert(client, wallet.clone());
+        }
+        let journaled = self
+            .transaction_journal
+            .get(transaction.tx_id())
+            .map_err(|e| self.journal_failure(client, transaction.tx_id(), e))?;
+        if let Some(journaled) = journaled {
+            scratch
+                .transaction_journal
+                .record(journaled)
+                .map_err(|e| self.journal_failure(client, transaction.tx_id(), e))?;
+        }
+        if let Some(risk) = self.risk.get(&client) {
+            scratch.risk.insert(client, risk.clone());
+        }
+        if let Some(windows) = self.deposit_windows.get(&client) {
+            scratch.deposit_windows.insert(client, windows.clone());
+        }
+        scratch.latest_timestamp.store(
+            self.latest_timestamp.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        scratch
+            .sequence
+            .store(self.last_sequence(), Ordering::Relaxed);
+        scratch.apply(transaction.into())?;
+        let wallet = scratch
+            .wallets
+            .get(&client)
+            .map_or_else(|| self.new_wallet(client), |w| w.clone());
+        Ok(ProjectedBalance {
+            client,
+            available: wallet.available(),
+            held: wallet.held(),
+            total: wallet.total(),
+            locked: wallet.is_locked(),
+        })
+    }
+
+    /// Recomputes the balance of the wallet `client` transacts on from its journaled deposits and
+    /// withdrawals, its open disputes and chargebacks and its reservations, discarding the current
+    /// one, e.g. to repair a wallet damaged by a bug or a bad manual adjustment. Funds the journal
+    /// doesn't account for, such as an opening balance restored from a snapshot or fees, are not
+    /// recovered.
+    pub fn rebuild(&self, client: Client) -> anyhow::Result<Rebuilt> {
+        anyhow::ensure!(
+            !self.config().skip_journal,
+            "the transaction journal is disabled, so wallets can't be rebuilt"
+        );
+        let client = self.config().wallet_of(client);
+        let mut wallet = self
+            .wallets
+            .get_mut(&client)
+            .with_context(|| format!("no wallet for client {}", client.id()))?;
+        let journaled: Amount = wallet
+            .owners()
+            .map(|owner| {
+                let transactions = self.transaction_journal.transactions_of(owner);
+                Ok(funds_of(self.journaled(transactions)?.values()))
+            })
+            .sum::<io::Result<Amount>>()?;
+        let reserved: Amount = self
+            .reservations
+            .iter()
+            .filter(|reservation| reservation.client == client)
+            .map(|reservation| reservation.amount)
+            .sum();
+        let held = wallet.open_disputes.values().copied().sum::<Amount>() + reserved;
+        let charged_back: Amount = wallet.charged_back.values().copied().sum();
+        // Disputed withdrawals are credited back while open, and for good once reversed.
+        let credited_back: Amount = wallet
+            .disputed_withdrawals
+            .iter()
+            .filter_map(|tx| wallet.open_disputes.get(tx))
+            .chain(wallet.reversed_withdrawals.values())
+            .copied()
+            .sum();
+        let total = journaled - charged_back + credited_back;
+        let before = wallet.balance.clone();
+        wallet.balance = Balance {
+            available: total - held,
+            held,
+            total,
+        };
+        let after = wallet.balance.clone();
+        drop(wallet);
+        if after != before {
+            warn!(
+                "Rebuilt wallet {}: total {} -> {}",
+                client.id(),
+                before.total,
+                after.total
+            );
+        }
+        Ok(Rebuilt {
+            client,
+            before,
+            after,
+        })
+    }
+
+    /// Starts from `wallet` as exported by a previous run. Replayed transactions up to the
+    /// wallet's sequence number count as applied.
+    ///
+    /// Wallet exports carry neither the journal nor the open disputes. Earlier transactions
+    /// can't be disputed anymore, and disputes open at the export can't be resolved or charged
+    /// back, their funds staying held; only open disputes `wallet` does carry can. Checkpoints
+    /// of `Config::persistence`, which `recover` starts from, keep both.
+    pub fn restore(&self, wallet: Wallet) {
+        self.sequence.fetch_max(wallet.last_seq, Ordering::Relaxed);
+        let restored = Wallet {
+            balance: wallet.balance,
+            locked: wallet.locked,
+            open_disputes: wallet.open_disputes,
+            disputed_withdrawals: wallet.disputed_withdrawals,
+            last_seq: wallet.last_seq,
+            frozen: wallet.frozen,
+            lock_reasons: wallet.lock_reasons,
+            ..self.new_wallet(wallet.client)
+        };
+        self.house().opening_balance(restored.total());
+        if let Some(previous) = self.wallets.insert(restored.client, restored) {
+            // Restoring the same client twice replaces its first balance.
+            self.house().opening_balance(-previous.total());
+        }
+    }
+
+    /// Freezes or unfreezes the wallet `client` transacts on, returning whether it exists.
+    pub fn set_frozen(&self, client: Client, frozen: bool) -> bool {
+        let client = self.config().wallet_of(client);
+        let before = self.lifecycle_state(client);
+        match self.wallets.get_mut(&client) {
+            Some(mut wallet) => {
+                if frozen && !wallet.frozen {
+                    wallet.lock_reasons.push(LockReason::Admin);
+                }
+                wallet.frozen = frozen;
+            }
+            None => return false,
+        }
+        let now = self.latest_timestamp();
+        self.record_lifecycle(client, before, self.last_sequence(), None, now);
+        true
+    }
+
+    /// Lifts the chargeback lock of the wallet `client` transacts on, returning whether it
+    /// exists.
+    pub fn unlock(&self, client: Client) -> bool {
+        let client = self.config().wallet_of(client);
+        let before = self.lifecycle_state(client);
+        match self.wallets.get_mut(&client) {
+            Some(mut wallet) => wallet.locked = false,
+            None => return false,
+        }
+        let now = self.latest_timestamp();
+        self.record_lifecycle(client, before, self.last_sequence(), None, now);
+        true
+    }
+
+    /// What each session did to each wallet, sorted by session and client.
+    pub fn session_activity(&self) -> Vec<SessionActivity> {
+        let mut activity: Vec<SessionActivity> =
+            self.sessions.iter().map(|r| r.value().clone()).collect();
+        activity.sort_by(|a, b| (&a.session, a.client).cmp(&(&b.session, b.client)));
+        activity
+    }
+
+    /// Every recorded wallet status transition, in order. Empty unless `Config::keep_lifecycle`
+    /// is set.
+    pub fn lifecycle_events(&self) -> Vec<LifecycleEvent> {
+        self.lifecycle
+            .as_ref()
+            .map(|l| l.lock().expect("lifecycle lock poisoned").clone())
+            .unwrap_or_default()
+    }
+
+    /// Risk scoring history of every scored wallet, sorted by client.
+    pub fn risk_scores(&self) -> Vec<RiskScore> {
+        let mut scores: Vec<RiskScore> = self
+            .risk
+            .iter()
+            .map(|risk| risk.value().report(*risk.key()))
+            .collect();
+        scores.sort_by_key(|score| score.client);
+        scores
+    }
+
+    /// Every freeze decided by risk monitoring, in the order they were made.
+    pub fn risk_decisions(&self) -> Vec<RiskDecision> {
+        self.risk_journal
+            .lock()
+            .expect("risk journal lock poisoned")
+            .clone()
+    }
+
+    /// Deposit volume of the wallet `client` transacts on over the hour and the day up to the
+    /// latest input timestamp. Only timestamped deposits are counted.
+    pub fn deposit_volume(&self, client: Client) -> DepositVolume {
+        let client = self.config().wallet_of(client);
+        self.deposit_volume_at(client, self.latest_timestamp())
+    }
+
+    /// Moves `amount` from the wallet of `from` to the wallet of `to`, or nothing at all.
+    pub fn transfer(
+        &self,
+        from: Client,
+        to: Client,
+        tx_id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), Failure> {
+        self.apply_legs(
+            tx_id,
+            &[
+                Leg::Debit {
+                    client: from,
+                    amount,
+                },
+                Leg::Credit { client: to, amount },
+            ],
+        )
+    }
+
+    /// Applies every leg of a multi-wallet operation or none of them. All legs are prepared
+    /// first, reserving the funds of debits; a leg failing to prepare releases the reservations
+    /// made before it, otherwise every leg is committed. The legs have to add up to zero, so the
+    /// operation leaves the house accounts untouched.
+    ///
+    /// Like a transaction, the operation is numbered and, with `Config::persistence`, logged
+    /// before it is applied, and fails if a journaled transaction already has its id.
+    pub fn apply_legs(&self, tx_id: TransactionId, legs: &[Leg]) -> Result<(), Failure> {
+        let Some(persistence) = &self.persistence else {
+            return self.apply_legs_numbered(tx_id, legs, None);
+        };
+        let res = {
+            let _applying = persistence.applying();
+            self.apply_legs_numbered(tx_id, legs, None)
+        };
+        self.checkpoint_if_due(persistence);
+        res
+    }
+
+    /// `apply_legs`, under the sequence number `seq` the legs were logged with when replayed,
+    /// or numbering and logging them first otherwise.
+    fn apply_legs_numbered(
+        &self,
+        tx_id: TransactionId,
+        legs: &[Leg],
+        seq: Option<u64>,
+    ) -> Result<(), Failure> {
+        let config = self.config();
+        debug_assert!(
+            legs.iter()
+                .map(Leg::signed_amount)
+                .sum::<Amount>()
+                .same_to_precision(Amount::zero()),
+            "unbalanced legs {legs:?}"
+        );
+        let seq = match seq {
+            Some(seq) => {
+                self.sequence.fetch_max(seq, Ordering::Relaxed);
+                seq
+            }
+            None => {
+                let envelopes: Vec<Envelope> = legs
+                    .iter()
+                    .map(|leg| leg.transaction(tx_id).into())
+                    .collect();
+                self.log_next(&envelopes)
+            }
+        };
+        let legs: Vec<Leg> = legs
+            .iter()
+            .map(|leg| match *leg {
+                Leg::Debit { client, amount } => Leg::Debit {
+                    client: config.wallet_of(client),
+                    amount,
+                },
+                Leg::Credit { client, amount } => Leg::Credit {
+                    client: config.wallet_of(client),
+                    amount,
+                },
+            })
+            .collect();
+        let failed = |mut failure: Failure| {
+            failure.seq = Some(seq);
+            failure
+        };
+        for leg in &legs {
+            self.check_tx_id(&leg.transaction(tx_id)).map_err(failed)?;
+        }
+        for (prepared, leg) in legs.iter().enumerate() {
+            if let Err(failure) = self.prepare_leg(tx_id, leg) {
+                for leg in legs[..prepared].iter().rev() {
+                    self.abort_leg(leg);
+                }
+                return Err(failed(failure));
+            }
+        }
+        for leg in &legs {
+            self.commit_leg(tx_id, leg);
+        }
+        Ok(())
+    }
+
+    fn prepare_leg(&self, tx_id: TransactionId, leg: &Leg) -> Result<(), Failure> {
+        let client = leg.client();
+        let Some(mut wallet) = self.wallets.get_mut(&client) else {
+            // Credits open the wallet on commit, like a deposit does.
+            return match leg {
+                Leg::Debit { .. } => Err(Failure::no_wallet(client, tx_id)),
+                Leg::Credit { .. } => Ok(()),
+            };
+        };
+        if wallet.frozen {
+            return Err(Failure::frozen(client, tx_id));
+        }
+        if wallet.quarantined {
+            return Err(Failure::quarantined(client, tx_id));
+        }
+        if wallet.locked {
+            return Err(Failure::account_locked(client, tx_id));
+        }
+        match *leg {
+            Leg::Debit { amount, .. } => {
+                wallet.reserve(tx_id, amount, self.config().minimum_balance_for(client))
+            }
+            Leg::Credit { .. } => Ok(()),
+        }
+    }
+
+    fn abort_leg(&self, leg: &Leg) {
+        if let Leg::Debit { client, amount } = *leg
+            && let Some(mut wallet) = self.wallets.get_mut(&client)
+        {
+            wallet.release(amount);
+        }
+    }
+
+    fn commit_leg(&self, tx_id: TransactionId, leg: &Leg) {
+        match *leg {
+            Leg::Debit { client, amount } => {
+                if let Some(mut wallet) = self.wallets.get_mut(&client) {
+                    wallet.commit_reserved(amount);
+                }
+            }
+            Leg::Credit { client, amount } => {
+                self.wallets
+                    .entry(client)
+                    .or_insert_with(|| self.new_wallet(client))
+                    .deposit(tx_id, amount);
+            }
+        }
+    }
+
+    /// Sets `amount` of the wallet `client` transacts on aside for a pending operation, e.g. a
+    /// payment between its authorization and settlement. The funds stay held until `commit`
+    /// withdraws them as the transaction `tx_id` or `release` makes them available again.
+    pub fn reserve(
+        &self,
+        client: Client,
+        tx_id: TransactionId,
+        amount: Amount,
+    ) -> Result<ReservationId, Failure> {
+        let config = self.config();
+        let client = config.wallet_of(client);
+        let owner = self
+            .transaction_journal
+            .owner(tx_id)
+            .map_err(|e| self.journal_failure(client, tx_id, e))?;
+        if owner.is_some() || self.reservations.iter().any(|r| r.tx_id == tx_id) {
+            return Err(Failure::duplicate_transaction(client, tx_id));
+        }
+        let _applying = self.persistence.as_ref().map(Persistence::applying);
+        let Some(mut wallet) = self.wallets.get_mut(&client) else {
+            return Err(Failure::no_wallet(client, tx_id));
+        };
+        if wallet.frozen {
+            return Err(Failure::frozen(client, tx_id));
+        }
+        if wallet.quarantined {
+            return Err(Failure::quarantined(client, tx_id));
+        }
+        if wallet.locked {
+            return Err(Failure::account_locked(client, tx_id));
+        }
+        wallet.reserve(tx_id, amount, config.minimum_balance_for(client))?;
+        drop(wallet);
+        let id = ReservationId::new(self.next_reservation.fetch_add(1, Ordering::Relaxed) + 1);
+        self.reservations.insert(
+            id,
+            Reservation {
+                client,
+                tx_id,
+                amount,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Withdraws the funds of a reservation, numbered, logged and journaled as a withdrawal
+    /// under its transaction id. Returns the reservation, `None` if it's unknown or its wallet
+    /// was closed meanwhile. The reservation stays pending if the journal fails.
+    pub fn commit(&self, id: ReservationId) -> Result<Option<Reservation>, Failure> {
+        let _applying = self.persistence.as_ref().map(Persistence::applying);
+        let Some((_, reservation)) = self.reservations.remove(&id) else {
+            return Ok(None);
+        };
+        let Reservation {
+            client,
+            tx_id,
+            amount,
+        } = reservation;
+        let Some(mut wallet) = self.wallets.get_mut(&client) else {
+            return Ok(None);
+        };
+        let withdrawal = Transaction::Withdrawal {
+            client,
+            tx_id,
+            amount,
+        };
+        let seq = self.log_next(&[withdrawal.into()]);
+        if let Err(mut failure) = self.journal(&self.config(), withdrawal, Some(seq)) {
+            self.reservations.insert(id, reservation);
+            failure.seq = Some(seq);
+            return Err(failure);
+        }
+        wallet.commit_reserved(amount);
+        drop(wallet);
+        self.house().withdrawal(amount);
+        if let Some(ledger) = &self.ledger {
+            ledger
+                .lock()
+                .expect("ledger lock poisoned")
+                .push(LedgerEntry {
+                    timestamp: self.latest_timestamp(),
+                    seq: Some(seq),
+                    client,
+                    tx_id: Some(tx_id),
+                    movement: Movement::Withdrawal,
+                    amount,
+                    attributes: Attributes::default(),
+                });
+        }
+        Ok(Some(reservation))
+    }
+
+    /// Makes the funds of a reservation available again. Returns the reservation, `None` if
+    /// it's unknown or its wallet was closed meanwhile.
+    pub fn release(&self, reservation: ReservationId) -> Option<Reservation> {
+        let _applying = self.persistence.as_ref().map(Persistence::applying);
+        let (_, reservation) = self.reservations.remove(&reservation)?;
+        self.wallets
+            .get_mut(&reservation.client)?
+            .release(reservation.amount);
+        Some(reservation)
+    }
+
+    pub fn wallet_count(&self) -> usize {
+        self.wallets.len()
+    }
+
+    /// Removes the wallet `client` transacts on, along with its transaction history, once no
+    /// more transactions are expected for it. A later transaction opens a new wallet. Nothing
+    /// else is removed if the journal fails to forget the history.
+    pub fn close(&self, client: Client) -> io::Result<Option<Wallet>> {
+        let client = self.config().wallet_of(client);
+        self.journaled(self.transaction_journal.forget(client))?;
+        self.deposit_windows.remove(&client);
+        self.reservations
+            .retain(|_, reservation| reservation.client != client);
+        let before = self.lifecycle_state(client);
+        let wallet = self.wallets.remove(&client).map(|(_, wallet)| wallet);
+        let now = self.latest_timestamp();
+        self.record_lifecycle(client, before, self.last_sequence(), None, now);
+        Ok(wallet)
+    }
+
+    /// Saves every wallet with its journal slice as the checkpoint of `Config::persistence`,
+    /// waiting for the transactions being applied, and empties the write-ahead log. Pending
+    /// reservations aren't saved: their funds are saved as available, as if released.
+    pub fn checkpoint(&self) -> anyhow::Result<()> {
+        let persistence = self
+            .persistence
+            .as_ref()
+            .context("checkpoints need Config::persistence")?;
+        let _exclusive = persistence.exclusive();
+        let mut reserved = HashMap::<Client, Amount>::new();
+        for reservation in self.reservations.iter() {
+            *reserved
+                .entry(reservation.client)
+                .or_insert_with(Amount::zero) += reservation.amount;
+        }
+        let mut wallets = self
+            .wallets
+            .iter()
+            .map(|wallet| {
+                let journal = self.transaction_journal.transactions_of(*wallet.key());
+                let mut wallet = wallet.clone();
+                if let Some(&amount) = reserved.get(&wallet.client()) {
+                    wallet.release(amount);
+                }
+                Ok(Handoff {
+                    wallet,
+                    journal: self.journaled(journal)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        wallets.sort_unstable_by_key(|handoff| handoff.wallet.client());
+        persistence.save(&Snapshot {
+            seq: self.last_sequence(),
+            wallets,
+        })
+    }
+
+    /// Rebuilds the state a crashed run left in the directory of `Config::persistence`: loads its
+    /// last checkpoint, replays the write-ahead log over it and checkpoints the result. Meant
+    /// for a fresh manager, before any transaction is applied. Transactions failing again while
+    /// replayed aren't reported anew.
+    pub fn recover(&self) -> anyhow::Result<Recovery> {
+        let persistence = self
+            .persistence
+            .as_ref()
+            .context("recovery needs Config::persistence")?;
+        let (snapshot, log) = persistence.load()?;
+        let mut recovery = Recovery {
+            torn: log.torn,
+            ..Recovery::default()
+        };
+        if let Some(snapshot) = snapshot {
+            recovery.snapshot_seq = snapshot.seq;
+            recovery.wallets = snapshot.wallets.len();
+            self.sequence.fetch_max(snapshot.seq, Ordering::Relaxed);
+            for handoff in snapshot.wallets {
+                self.take_over(handoff)?;
+            }
+        }
+        // Logs written before sequence numbers were taken under the log's lock may hold
+        // concurrently applied transactions out of order.
+        let mut envelopes = log.envelopes;
+        envelopes.sort_by_key(|envelope| envelope.seq);
+        for frames in envelopes.chunk_by(|a, b| a.seq == b.seq) {
+            let Some(seq) = frames[0].seq.filter(|&seq| seq > self.last_sequence()) else {
+                continue;
+            };
+            recovery.replayed += 1;
+            if let [envelope] = frames {
+                let _ = self.apply_logged(envelope.clone(), false);
+            } else {
+                // The legs of a multi-wallet operation share their sequence number.
+                let legs: Vec<Leg> = frames
+                    .iter()
+                    .filter_map(|frame| Leg::of_transaction(&frame.transaction))
+                    .collect();
+                let tx_id = frames[0].transaction.tx_id();
+                let _ = self.apply_legs_numbered(tx_id, &legs, Some(seq));
+            }
+        }
+        self.checkpoint()?;
+        Ok(recovery)
+    }
+
+    /// Removes the wallet of `client` with its transaction history for another manager to
+    /// `take_over`. Its funds leave this manager's books as if the wallet had never been opened.
+    /// The wallet stays if the journal fails to give up the history.
+    pub fn hand_off(&self, client: Client) -> io::Result<Option<Handoff>> {
+        let Some((_, wallet)) = self.wallets.remove(&client) else {
+            return Ok(None);
+        };
+        let journal = match self.journaled(self.transaction_journal.take(client)) {
+            Ok(journal) => journal,
+            Err(e) => {
+                self.wallets.insert(client, wallet);
+                return Err(e);
+            }
+        };
+        self.deposit_windows.remove(&client);
+        let handoff = Handoff { wallet, journal };
+        self.house().opening_balance(-handoff.carried());
+        Ok(Some(handoff))
+    }
+
+    /// Continues a wallet another manager handed off, disputes of its journaled transactions
+    /// included. The wallet isn't taken over if the journal fails to record the history.
+    pub fn take_over(&self, handoff: Handoff) -> io::Result<()> {
+        for &entry in handoff.journal.values() {
+            self.journaled(self.transaction_journal.record(entry))?;
+        }
+        self.house().opening_balance(handoff.carried());
+        self.wallets.insert(handoff.wallet.client(), handoff.wallet);
+        Ok(())
+    }
+
+    pub fn export_wallets(&self) -> Vec<Wallet> {
+        self.wallets.iter().map(|r| r.value().clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::{ChargebackPolicy, RiskScorer, RiskThresholds};
+    use crate::wallet::WalletStats;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_deposit_withdraw_transaction() {
+        let wallet_manager = Arc::new(WalletManager::init());
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let wallet_manager_runner = tokio::spawn({
+            let wallet_manager = wallet_manager.clone();
+            async move { wallet_manager.run(tx_receiver, err_sender).await }
+        });
+        let client = Client::new(1);
+        let deposit_amount = Amount::from_major(100, 0);
+        let transactions = vec![
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(1),
+                amount: deposit_amount,
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(2),
+                amount: deposit_amount,
+            },
+        ];
+        for transaction in transactions {
+            tx_sender.send(transaction.into()).unwrap();
+        }
+        drop(tx_sender);
+        wallet_manager_runner.await.unwrap();
+
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].client, client);
+        assert_eq!(
+            wallets[0].balance,
+            Balance {
+                available: Amount::zero(),
+                held: Amount::zero(),
+                total: Amount::zero(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispute_resolve_transaction() {
+        let wallet_manager = Arc::new(WalletManager::init());
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let wallet_manager_runner = tokio::spawn({
+            let wallet_manager = wallet_manager.clone();
+            async move { wallet_manager.run(tx_receiver, err_sender).await }
+        });
+        let client = Client::new(1);
+        let deposit_amount = Amount::from_major(100, 0);
+        tx_sender
+            .send(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: deposit_amount,
+                }
+                .into(),
+            )
+            .unwrap();
+        tx_sender
+            .send(
+                Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        tx_sender
+            .send(
+                Transaction::Resolve {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        drop(tx_sender);
+        wallet_manager_runner.await.unwrap();
+
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].client, client);
+        assert_eq!(
+            wallets[0].balance,
+            Balance {
+                available: deposit_amount,
+                held: Amount::zero(),
+                total: deposit_amount,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_once_the_failure_policy_aborts() {
+        let config = Config {
+            failure_policy: FailurePolicy::Abort,
+            max_failures: 0,
+            ..Config::default()
+        };
+        let client = Client::new(1);
+        let transactions = [
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(1),
+                amount: Amount::from_major(5, 0),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(10, 0),
+            },
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(20, 0),
+            },
+        ];
+        let send_all = || {
+            let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+            for transaction in transactions {
+                tx_sender.send(transaction.into()).unwrap();
+            }
+            tx_receiver
+        };
+
+        let wallet_manager = WalletManager::with_config(config.clone());
+        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let report = wallet_manager.run(send_all(), err_sender).await;
+        assert_eq!((report.processed, report.failed), (2, 1));
+        assert!(report.stopped_early);
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.total(), Amount::from_major(5, 0));
+
+        let wallet_manager = Arc::new(WalletManager::with_config(config));
+        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let report = wallet_manager
+            .clone()
+            .run_sharded(send_all(), err_sender, 2)
+            .await;
+        assert!(report.stopped_early);
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.total(), Amount::from_major(5, 0));
+    }
+
+    #[tokio::test]
+    async fn test_run_spools_failures_once_their_receiver_is_dropped() {
+        let wallet_manager = WalletManager::init();
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        drop(err_receiver);
+        let client = Client::new(1);
+        for (tx, amount) in [(1, 5), (2, 10), (3, 20)] {
+            let transaction = if tx == 2 {
+                Transaction::Withdrawal {
+                    client,
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(amount, 0),
+                }
+            } else {
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(amount, 0),
+                }
+            };
+            tx_sender.send(transaction.into()).unwrap();
+        }
+        drop(tx_sender);
+
+        let report = wallet_manager.run(tx_receiver, err_sender).await;
+        assert_eq!((report.processed, report.failed), (3, 1));
+        assert!(!report.stopped_early);
+        let spooled = wallet_manager.failure_spool().take();
+        assert_eq!(spooled.len(), 1);
+        assert_eq!(spooled[0].tx, TransactionId::new(2));
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.total(), Amount::from_major(25, 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sharded_run_keeps_per_client_order() {
+        // Every client deposits, withdraws everything and withdraws again, which only fails in
+        // that order.
+        let transactions: Vec<Transaction> = (1..=200)
+            .flat_map(|client| {
+                let tx = u32::from(client) * 3;
+                let client = Client::new(client);
+                let amount = Amount::from_major(10, 0);
+                [
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(tx),
+                        amount,
+                    },
+                    Transaction::Withdrawal {
+                        client,
+                        tx_id: TransactionId::new(tx + 1),
+                        amount,
+                    },
+                    Transaction::Withdrawal {
+                        client,
+                        tx_id: TransactionId::new(tx + 2),
+                        amount,
+                    },
+                ]
+            })
+            .collect();
+        let wallet_manager = Arc::new(WalletManager::init());
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, mut err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let runner = tokio::spawn(
+            wallet_manager
+                .clone()
+                .run_sharded(tx_receiver, err_sender, 4),
+        );
+        for transaction in transactions {
+            tx_sender.send(transaction.into()).unwrap();
+        }
+        drop(tx_sender);
+        let report = runner.await.unwrap();
+
+        assert_eq!((report.processed, report.failed), (600, 200));
+        assert_eq!((report.deposits, report.withdrawals), (200, 400));
+        let mut failed = Vec::new();
+        while let Some(failure) = err_receiver.recv().await {
+            failed.push(failure.tx.id() % 3);
+        }
+        assert!(failed.len() == 200 && failed.iter().all(|&tx| tx == 2));
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 200);
+        assert!(wallets.iter().all(|w| w.total() == Amount::zero()));
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dispute_chargeback_transaction() {
+        let wallet_manager = Arc::new(WalletManager::init());
+        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let wallet_manager_runner = tokio::spawn({
+            let wallet_manager = wallet_manager.clone();
+            async move { wallet_manager.run(tx_receiver, err_sender).await }
+        });
+        let client = Client::new(1);
+        let deposit_amount = Amount::from_major(100, 0);
+        tx_sender
+            .send(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: deposit_amount,
+                }
+                .into(),
+            )
+            .unwrap();
+        tx_sender
+            .send(
+                Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        tx_sender
+            .send(
+                Transaction::ChargeBack {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        drop(tx_sender);
+        wallet_manager_runner.await.unwrap();
+
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].client, client);
+        assert!(wallets[0].locked);
+        assert_eq!(
+            wallets[0].balance,
+            Balance {
+                available: Amount::zero(),
+                held: Amount::zero(),
+                total: Amount::zero(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_totals_after_a_chargeback() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        for (tx, amount) in [(1, 100), (2, 40)] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(tx),
+                        amount: Amount::from_major(amount, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+        let tx_id = TransactionId::new(1);
+        for transaction in [
+            Transaction::Dispute { client, tx_id },
+            Transaction::ChargeBack { client, tx_id },
+        ] {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+
+        assert_eq!(
+            wallet_manager.wallet(client).unwrap().total(),
+            Amount::from_major(40, 0)
+        );
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[test]
+    fn test_house_accounts_follow_a_chargeback() {
+        let wallet_manager = WalletManager::init();
+        let (client, tx_id) = (Client::new(1), TransactionId::new(1));
+        let amount = Amount::from_major(100, 0);
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount,
+                }
+                .into(),
+            )
+            .unwrap();
+        let house = wallet_manager.house_accounts();
+        assert_eq!(house.settlement, amount);
+        assert_eq!(house.client_liability, amount);
+        assert_eq!(house.chargeback_losses, Amount::zero());
+
+        for transaction in [
+            Transaction::Dispute { client, tx_id },
+            Transaction::ChargeBack { client, tx_id },
+        ] {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+        let house = wallet_manager.house_accounts();
+        assert_eq!(house.settlement, amount);
+        assert_eq!(house.client_liability, Amount::zero());
+        assert_eq!(house.chargeback_losses, amount);
+    }
+
+    #[test]
+    fn test_representment_recredits_chargeback() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_ledger: true,
+            keep_lifecycle: true,
+            unlock_on_representment: true,
+            ..Config::default()
+        });
+        let (client, tx_id) = (Client::new(1), TransactionId::new(1));
+        let amount = Amount::from_major(10, 0);
+        let represent = Transaction::Represent { client, tx_id };
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount,
+                }
+                .into(),
+            )
+            .unwrap();
+        let failure = wallet_manager.apply(represent.into()).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::ChargeBackNotFound);
+        for transaction in [
+            Transaction::Dispute { client, tx_id },
+            Transaction::ChargeBack { client, tx_id },
+            represent,
+        ] {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+        assert!(wallet_manager.apply(represent.into()).is_err());
+
+        let wallets = wallet_manager.export_wallets();
+        assert!(!wallets[0].is_locked());
+        assert_eq!(wallets[0].balance.available, amount);
+        assert_eq!(wallets[0].balance.total, amount);
+        let house = wallet_manager.house_accounts();
+        assert_eq!(house.client_liability, amount);
+        assert_eq!(house.chargeback_losses, Amount::zero());
+        wallet_manager.verify_totals().unwrap();
+
+        // The deposit, its chargeback and the re-presentment share the transaction id.
+        let movements: Vec<_> = wallet_manager
+            .ledger_entries()
+            .iter()
+            .map(|entry| (entry.tx_id, entry.movement))
+            .collect();
+        assert_eq!(
+            movements,
+            [
+                (Some(tx_id), Movement::Deposit),
+                (Some(tx_id), Movement::Hold),
+                (Some(tx_id), Movement::ChargeBack),
+                (Some(tx_id), Movement::Representment),
+            ]
+        );
+        let changes: Vec<_> = wallet_manager
+            .lifecycle_events()
+            .iter()
+            .map(|event| (event.seq, event.change, event.reason))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (1, LifecycleChange::Created, None),
+                (
+                    4,
+                    LifecycleChange::Locked,
+                    Some(LockReason::ChargeBack(tx_id))
+                ),
+                (5, LifecycleChange::Unlocked, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_disputes_resolve_or_reverse() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_ledger: true,
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let (deposit, withdrawal) = (TransactionId::new(1), TransactionId::new(2));
+        let amount = Amount::from_major(30, 0);
+        for transaction in [
+            Transaction::Deposit {
+                client,
+                tx_id: deposit,
+                amount: Amount::from_major(100, 0),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: withdrawal,
+                amount,
+            },
+            Transaction::Dispute {
+                client,
+                tx_id: withdrawal,
+            },
+        ] {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+        let balance = |manager: &WalletManager| manager.export_wallets()[0].balance.clone();
+        assert_eq!(
+            balance(&wallet_manager).available,
+            Amount::from_major(70, 0)
+        );
+        assert_eq!(balance(&wallet_manager).held, amount);
+        assert_eq!(balance(&wallet_manager).total, Amount::from_major(100, 0));
+        assert_eq!(
+            wallet_manager.house_accounts().withdrawal_receivables,
+            amount
+        );
+        wallet_manager.verify_totals().unwrap();
+
+        // Resolved, the withdrawal stands.
+        let disputed = wallet_manager.savepoint();
+        wallet_manager
+            .apply(
+                Transaction::Resolve {
+                    client,
+                    tx_id: withdrawal,
+                }
+                .into(),
+            )
+            .unwrap();
+        assert_eq!(
+            balance(&wallet_manager).available,
+            Amount::from_major(70, 0)
+        );
+        assert_eq!(balance(&wallet_manager).held, Amount::zero());
+        assert_eq!(balance(&wallet_manager).total, Amount::from_major(70, 0));
+        let house = wallet_manager.house_accounts();
+        assert_eq!(house.withdrawal_receivables, Amount::zero());
+        wallet_manager.verify_totals().unwrap();
+        wallet_manager.rollback_to_savepoint(disputed).unwrap();
+
+        // Charged back, the funds return to the client, whose wallet stays unlocked.
+        wallet_manager
+            .apply(
+                Transaction::ChargeBack {
+                    client,
+                    tx_id: withdrawal,
+                }
+                .into(),
+            )
+            .unwrap();
+        let wallet = &wallet_manager.export_wallets()[0];
+        assert_eq!(wallet.balance.available, Amount::from_major(100, 0));
+        assert_eq!(wallet.balance.held, Amount::zero());
+        assert_eq!(wallet.balance.total, Amount::from_major(100, 0));
+        assert!(!wallet.is_locked());
+        let house = wallet_manager.house_accounts();
+        assert_eq!(house.chargeback_losses, Amount::zero());
+        assert_eq!(house.withdrawal_receivables, amount);
+        wallet_manager.verify_totals().unwrap();
+        let again = wallet_manager.apply(
+            Transaction::Dispute {
+                client,
+                tx_id: withdrawal,
+            }
+            .into(),
+        );
+        assert_eq!(again.unwrap_err().kind, FailureKind::InvalidDispute);
+        assert_eq!(
+            wallet_manager.rebuild(client).unwrap().after,
+            wallet.balance
+        );
+
+        let movements: Vec<_> = wallet_manager
+            .ledger_entries()
+            .iter()
+            .map(|entry| entry.movement)
+            .collect();
+        assert_eq!(
+            movements,
+            [
+                Movement::Deposit,
+                Movement::Withdrawal,
+                Movement::WithdrawalHold,
+                Movement::WithdrawalReversal,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dispute_holds_expire_with_input_time() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_ledger: true,
+            dispute_expiry_secs: Some(60),
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let apply = |transaction: Transaction, secs: i64| {
+            let mut envelope = Envelope::from(transaction);
+            envelope.timestamp = Some(Timestamp::from_secs(secs));
+            wallet_manager.apply(envelope).unwrap();
+        };
+        let deposit = |tx: u32| Transaction::Deposit {
+            client,
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(10, 0),
+        };
+        let dispute = Transaction::Dispute {
+            client,
+            tx_id: TransactionId::new(1),
+        };
+        let held = || wallet_manager.export_wallets()[0].held();
+
+        apply(deposit(1), 0);
+        apply(dispute, 10);
+        apply(deposit(2), 70);
+        assert_eq!(held(), Amount::from_major(10, 0));
+        apply(deposit(3), 71);
+        assert_eq!(held(), Amount::zero());
+        let release = wallet_manager.ledger_entries().pop().unwrap();
+        assert_eq!(release.movement, Movement::Release);
+        assert_eq!(release.seq, None);
+
+        // The hold of the second dispute doesn't release the third one.
+        apply(dispute, 80);
+        apply(
+            Transaction::Resolve {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+            90,
+        );
+        apply(dispute, 100);
+        apply(deposit(4), 141);
+        assert_eq!(held(), Amount::from_major(10, 0));
+        assert_eq!(wallet_manager.expire_disputes(), 0);
+        apply(deposit(5), 161);
+        assert_eq!(held(), Amount::zero());
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[test]
+    fn test_verify_totals_detects_divergence() {
+        let wallet_manager = WalletManager::init();
+        for (client, tx) in [(1, 1), (2, 2)] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client: Client::new(client),
+                        tx_id: TransactionId::new(tx),
+                        amount: Amount::from_major(10, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+        wallet_manager.verify_totals().unwrap();
+
+        wallet_manager
+            .wallets
+            .get_mut(&Client::new(2))
+            .unwrap()
+            .balance
+            .total += Amount::from_major(0, 5000);
+        assert!(wallet_manager.verify_totals().is_err());
+    }
+
+    #[test]
+    fn test_verify_totals_holds_for_large_inputs() {
+        let wallet_manager = WalletManager::init();
+        // 27k deposits and withdrawals summing to about 1e8, where f32 keeps no decimals.
+        let mut state = 42u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            state >> 33
+        };
+        for tx in 1..=27_000 {
+            let client = Client::new((next() % 50) as u16);
+            let amount = Amount::from_major(next() % 10_000, (next() % 10_000) as u32);
+            let tx_id = TransactionId::new(tx);
+            let transaction = if tx % 4 == 0 {
+                Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount,
+                }
+            } else {
+                Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount,
+                }
+            };
+            let _ = wallet_manager.apply(transaction.into());
+        }
+        let total = wallet_manager.wallet_totals();
+        assert!(total > Amount::from_major(50_000_000, 0), "{total}");
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[test]
+    fn test_state_hash_covers_balances_and_disputes() {
+        let run = |order: &[u32], dispute: bool| {
+            let wallet_manager = WalletManager::init();
+            for &tx in order {
+                let deposit = Transaction::Deposit {
+                    client: Client::new(tx as u16),
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(10, 0),
+                };
+                wallet_manager.apply(deposit.into()).unwrap();
+            }
+            if dispute {
+                let dispute = Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(1),
+                };
+                wallet_manager.apply(dispute.into()).unwrap();
+            }
+            wallet_manager.state_hash()
+        };
+        assert_eq!(run(&[1, 2, 3], false), run(&[3, 1, 2], false));
+        assert_ne!(run(&[1, 2, 3], false), run(&[1, 2, 3], true));
+        // One wallet of 10 available, as a reference implementation would hash it.
+        assert_eq!(
+            run(&[1], false),
+            to_hex(&Sha256::digest("1,10.0000,0.0000,10.0000,false\n").into())
+        );
+    }
+
+    #[test]
+    fn test_dormancy_measured_against_latest_timestamp() {
+        let wallet_manager = WalletManager::init();
+        let day = Timestamp::SECONDS_PER_DAY;
+        let deposit = |client: u16, tx: u32, secs: i64| Envelope {
+            transaction: Transaction::Deposit {
+                client: Client::new(client),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            },
+            timestamp: Some(Timestamp::from_secs(secs)),
+            tenant: None,
+            seq: None,
+            attributes: Attributes::default(),
+            origin: None,
+        };
+        wallet_manager.apply(deposit(1, 1, 0)).unwrap();
+        wallet_manager.apply(deposit(2, 2, 20 * day)).unwrap();
+        wallet_manager.apply(deposit(3, 3, 40 * day)).unwrap();
+
+        let policy = DormancyPolicy {
+            inactive_days: 30,
+            fee: Some(Amount::from_major(1, 0)),
+        };
+        let report = wallet_manager.apply_dormancy(&policy);
+
+        assert_eq!(
+            report,
+            vec![DormantWallet {
+                client: Client::new(1),
+                last_activity: Timestamp::from_secs(0),
+                days_inactive: 40,
+                fee_charged: Amount::from_major(1, 0),
+            }]
+        );
+        let wallet = wallet_manager.wallets.get(&Client::new(1)).unwrap();
+        assert!(wallet.dormant);
+        assert_eq!(wallet.balance.total, Amount::from_major(9, 0));
+        drop(wallet);
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[test]
+    fn test_deposit_volume_feeds_risk_features() {
+        /// Scores the deposits of the last hour alone.
+        #[derive(Debug)]
+        struct HourlyVolumeScorer;
+
+        impl RiskScorer for HourlyVolumeScorer {
+            fn score(&self, features: &RiskFeatures) -> f32 {
+                features.deposits_last_hour.as_f32()
+            }
+        }
+
+        let wallet_manager = WalletManager::with_config(Config {
+            risk_scorer: Some(Arc::new(HourlyVolumeScorer)),
+            risk_thresholds: RiskThresholds {
+                reject: Some(25.0),
+                ..RiskThresholds::default()
+            },
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let deposit = |tx: u32, secs: i64| Envelope {
+            transaction: Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            },
+            timestamp: Some(Timestamp::from_secs(secs)),
+            tenant: None,
+            seq: None,
+            attributes: Attributes::default(),
+            origin: None,
+        };
+        for tx in 1..=3 {
+            wallet_manager.apply(deposit(tx, tx as i64 * 600)).unwrap();
+        }
+        let failure = wallet_manager.apply(deposit(4, 2400)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::RiskRejected);
+        // The oldest deposit left the hour, so the next one scores 20 again.
+        wallet_manager.apply(deposit(5, 4200)).unwrap();
+
+        let volume = wallet_manager.deposit_volume(client);
+        assert_eq!(volume.last_hour, Amount::from_major(30, 0));
+        assert_eq!(volume.last_day, Amount::from_major(40, 0));
+    }
+
+    #[test]
+    fn test_withdrawal_respects_client_minimum_balance() {
+        let mut config = Config {
+            minimum_balance: Some(Amount::from_major(10, 0)),
+            ..Config::default()
+        };
+        config
+            .client_minimum_balances
+            .insert(Client::new(2), Amount::from_major(50, 0));
+        let wallet_manager = WalletManager::with_config(config);
+        for client in [1, 2] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client: Client::new(client),
+                        tx_id: TransactionId::new(client as u32),
+                        amount: Amount::from_major(100, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+        let withdraw = |client: u16, tx: u32| {
+            wallet_manager.apply(
+                Transaction::Withdrawal {
+                    client: Client::new(client),
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(60, 0),
+                }
+                .into(),
+            )
+        };
+
+        assert!(withdraw(1, 3).is_ok());
+        let failure = withdraw(2, 4).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::BelowMinimumBalance);
+        assert_eq!(failure.client, Client::new(2));
+    }
+
+    #[test]
+    fn test_withdrawal_without_wallet_follows_policy() {
+        let withdrawal = Transaction::Withdrawal {
+            client: Client::new(1),
+            tx_id: TransactionId::new(1),
+            amount: Amount::from_major(5, 0),
+        };
+
+        let wallet_manager = WalletManager::init();
+        let failure = wallet_manager.apply(withdrawal.into()).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::NoWallet);
+        assert!(wallet_manager.export_wallets().is_empty());
+
+        let wallet_manager = WalletManager::with_config(Config {
+            missing_wallet: MissingWallet::Create,
+            ..Config::default()
+        });
+        let failure = wallet_manager.apply(withdrawal.into()).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::InsufficientFunds);
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].balance, Balance::new());
+    }
+
+    #[test]
+    fn test_transaction_ids_are_unique_across_clients() {
+        let wallet_manager = WalletManager::init();
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        let tx_id = TransactionId::new(1);
+        let deposit = |client| {
+            Envelope::from(Transaction::Deposit {
+                client,
+                tx_id,
+                amount: Amount::from_major(5, 0),
+            })
+        };
+        wallet_manager.apply(deposit(alice)).unwrap();
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client: bob,
+                    tx_id: TransactionId::new(2),
+                    amount: Amount::from_major(5, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+
+        // Replayed, or reused by another client, the deposit isn't credited twice.
+        for client in [alice, bob] {
+            let failure = wallet_manager.apply(deposit(client)).unwrap_err();
+            assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        }
+        let withdrawal = Transaction::Withdrawal {
+            client: bob,
+            tx_id,
+            amount: Amount::from_major(1, 0),
+        };
+        let failure = wallet_manager.apply(withdrawal.into()).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        let failure = wallet_manager
+            .simulate(deposit(alice).transaction)
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+
+        let failure = wallet_manager
+            .apply(Transaction::Dispute { client: bob, tx_id }.into())
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::ClientMismatch);
+        assert_eq!(failure.reason, "Transaction belongs to client 1");
+        wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client: alice,
+                    tx_id,
+                }
+                .into(),
+            )
+            .unwrap();
+        let failure = wallet_manager
+            .apply(Transaction::Resolve { client: bob, tx_id }.into())
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::ClientMismatch);
+        assert_eq!(
+            wallet_manager.wallet(alice).unwrap().held(),
+            Amount::from_major(5, 0)
+        );
+        assert_eq!(
+            wallet_manager.wallet(bob).unwrap().total(),
+            Amount::from_major(5, 0)
+        );
+        wallet_manager.verify_totals().unwrap();
+    }
+
+    #[test]
+    fn test_unusable_journal_dir_is_an_error() {
+        let missing = std::env::temp_dir().join(format!("no-journal-{}", std::process::id()));
+        let e = WalletManager::try_with_config(Config {
+            journal_dir: Some(missing.clone()),
+            ..Config::default()
+        })
+        .err()
+        .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().contains(&missing.display().to_string()));
+    }
+
+    #[test]
+    fn test_unusable_persistence_dir_is_an_error() {
+        use crate::durability::FsyncPolicy;
+        use crate::persistence::PersistenceOptions;
+
+        // A file where the directory of the write-ahead log goes.
+        let file = std::env::temp_dir().join(format!("not-a-wal-dir-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let e = WalletManager::try_with_config(Config {
+            persistence: Some(PersistenceOptions {
+                dir: file.clone(),
+                fsync: FsyncPolicy::Never,
+                checkpoint_every: None,
+            }),
+            ..Config::default()
+        })
+        .err()
+        .unwrap();
+        assert!(e.to_string().contains("write-ahead log"), "{e}");
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_disk_journal_serves_disputes() {
+        let wallet_manager = WalletManager::with_config(Config {
+            journal_dir: Some(std::env::temp_dir()),
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        for (tx, transaction) in [
+            (1, "deposit"),
+            (2, "withdrawal"),
+            (1, "dispute"),
+            (2, "dispute"),
+            (1, "resolve"),
+            (2, "chargeback"),
+        ] {
+            let tx_id = TransactionId::new(tx);
+            let amount = Amount::from_major(u64::from(tx) * 10, 0);
+            let transaction = match transaction {
+                "deposit" => Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount: Amount::from_major(50, 0),
+                },
+                "withdrawal" => Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount,
+                },
+                "dispute" => Transaction::Dispute { client, tx_id },
+                "resolve" => Transaction::Resolve { client, tx_id },
+                _ => Transaction::ChargeBack { client, tx_id },
+            };
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+        let failure = wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client: Client::new(2),
+                    tx_id: TransactionId::new(2),
+                    amount: Amount::from_major(1, 0),
+                }
+                .into(),
+            )
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.available(), Amount::from_major(50, 0));
+        wallet_manager.verify_totals().unwrap();
+        assert_eq!(
+            wallet_manager.rebuild(client).unwrap().after,
+            wallet.balance
+        );
+    }
+
+    #[test]
+    fn test_skipping_the_journal_fails_disputes() {
+        let client = Client::new(1);
+        let wallet_manager = WalletManager::with_config(Config {
+            skip_journal: true,
+            ..Config::default()
+        });
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(2, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+        let failure = wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::TransactionNotFound);
+        assert!(wallet_manager.transaction_journal.is_empty());
+    }
+
+    #[test]
+    fn test_dispute_without_wallet_follows_policy() {
+        let client = Client::new(1);
+        let transactions = [
+            Transaction::Dispute {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+            Transaction::Resolve {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+            Transaction::ChargeBack {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+        ];
+
+        let wallet_manager = WalletManager::init();
+        for transaction in transactions {
+            assert!(wallet_manager.apply(transaction.into()).is_err());
+        }
+        assert!(wallet_manager.export_wallets().is_empty());
+
+        let wallet_manager = WalletManager::with_config(Config {
+            missing_wallet_on_dispute: MissingWallet::Create,
+            ..Config::default()
+        });
+        let kinds: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| wallet_manager.apply(transaction.into()).unwrap_err().kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                FailureKind::TransactionNotFound,
+                FailureKind::DisputeNotFound,
+                FailureKind::DisputeNotFound
+            ]
+        );
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].balance, Balance::new());
+        assert_eq!(wallets[0].stats.failures, 3);
+        assert!(!wallets[0].is_locked());
+    }
+
+    #[test]
+    fn test_joint_owners_share_one_wallet() {
+        let config = Config {
+            joint_owners: HashMap::from([(Client::new(2), Client::new(1))]),
+            ..Config::default()
+        };
+        let wallet_manager = WalletManager::with_config(config);
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client: Client::new(2),
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(100, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+        wallet_manager
+            .apply(
+                Transaction::Withdrawal {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(2),
+                    amount: Amount::from_major(30, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+        wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        let failure = wallet_manager
+            .apply(
+                Transaction::Withdrawal {
+                    client: Client::new(2),
+                    tx_id: TransactionId::new(3),
+                    amount: Amount::from_major(500, 0),
+                }
+                .into(),
+            )
+            .unwrap_err();
+        assert_eq!(failure.client, Client::new(2));
+
+        let wallets = wallet_manager.export_wallets();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(
+            wallets[0].owners().collect::<Vec<_>>(),
+            vec![Client::new(1), Client::new(2)]
+        );
+        assert_eq!(wallets[0].balance.total, Amount::from_major(70, 0));
+        assert_eq!(wallets[0].balance.held, Amount::from_major(100, 0));
+        assert_eq!(
+            wallet_manager.wallet(Client::new(2)).map(|w| w.client),
+            Some(Client::new(1))
+        );
+    }
+
+    #[test]
+    fn test_wallet_stats_count_processed_transactions() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        for envelope in [
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(1),
+                amount: Amount::from_major(10, 0),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(50, 0),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(5, 0),
+            },
+            Transaction::Dispute {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+            Transaction::Resolve {
+                client,
+                tx_id: TransactionId::new(1),
+            },
+        ] {
+            let _ = wallet_manager.apply(envelope.into());
+        }
+
+        assert_eq!(
+            wallet_manager.wallets.get(&client).unwrap().stats,
+            WalletStats {
+                deposits: 1,
+                withdrawals: 1,
+                disputes: 1,
+                failures: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_closed_wallet_is_forgotten() {
+        let wallet_manager = WalletManager::init();
+        let deposit = |tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        wallet_manager.apply(deposit(1)).unwrap();
+
+        let wallet = wallet_manager.close(Client::new(1)).unwrap().unwrap();
+        assert_eq!(wallet.balance.total, Amount::from_major(10, 0));
+        assert!(wallet_manager.export_wallets().is_empty());
+        assert!(wallet_manager.close(Client::new(1)).unwrap().is_none());
+        let dispute = Transaction::Dispute {
+            client: Client::new(1),
+            tx_id: TransactionId::new(1),
+        };
+        wallet_manager.apply(deposit(2)).unwrap();
+        assert_eq!(
+            wallet_manager.apply(dispute.into()).unwrap_err().kind,
+            FailureKind::TransactionNotFound
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_audit_records_every_transition() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_lifecycle: true,
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let deposit = |tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        let changes = || -> Vec<_> {
+            wallet_manager
+                .lifecycle_events()
+                .iter()
+                .map(|e| (e.seq, e.change, e.tx.map(|tx| tx.id()), e.reason))
+                .collect()
+        };
+        wallet_manager.apply(deposit(1)).unwrap();
+        wallet_manager.close(client).unwrap().unwrap();
+        wallet_manager.apply(deposit(2)).unwrap();
+        assert!(wallet_manager.set_frozen(client, true));
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.lock_reason(), Some(LockReason::Admin));
+        let audited = [
+            (1, LifecycleChange::Created, Some(1), None),
+            (1, LifecycleChange::Closed, None, None),
+            (2, LifecycleChange::Created, Some(2), None),
+            (2, LifecycleChange::Frozen, None, Some(LockReason::Admin)),
+        ];
+        assert_eq!(changes(), audited);
+
+        // Rolling back drops the transitions recorded since the savepoint.
+        let savepoint = wallet_manager.savepoint();
+        assert!(wallet_manager.set_frozen(client, false));
+        wallet_manager.apply(deposit(3)).unwrap();
+        assert_eq!(changes().len(), audited.len() + 1);
+        wallet_manager.rollback_to_savepoint(savepoint).unwrap();
+        assert_eq!(changes(), audited);
+    }
+
+    #[test]
+    fn test_quarantine_freezes_failing_wallet() {
+        let wallet_manager = WalletManager::with_config(Config {
+            failure_policy: FailurePolicy::Quarantine,
+            ..Config::default()
+        });
+        let transaction = |client: u16, tx: u32, amount: u64| -> Envelope {
+            let (client, tx_id) = (Client::new(client), TransactionId::new(tx));
+            let amount = Amount::from_major(amount, 0);
+            if tx % 2 == 1 {
+                Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount,
+                }
+            } else {
+                Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount,
+                }
+            }
+            .into()
+        };
+        wallet_manager.apply(transaction(1, 1, 10)).unwrap();
+        wallet_manager.apply(transaction(2, 3, 10)).unwrap();
+        let failure = wallet_manager.apply(transaction(1, 2, 50)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::InsufficientFunds);
+
+        let failure = wallet_manager.apply(transaction(1, 5, 1)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Quarantined);
+        wallet_manager.apply(transaction(2, 4, 1)).unwrap();
+        assert_eq!(wallet_manager.failure_count(), 2);
+        assert!(!wallet_manager.aborted());
+        let quarantined = |client: u16| {
+            wallet_manager
+                .wallets
+                .get(&Client::new(client))
+                .unwrap()
+                .quarantined
+        };
+        assert!(quarantined(1));
+        assert!(!quarantined(2));
+    }
+
+    #[test]
+    fn test_dispute_before_its_deposit_is_deferred() {
+        let wallet_manager = WalletManager::with_config(Config {
+            dispute_deferral: Some(2),
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let dispute = |tx: u32| Transaction::Dispute {
+            client,
+            tx_id: TransactionId::new(tx),
+        };
+        let deposit = |tx: u32| Transaction::Deposit {
+            client,
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(10, 0),
+        };
+        wallet_manager.apply(dispute(1).into()).unwrap();
+        wallet_manager.apply(dispute(2).into()).unwrap();
+        wallet_manager.apply(deposit(1).into()).unwrap();
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.held(), Amount::from_major(10, 0));
+        assert!(wallet_manager.take_deferred_failures().is_empty());
+
+        // The dispute of tx 2 waited for two more transactions.
+        wallet_manager.apply(deposit(3).into()).unwrap();
+        let failures = wallet_manager.take_deferred_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].tx, TransactionId::new(2));
+        assert_eq!(failures[0].kind, FailureKind::TransactionNotFound);
+
+        wallet_manager.apply(dispute(4).into()).unwrap();
+        wallet_manager.expire_pending_disputes();
+        assert_eq!(wallet_manager.take_deferred_failures().len(), 1);
+        assert_eq!(wallet_manager.failure_count(), 2);
+    }
+
+    #[test]
+    fn test_reservations_hold_funds_until_committed_or_released() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(10, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+        let balance = || {
+            let wallet = wallet_manager.wallet(client).unwrap();
+            [wallet.available(), wallet.held(), wallet.total()].map(|a| a.to_string())
+        };
+
+        let payment = wallet_manager
+            .reserve(client, TransactionId::new(2), Amount::from_major(6, 0))
+            .unwrap();
+        assert_eq!(balance(), ["4.0000", "6.0000", "10.0000"]);
+        let failure = wallet_manager
+            .reserve(client, TransactionId::new(3), Amount::from_major(5, 0))
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::InsufficientFunds);
+        let refunded = wallet_manager
+            .reserve(client, TransactionId::new(3), Amount::from_major(3, 0))
+            .unwrap();
+        assert_ne!(payment, refunded);
+        let failure = wallet_manager
+            .reserve(client, TransactionId::new(3), Amount::from_major(1, 0))
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        wallet_manager.rebuild(client).unwrap();
+        assert_eq!(balance(), ["1.0000", "9.0000", "10.0000"]);
+
+        assert_eq!(
+            wallet_manager.release(refunded).map(|r| r.amount),
+            Some(Amount::from_major(3, 0))
+        );
+        assert_eq!(wallet_manager.release(refunded), None);
+        assert_eq!(balance(), ["4.0000", "6.0000", "10.0000"]);
+        let committed = wallet_manager.commit(payment).unwrap().unwrap();
+        assert_eq!(committed.tx_id, TransactionId::new(2));
+        assert_eq!(wallet_manager.commit(payment).unwrap(), None);
+        assert_eq!(balance(), ["4.0000", "0.0000", "4.0000"]);
+        assert_eq!(
+            wallet_manager.house_accounts().settlement,
+            Amount::from_major(4, 0)
+        );
+        wallet_manager.verify_totals().unwrap();
+
+        // The committed reservation is journaled like any withdrawal.
+        wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(2),
+                }
+                .into(),
+            )
+            .unwrap();
+        assert_eq!(balance(), ["4.0000", "6.0000", "10.0000"]);
+    }
+
+    #[test]
+    fn test_transfer_applies_both_legs_or_neither() {
+        let wallet_manager = WalletManager::init();
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        for client in [alice, bob] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(client.id().into()),
+                        amount: Amount::from_major(10, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+        let totals = || {
+            [alice, bob, Client::new(3)]
+                .map(|c| wallet_manager.wallet(c).map(|w| w.total().to_string()))
+        };
+        let tx = TransactionId::new(10);
+
+        wallet_manager
+            .transfer(alice, bob, tx, Amount::from_major(4, 0))
+            .unwrap();
+        assert_eq!(
+            totals(),
+            [Some("6.0000".into()), Some("14.0000".into()), None]
+        );
+
+        // A credit leg that can't be applied leaves the debited wallet untouched.
+        wallet_manager.set_frozen(bob, true);
+        let failure = wallet_manager
+            .transfer(alice, bob, tx, Amount::from_major(1, 0))
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Frozen);
+        let failure = wallet_manager
+            .transfer(alice, Client::new(3), tx, Amount::from_major(7, 0))
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::InsufficientFunds);
+        assert_eq!(
+            totals(),
+            [Some("6.0000".into()), Some("14.0000".into()), None]
+        );
+        assert_eq!(
+            wallet_manager.wallet(alice).unwrap().available(),
+            Amount::from_major(6, 0)
+        );
+
+        wallet_manager
+            .transfer(alice, Client::new(3), tx, Amount::from_major(6, 0))
+            .unwrap();
+        assert_eq!(
+            totals(),
+            [
+                Some("0.0000".into()),
+                Some("14.0000".into()),
+                Some("6.0000".into())
+            ]
+        );
+        assert!(wallet_manager.verify_totals().is_ok());
+    }
+
+    #[test]
+    fn test_transfer_reusing_a_journaled_tx_id_fails() {
+        let wallet_manager = WalletManager::init();
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        for (client, tx) in [(alice, 1), (bob, 2)] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(tx),
+                        amount: Amount::from_major(10, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+
+        let amount = Amount::from_major(4, 0);
+        let failure = wallet_manager
+            .transfer(alice, bob, TransactionId::new(2), amount)
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        assert_eq!(failure.seq, Some(3));
+        assert_eq!(
+            [alice, bob].map(|client| wallet_manager.wallet(client).unwrap().total()),
+            [Amount::from_major(10, 0), Amount::from_major(10, 0)]
+        );
+        wallet_manager
+            .transfer(alice, bob, TransactionId::new(3), amount)
+            .unwrap();
+        assert_eq!(wallet_manager.last_sequence(), 4);
+    }
+
+    #[test]
+    fn test_transfer_out_of_charged_back_wallet_fails() {
+        let wallet_manager = WalletManager::init();
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        for (client, tx) in [(alice, 1), (alice, 2), (bob, 3)] {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(tx),
+                        amount: Amount::from_major(10, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+        }
+        let tx_id = TransactionId::new(1);
+        for transaction in [
+            Transaction::Dispute {
+                client: alice,
+                tx_id,
+            },
+            Transaction::ChargeBack {
+                client: alice,
+                tx_id,
+            },
+        ] {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+
+        let amount = Amount::from_major(5, 0);
+        let failure = wallet_manager
+            .transfer(alice, bob, TransactionId::new(10), amount)
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AccountLocked);
+        let failure = wallet_manager
+            .transfer(bob, alice, TransactionId::new(11), amount)
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AccountLocked);
+        assert_eq!(
+            [alice, bob].map(|client| wallet_manager.wallet(client).unwrap().available()),
+            [Amount::from_major(10, 0), Amount::from_major(10, 0)]
+        );
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint_undoes_batch() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_ledger: true,
+            ..Config::default()
+        });
+        let deposit = |client: u16, tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client: Client::new(client),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        wallet_manager.apply(deposit(1, 1)).unwrap();
+        let savepoint = wallet_manager.savepoint();
+
+        wallet_manager.apply(deposit(1, 2)).unwrap();
+        wallet_manager.apply(deposit(2, 3)).unwrap();
+        wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        wallet_manager.rollback_to_savepoint(savepoint).unwrap();
+
+        assert_eq!(wallet_manager.wallet_count(), 1);
+        let wallet = wallet_manager.wallet(Client::new(1)).unwrap();
+        assert_eq!(wallet.available(), Amount::from_major(10, 0));
+        assert_eq!(wallet.held(), Amount::zero());
+        assert_eq!(wallet_manager.last_sequence(), 1);
+        assert_eq!(wallet_manager.ledger_entries().len(), 1);
+        assert!(wallet_manager.verify_totals().is_ok());
+        // Transactions rolled back can be applied again.
+        wallet_manager.apply(deposit(1, 2)).unwrap();
+        assert_eq!(
+            wallet_manager.wallet(Client::new(1)).unwrap().total(),
+            Amount::from_major(20, 0)
+        );
+    }
+
+    #[test]
+    fn test_frozen_wallet_rejects_activity_until_unfrozen() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        let deposit = |tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        assert!(!wallet_manager.set_frozen(client, true));
+        wallet_manager.apply(deposit(1)).unwrap();
+
+        assert!(wallet_manager.set_frozen(client, true));
+        let failure = wallet_manager.apply(deposit(2)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::Frozen);
+        assert_eq!(wallet_manager.wallet(client).unwrap().status(), "frozen");
+
+        assert!(wallet_manager.set_frozen(client, false));
+        wallet_manager.apply(deposit(3)).unwrap();
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.status(), "active");
+        assert_eq!(wallet.total(), Amount::from_major(20, 0));
+    }
+
+    #[test]
+    fn test_frozen_wallet_quarantines_what_it_turns_away() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_quarantine: true,
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let deposit = |tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        wallet_manager.apply(deposit(1)).unwrap();
+        assert!(wallet_manager.set_frozen(client, true));
+        wallet_manager.apply(deposit(2)).unwrap_err();
+        let quarantined = wallet_manager.quarantined_transactions();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(
+            (
+                quarantined[0].tx,
+                quarantined[0].status,
+                quarantined[0].total
+            ),
+            (TransactionId::new(2), "frozen", Amount::from_major(10, 0))
+        );
+
+        // Re-applying the quarantined transaction after the unfreeze.
+        assert!(wallet_manager.set_frozen(client, false));
+        wallet_manager.apply(deposit(2)).unwrap();
+        assert_eq!(
+            wallet_manager.wallet(client).unwrap().total(),
+            Amount::from_major(20, 0)
+        );
+    }
+
+    #[test]
+    fn test_locked_wallet_rejects_activity_until_unlocked() {
+        let wallet_manager = WalletManager::with_config(Config {
+            keep_quarantine: true,
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let deposit = |tx: u32| -> Envelope {
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(10, 0),
+            }
+            .into()
+        };
+        assert!(!wallet_manager.unlock(client));
+        wallet_manager.apply(deposit(1)).unwrap();
+        wallet_manager.apply(deposit(2)).unwrap();
+        let tx_id = TransactionId::new(1);
+        wallet_manager
+            .apply(Transaction::Dispute { client, tx_id }.into())
+            .unwrap();
+        wallet_manager
+            .apply(Transaction::ChargeBack { client, tx_id }.into())
+            .unwrap();
+        assert_eq!(wallet_manager.wallet(client).unwrap().status(), "locked");
+
+        let failure = wallet_manager.apply(deposit(3)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::AccountLocked);
+        let quarantined = wallet_manager.quarantined_transactions();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(
+            (
+                quarantined[0].tx,
+                quarantined[0].status,
+                quarantined[0].total
+            ),
+            (TransactionId::new(3), "locked", Amount::from_major(10, 0))
+        );
+
+        assert!(wallet_manager.unlock(client));
+        wallet_manager.apply(deposit(3)).unwrap();
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.status(), "active");
+        assert_eq!(wallet.total(), Amount::from_major(20, 0));
+    }
+
+    #[test]
+    fn test_risk_score_thresholds_flag_hold_and_reject() {
+        /// Scores the amount alone.
+        #[derive(Debug)]
+        struct AmountScorer;
+
+        impl RiskScorer for AmountScorer {
+            fn score(&self, features: &RiskFeatures) -> f32 {
+                features.amount.as_f32()
+            }
+        }
+
+        let wallet_manager = WalletManager::with_config(Config {
+            risk_scorer: Some(Arc::new(AmountScorer)),
+            risk_thresholds: RiskThresholds {
+                flag: Some(10.0),
+                hold: Some(50.0),
+                reject: Some(100.0),
+            },
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let deposit = |tx: u32, amount: u64| -> Envelope {
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(amount, 0),
+            }
+            .into()
+        };
+
+        wallet_manager.apply(deposit(1, 20)).unwrap();
+        let failure = wallet_manager.apply(deposit(2, 200)).unwrap_err();
+        assert_eq!(failure.kind, FailureKind::RiskRejected);
+        wallet_manager.apply(deposit(3, 60)).unwrap();
+        assert!(wallet_manager.wallet(client).unwrap().is_frozen());
+        assert_eq!(
+            wallet_manager.apply(deposit(4, 1)).unwrap_err().kind,
+            FailureKind::Frozen
+        );
+        assert_eq!(wallet_manager.risk_decisions()[0].seq, 3);
+
+        assert_eq!(
+            wallet_manager.risk_scores(),
+            vec![RiskScore {
+                client,
+                scored: 3,
+                last_score: 60.0,
+                max_score: 200.0,
+                flagged: 1,
+                held: 1,
+                rejected: 1,
+                chargebacks: 0,
+                chargeback_ratio: 0.0,
+                auto_frozen_at: None,
+            }]
+        );
+        assert_eq!(
+            wallet_manager.wallet(client).unwrap().total(),
+            Amount::from_major(80, 0)
+        );
+    }
+
+    #[test]
+    fn test_failed_transactions_dont_count_towards_velocity() {
+        /// Scores the velocity alone.
+        #[derive(Debug)]
+        struct VelocityScorer(Mutex<Vec<usize>>);
+
+        impl RiskScorer for VelocityScorer {
+            fn score(&self, features: &RiskFeatures) -> f32 {
+                self.0.lock().unwrap().push(features.velocity);
+                0.0
+            }
         }
-    }
 
-    pub async fn run(
-        &self,
-        mut tx_recv: UnboundedReceiver<Transaction>,
-        err_send: UnboundedSender<Failure>,
-    ) {
-        while let Some(transaction) = tx_recv.recv().await {
-            let res = match transaction {
+        let scorer = Arc::new(VelocityScorer(Mutex::new(Vec::new())));
+        let wallet_manager = WalletManager::with_config(Config {
+            risk_scorer: Some(scorer.clone()),
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        let at = |secs: i64, transaction: Transaction| Envelope {
+            timestamp: Some(Timestamp::from_secs(secs)),
+            ..transaction.into()
+        };
+        let withdrawal = |tx: u32| Transaction::Withdrawal {
+            client,
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(50, 0),
+        };
+
+        wallet_manager
+            .apply(at(
+                0,
                 Transaction::Deposit {
                     client,
-                    tx_id,
-                    amount,
-                } => {
-                    self.wallets
-                        .entry(client)
-                        .or_insert_with(|| Wallet::new(client))
-                        .deposit(tx_id, amount);
-                    self.transaction_journal
-                        .entry(client)
-                        .or_insert_with(|| HashMap::new())
-                        .insert(
-                            tx_id,
-                            Transaction::Deposit {
-                                client,
-                                tx_id,
-                                amount,
-                            },
-                        );
-                    Ok(())
-                }
-                Transaction::Withdrawal {
-                    client,
-                    tx_id,
-                    amount,
-                } => {
-                    if let Some(mut wallet) = self.wallets.get_mut(&client) {
-                        wallet.withdraw(tx_id, amount).and_then(|_| {
-                            self.transaction_journal
-                                .entry(client)
-                                .or_insert_with(|| HashMap::new())
-                                .insert(
-                                    tx_id,
-                                    Transaction::Withdrawal {
-                                        client,
-                                        tx_id,
-                                        amount,
-                                    },
-                                );
-                            Ok(())
-                        })
-                    } else {
-                        Err(Failure::no_wallet(client, tx_id))
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(10, 0),
+                },
+            ))
+            .unwrap();
+        wallet_manager.apply(at(10, withdrawal(2))).unwrap_err();
+        wallet_manager.apply(at(20, withdrawal(3))).unwrap_err();
+
+        assert_eq!(*scorer.0.lock().unwrap(), [0, 1, 1]);
+        assert_eq!(wallet_manager.risk_scores()[0].scored, 3);
+    }
+
+    #[test]
+    fn test_chargeback_ratio_freezes_wallet_and_is_journaled() {
+        let wallet_manager = WalletManager::with_config(Config {
+            chargeback_policy: ChargebackPolicy {
+                max_ratio: Some(0.5),
+                window: 10,
+                min_chargebacks: 2,
+            },
+            ..Config::default()
+        });
+        let client = Client::new(1);
+        for tx in 1..=3 {
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(tx),
+                        amount: Amount::from_major(10, 0),
                     }
+                    .into(),
+                )
+                .unwrap();
+        }
+        for tx in 1..=2 {
+            let tx_id = TransactionId::new(tx);
+            wallet_manager
+                .apply(Transaction::Dispute { client, tx_id }.into())
+                .unwrap();
+            wallet_manager
+                .apply(Transaction::ChargeBack { client, tx_id }.into())
+                .unwrap();
+            assert_eq!(wallet_manager.wallet(client).unwrap().is_frozen(), tx == 2);
+            assert!(wallet_manager.unlock(client));
+        }
+
+        let decisions = wallet_manager.risk_decisions();
+        assert_eq!(decisions.len(), 1);
+        assert_eq!((decisions[0].seq, decisions[0].client), (7, client));
+        let score = &wallet_manager.risk_scores()[0];
+        assert_eq!(score.chargebacks, 2);
+        assert_eq!(score.auto_frozen_at, Some(7));
+    }
+
+    #[test]
+    fn test_abort_after_max_failures() {
+        let wallet_manager = WalletManager::with_config(Config {
+            failure_policy: FailurePolicy::Abort,
+            max_failures: 1,
+            ..Config::default()
+        });
+        let withdraw = |tx: u32| {
+            wallet_manager.apply(
+                Transaction::Withdrawal {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(tx),
+                    amount: Amount::from_major(1, 0),
                 }
-                Transaction::Dispute { client, tx_id } => {
-                    let tx = self
-                        .transaction_journal
-                        .get(&client)
-                        .and_then(|txs| txs.get(&tx_id).cloned());
-
-                    match tx {
-                        Some(Transaction::Deposit { amount, .. }) => {
-                            if let Some(mut wallet) = self.wallets.get_mut(&client) {
-                                Ok(wallet.dispute(tx_id, amount))
-                            } else {
-                                Err(Failure::no_wallet(client, tx_id))
-                            }
-                        }
-                        Some(Transaction::Withdrawal { .. }) => Err(Failure::new(
-                            client,
-                            tx_id,
-                            "Can't dispute a withdraw!".to_string(),
-                        )),
-                        _ => Err(Failure::new(
-                            client,
-                            tx_id,
-                            "Transaction to dispute was not found!".to_string(),
-                        )),
-                    }
+                .into(),
+            )
+        };
+        withdraw(1).unwrap_err();
+        assert!(!wallet_manager.aborted());
+        withdraw(2).unwrap_err();
+        assert!(wallet_manager.aborted());
+    }
+
+    #[test]
+    fn test_simulate_projects_without_applying() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(100, 0),
                 }
-                Transaction::Resolve { client, tx_id } => {
-                    if let Some(mut wallet) = self.wallets.get_mut(&client) {
-                        wallet.settle_dispute(tx_id)
-                    } else {
-                        Err(Failure::no_wallet(client, tx_id))
-                    }
+                .into(),
+            )
+            .unwrap();
+        let withdraw = |amount| Transaction::Withdrawal {
+            client,
+            tx_id: TransactionId::new(2),
+            amount: Amount::from_major(amount, 0),
+        };
+
+        let projected = wallet_manager.simulate(withdraw(30)).unwrap();
+        assert_eq!(
+            (projected.available, projected.total),
+            (Amount::from_major(70, 0), Amount::from_major(70, 0))
+        );
+        assert_eq!(
+            wallet_manager.simulate(withdraw(200)).unwrap_err().kind,
+            FailureKind::InsufficientFunds
+        );
+        let dispute = Transaction::Dispute {
+            client,
+            tx_id: TransactionId::new(1),
+        };
+        let projected = wallet_manager.simulate(dispute).unwrap();
+        assert_eq!(projected.held, Amount::from_major(100, 0));
+
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(
+            (wallet.available(), wallet.held()),
+            (Amount::from_major(100, 0), Amount::zero())
+        );
+        assert_eq!(wallet_manager.failure_count(), 0);
+        assert_eq!(wallet_manager.last_sequence(), 1);
+    }
+
+    #[test]
+    fn test_rebuild_recomputes_balance_from_journal() {
+        let wallet_manager = WalletManager::init();
+        let client = Client::new(1);
+        let transactions = [
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(1),
+                amount: Amount::from_major(100, 0),
+            },
+            Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(50, 0),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(30, 0),
+            },
+            Transaction::Dispute {
+                client,
+                tx_id: TransactionId::new(2),
+            },
+        ];
+        for transaction in transactions {
+            wallet_manager.apply(transaction.into()).unwrap();
+        }
+        let healthy = wallet_manager.wallet(client).unwrap().balance;
+        wallet_manager.wallets.get_mut(&client).unwrap().balance = Balance {
+            available: Amount::from_major(999, 0),
+            held: Amount::from_major(50, 0),
+            total: Amount::from_major(1_049, 0),
+        };
+        assert!(wallet_manager.verify_totals().is_err());
+
+        let rebuilt = wallet_manager.rebuild(client).unwrap();
+        assert_eq!(rebuilt.before.available, Amount::from_major(999, 0));
+        assert_eq!(rebuilt.after, healthy);
+        assert_eq!(healthy.held, Amount::from_major(50, 0));
+        wallet_manager.verify_totals().unwrap();
+        assert!(wallet_manager.rebuild(Client::new(2)).is_err());
+    }
+
+    #[test]
+    fn test_applied_transactions_publish_wallet_events() {
+        let events = EventHub::new(8);
+        let mut subscriber = events.subscribe();
+        let wallet_manager = WalletManager::init().with_events(events);
+        let deposit = Transaction::Deposit {
+            client: Client::new(1),
+            tx_id: TransactionId::new(1),
+            amount: Amount::from_major(10, 0),
+        };
+        wallet_manager.apply(deposit.into()).unwrap();
+        let failure = wallet_manager
+            .apply(
+                Transaction::Withdrawal {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(2),
+                    amount: Amount::from_major(50, 0),
                 }
-                Transaction::ChargeBack { client, tx_id } => {
-                    if let Some(mut wallet) = self.wallets.get_mut(&client) {
-                        wallet.charge_back(tx_id)
-                    } else {
-                        Err(Failure::no_wallet(client, tx_id))
-                    }
+                .into(),
+            )
+            .unwrap_err();
+        assert_eq!(failure.seq, Some(2));
+        wallet_manager
+            .apply(
+                Transaction::Dispute {
+                    client: Client::new(1),
+                    tx_id: TransactionId::new(1),
                 }
+                .into(),
+            )
+            .unwrap();
+
+        let event = subscriber.try_recv().unwrap();
+        assert_eq!(event.seq, 1);
+        assert_eq!(event.movement, Movement::Deposit);
+        assert_eq!(event.delta.total, Amount::from_major(10, 0));
+        let event = subscriber.try_recv().unwrap();
+        assert_eq!(event.seq, 3);
+        assert_eq!(event.tx_id, TransactionId::new(1));
+        assert_eq!(event.movement, Movement::Hold);
+        assert_eq!(event.delta.available, -Amount::from_minor_units(100_000));
+        assert_eq!(event.delta.held, Amount::from_major(10, 0));
+        assert_eq!(event.balance.total, Amount::from_major(10, 0));
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_replaying_overlapping_journal_over_snapshot_is_idempotent() {
+        use crate::export::{ExportOptions, read_wallets_csv, write_wallets_csv};
+
+        let journal: Vec<Envelope> = [
+            (1, 1, Some(10_i64)),
+            (2, 2, Some(20)),
+            (1, 3, Some(-4)),
+            (1, 1, None),
+            (2, 4, Some(-50)),
+            (2, 5, Some(5)),
+        ]
+        .into_iter()
+        .zip(1..)
+        .map(|((client, tx, amount), seq)| {
+            let (client, tx_id) = (Client::new(client), TransactionId::new(tx));
+            let transaction = match amount {
+                Some(amount) if amount < 0 => Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount: Amount::from_minor_units(-amount * 10_000),
+                },
+                Some(amount) => Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount: Amount::from_minor_units(amount * 10_000),
+                },
+                None => Transaction::Dispute { client, tx_id },
             };
-            if let Err(e) = res {
-                if err_send.send(e).is_err() {
-                    break;
-                }
+            Envelope {
+                seq: Some(seq),
+                ..transaction.into()
             }
+        })
+        .collect();
+        let balances = |manager: &WalletManager| {
+            let mut wallets = manager.export_wallets();
+            wallets.sort_by_key(|w| w.client().id());
+            wallets
+                .iter()
+                .map(|w| (w.client(), w.balance().clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let reference = WalletManager::init();
+        for envelope in journal.iter().cloned() {
+            let _ = reference.apply(envelope);
         }
-    }
+        let expected = balances(&reference);
+        for envelope in journal.iter().cloned() {
+            let _ = reference.apply(envelope);
+        }
+        assert_eq!(balances(&reference), expected);
 
-    pub fn export_wallets(&self) -> Vec<Wallet> {
-        self.wallets.iter().map(|r| r.value().clone()).collect()
+        let crashed = WalletManager::init();
+        for envelope in journal[..4].iter().cloned() {
+            let _ = crashed.apply(envelope);
+        }
+        let mut snapshot = Vec::new();
+        let options = ExportOptions {
+            seq: true,
+            ..ExportOptions::default()
+        };
+        write_wallets_csv(&mut snapshot, &crashed.export_wallets(), &options).unwrap();
+        let recovered = WalletManager::init();
+        for wallet in read_wallets_csv(snapshot.as_slice()).unwrap() {
+            recovered.restore(wallet);
+        }
+        assert_eq!(recovered.last_sequence(), 4);
+        for envelope in journal.iter().cloned() {
+            let _ = recovered.apply(envelope);
+        }
+        assert_eq!(balances(&recovered), expected);
+        assert_eq!(recovered.last_sequence(), 6);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::transaction::Amount;
-    use crate::wallet::Balance;
-    use std::sync::Arc;
+    #[test]
+    fn test_restoring_a_wallet_export_drops_its_disputes_and_journal() {
+        use crate::export::{ExportOptions, read_wallets_csv, write_wallets_csv};
 
-    #[tokio::test]
-    async fn test_deposit_withdraw_transaction() {
-        let wallet_manager = Arc::new(WalletManager::init());
-        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let wallet_manager_runner = tokio::spawn({
-            let wallet_manager = wallet_manager.clone();
-            async move { wallet_manager.run(tx_receiver, err_sender).await }
-        });
         let client = Client::new(1);
-        let deposit_amount = Amount::unsafe_new(100.0);
-        let transactions = vec![
+        let deposit = |tx: u32| Transaction::Deposit {
+            client,
+            tx_id: TransactionId::new(tx),
+            amount: Amount::from_major(10, 0),
+        };
+        let exported = WalletManager::init();
+        exported.apply(deposit(1).into()).unwrap();
+        exported.apply(deposit(2).into()).unwrap();
+        exported
+            .apply(
+                Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(1),
+                }
+                .into(),
+            )
+            .unwrap();
+        let mut export = Vec::new();
+        write_wallets_csv(
+            &mut export,
+            &exported.export_wallets(),
+            &ExportOptions::default(),
+        )
+        .unwrap();
+
+        let restored = WalletManager::init();
+        for wallet in read_wallets_csv(export.as_slice()).unwrap() {
+            restored.restore(wallet);
+        }
+        let resolve = Transaction::Resolve {
+            client,
+            tx_id: TransactionId::new(1),
+        };
+        assert_eq!(
+            restored.apply(resolve.into()).unwrap_err().kind,
+            FailureKind::DisputeNotFound
+        );
+        let dispute = Transaction::Dispute {
+            client,
+            tx_id: TransactionId::new(2),
+        };
+        assert_eq!(
+            restored.apply(dispute.into()).unwrap_err().kind,
+            FailureKind::TransactionNotFound
+        );
+        let wallet = restored.wallet(client).unwrap();
+        assert_eq!(wallet.held(), Amount::from_major(10, 0));
+        assert_eq!(wallet.available(), Amount::from_major(10, 0));
+    }
+
+    /// Peak resident set size of this process, where the platform reports it.
+    fn peak_rss_bytes() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let kib = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kib * 1024)
+    }
+
+    /// Run with `cargo test --release -- --ignored stress`.
+    #[test]
+    #[ignore = "takes a while; run in release mode"]
+    fn stress_ten_million_transactions() {
+        const TRANSACTIONS: u32 = 10_000_000;
+        const MIN_PER_SECOND: f64 = 500_000.0;
+        const MAX_RSS_BYTES: u64 = 2 << 30;
+
+        let wallet_manager = WalletManager::init();
+        let started = std::time::Instant::now();
+        for tx in 0..TRANSACTIONS {
+            let client = Client::new((tx % u16::MAX as u32) as u16);
+            let tx_id = TransactionId::new(tx);
+            let transaction = match tx % 10 {
+                0..=6 => Transaction::Deposit {
+                    client,
+                    tx_id,
+                    amount: Amount::from_major(10, 0),
+                },
+                7 | 8 => Transaction::Withdrawal {
+                    client,
+                    tx_id,
+                    amount: Amount::from_major(5, 0),
+                },
+                _ => Transaction::Dispute {
+                    client,
+                    tx_id: TransactionId::new(tx - 9),
+                },
+            };
+            let _ = wallet_manager.apply(transaction.into());
+        }
+        let per_second = f64::from(TRANSACTIONS) / started.elapsed().as_secs_f64();
+
+        assert_eq!(wallet_manager.export_wallets().len(), u16::MAX as usize);
+        assert!(
+            per_second >= MIN_PER_SECOND,
+            "processed {per_second:.0} transactions/s, expected at least {MIN_PER_SECOND}"
+        );
+        if let Some(rss) = peak_rss_bytes() {
+            assert!(
+                rss <= MAX_RSS_BYTES,
+                "peak RSS was {rss} bytes, expected at most {MAX_RSS_BYTES}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_journal_keeps_sequence_numbers() {
+        for journal_dir in [None, Some(std::env::temp_dir())] {
+            let wallet_manager = WalletManager::with_config(Config {
+                journal_dir,
+                ..Config::default()
+            });
+            let client = Client::new(1);
+            let withdrawal = |tx: u32, amount: u64| Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(amount, 0),
+            };
+            let seq_of = |manager: &WalletManager, tx: u32| {
+                let entry = manager.transaction_journal.get(TransactionId::new(tx));
+                entry.unwrap().map(|entry| entry.seq)
+            };
+
+            wallet_manager
+                .apply(
+                    Transaction::Deposit {
+                        client,
+                        tx_id: TransactionId::new(1),
+                        amount: Amount::from_major(10, 0),
+                    }
+                    .into(),
+                )
+                .unwrap();
+            wallet_manager.apply(withdrawal(2, 50).into()).unwrap_err();
+            wallet_manager.apply(withdrawal(3, 4).into()).unwrap();
+            let reservation = wallet_manager
+                .reserve(client, TransactionId::new(4), Amount::from_major(1, 0))
+                .unwrap();
+            wallet_manager.commit(reservation).unwrap().unwrap();
+
+            assert_eq!(seq_of(&wallet_manager, 1), Some(Some(1)));
+            assert_eq!(seq_of(&wallet_manager, 2), None);
+            assert_eq!(seq_of(&wallet_manager, 3), Some(Some(3)));
+            assert_eq!(seq_of(&wallet_manager, 4), Some(Some(4)));
+
+            // Moving the wallet to another manager keeps them.
+            let other = WalletManager::init();
+            let handoff = wallet_manager.hand_off(client).unwrap().unwrap();
+            other.take_over(handoff).unwrap();
+            assert_eq!(seq_of(&other, 3), Some(Some(3)));
+            assert_eq!(seq_of(&other, 4), Some(Some(4)));
+        }
+    }
+
+    #[test]
+    fn test_recovering_after_a_crash_matches_an_uninterrupted_run() {
+        use crate::durability::FsyncPolicy;
+        use crate::persistence::PersistenceOptions;
+        use std::io::Write;
+
+        let dir =
+            std::env::temp_dir().join(format!("walletmanagermock-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config {
+            persistence: Some(PersistenceOptions {
+                dir: dir.clone(),
+                fsync: FsyncPolicy::Never,
+                checkpoint_every: Some(3),
+            }),
+            ..Config::default()
+        };
+        let (one, two) = (Client::new(1), Client::new(2));
+        let transactions = [
             Transaction::Deposit {
-                client: client.clone(),
+                client: one,
                 tx_id: TransactionId::new(1),
-                amount: deposit_amount.clone(),
+                amount: Amount::from_major(10, 0),
+            },
+            Transaction::Deposit {
+                client: two,
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(20, 0),
+            },
+            Transaction::Withdrawal {
+                client: one,
+                tx_id: TransactionId::new(3),
+                amount: Amount::from_major(4, 0),
+            },
+            Transaction::Dispute {
+                client: two,
+                tx_id: TransactionId::new(2),
             },
             Transaction::Withdrawal {
-                client: client.clone(),
+                client: two,
+                tx_id: TransactionId::new(4),
+                amount: Amount::from_major(50, 0),
+            },
+            Transaction::ChargeBack {
+                client: two,
                 tx_id: TransactionId::new(2),
-                amount: deposit_amount.clone(),
+            },
+            Transaction::Dispute {
+                client: one,
+                tx_id: TransactionId::new(1),
+            },
+            Transaction::Resolve {
+                client: one,
+                tx_id: TransactionId::new(1),
             },
         ];
-        for transaction in transactions {
-            tx_sender.send(transaction).unwrap();
+        let wallets = |manager: &WalletManager| {
+            let mut wallets = manager.export_wallets();
+            wallets.sort_by_key(|w| w.client());
+            wallets
+        };
+
+        let reference = WalletManager::init();
+        for &transaction in &transactions {
+            let _ = reference.apply(transaction.into());
         }
-        drop(tx_sender);
-        wallet_manager_runner.await.unwrap();
 
-        let wallets = wallet_manager.export_wallets();
-        assert_eq!(wallets.len(), 1);
-        assert_eq!(wallets[0].client, client);
+        // Checkpointed after the third transaction, the next two only in the log.
+        let crashed = WalletManager::with_config(config.clone());
+        for &transaction in &transactions[..5] {
+            let _ = crashed.apply(transaction.into());
+        }
+        drop(crashed);
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("wal.log"))
+            .unwrap();
+        log.write_all(&[7, 0, 0]).unwrap();
+
+        let recovered = WalletManager::with_config(config);
+        let recovery = recovered.recover().unwrap();
+        assert_eq!(recovery.snapshot_seq, 3);
+        assert_eq!(recovery.wallets, 2);
+        assert_eq!(recovery.replayed, 2);
+        assert!(recovery.torn);
+        assert_eq!(recovered.last_sequence(), 5);
+        for &transaction in &transactions[5..] {
+            let _ = recovered.apply(transaction.into());
+        }
+        assert_eq!(wallets(&recovered), wallets(&reference));
+        recovered.verify_totals().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_replays_committed_reservations_and_releases_pending_ones() {
+        use crate::durability::FsyncPolicy;
+        use crate::persistence::PersistenceOptions;
+
+        let dir = std::env::temp_dir().join(format!(
+            "walletmanagermock-wal-reservations-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config {
+            persistence: Some(PersistenceOptions {
+                dir: dir.clone(),
+                fsync: FsyncPolicy::Never,
+                checkpoint_every: None,
+            }),
+            ..Config::default()
+        };
+        let client = Client::new(1);
+        let crashed = WalletManager::with_config(config.clone());
+        crashed
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(10, 0),
+                }
+                .into(),
+            )
+            .unwrap();
+        let payment = crashed
+            .reserve(client, TransactionId::new(2), Amount::from_major(4, 0))
+            .unwrap();
+        crashed
+            .reserve(client, TransactionId::new(3), Amount::from_major(3, 0))
+            .unwrap();
+        crashed.checkpoint().unwrap();
+        crashed.commit(payment).unwrap().unwrap();
+        assert_eq!(crashed.last_sequence(), 2);
+        drop(crashed);
+
+        let recovered = WalletManager::with_config(config);
+        let recovery = recovered.recover().unwrap();
+        assert_eq!(recovery.snapshot_seq, 1);
+        assert_eq!(recovery.replayed, 1);
+        let wallet = recovered.wallet(client).unwrap();
         assert_eq!(
-            wallets[0].balance,
-            Balance {
-                available: Amount::zero(),
-                held: Amount::zero(),
-                total: Amount::zero(),
-            }
+            [wallet.available(), wallet.held(), wallet.total()].map(|a| a.to_string()),
+            ["6.0000", "0.0000", "6.0000"]
         );
+        // The replayed commit is journaled, so its transaction id stays taken.
+        let failure = recovered
+            .reserve(client, TransactionId::new(2), Amount::from_major(1, 0))
+            .unwrap_err();
+        assert_eq!(failure.kind, FailureKind::DuplicateTransaction);
+        recovered.verify_totals().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    #[tokio::test]
-    async fn test_dispute_resolve_transaction() {
-        let wallet_manager = Arc::new(WalletManager::init());
-        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let wallet_manager_runner = tokio::spawn({
-            let wallet_manager = wallet_manager.clone();
-            async move { wallet_manager.run(tx_receiver, err_sender).await }
-        });
-        let client = Client::new(1);
-        let deposit_amount = Amount::unsafe_new(100.0);
-        tx_sender
-            .send(Transaction::Deposit {
-                client: client.clone(),
-                tx_id: TransactionId::new(1),
-                amount: deposit_amount.clone(),
-            })
+    #[test]
+    fn test_recovery_replays_transfers() {
+        use crate::durability::FsyncPolicy;
+        use crate::persistence::PersistenceOptions;
+
+        let dir = std::env::temp_dir().join(format!(
+            "walletmanagermock-wal-transfers-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config {
+            persistence: Some(PersistenceOptions {
+                dir: dir.clone(),
+                fsync: FsyncPolicy::Never,
+                checkpoint_every: None,
+            }),
+            ..Config::default()
+        };
+        let (alice, bob) = (Client::new(1), Client::new(2));
+        let crashed = WalletManager::with_config(config.clone());
+        crashed
+            .apply(
+                Transaction::Deposit {
+                    client: alice,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(10, 0),
+                }
+                .into(),
+            )
             .unwrap();
-        tx_sender
-            .send(Transaction::Dispute {
-                client: client.clone(),
-                tx_id: TransactionId::new(1),
-            })
+        crashed
+            .transfer(alice, bob, TransactionId::new(2), Amount::from_major(4, 0))
             .unwrap();
-        tx_sender
-            .send(Transaction::Resolve {
-                client: client.clone(),
-                tx_id: TransactionId::new(1),
+        crashed
+            .transfer(alice, bob, TransactionId::new(3), Amount::from_major(7, 0))
+            .unwrap_err();
+        drop(crashed);
+
+        let recovered = WalletManager::with_config(config);
+        let recovery = recovered.recover().unwrap();
+        assert_eq!(recovery.replayed, 3);
+        assert_eq!(recovered.last_sequence(), 3);
+        assert_eq!(
+            [alice, bob].map(|client| recovered.wallet(client).unwrap().total()),
+            [Amount::from_major(6, 0), Amount::from_major(4, 0)]
+        );
+        recovered.verify_totals().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_replays_log_frames_written_out_of_order() {
+        use crate::durability::FsyncPolicy;
+        use crate::persistence::PersistenceOptions;
+        use crate::wal::Wal;
+
+        let dir = std::env::temp_dir().join(format!(
+            "walletmanagermock-wal-interleaved-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let deposit = |seq, tx, amount| Envelope {
+            seq: Some(seq),
+            ..Envelope::from(Transaction::Deposit {
+                client: Client::new(1),
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(amount, 0),
             })
-            .unwrap();
-        drop(tx_sender);
-        wallet_manager_runner.await.unwrap();
+        };
+        // Two appliers numbered their transactions 2 and 3, the second logging first.
+        let mut wal = Wal::open(&dir.join("wal.log"), FsyncPolicy::Never).unwrap();
+        for envelope in [deposit(1, 1, 1), deposit(3, 3, 4), deposit(2, 2, 2)] {
+            wal.append(&envelope).unwrap();
+        }
+        drop(wal);
 
-        let wallets = wallet_manager.export_wallets();
-        assert_eq!(wallets.len(), 1);
-        assert_eq!(wallets[0].client, client);
+        let recovered = WalletManager::with_config(Config {
+            persistence: Some(PersistenceOptions {
+                dir: dir.clone(),
+                fsync: FsyncPolicy::Never,
+                checkpoint_every: None,
+            }),
+            ..Config::default()
+        });
+        let recovery = recovered.recover().unwrap();
+        assert_eq!(recovery.replayed, 3);
+        assert_eq!(recovered.last_sequence(), 3);
         assert_eq!(
-            wallets[0].balance,
-            Balance {
-                available: deposit_amount.clone(),
-                held: Amount::zero(),
-                total: deposit_amount.clone(),
-            }
+            recovered.wallet(Client::new(1)).unwrap().total(),
+            Amount::from_major(7, 0)
         );
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    #[tokio::test]
-    async fn test_dispute_chargeback_transaction() {
-        let wallet_manager = Arc::new(WalletManager::init());
-        let (tx_sender, tx_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let (err_sender, _err_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let wallet_manager_runner = tokio::spawn({
-            let wallet_manager = wallet_manager.clone();
-            async move { wallet_manager.run(tx_receiver, err_sender).await }
+    #[test]
+    fn test_sessions_account_for_their_changes_to_shared_wallets() {
+        let wallet_manager = WalletManager::with_config(Config {
+            session: Some("day-1".to_string()),
+            ..Config::default()
         });
         let client = Client::new(1);
-        let deposit_amount = Amount::unsafe_new(100.0);
-        tx_sender
-            .send(Transaction::Deposit {
-                client: client.clone(),
-                tx_id: TransactionId::new(1),
-                amount: deposit_amount.clone(),
-            })
+        let in_day_2 = |transaction: Transaction| {
+            let mut envelope = Envelope::from(transaction);
+            envelope.attributes.insert(SESSION_ATTRIBUTE, "day-2");
+            envelope
+        };
+        wallet_manager
+            .apply(
+                Transaction::Deposit {
+                    client,
+                    tx_id: TransactionId::new(1),
+                    amount: Amount::from_major(10, 0),
+                }
+                .into(),
+            )
             .unwrap();
-        tx_sender
-            .send(Transaction::Dispute {
-                client: client.clone(),
-                tx_id: TransactionId::new(1),
-            })
+        wallet_manager
+            .apply(in_day_2(Transaction::Withdrawal {
+                client,
+                tx_id: TransactionId::new(2),
+                amount: Amount::from_major(4, 0),
+            }))
             .unwrap();
-        tx_sender
-            .send(Transaction::ChargeBack {
-                client: client.clone(),
+        wallet_manager
+            .apply(in_day_2(Transaction::Dispute {
+                client,
                 tx_id: TransactionId::new(1),
-            })
+            }))
             .unwrap();
-        drop(tx_sender);
-        wallet_manager_runner.await.unwrap();
-
-        let wallets = wallet_manager.export_wallets();
-        assert_eq!(wallets.len(), 1);
-        assert_eq!(wallets[0].client, client);
-        assert_eq!(wallets[0].locked, true);
-        assert_eq!(
-            wallets[0].balance,
-            Balance {
-                available: Amount::zero(),
-                held: Amount::zero(),
-                total: Amount::zero(),
-            }
+        assert!(
+            wallet_manager
+                .apply(in_day_2(Transaction::Withdrawal {
+                    client,
+                    tx_id: TransactionId::new(3),
+                    amount: Amount::from_major(1, 0),
+                }))
+                .is_err()
         );
+
+        let activity = wallet_manager.session_activity();
+        assert_eq!(activity.len(), 2);
+        assert_eq!(activity[0].session, "day-1");
+        assert_eq!(activity[0].total, Amount::from_major(10, 0));
+        assert_eq!((activity[0].first_seq, activity[0].last_seq), (1, 1));
+        let day_2 = &activity[1];
+        assert_eq!((day_2.transactions, day_2.failed), (3, 1));
+        assert_eq!(day_2.available, -Amount::from_minor_units(140_000));
+        assert_eq!(day_2.held, Amount::from_major(10, 0));
+        assert_eq!(day_2.total, -Amount::from_minor_units(40_000));
+        assert_eq!((day_2.first_seq, day_2.last_seq), (2, 4));
+        let wallet = wallet_manager.wallet(client).unwrap();
+        assert_eq!(wallet.total(), activity[0].total + day_2.total);
     }
 }
@@ -0,0 +1,109 @@
+//! Incremental runs over a cumulative feed, which gains rows at its end between runs. Starting
+//! from the wallet export of the previous run, the rows that run applied already are skipped up
+//! to its high watermark instead of being applied twice.
+
+use crate::transaction::{Envelope, Timestamp, Transaction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+    /// The first deposit or withdrawal id not applied yet.
+    Tx(u32),
+    /// The first timestamp not applied yet.
+    Timestamp(Timestamp),
+}
+
+/// Tells the rows of the already processed prefix of an input, and tracks the watermark to pass to
+/// the next run.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessedPrefix {
+    watermark: Option<Watermark>,
+    passed: bool,
+    skipped: u64,
+    highest_tx: Option<u32>,
+}
+
+impl ProcessedPrefix {
+    pub fn new(watermark: Option<Watermark>) -> Self {
+        ProcessedPrefix {
+            passed: watermark.is_none(),
+            watermark,
+            ..ProcessedPrefix::default()
+        }
+    }
+
+    /// Whether `envelope` is part of the processed prefix. The prefix ends at the first deposit
+    /// or withdrawal at or above a `Tx` watermark, or the first row stamped at or after a
+    /// `Timestamp` one; no later row is skipped, so disputes of earlier transactions still apply.
+    pub fn skips(&mut self, envelope: &Envelope) -> bool {
+        let transaction = &envelope.transaction;
+        if let Transaction::Deposit { tx_id, .. } | Transaction::Withdrawal { tx_id, .. } =
+            transaction
+        {
+            self.highest_tx = self.highest_tx.max(Some(tx_id.id()));
+        }
+        if !self.passed {
+            self.passed = match self.watermark {
+                Some(Watermark::Tx(tx)) => {
+                    transaction.amount().is_some() && transaction.tx_id().id() >= tx
+                }
+                Some(Watermark::Timestamp(at)) => envelope.timestamp.is_some_and(|t| t >= at),
+                None => true,
+            };
+        }
+        if !self.passed {
+            self.skipped += 1;
+        }
+        !self.passed
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// The `--skip-before-tx` watermark of a run over the same feed once it has grown.
+    pub fn next_watermark(&self) -> Option<u32> {
+        self.highest_tx.map(|tx| tx + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Amount, Client, TransactionId};
+
+    #[test]
+    fn test_prefix_ends_at_watermark() {
+        let client = Client::new(1);
+        let deposit = |tx| {
+            Envelope::from(Transaction::Deposit {
+                client,
+                tx_id: TransactionId::new(tx),
+                amount: Amount::from_major(1, 0),
+            })
+        };
+        let dispute = |tx| {
+            Envelope::from(Transaction::Dispute {
+                client,
+                tx_id: TransactionId::new(tx),
+            })
+        };
+        let feed = [deposit(1), dispute(1), deposit(2), dispute(1), deposit(3)];
+
+        let mut prefix = ProcessedPrefix::new(Some(Watermark::Tx(2)));
+        let skipped: Vec<_> = feed.iter().map(|e| prefix.skips(e)).collect();
+        assert_eq!(skipped, [true, true, false, false, false]);
+        assert_eq!(prefix.skipped(), 2);
+        assert_eq!(prefix.next_watermark(), Some(4));
+
+        let mut prefix = ProcessedPrefix::new(None);
+        assert!(feed.iter().all(|e| !prefix.skips(e)));
+
+        let mut stamped = feed.clone();
+        for (secs, envelope) in stamped.iter_mut().enumerate() {
+            envelope.timestamp = Some(Timestamp::from_secs(secs as i64));
+        }
+        let mut prefix = ProcessedPrefix::new(Some(Watermark::Timestamp(Timestamp::from_secs(3))));
+        let skipped: Vec<_> = stamped.iter().map(|e| prefix.skips(e)).collect();
+        assert_eq!(skipped, [true, true, true, false, false]);
+    }
+}
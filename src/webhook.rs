@@ -0,0 +1,247 @@
+//! Failure sink POSTing batches of failures as JSON arrays to a webhook. Deliveries are retried
+//! with exponential backoff; batches the endpoint still refuses are appended to a local spool
+//! file and resent, oldest first, once it accepts requests again.
+
+use crate::outbox;
+use crate::transaction::Failure;
+use log::{error, warn};
+use reqwest::header::CONTENT_TYPE;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub struct WebhookOptions {
+    pub url: String,
+    /// Failures sent in one request at most.
+    pub batch_size: usize,
+    /// A partial batch is sent once it is this old, and the spool is retried this often.
+    pub flush_interval: Duration,
+    /// JSON lines file holding one undelivered batch per line.
+    pub spool: PathBuf,
+    pub initial_backoff: Duration,
+    pub max_attempts: u32,
+}
+
+pub struct WebhookSink {
+    options: WebhookOptions,
+    http: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(options: WebhookOptions) -> Self {
+        WebhookSink {
+            options,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Delivers failures until the channel closes, then flushes the last batch.
+    pub async fn run(self, mut failures: UnboundedReceiver<Failure>) {
+        let mut batch = Vec::new();
+        let mut ticker = tokio::time::interval(self.options.flush_interval);
+        loop {
+            tokio::select! {
+                failure = failures.recv() => match failure {
+                    Some(failure) => {
+                        batch.push(failure);
+                        if batch.len() >= self.options.batch_size {
+                            self.flush(&mut batch).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush(&mut batch).await,
+            }
+        }
+        self.flush(&mut batch).await;
+    }
+
+    async fn flush(&self, batch: &mut Vec<Failure>) {
+        let spool_drained = self.drain_spool().await;
+        if batch.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&batch) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to encode {} failures: {e}", batch.len());
+                batch.clear();
+                return;
+            }
+        };
+        batch.clear();
+        // Keep the spool in order: while older batches wait, newer ones queue up behind them.
+        if (!spool_drained || !self.post(&body).await)
+            && let Err(e) = self.append_to_spool(&body)
+        {
+            error!("Failed to spool failures, dropping them: {e}");
+        }
+    }
+
+    /// Resends spooled batches, returning whether the spool is now empty.
+    async fn drain_spool(&self) -> bool {
+        let spooled = match fs::read_to_string(&self.options.spool) {
+            Ok(spooled) => spooled,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return true,
+            Err(e) => {
+                error!("Failed to read failure spool: {e}");
+                return false;
+            }
+        };
+        let lines: Vec<&str> = spooled.lines().filter(|l| !l.is_empty()).collect();
+        let mut delivered = 0;
+        for line in &lines {
+            if !self.post(line).await {
+                break;
+            }
+            delivered += 1;
+        }
+        let result = if delivered == lines.len() {
+            fs::remove_file(&self.options.spool)
+        } else if delivered > 0 {
+            let remaining: String = lines[delivered..]
+                .iter()
+                .map(|l| format!("{l}\n"))
+                .collect();
+            fs::write(&self.options.spool, remaining)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            error!("Failed to update failure spool: {e}");
+        }
+        delivered == lines.len()
+    }
+
+    /// POSTs `body`, retrying with exponential backoff. Returns whether it was accepted.
+    async fn post(&self, body: &str) -> bool {
+        let mut backoff = self.options.initial_backoff;
+        for attempt in 1..=self.options.max_attempts {
+            let response = self
+                .http
+                .post(&self.options.url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match response {
+                Ok(_) => return true,
+                Err(e) => warn!("Failure webhook attempt {attempt} failed: {e}"),
+            }
+            if attempt < self.options.max_attempts {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        false
+    }
+
+    fn append_to_spool(&self, body: &str) -> io::Result<()> {
+        let mut spool = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.options.spool)?;
+        writeln!(spool, "{body}")
+    }
+}
+
+/// Outbox records are POSTed one by one, with the same retries as batches.
+impl outbox::Target for WebhookSink {
+    async fn deliver(&self, record: &str) -> bool {
+        self.post(record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Client, TransactionId};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Minimal HTTP endpoint answering 200 and forwarding every request body.
+    async fn endpoint(listener: TcpListener) -> mpsc::UnboundedReceiver<String> {
+        let (bodies, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufReader::new(stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                stream.read_exact(&mut body).await.unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                bodies.send(String::from_utf8(body).unwrap()).unwrap();
+            }
+        });
+        received
+    }
+
+    fn failure(tx: u32) -> Failure {
+        Failure::insufficient_funds(Client::new(1), TransactionId::new(tx))
+    }
+
+    #[tokio::test]
+    async fn test_spools_while_down_and_resends_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let spool = std::env::temp_dir().join(format!(
+            "walletmanagermock-webhook-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&spool);
+        let options = || WebhookOptions {
+            url: format!("http://{addr}/failures"),
+            batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            spool: spool.clone(),
+            initial_backoff: Duration::from_millis(1),
+            max_attempts: 2,
+        };
+
+        // Nobody accepts connections yet: both batches end up in the spool.
+        drop(listener);
+        let (send, recv) = mpsc::unbounded_channel();
+        for tx in 1..=3 {
+            send.send(failure(tx)).unwrap();
+        }
+        drop(send);
+        WebhookSink::new(options()).run(recv).await;
+        assert_eq!(fs::read_to_string(&spool).unwrap().lines().count(), 2);
+
+        let mut bodies = endpoint(TcpListener::bind(addr).await.unwrap()).await;
+        let (send, recv) = mpsc::unbounded_channel();
+        send.send(failure(4)).unwrap();
+        drop(send);
+        WebhookSink::new(options()).run(recv).await;
+
+        let mut txs = Vec::new();
+        for _ in 0..3 {
+            let body: serde_json::Value =
+                serde_json::from_str(&bodies.recv().await.unwrap()).unwrap();
+            txs.extend(
+                body.as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|f| f["tx"].as_u64().unwrap()),
+            );
+        }
+        assert_eq!(txs, vec![1, 2, 3, 4]);
+        assert!(!spool.exists());
+    }
+}
@@ -0,0 +1,191 @@
+//! Snapshot tests running the binary over every scenario in `tests/fixtures`. Review changed
+//! snapshots with `cargo insta review`.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_scenarios() {
+    insta::glob!("fixtures/*.csv", |path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+            .arg(path)
+            .arg("--summary")
+            .env("RUST_LOG", "info")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+
+        // Wallets are exported in hash map order.
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        if let Some(rows) = lines.get_mut(1..) {
+            rows.sort_unstable();
+        }
+        insta::assert_snapshot!("wallets", lines.join("\n"));
+
+        // Failures are logged, followed by the summary.
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        insta::with_settings!({ filters => vec![
+            (r"\[\S+Z ", "["),
+            (r#""duration_ms":\d+"#, r#""duration_ms":[ms]"#),
+            // Built with the napi feature, the binary looks for a Node-API host on start.
+            (r"Load Node-API \[\w+\] from host runtime failed: .*\n", ""),
+        ] }, {
+            insta::assert_snapshot!("failures_and_summary", stderr);
+        });
+    });
+}
+
+#[test]
+fn test_input_streams_from_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+}
+
+#[test]
+fn test_incremental_run_skips_applied_prefix() {
+    let dir = std::env::temp_dir().join(format!("incremental-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |feed: &str, args: &[&str]| {
+        let input = dir.join("feed.csv");
+        std::fs::write(&input, feed).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+            .arg(&input)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let first = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.0\n";
+    let grown = format!("{first}deposit,1,3,2.0\nwithdrawal,1,4,0.5\n");
+
+    let state = dir.join("state.csv");
+    std::fs::write(&state, run(first, &["--export-seq"])).unwrap();
+    let state = state.to_str().unwrap();
+    assert_eq!(
+        run(&grown, &["--initial-state", state, "--skip-before-tx", "3"]),
+        "client,available,held,total,locked\n1,5.5000,0.0000,5.5000,false\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_environment_ranks_below_config_file_and_flags() {
+    let dir = std::env::temp_dir().join(format!("environment-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\nwithdrawal,1,3,2.0\n",
+    )
+    .unwrap();
+    let config = dir.join("config.toml");
+    std::fs::write(&config, "max_withdrawal = \"3.0\"\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+            .env("WM_INPUT", &input)
+            .env("WM_MAX_WITHDRAWAL", "1.0")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let config = config.to_str().unwrap();
+    assert_eq!(
+        run(&[]),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+    );
+    assert_eq!(
+        run(&["--config", config]),
+        "client,available,held,total,locked\n1,8.0000,0.0000,8.0000,false\n"
+    );
+    assert_eq!(
+        run(&["--config", config, "--max-withdrawal", "5.0"]),
+        "client,available,held,total,locked\n1,4.0000,0.0000,4.0000,false\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_streamed_wallets_are_not_reopened_by_ungrouped_rows() {
+    let dir = std::env::temp_dir().join(format!("ungrouped-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\ndeposit,1,3,1.0\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+        .arg(&input)
+        .arg("--stream-closed-wallets")
+        .env("RUST_LOG", "info")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,5.0000,0.0000,5.0000,false\n\
+         2,3.0000,0.0000,3.0000,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("client 1 tx 3: Wallet was closed earlier in the input (WalletClosed)"),
+        "{stderr}"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_quarantine_output_is_valid_input() {
+    let dir = std::env::temp_dir().join(format!("quarantine-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (input, quarantine) = (dir.join("input.csv"), dir.join("quarantine.csv"));
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\nchargeback,1,1,\n\
+         deposit,1,2,2.5\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+        .arg(&input)
+        .arg("--quarantine-output")
+        .arg(&quarantine)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let quarantined = std::fs::read_to_string(&quarantine).unwrap();
+    assert_eq!(quarantined.lines().count(), 2, "{quarantined}");
+
+    // Passed back as input, e.g. once the wallet is unlocked, the deposit goes through.
+    let output = Command::new(env!("CARGO_BIN_EXE_walletmanagermock"))
+        .arg(&quarantine)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}